@@ -1,11 +1,15 @@
 use serde::{Deserialize, Serialize};
-use tauri::{AppHandle, Manager};
-use tracing::info;
+use tauri::{AppHandle, Emitter, Manager};
+use tracing::{info, warn};
 use std::path::PathBuf;
 
 use crate::app_conf::AppConf;
 use crate::config::{self, ProxyState};
+use crate::error::CommandError;
+use crate::locale;
 use crate::proxy;
+use crate::session_store;
+use crate::web_storage::{self, WebStorageUsage};
 
 /// Login result returned to the frontend
 #[derive(Debug, Serialize, Deserialize)]
@@ -16,6 +20,17 @@ pub struct LoginResult {
     pub auth_mode: String,
 }
 
+/// Who the current session belongs to, from `session_info`
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SessionInfo {
+    pub authenticated: bool,
+    /// User-info payload as returned by the server
+    pub user: Option<serde_json::Value>,
+    pub roles: Vec<String>,
+    /// Token expiry (Unix seconds); 0 = unknown
+    pub expires_at: u64,
+}
+
 /// Server metadata from .well-known/yao
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct WellKnownInfo {
@@ -24,6 +39,23 @@ pub struct WellKnownInfo {
     pub openapi: Option<String>,
     pub dashboard: Option<String>,
     pub issuer_url: Option<String>,
+    /// Set when the URL to connect with differs from the one checked: it was
+    /// normalized, or the server only answered over plain http (see `check_server`)
+    #[serde(default, skip_deserializing)]
+    pub effective_url: Option<String>,
+}
+
+/// Summary of an open webview window
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WindowInfo {
+    pub label: String,
+    /// Current URL (empty if the webview could not report it)
+    pub url: String,
+    pub title: String,
+    pub visible: bool,
+    pub focused: bool,
+    pub fullscreen: bool,
+    pub is_popup: bool,
 }
 
 /// Resolve the CUI build output directory
@@ -63,45 +95,221 @@ fn get_cui_dist_path(app: &AppHandle) -> PathBuf {
     fallback
 }
 
-/// Get the developer app config (loaded at startup)
+/// Get the developer app config (loaded at startup), minus secrets, with
+/// `logo` resolved for the current theme
+#[tauri::command]
+pub async fn get_app_conf(app: AppHandle) -> AppConf {
+    public_app_conf(&app)
+}
+
+/// Re-read config.json and apply settings that can change at runtime
+/// (global shortcut, tray entries)
+#[tauri::command]
+pub async fn reload_app_conf(app: AppHandle) -> AppConf {
+    crate::reload_app_conf(&app);
+    public_app_conf(&app)
+}
+
+/// The app's log level (`error` … `trace`), or the `RUST_LOG` filter it
+/// was started with
+#[tauri::command]
+pub fn get_log_level() -> String {
+    crate::logging::level()
+}
+
+/// Change the log level without a restart (`error`, `warn`, `info`,
+/// `debug` or `trace`); not saved across launches
+#[tauri::command]
+pub fn set_log_level(level: String) -> Result<String, String> {
+    let level = crate::logging::set_level(&level)?;
+    info!("Log level set to {}", level);
+    Ok(level)
+}
+
+/// Recent log lines for bug reports, oldest first (the last `limit`, or
+/// all that are kept)
 #[tauri::command]
-pub async fn get_app_conf() -> AppConf {
-    crate::app_conf::get_app_conf()
+pub fn get_recent_logs(limit: Option<usize>) -> Vec<crate::logging::LogEntry> {
+    crate::logging::recent(limit)
 }
 
-/// Check remote server availability via .well-known/yao
+/// Show the log files (`logs/` in the app data dir) in the OS file manager.
+/// Refused in kiosk mode.
 #[tauri::command]
-pub async fn check_server(server_url: String) -> Result<WellKnownInfo, String> {
-    let client = reqwest::Client::builder()
+pub fn open_log_dir() -> Result<(), String> {
+    config::check_not_kiosk("Showing the log folder")?;
+    let path = crate::logging::log_path().ok_or("Logs are not being written to disk")?;
+    crate::downloads::reveal_file(&path);
+    Ok(())
+}
+
+fn public_app_conf(app: &AppHandle) -> AppConf {
+    let mut conf = crate::app_conf::get_app_conf().without_secrets();
+    let dark = match config::cookie_value("__theme").as_deref().and_then(config::known_theme) {
+        Some("dark") => true,
+        Some("auto") => app.get_webview_window("main")
+            .and_then(|w| w.theme().ok())
+            .is_some_and(|t| t == tauri::Theme::Dark),
+        _ => false,
+    };
+    let logo = conf.logo_for_theme(dark).to_string();
+    if logo != conf.logo {
+        info!("Using {} logo: {}", if dark { "dark" } else { "light" }, logo);
+    }
+    conf.logo = logo;
+    conf
+}
+
+/// Problems found in config.json by the last (re)load; invalid fields use defaults
+#[tauri::command]
+pub fn get_config_errors() -> Vec<String> {
+    crate::app_conf::get_conf_errors()
+}
+
+/// Downloads started by the app this session, oldest first
+#[tauri::command]
+pub fn get_downloads() -> Vec<crate::downloads::DownloadInfo> {
+    crate::downloads::list()
+}
+
+/// Change where downloads are saved (empty = system Downloads) and persist it
+#[tauri::command]
+pub fn set_download_dir(dir: String) -> Result<(), String> {
+    let dir = crate::downloads::validate_dir(&dir)?;
+    if !dir.is_empty() {
+        std::fs::create_dir_all(&dir)
+            .map_err(|e| format!("Cannot create download directory {}: {}", dir, e))?;
+    }
+    config::save_download_dir(&dir)?;
+    info!("Download directory set to: {}", if dir.is_empty() { "system Downloads" } else { &dir });
+    Ok(())
+}
+
+/// Abort an in-flight download and delete its partial file
+#[tauri::command]
+pub async fn cancel_download(app: AppHandle, id: u64) -> Result<(), String> {
+    crate::cancel_file_download(&app, id).await
+}
+
+/// Restart a failed or cancelled download
+#[tauri::command]
+pub fn retry_download(app: AppHandle, id: u64) -> Result<(), String> {
+    crate::retry_file_download(&app, id)
+}
+
+/// Check remote server availability via .well-known/yao. Connection failures
+/// are diagnosed (DNS, refused, TLS, timeout); when an https URL on localhost
+/// or a private address can't be reached, plain http is tried once.
+#[tauri::command]
+pub async fn check_server(server_url: String) -> Result<WellKnownInfo, CommandError> {
+    let client = crate::upstream::client_builder()
         .timeout(std::time::Duration::from_secs(10))
         .build()
-        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+        .map_err(|e| CommandError::Internal(format!("Failed to create HTTP client: {}", e)))?;
+
+    let normalized = normalize_server_url(&server_url)?;
+    let result = match fetch_well_known(&client, &normalized).await {
+        Err(CommandError::Connect(failure)) => {
+            let Some(http_url) = http_fallback_url(&normalized) else {
+                return Err(CommandError::Connect(failure));
+            };
+            warn!("{}; trying {}", failure.message, http_url);
+            match fetch_well_known(&client, &http_url).await {
+                Ok(info) => Ok(WellKnownInfo { effective_url: Some(http_url), ..info }),
+                Err(_) => Err(CommandError::Connect(failure)),
+            }
+        }
+        result => result,
+    };
+    result.map(|info| match info.effective_url {
+        None if normalized != server_url.trim().trim_end_matches('/') => {
+            WellKnownInfo { effective_url: Some(normalized.clone()), ..info }
+        }
+        _ => info,
+    })
+}
 
-    let url = format!("{}/.well-known/yao", server_url.trim_end_matches('/'));
+/// Canonical server URL: scheme, host and port only. A missing scheme
+/// becomes https, or http for localhost and private addresses.
+fn normalize_server_url(input: &str) -> Result<String, CommandError> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Err(CommandError::Config("Server URL is empty".to_string()));
+    }
+    let invalid = |reason: &dyn std::fmt::Display| CommandError::Config(format!("Invalid server URL {:?}: {}", input, reason));
+    let url = if input.contains("://") {
+        url::Url::parse(input).map_err(|e| invalid(&e))?
+    } else {
+        let mut url = url::Url::parse(&format!("https://{}", input)).map_err(|e| invalid(&e))?;
+        if crate::error::is_private_host(&url) {
+            let _ = url.set_scheme("http");
+        }
+        url
+    };
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(CommandError::Config(format!("Unsupported server URL scheme: {}", url.scheme())));
+    }
+    if url.host_str().is_none_or(str::is_empty) {
+        return Err(invalid(&"missing host"));
+    }
+    Ok(url.origin().ascii_serialization())
+}
+
+/// Check each hop to `server_url` (server metadata, TLS, the user entry
+/// endpoint, the local port, a round trip through the proxy) for support
+/// reports. Proxy state is left as it is.
+#[tauri::command]
+pub async fn diagnose(server_url: String) -> Result<crate::diagnostics::DiagnosticReport, CommandError> {
+    let server_url = normalize_server_url(&server_url)?;
+    let client = crate::upstream::client_builder()
+        .timeout(std::time::Duration::from_secs(10))
+        .build()
+        .map_err(|e| CommandError::Internal(format!("Failed to create HTTP client: {}", e)))?;
+    let state = config::get_proxy_state();
+    let port = select_proxy_port(&crate::app_conf::get_app_conf(), &state);
+    Ok(crate::diagnostics::run(&client, &server_url, port, &state).await)
+}
+
+/// The http:// form of an https URL on localhost or a private address
+fn http_fallback_url(server_url: &str) -> Option<String> {
+    let mut url = url::Url::parse(server_url).ok()?;
+    if url.scheme() != "https" || !crate::error::is_private_host(&url) {
+        return None;
+    }
+    url.set_scheme("http").ok()?;
+    Some(url.as_str().trim_end_matches('/').to_string())
+}
+
+pub(crate) async fn fetch_well_known(client: &reqwest::Client, server_url: &str) -> Result<WellKnownInfo, CommandError> {
+    let url = format!("{}/.well-known/yao", server_url);
     info!("Checking server: {}", url);
 
-    let resp = client.get(&url).send().await
-        .map_err(|e| format!("Cannot connect to server: {}", e))?;
+    let resp = client.get(&url).send().await?;
 
     if !resp.status().is_success() {
-        return Err(format!("Server returned {}", resp.status()));
+        return Err(resp.status().into());
     }
 
-    let info: WellKnownInfo = resp.json().await
-        .map_err(|e| format!("Failed to parse server response: {}", e))?;
+    let body = resp.bytes().await?;
+    let info: WellKnownInfo = serde_json::from_slice(&body)?;
 
     Ok(info)
 }
 
-/// Start the local proxy server
+/// Start the local proxy server. `dashboard` and `issuer_url` come from
+/// `check_server`; callers that omit them get none.
 #[tauri::command]
 pub async fn start_proxy(
     app: AppHandle,
     server_url: String,
     token: String,
     auth_mode: String,
-    dashboard: String,
-) -> Result<u16, String> {
+    dashboard: Option<String>,
+    issuer_url: Option<String>,
+) -> Result<u16, CommandError> {
+    let server_url = normalize_server_url(&server_url)?;
+    let dashboard = dashboard.unwrap_or_default();
+    config::set_issuer_url(issuer_url.as_deref().unwrap_or_default());
     let state = config::get_proxy_state();
     if state.running {
         let server_changed = state.server_url != server_url;
@@ -125,31 +333,547 @@ pub async fn start_proxy(
         info!("Cookie file: {:?}", cookie_file);
         config::set_cookie_file(cookie_file);
         config::load_cookies();
+        if crate::app_conf::get_app_conf().proxy.request_log {
+            crate::request_log::start(app_data.join("logs"));
+        }
+        let key_dir = app.path().app_local_data_dir().unwrap_or_else(|_| app_data.clone());
+        session_store::init(&app_data, &key_dir);
     }
+    let restored = token.is_empty() && restore_session(&server_url, &dashboard);
 
     let cui_dist = get_cui_dist_path(&app);
     info!("CUI dist path: {:?}", cui_dist);
 
-    // Use port from developer config
     let conf = crate::app_conf::get_app_conf();
-    let port = proxy::start_proxy_server(cui_dist, conf.port).await?;
+    let port = select_proxy_port(&conf, &config::get_proxy_state());
+    let port = proxy::start_proxy_server(cui_dist, port).await?;
+    if restored {
+        spawn_session_check(app.clone());
+    }
+    spawn_health_monitor(app);
     Ok(port)
 }
 
+/// Server's user-info endpoint; also used to check a restored token
+const USER_INFO_PATH: &str = "/v1/user/profile";
+
+/// Apply the session saved for `server_url` on the last run, when
+/// "remember me" is on. Returns whether a token was restored.
+fn restore_session(server_url: &str, dashboard: &str) -> bool {
+    if !config::get_user_preferences().remembers_session() {
+        session_store::wipe();
+        return false;
+    }
+    let Some(saved) = session_store::load() else {
+        return false;
+    };
+    if saved.server_url.trim_end_matches('/') != server_url.trim_end_matches('/') {
+        return false;
+    }
+    config::update_proxy_state(server_url, &saved.token, &saved.auth_mode, dashboard);
+    if saved.token_expires_at != 0 {
+        config::set_token_expiry(saved.token_expires_at);
+    }
+    config::set_refresh_token(&saved.refresh_token);
+    info!("Restored saved session for {}", server_url);
+    true
+}
+
+/// Save the current session for the next launch, or forget it when
+/// "remember me" is off
+fn persist_session() {
+    let state = config::get_proxy_state();
+    if !config::get_user_preferences().remembers_session() {
+        session_store::wipe();
+    } else if state.running {
+        session_store::save(&state);
+    }
+}
+
+/// Check the restored token with the server (refreshing it first if it has
+/// expired). A rejected session is cleared and `session://expired` emitted.
+fn spawn_session_check(app: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let client = match crate::upstream::client_builder()
+            .timeout(std::time::Duration::from_secs(10))
+            .build()
+        {
+            Ok(c) => c,
+            Err(e) => {
+                warn!("Session check skipped: {}", e);
+                return;
+            }
+        };
+        if needs_refresh(&config::get_proxy_state(), unix_now()) {
+            if let Err(e) = renew_token(&client).await {
+                warn!("Restored session could not be refreshed: {}", e);
+            }
+        }
+        let state = config::get_proxy_state();
+        if state.token.is_empty() || session_accepted(&client, &state).await {
+            return;
+        }
+        // Leave alone a session the user signed in to while we were waiting
+        let current = config::get_proxy_state();
+        if current.server_url != state.server_url || current.token != state.token {
+            return;
+        }
+        config::update_proxy_state(&current.server_url, "", &current.auth_mode, &current.dashboard);
+        session_store::wipe();
+        info!("Saved session was rejected by the server");
+        let _ = app.emit("session://expired", "Saved session is no longer valid");
+    });
+}
+
+/// Whether the server still accepts the session's token. Only 401/403
+/// count as a rejection; an offline or erroring server keeps the session.
+async fn session_accepted(client: &reqwest::Client, state: &ProxyState) -> bool {
+    let url = format!("{}{}", state.server_url.trim_end_matches('/'), USER_INFO_PATH);
+    let mut req = client.get(&url).bearer_auth(&state.token);
+    let cookies = config::get_merged_cookies("", USER_INFO_PATH);
+    if !cookies.is_empty() {
+        req = req.header("Cookie", cookies);
+    }
+    match req.send().await {
+        Ok(resp) => !matches!(resp.status(), reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN),
+        Err(e) => {
+            warn!("Session check failed: {}", e);
+            true
+        }
+    }
+}
+
+/// Payload of the `server://switched` event
+#[derive(Debug, Clone, Serialize)]
+pub struct ServerSwitched {
+    pub server_url: String,
+    pub previous_server_url: String,
+    /// Local proxy port (unchanged by a switch)
+    pub port: u16,
+}
+
+/// Point the running proxy at another server. The local port stays the same,
+/// so the CUI origin, OAuth redirect URI and open SSE streams are unaffected;
+/// requests already in flight finish against the previous server.
+/// Cookies are kept per server (matched by domain), so switching back restores
+/// the earlier session. An empty `token` keeps the current one only when the
+/// host is unchanged.
+#[tauri::command]
+pub async fn switch_server(
+    app: AppHandle,
+    server_url: String,
+    token: String,
+    auth_mode: String,
+) -> Result<u16, String> {
+    let server_url = normalize_server_url(&server_url).map_err(|e| e.to_string())?;
+
+    let state = config::get_proxy_state();
+    if !state.running {
+        return Err("Proxy is not running".to_string());
+    }
+
+    let plan = plan_server_switch(&state, &server_url, &token, &auth_mode);
+    if plan.host_changed {
+        if let Some(host) = server_host(&state.server_url) {
+            config::adopt_legacy_cookies(&host);
+        }
+    }
+    config::update_proxy_state(&server_url, &plan.token, &plan.auth_mode, &plan.dashboard);
+    config::set_issuer_url(&plan.issuer_url);
+    persist_session();
+    info!("Switched server {} -> {} (port {})", state.server_url, server_url, state.port);
+
+    let _ = app.emit("server://switched", ServerSwitched {
+        server_url,
+        previous_server_url: state.server_url,
+        port: state.port,
+    });
+    Ok(state.port)
+}
+
+/// Proxy state to apply when switching servers
+#[derive(Debug, PartialEq)]
+struct ServerSwitchPlan {
+    token: String,
+    auth_mode: String,
+    dashboard: String,
+    issuer_url: String,
+    host_changed: bool,
+}
+
+fn server_host(server_url: &str) -> Option<String> {
+    url::Url::parse(server_url).ok()?.host_str().map(|h| h.to_lowercase())
+}
+
+/// Decide token/auth/dashboard/issuer for a switch. A new host never inherits
+/// the previous bearer token, dashboard path or OAuth issuer.
+fn plan_server_switch(state: &ProxyState, server_url: &str, token: &str, auth_mode: &str) -> ServerSwitchPlan {
+    let host_changed = server_host(&state.server_url) != server_host(server_url);
+    let keep = |new: &str, current: &str| {
+        if new.is_empty() && !host_changed { current.to_string() } else { new.to_string() }
+    };
+    ServerSwitchPlan {
+        token: keep(token, &state.token),
+        auth_mode: match keep(auth_mode, &state.auth_mode) {
+            m if m.is_empty() => "openapi".to_string(),
+            m => m,
+        },
+        dashboard: if host_changed { String::new() } else { state.dashboard.clone() },
+        issuer_url: if host_changed { String::new() } else { state.issuer_url.clone() },
+        host_changed,
+    }
+}
+
+/// Pick the port the proxy should bind: reuse the last bound port (so a
+/// restarted proxy keeps the same origin), otherwise the developer config.
+fn select_proxy_port(conf: &AppConf, state: &ProxyState) -> u16 {
+    if state.port != 0 {
+        state.port
+    } else {
+        conf.port
+    }
+}
+
 /// Get current proxy status
 #[tauri::command]
 pub async fn get_proxy_status() -> ProxyState {
     config::get_proxy_state()
 }
 
-/// Update the proxy auth token
+/// Update the proxy auth token. `expires_at` (Unix seconds) is only needed
+/// for opaque tokens; JWT expiry is read from the `exp` claim.
 #[tauri::command]
-pub async fn update_proxy_token(token: String) -> Result<(), String> {
+pub async fn update_proxy_token(token: String, expires_at: Option<u64>) -> Result<(), String> {
     let state = config::get_proxy_state();
     config::update_proxy_state(&state.server_url, &token, &state.auth_mode, &state.dashboard);
+    if let Some(ts) = expires_at {
+        config::set_token_expiry(ts);
+    }
+    persist_session();
     Ok(())
 }
 
+/// Sign in with the OAuth authorization-code flow (PKCE) in a popup or the
+/// system browser, then use the tokens for proxied requests. The server must
+/// allow `http://127.0.0.1:<port>/__yao_desktop/oauth/callback` as redirect_uri.
+#[tauri::command]
+pub async fn login_oauth(app: AppHandle) -> Result<LoginResult, CommandError> {
+    let tokens = crate::oauth::login(&app).await?;
+    let state = config::get_proxy_state();
+    config::update_proxy_state(&state.server_url, &tokens.access_token, "oauth", &state.dashboard);
+    if let Some(secs) = tokens.expires_in {
+        config::set_token_expiry(unix_now() + secs);
+    }
+    config::set_refresh_token(tokens.refresh_token.as_deref().unwrap_or_default());
+    persist_session();
+    info!("Signed in with OAuth (refresh token: {})", tokens.refresh_token.is_some());
+    Ok(LoginResult {
+        success: true,
+        message: String::new(),
+        token: tokens.access_token,
+        auth_mode: "oauth".to_string(),
+    })
+}
+
+/// Refresh the token this many seconds before it expires
+const TOKEN_REFRESH_MARGIN: u64 = 60;
+
+/// How often the background refresher checks the token expiry
+const TOKEN_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(15);
+
+/// Refresh the auth token now. Emits `session://expired` when the server
+/// rejects the session.
+#[tauri::command]
+pub async fn refresh_token(app: AppHandle) -> Result<(), String> {
+    let client = crate::upstream::client_builder()
+        .timeout(std::time::Duration::from_secs(10))
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+    renew_token(&client).await.map_err(|e| {
+        if matches!(e, CommandError::Unauthorized(_)) {
+            let _ = app.emit("session://expired", e.to_string());
+        }
+        e.to_string()
+    })
+}
+
+/// Wait before retry `failures` (1-based) of a refresh that failed on the
+/// network or with a server error: 5s, doubling up to a minute
+fn refresh_retry_delay(failures: u32) -> std::time::Duration {
+    let secs = 5u64.saturating_mul(1 << failures.saturating_sub(1).min(4));
+    std::time::Duration::from_secs(secs.min(60))
+}
+
+/// Whether a failed refresh ends the session: the server refused it
+/// (401/403, or an OAuth grant error), or the token has run out by now.
+/// Timeouts, DNS failures and 5xx responses are retried until then.
+fn refresh_failure_is_final(error: &CommandError, token_expires_at: u64, now: u64) -> bool {
+    matches!(error, CommandError::Unauthorized(_)) || now >= token_expires_at
+}
+
+/// Start the background task that refreshes the token shortly before expiry
+pub fn spawn_token_refresher(app: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let client = match crate::upstream::client_builder()
+            .timeout(std::time::Duration::from_secs(10))
+            .build()
+        {
+            Ok(c) => c,
+            Err(e) => {
+                warn!("Token refresher disabled: {}", e);
+                return;
+            }
+        };
+        let mut failures = 0;
+        loop {
+            let state = config::get_proxy_state();
+            if !needs_refresh(&state, unix_now()) {
+                failures = 0;
+            } else {
+                match renew_token(&client).await {
+                    Ok(()) => failures = 0,
+                    Err(e) if refresh_failure_is_final(&e, state.token_expires_at, unix_now()) => {
+                        warn!("Token refresh failed: {}", e);
+                        // Stop retrying this token; the user has to sign in again
+                        failures = 0;
+                        config::set_token_expiry(0);
+                        let _ = app.emit("session://expired", e.to_string());
+                    }
+                    Err(e) => {
+                        failures += 1;
+                        warn!("Token refresh failed, retrying in {:?}: {}", refresh_retry_delay(failures), e);
+                    }
+                }
+            }
+            let wait = if failures == 0 { TOKEN_CHECK_INTERVAL } else { refresh_retry_delay(failures) };
+            tokio::time::sleep(wait).await;
+        }
+    });
+}
+
+/// Consecutive failed checks before the server is reported offline
+const HEALTH_FAILURES_BEFORE_OFFLINE: u32 = 2;
+
+/// Payload of the `server://online` and `server://offline` events
+#[derive(Debug, Clone, Serialize)]
+pub struct ServerHealth {
+    pub server_url: String,
+    /// Why the last check failed (offline only)
+    pub error: Option<String>,
+}
+
+/// Reachability of one server from consecutive check results
+#[derive(Debug, Default)]
+struct HealthTracker {
+    online: Option<bool>,
+    failures: u32,
+}
+
+impl HealthTracker {
+    /// Record a check; returns the new state when it changed. One success
+    /// brings the server online, a short run of failures takes it offline.
+    fn record(&mut self, ok: bool) -> Option<bool> {
+        let next = if ok {
+            self.failures = 0;
+            true
+        } else {
+            self.failures += 1;
+            if self.failures < HEALTH_FAILURES_BEFORE_OFFLINE {
+                return None;
+            }
+            false
+        };
+        if self.online == Some(next) {
+            return None;
+        }
+        self.online = Some(next);
+        Some(next)
+    }
+}
+
+/// Start the task that checks `proxy.healthCheckPath` on the server every
+/// `proxy.healthCheckSecs`, emitting `server://online` / `server://offline`
+/// on changes. It ends when the proxy stops.
+fn spawn_health_monitor(app: AppHandle) {
+    let conf = crate::app_conf::get_app_conf().proxy;
+    if conf.health_check_secs == 0 {
+        return;
+    }
+    tauri::async_runtime::spawn(async move {
+        let client = match crate::upstream::client_builder()
+            .timeout(std::time::Duration::from_secs(10))
+            .build()
+        {
+            Ok(c) => c,
+            Err(e) => {
+                warn!("Health monitor disabled: {}", e);
+                return;
+            }
+        };
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(conf.health_check_secs));
+        let mut tracker = HealthTracker::default();
+        let mut server_url = String::new();
+        loop {
+            interval.tick().await;
+            let state = config::get_proxy_state();
+            if !state.running {
+                break;
+            }
+            if state.server_url != server_url {
+                server_url = state.server_url.clone();
+                tracker = HealthTracker::default();
+                config::set_server_online(None);
+            }
+            if server_url.is_empty() {
+                continue;
+            }
+            let url = format!("{}{}", server_url.trim_end_matches('/'), conf.health_check_path);
+            let error = match client.get(&url).send().await {
+                Ok(resp) if !resp.status().is_server_error() => None,
+                Ok(resp) => Some(format!("Server returned {}", resp.status())),
+                Err(e) => Some(crate::error::CommandError::from(e).to_string()),
+            };
+            // The server may have been switched while we waited
+            if config::get_proxy_state().server_url != server_url {
+                continue;
+            }
+            let Some(online) = tracker.record(error.is_none()) else {
+                continue;
+            };
+            config::set_server_online(Some(online));
+            if online {
+                info!("Server online: {}", server_url);
+                let _ = app.emit("server://online", ServerHealth { server_url: server_url.clone(), error: None });
+            } else {
+                warn!("Server offline: {} ({})", server_url, error.as_deref().unwrap_or_default());
+                let _ = app.emit("server://offline", ServerHealth { server_url: server_url.clone(), error });
+            }
+        }
+        info!("Health monitor stopped");
+    });
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Whether the current token has a known expiry within the refresh margin
+fn needs_refresh(state: &ProxyState, now: u64) -> bool {
+    !state.token.is_empty()
+        && state.token_expires_at != 0
+        && now + TOKEN_REFRESH_MARGIN >= state.token_expires_at
+}
+
+/// Exchange the current token for a new one at the server's refresh endpoint.
+/// A refused refresh is `Unauthorized`; anything else may succeed on retry.
+async fn renew_token(client: &reqwest::Client) -> Result<(), CommandError> {
+    let state = config::get_proxy_state();
+    if state.server_url.is_empty() || state.token.is_empty() {
+        return Err(CommandError::Unauthorized("No session to refresh".to_string()));
+    }
+
+    let body = if state.auth_mode == "oauth" && !state.refresh_token.is_empty() {
+        crate::oauth::refresh(client, &state.refresh_token).await?
+    } else {
+        refresh_at_server(client, &state).await?
+    };
+
+    // Don't clobber a session the user switched to while we were waiting
+    let current = config::get_proxy_state();
+    if current.server_url != state.server_url || current.token != state.token {
+        return Ok(());
+    }
+    config::update_proxy_state(&current.server_url, &body.access_token, &current.auth_mode, &current.dashboard);
+    if let Some(secs) = body.expires_in {
+        config::set_token_expiry(unix_now() + secs);
+    }
+    // Servers may rotate the refresh token or keep the old one valid
+    config::set_refresh_token(body.refresh_token.as_deref().unwrap_or(&state.refresh_token));
+    persist_session();
+    info!("Auth token refreshed");
+    Ok(())
+}
+
+/// Call the server's own refresh endpoint with the current bearer token
+async fn refresh_at_server(client: &reqwest::Client, state: &ProxyState) -> Result<crate::oauth::TokenResponse, CommandError> {
+    let path = "/v1/user/token/refresh";
+    let url = format!("{}{}", state.server_url.trim_end_matches('/'), path);
+    let mut req = client.post(&url).bearer_auth(&state.token);
+    let cookies = config::get_merged_cookies("", path);
+    if !cookies.is_empty() {
+        req = req.header("Cookie", cookies);
+    }
+    let resp = req.send().await?;
+    if !resp.status().is_success() {
+        return Err(resp.status().into());
+    }
+    let body: crate::oauth::TokenResponse = resp.json().await
+        .map_err(|e| CommandError::Parse(format!("Failed to parse token refresh response: {}", e)))?;
+    if body.access_token.is_empty() {
+        return Err(CommandError::Parse("Token refresh returned an empty token".to_string()));
+    }
+    Ok(body)
+}
+
+/// Ask the server who the current session belongs to. A rejected session
+/// (401/403) returns `authenticated: false` rather than an error.
+#[tauri::command]
+pub async fn session_info() -> Result<SessionInfo, String> {
+    let client = crate::upstream::client_builder()
+        .timeout(std::time::Duration::from_secs(10))
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+    fetch_session_info(&client, &config::get_proxy_state()).await
+}
+
+/// User-info request with the proxy's credentials (bearer token and cookies)
+async fn fetch_session_info(client: &reqwest::Client, state: &ProxyState) -> Result<SessionInfo, String> {
+    if state.server_url.is_empty() {
+        return Err("Not connected to a server".to_string());
+    }
+    let url = format!("{}{}", state.server_url.trim_end_matches('/'), USER_INFO_PATH);
+    let mut req = client.get(&url);
+    if !state.token.is_empty() {
+        req = req.bearer_auth(&state.token);
+    }
+    let cookies = config::get_merged_cookies("", USER_INFO_PATH);
+    if !cookies.is_empty() {
+        req = req.header("Cookie", cookies);
+    }
+    let resp = req.send().await
+        .map_err(|e| format!("User info request failed: {}", e))?;
+    if matches!(resp.status(), reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN) {
+        return Ok(SessionInfo { expires_at: state.token_expires_at, ..SessionInfo::default() });
+    }
+    if !resp.status().is_success() {
+        return Err(format!("User info returned {}", resp.status()));
+    }
+    let user: serde_json::Value = resp.json().await
+        .map_err(|e| format!("Failed to parse user info: {}", e))?;
+    Ok(SessionInfo {
+        authenticated: true,
+        roles: user_roles(&user),
+        user: Some(user),
+        expires_at: state.token_expires_at,
+    })
+}
+
+/// Role names from a user payload: `roles` (strings or `{name}` objects)
+/// or a single `role`
+fn user_roles(user: &serde_json::Value) -> Vec<String> {
+    if let Some(roles) = user.get("roles").and_then(|r| r.as_array()) {
+        return roles
+            .iter()
+            .filter_map(|r| r.as_str().or_else(|| r.get("name")?.as_str()))
+            .map(str::to_string)
+            .collect();
+    }
+    user.get("role").and_then(|r| r.as_str()).map(|r| vec![r.to_string()]).unwrap_or_default()
+}
+
 /// Clear all stored cookies
 #[tauri::command]
 pub async fn clear_cookies() -> Result<(), String> {
@@ -158,10 +882,274 @@ pub async fn clear_cookies() -> Result<(), String> {
     Ok(())
 }
 
-/// Set the window theme (title bar color) for all windows.
-/// Accepts "dark" or "light".
+/// Cookie jar as JSON, for support or moving to another machine. HttpOnly
+/// and `__Secure-`/`__Host-` values are hidden unless `redact` is false in
+/// a debug build.
+#[tauri::command]
+pub async fn export_cookies(redact: Option<bool>) -> Result<String, String> {
+    let redact = redact.unwrap_or(true) || !cfg!(debug_assertions);
+    config::export_cookies(redact)
+}
+
+/// Cookie jar for CUI's session details, values masked. `reveal` shows
+/// full values in debug builds only.
+#[tauri::command]
+pub async fn get_cookies(reveal: Option<bool>) -> Result<Vec<config::CookieInfo>, String> {
+    let reveal = reveal.unwrap_or(false) && cfg!(debug_assertions);
+    Ok(config::cookie_infos(reveal))
+}
+
+/// Remove a cookie by name (on one domain when given)
+#[tauri::command]
+pub async fn delete_cookie(name: String, domain: Option<String>) -> Result<usize, String> {
+    let removed = config::delete_cookie(&name, domain.as_deref());
+    info!("Deleted {} cookie(s) named {}", removed, name);
+    Ok(removed)
+}
+
+/// Merge cookies from `export_cookies` JSON into the jar
+#[tauri::command]
+pub async fn import_cookies(json: String) -> Result<config::CookieImport, String> {
+    let result = config::import_cookies(&json)?;
+    info!("Imported {} cookies ({} skipped)", result.imported, result.skipped);
+    Ok(result)
+}
+
+/// End the current session: notify the server (best-effort), drop the proxy
+/// token and cookie jar, then emit `session://logout` so CUI returns to login.
+#[tauri::command]
+pub async fn logout(app: AppHandle) -> Result<(), String> {
+    let client = crate::upstream::client_builder()
+        .timeout(std::time::Duration::from_secs(5))
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+    end_session(&client).await;
+    let _ = app.emit("session://logout", ());
+    Ok(())
+}
+
+/// Server logout call plus local cleanup. Server errors are logged only;
+/// the local session is always cleared.
+async fn end_session(client: &reqwest::Client) {
+    let state = config::get_proxy_state();
+    if !state.server_url.is_empty() {
+        let path = "/v1/user/logout";
+        let url = format!("{}{}", state.server_url.trim_end_matches('/'), path);
+        let mut req = client.post(&url);
+        if !state.token.is_empty() {
+            req = req.bearer_auth(&state.token);
+        }
+        let cookies = config::get_merged_cookies("", path);
+        if !cookies.is_empty() {
+            req = req.header("Cookie", cookies);
+        }
+        match req.send().await {
+            Ok(resp) if resp.status().is_success() => info!("Server session revoked"),
+            Ok(resp) => warn!("Server logout returned {}", resp.status()),
+            Err(e) => warn!("Server logout failed: {}", e),
+        }
+    }
+
+    config::update_proxy_state(&state.server_url, "", &state.auth_mode, &state.dashboard);
+    config::clear_cookies();
+    session_store::wipe();
+    info!("Logged out");
+}
+
+/// Approximate localStorage / IndexedDB / Cache Storage usage of the CUI origin
+#[tauri::command]
+pub async fn get_web_storage_usage(app: AppHandle) -> Result<WebStorageUsage, String> {
+    web_storage::usage(&app).await
+}
+
+/// Clear selected web storage kinds ("localStorage", "indexedDB", "cacheStorage")
+/// on the CUI origin, leaving cookies and the HTTP cache alone.
+#[tauri::command]
+pub async fn clear_web_storage(app: AppHandle, kinds: Vec<String>) -> Result<(), String> {
+    let kinds = web_storage::parse_kinds(&kinds)?;
+    web_storage::clear(&app, &kinds).await
+}
+
+/// Quit the app the same way the tray "Quit" does. `logout` overrides the
+/// `logoutOnQuit` config flag. Works while the main window is hidden.
+/// Refused in kiosk mode.
+#[tauri::command]
+pub async fn quit_app(app: AppHandle, logout: Option<bool>) -> Result<(), String> {
+    if config::kiosk() {
+        return Err("quitting is disabled in kiosk mode".into());
+    }
+    graceful_quit(&app, logout).await;
+    Ok(())
+}
+
+/// One step of the quit sequence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ShutdownStep {
+    Logout,
+    SaveWindowState,
+    FlushCookies,
+    StopProxy,
+    Exit,
+}
+
+/// Quit sequence: logout first (it clears the jar), then persist window
+/// state and cookies, then stop the proxy, exit last.
+fn shutdown_steps(logout: bool) -> Vec<ShutdownStep> {
+    let mut steps = Vec::new();
+    if logout {
+        steps.push(ShutdownStep::Logout);
+    }
+    steps.extend([
+        ShutdownStep::SaveWindowState,
+        ShutdownStep::FlushCookies,
+        ShutdownStep::StopProxy,
+        ShutdownStep::Exit,
+    ]);
+    steps
+}
+
+/// Run the quit sequence and exit the process
+pub async fn graceful_quit(app: &AppHandle, logout: Option<bool>) {
+    let logout = logout.unwrap_or_else(|| crate::app_conf::get_app_conf().logout_on_quit);
+    info!("Quitting (logout={})", logout);
+    for step in shutdown_steps(logout) {
+        match step {
+            ShutdownStep::Logout => {
+                if let Ok(client) = crate::upstream::client_builder()
+                    .timeout(std::time::Duration::from_secs(3))
+                    .build()
+                {
+                    end_session(&client).await;
+                }
+            }
+            ShutdownStep::SaveWindowState => crate::window_state::save_main(app),
+            ShutdownStep::FlushCookies => config::flush_cookies(),
+            ShutdownStep::StopProxy => proxy::stop_proxy_server(std::time::Duration::from_secs(2)).await,
+            ShutdownStep::Exit => {
+                crate::logging::flush();
+                app.exit(0)
+            }
+        }
+    }
+}
+
+/// List all open webview windows with their URL and state, sorted by label.
+/// Window queries are best-effort: a failing getter yields an empty/false value.
+#[tauri::command]
+pub fn get_windows(app: AppHandle) -> Vec<WindowInfo> {
+    let windows = app.webview_windows();
+    let mut list: Vec<WindowInfo> = windows
+        .values()
+        .map(window_info)
+        .collect();
+    list.sort_by(|a, b| a.label.cmp(&b.label));
+    list
+}
+
+/// Open popup windows, oldest first
+#[tauri::command]
+pub fn list_windows(app: AppHandle) -> Vec<WindowInfo> {
+    config::popup_labels()
+        .iter()
+        .filter_map(|label| app.get_webview_window(label))
+        .map(|w| window_info(&w))
+        .collect()
+}
+
+/// Close an open popup (other windows can't be closed this way)
+#[tauri::command]
+pub fn close_window(app: AppHandle, label: String) -> Result<(), String> {
+    if !config::is_open_popup(&label) {
+        return Err(format!("No open popup window '{}'", label));
+    }
+    let win = app
+        .get_webview_window(&label)
+        .ok_or_else(|| format!("No open popup window '{}'", label))?;
+    win.close().map_err(|e| format!("Failed to close '{}': {}", label, e))
+}
+
+/// Payload of `print://done`
+#[derive(Debug, Clone, Serialize)]
+struct PrintDone {
+    path: String,
+}
+
+/// Save the focused (or main) window's page as a PDF in the download
+/// directory and emit `print://done`. Where the page can't be printed
+/// silently, the native print dialog opens instead (it offers "Save as PDF").
+#[tauri::command]
+pub async fn print_to_pdf(app: AppHandle, options: Option<crate::print::PdfOptions>) -> Result<crate::print::PrintResult, String> {
+    let options = options.unwrap_or_default();
+    options.validate()?;
+    let win = crate::focused_or_main_window(&app).ok_or("No window to print")?;
+    let dir = crate::downloads::target_dir(&app)?;
+    std::fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {:?}: {}", dir, e))?;
+    let path = crate::ensure_unique_path(dir.join(crate::print::pdf_file_name(&win.title().unwrap_or_default())));
+
+    match crate::print::save_pdf(&win, &path, &options).await {
+        Ok(()) => {
+            info!("Saved PDF of '{}': {:?}", win.label(), path);
+            let path_str = path.to_string_lossy().to_string();
+            let _ = app.emit("print://done", PrintDone { path: path_str.clone() });
+            crate::downloads::open_if_configured(&path);
+            Ok(crate::print::PrintResult { path: Some(path_str), dialog: false })
+        }
+        Err(e) => {
+            let _ = std::fs::remove_file(&path);
+            warn!("Silent PDF export failed ({}); opening the print dialog", e);
+            win.print().map_err(|e| format!("Failed to open the print dialog: {}", e))?;
+            Ok(crate::print::PrintResult { path: None, dialog: true })
+        }
+    }
+}
+
+/// Cold-start milestones recorded so far (ms since process start)
+#[tauri::command]
+pub fn get_startup_timings() -> Vec<config::StartupTiming> {
+    config::get_startup_timings()
+}
+
+/// WindowInfo of an open window
+fn window_info(w: &tauri::WebviewWindow) -> WindowInfo {
+    build_window_info(
+        w.label(),
+        w.url().ok().map(|u| u.to_string()),
+        w.title().ok(),
+        w.is_visible().unwrap_or(false),
+        w.is_focused().unwrap_or(false),
+        w.is_fullscreen().unwrap_or(false),
+    )
+}
+
+/// Assemble a WindowInfo from raw window properties
+fn build_window_info(
+    label: &str,
+    url: Option<String>,
+    title: Option<String>,
+    visible: bool,
+    focused: bool,
+    fullscreen: bool,
+) -> WindowInfo {
+    WindowInfo {
+        label: label.to_string(),
+        url: url.unwrap_or_default(),
+        title: title.unwrap_or_default(),
+        visible,
+        focused,
+        fullscreen,
+        is_popup: label.starts_with("popup_"),
+    }
+}
+
+/// Set the theme: saves it to the user preferences and the `__theme`
+/// cookie (injected on the next CUI page load) and sets the title bar
+/// color of all windows. Accepts "dark", "light" or "auto"; "auto"
+/// follows the OS theme and keeps CUI pages in sync with it (see
+/// `system_theme_changed`).
 #[tauri::command]
 pub async fn set_window_theme(app: AppHandle, theme: String) -> Result<(), String> {
+    let theme = save_theme_preference(&theme)?.theme;
+    let follow = theme == "auto";
     let t = match theme.as_str() {
         "dark" => Some(tauri::Theme::Dark),
         "light" => Some(tauri::Theme::Light),
@@ -170,15 +1158,71 @@ pub async fn set_window_theme(app: AppHandle, theme: String) -> Result<(), Strin
     for window in app.webview_windows().values() {
         let _ = window.set_theme(t);
     }
+    let was_following = config::theme_follows_system();
+    config::set_theme_follows_system(follow);
+    if follow && !was_following {
+        // The OS may have switched while the theme was pinned
+        if let Some(dark) = app.get_webview_window("main").and_then(|w| w.theme().ok()) {
+            config::set_system_dark(dark == tauri::Theme::Dark);
+        }
+        apply_system_theme(&app);
+    }
     info!("Window theme set to: {}", theme);
     Ok(())
 }
 
+/// Validate `theme` and store it in the user preferences and cookie jar
+fn save_theme_preference(theme: &str) -> Result<config::UserPreferences, String> {
+    let theme = config::known_theme(theme).ok_or_else(|| format!("Unsupported theme: {}", theme))?;
+    let mut prefs = config::get_user_preferences();
+    prefs.theme = theme.to_string();
+    if let Err(e) = config::save_user_preferences(prefs.clone()) {
+        warn!("Preferences not saved: {}", e);
+    }
+    apply_preference_cookies(&prefs);
+    Ok(prefs)
+}
+
+/// The OS switched between light and dark. When the theme preference is
+/// "auto", CUI pages get the new theme and `theme://changed` is emitted.
+pub(crate) fn system_theme_changed(app: &AppHandle, dark: bool) {
+    config::set_system_dark(dark);
+    if config::theme_follows_system() {
+        apply_system_theme(app);
+    }
+}
+
+#[derive(Clone, Serialize)]
+struct ThemeChanged {
+    /// "dark" or "light"
+    theme: &'static str,
+    preference: &'static str,
+}
+
+fn apply_system_theme(app: &AppHandle) {
+    let theme = config::resolve_theme("auto", config::system_dark());
+    let js = theme_sync_script(theme, &proxy::local_base(config::get_proxy_state().port));
+    for webview in app.webview_windows().values() {
+        let _ = webview.eval(&js);
+    }
+    info!("Following system theme: {}", theme);
+    let _ = app.emit("theme://changed", ThemeChanged { theme, preference: "auto" });
+}
+
+/// Updates the theme of a CUI page in place; other origins are left to
+/// `theme://changed`. `theme` is a resolved theme, never user input.
+fn theme_sync_script(theme: &str, proxy_origin: &str) -> String {
+    format!(
+        r#"(function(){{try{{if(window.location.origin!=="{proxy_origin}")return;localStorage.setItem("__theme","{theme}");localStorage.setItem("xgen:xgen_theme",JSON.stringify({{type:"String",value:"{theme}"}}));document.documentElement.setAttribute("data-theme","{theme}");window.dispatchEvent(new CustomEvent("cui:theme-sync",{{detail:{{theme:"{theme}"}}}}))}}catch(e){{}}}})()"#
+    )
+}
+
 /// Set UI language and rebuild tray menu with localized labels
 #[tauri::command]
 pub fn set_ui_language(app: AppHandle, lang: String) {
     config::save_ui_lang(&lang);
     crate::rebuild_tray(&app);
+    crate::rebuild_app_menu(&app);
 }
 
 /// Sync theme/lang preferences to all windows (including CUI proxy pages).
@@ -188,15 +1232,18 @@ pub fn set_ui_language(app: AppHandle, lang: String) {
 /// have our event listeners.
 #[tauri::command]
 pub fn sync_preferences(app: AppHandle, theme: String, lang: String) {
-    let umi_locale = match lang.as_str() {
+    // Both values are interpolated into JS below
+    let lang = if lang == "zh" { "zh" } else { "en" };
+    let theme = config::known_theme(&theme).unwrap_or_default();
+    let umi_locale = match lang {
         "zh" => "zh-CN",
         _ => "en-US",
     };
-    let locale_cookie = match lang.as_str() {
-        "zh" => "zh-cn",
-        _ => "en-us",
-    };
-    let theme_val = if theme == "dark" { "dark" } else { "" };
+    let locale_cookie = locale::to_cookie(umi_locale);
+    // "auto" renders as the OS theme until the OS switches (see `system_theme_changed`)
+    let resolved = config::resolve_theme(theme, config::system_dark());
+    let theme_val = if resolved == "dark" { "dark" } else { "" };
+    config::set_theme_follows_system(theme == "auto");
 
     // Update cookie jar so future proxy requests carry the new prefs
     config::store_cookie(&format!("__locale={}; Path=/; Max-Age=31536000", locale_cookie));
@@ -220,12 +1267,12 @@ pub fn sync_preferences(app: AppHandle, theme: String, lang: String) {
       localStorage.removeItem("__theme");
       localStorage.removeItem("xgen:xgen_theme");
     }}
-    document.documentElement.setAttribute("data-theme","{theme}");
+    document.documentElement.setAttribute("data-theme","{resolved}");
     // Notify SPA pages to re-render
     window.dispatchEvent(new CustomEvent("cui:theme-sync"));
     window.dispatchEvent(new CustomEvent("cui:lang-sync"));
     // If this is a CUI proxy page (not our SPA), reload to apply umi changes
-    if (window.location.hostname === "127.0.0.1") {{
+    if (window.location.origin === "{proxy_origin}") {{
       window.location.reload();
     }}
   }} catch(e) {{}}
@@ -234,6 +1281,8 @@ pub fn sync_preferences(app: AppHandle, theme: String, lang: String) {
         lang = lang,
         theme = theme,
         theme_val = theme_val,
+        resolved = resolved,
+        proxy_origin = proxy::local_base(config::get_proxy_state().port),
     );
 
     for (label, webview) in app.webview_windows() {
@@ -242,7 +1291,7 @@ pub fn sync_preferences(app: AppHandle, theme: String, lang: String) {
     }
 
     // Also sync the window chrome / title bar theme
-    let t = match theme.as_str() {
+    let t = match theme {
         "dark" => Some(tauri::Theme::Dark),
         "light" => Some(tauri::Theme::Light),
         _ => None,
@@ -254,18 +1303,517 @@ pub fn sync_preferences(app: AppHandle, theme: String, lang: String) {
 
 
 /// Set user preference cookies (__locale, __theme) in the cookie jar.
-/// These are sent to the server and injected into browser on CUI page load.
+/// These are sent to the server and injected into browser on CUI page load,
+/// so calling this before the first load avoids a flash of the default
+/// language. `locale` is one of `locale::SUPPORTED` (`zh-CN`, `en-US`,
+/// `ja-JP`, `zh-TW`, `ko-KR`; any case) and `theme` `dark`, `light`,
+/// `auto` or empty. The values are saved to the user
+/// preferences; an empty locale keeps the saved one.
 #[tauri::command]
 pub async fn set_preference_cookies(locale: String, theme: String) -> Result<(), String> {
+    let mut prefs = config::get_user_preferences();
     if !locale.is_empty() {
-        config::store_cookie(&format!("__locale={}; Path=/; Max-Age=31536000", locale));
+        prefs.locale = locale;
     }
-    if !theme.is_empty() {
-        config::store_cookie(&format!("__theme={}; Path=/; Max-Age=31536000", theme));
-    } else {
-        // Clear __theme cookie (empty = default/light)
-        config::store_cookie("__theme=; Path=/; Max-Age=0");
+    prefs.theme = theme;
+    // Later injected into CUI pages, so only known values are stored
+    let prefs = prefs.validated()?;
+    if let Err(e) = config::save_user_preferences(prefs.clone()) {
+        warn!("Preferences not saved: {}", e);
     }
-    info!("Preference cookies set: locale={}, theme={}", locale, theme);
+    apply_preference_cookies(&prefs);
     Ok(())
 }
+
+/// Check the configured update endpoints (`updater` in config.json)
+#[tauri::command]
+pub async fn check_for_update(app: AppHandle) -> Result<crate::updates::UpdateInfo, crate::updates::UpdateError> {
+    crate::updates::check(&app).await
+}
+
+/// Download and install the available update, emitting `update://progress`.
+/// Returns the installed version; the app applies it on restart.
+#[tauri::command]
+pub async fn install_update(app: AppHandle) -> Result<String, crate::updates::UpdateError> {
+    crate::updates::install(&app).await
+}
+
+/// Saved user preferences (locale, theme, zoom, font size)
+#[tauri::command]
+pub fn get_user_preferences() -> config::UserPreferences {
+    config::get_user_preferences()
+}
+
+/// Validate and save user preferences, updating the preference cookies.
+/// Returns the values as stored (canonical locale/theme, clamped zoom).
+#[tauri::command]
+pub fn set_user_preferences(prefs: config::UserPreferences) -> Result<config::UserPreferences, String> {
+    let prefs = config::save_user_preferences(prefs)?;
+    apply_preference_cookies(&prefs);
+    persist_session();
+    Ok(prefs)
+}
+
+fn apply_preference_cookies(prefs: &config::UserPreferences) {
+    for cookie in preference_cookies(prefs) {
+        config::store_cookie(&cookie);
+    }
+    info!("Preference cookies set: locale={}, theme={}", prefs.locale, prefs.theme);
+}
+
+/// `Set-Cookie` values for validated preferences: the locale in its cookie
+/// form (`zh-cn`), kept when unset; an unset theme clears `__theme`
+/// (empty = default/light). Not `Secure`, the proxy origin is plain http.
+fn preference_cookies(prefs: &config::UserPreferences) -> Vec<String> {
+    let mut cookies = Vec::new();
+    if !prefs.locale.is_empty() {
+        cookies.push(format!("__locale={}; Path=/; Max-Age=31536000", locale::to_cookie(&prefs.locale)));
+    }
+    if !prefs.theme.is_empty() {
+        cookies.push(format!("__theme={}; Path=/; Max-Age=31536000", prefs.theme));
+    } else {
+        cookies.push("__theme=; Path=/; Max-Age=0".to_string());
+    }
+    cookies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn end_session_revokes_on_server_and_clears_locally() {
+        use axum::{Router, extract::Request, routing::post};
+        use std::sync::{Arc, Mutex};
+
+        let seen: Arc<Mutex<Option<(String, String)>>> = Arc::default();
+        let seen_srv = seen.clone();
+        let upstream = Router::new().route("/v1/user/logout", post(move |req: Request| {
+            let seen = seen_srv.clone();
+            async move {
+                let header = |name: &str| req.headers().get(name)
+                    .and_then(|v| v.to_str().ok())
+                    .unwrap_or("")
+                    .to_string();
+                *seen.lock().unwrap() = Some((header("authorization"), header("cookie")));
+                "{}"
+            }
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, upstream).await.unwrap();
+        });
+
+        let _lock = config::lock_test_state().await;
+        config::update_proxy_state(&format!("http://{}", addr), "tok123", "openapi", "/dash");
+        config::clear_cookies();
+        config::store_cookie("__Secure-session=s1; Path=/; Secure");
+
+        let client = reqwest::Client::builder().no_proxy().build().unwrap();
+        end_session(&client).await;
+
+        let (auth, cookie) = seen.lock().unwrap().clone().expect("logout endpoint not called");
+        assert_eq!(auth, "Bearer tok123");
+        assert_eq!(cookie, "__Secure-session=s1");
+
+        let state = config::get_proxy_state();
+        assert!(state.token.is_empty());
+        assert_eq!(state.server_url, format!("http://{}", addr));
+        assert_eq!(state.dashboard, "/dash");
+        assert_eq!(config::cookie_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn end_session_clears_locally_when_server_unreachable() {
+        // Bind then drop to get a port nothing listens on
+        let addr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+
+        let _lock = config::lock_test_state().await;
+        config::update_proxy_state(&format!("http://{}", addr), "tok", "openapi", "");
+        config::clear_cookies();
+        config::store_cookie("sid=1; Path=/");
+
+        let client = reqwest::Client::builder().no_proxy().build().unwrap();
+        end_session(&client).await;
+
+        assert!(config::get_proxy_state().token.is_empty());
+        assert_eq!(config::cookie_count(), 0);
+    }
+
+    #[test]
+    fn select_proxy_port_uses_configured_port() {
+        let conf = AppConf { port: 18080, ..AppConf::default() };
+        assert_eq!(select_proxy_port(&conf, &ProxyState::default()), 18080);
+    }
+
+    #[test]
+    fn select_proxy_port_defaults_to_15099() {
+        assert_eq!(select_proxy_port(&AppConf::default(), &ProxyState::default()), 15099);
+    }
+
+    #[test]
+    fn health_tracker_reports_transitions() {
+        let mut tracker = HealthTracker::default();
+        assert_eq!(tracker.record(true), Some(true));
+        assert_eq!(tracker.record(true), None);
+        // One failed check is tolerated, the second goes offline
+        assert_eq!(tracker.record(false), None);
+        assert_eq!(tracker.record(false), Some(false));
+        assert_eq!(tracker.record(false), None);
+        assert_eq!(tracker.record(true), Some(true));
+
+        // Unreachable from the start
+        let mut tracker = HealthTracker::default();
+        assert_eq!(tracker.record(false), None);
+        assert_eq!(tracker.record(false), Some(false));
+    }
+
+    #[test]
+    fn http_fallback_only_for_private_https() {
+        assert_eq!(http_fallback_url("https://localhost:5099").as_deref(), Some("http://localhost:5099"));
+        assert_eq!(http_fallback_url("https://192.168.1.20/yao").as_deref(), Some("http://192.168.1.20/yao"));
+        assert_eq!(http_fallback_url("https://yao.example.com"), None);
+        assert_eq!(http_fallback_url("http://localhost:5099"), None);
+    }
+
+    #[test]
+    fn server_urls_are_normalized() {
+        let ok = |input: &str| normalize_server_url(input).unwrap();
+        assert_eq!(ok("yao.example.com"), "https://yao.example.com");
+        assert_eq!(ok("  https://Yao.Example.com/dashboard/login?next=/x#top "), "https://yao.example.com");
+        assert_eq!(ok("https://yao.example.com:443/"), "https://yao.example.com");
+        assert_eq!(ok("yao.example.com:8443/api"), "https://yao.example.com:8443");
+        assert_eq!(ok("localhost:5099"), "http://localhost:5099");
+        assert_eq!(ok("192.168.1.20:5099/"), "http://192.168.1.20:5099");
+        assert_eq!(ok("[::1]:5099"), "http://[::1]:5099");
+        assert_eq!(ok("https://user:pw@yao.example.com"), "https://yao.example.com");
+
+        let err = |input: &str| match normalize_server_url(input) {
+            Err(CommandError::Config(msg)) => msg,
+            other => panic!("{:?} -> {:?}", input, other),
+        };
+        assert_eq!(err("   "), "Server URL is empty");
+        assert_eq!(err("ftp://yao.example.com"), "Unsupported server URL scheme: ftp");
+        assert!(err("yao example.com").starts_with("Invalid server URL"));
+        assert!(err("localhost:port").starts_with("Invalid server URL"));
+        assert!(err("http://").starts_with("Invalid server URL"));
+    }
+
+    #[test]
+    fn plan_server_switch_same_host_keeps_session() {
+        let state = ProxyState {
+            running: true,
+            port: 15099,
+            server_url: "https://yao.example.com".into(),
+            token: "tok".into(),
+            auth_mode: "openapi".into(),
+            dashboard: "/dashboard".into(),
+            issuer_url: "https://yao.example.com/oauth".into(),
+            ..ProxyState::default()
+        };
+        let plan = plan_server_switch(&state, "https://yao.example.com:443", "", "");
+        assert_eq!(plan, ServerSwitchPlan {
+            token: "tok".into(),
+            auth_mode: "openapi".into(),
+            dashboard: "/dashboard".into(),
+            issuer_url: "https://yao.example.com/oauth".into(),
+            host_changed: false,
+        });
+        assert_eq!(plan_server_switch(&state, "https://yao.example.com", "new", "").token, "new");
+    }
+
+    #[test]
+    fn plan_server_switch_new_host_drops_token() {
+        let state = ProxyState {
+            server_url: "https://a.example.com".into(),
+            token: "tok".into(),
+            auth_mode: "custom".into(),
+            dashboard: "/dashboard".into(),
+            issuer_url: "https://a.example.com".into(),
+            ..ProxyState::default()
+        };
+        let plan = plan_server_switch(&state, "https://b.example.com", "", "");
+        assert_eq!(plan, ServerSwitchPlan {
+            token: String::new(),
+            auth_mode: "openapi".into(),
+            dashboard: String::new(),
+            issuer_url: String::new(),
+            host_changed: true,
+        });
+        assert_eq!(plan_server_switch(&state, "https://b.example.com", "t2", "").token, "t2");
+    }
+
+    #[test]
+    fn shutdown_steps_in_order() {
+        use ShutdownStep::*;
+        assert_eq!(shutdown_steps(false), vec![SaveWindowState, FlushCookies, StopProxy, Exit]);
+        assert_eq!(shutdown_steps(true), vec![Logout, SaveWindowState, FlushCookies, StopProxy, Exit]);
+    }
+
+    #[test]
+    fn token_refresh_only_gives_up_when_rejected_or_expired() {
+        let rejected = CommandError::Unauthorized("Server returned 401 Unauthorized".into());
+        let offline = CommandError::Network("Cannot connect to server".into());
+        let server_error = CommandError::HttpStatus(503);
+        assert!(refresh_failure_is_final(&rejected, 1000, 950));
+        assert!(!refresh_failure_is_final(&offline, 1000, 950));
+        assert!(!refresh_failure_is_final(&server_error, 1000, 999));
+        assert!(refresh_failure_is_final(&server_error, 1000, 1000));
+
+        let delays: Vec<u64> = (1..=7).map(|n| refresh_retry_delay(n).as_secs()).collect();
+        assert_eq!(delays, [5, 10, 20, 40, 60, 60, 60]);
+    }
+
+    #[test]
+    fn needs_refresh_within_margin() {
+        let state = ProxyState { token: "t".into(), token_expires_at: 1000, ..ProxyState::default() };
+        assert!(!needs_refresh(&state, 900));
+        assert!(needs_refresh(&state, 940));
+        assert!(needs_refresh(&state, 2000));
+
+        let unknown = ProxyState { token: "t".into(), ..ProxyState::default() };
+        assert!(!needs_refresh(&unknown, u64::MAX / 2));
+        let no_token = ProxyState { token_expires_at: 1000, ..ProxyState::default() };
+        assert!(!needs_refresh(&no_token, 2000));
+    }
+
+    #[tokio::test]
+    async fn renew_token_updates_proxy_state() {
+        use axum::{Router, http::HeaderMap, routing::post};
+
+        let upstream = Router::new().route("/v1/user/token/refresh", post(|headers: HeaderMap| async move {
+            match headers.get("authorization").and_then(|v| v.to_str().ok()) {
+                Some("Bearer old") => (
+                    axum::http::StatusCode::OK,
+                    r#"{"access_token":"new","expires_in":3600}"#,
+                ),
+                _ => (axum::http::StatusCode::UNAUTHORIZED, "{}"),
+            }
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, upstream).await.unwrap();
+        });
+
+        let _lock = config::lock_test_state().await;
+        let client = reqwest::Client::builder().no_proxy().build().unwrap();
+        config::update_proxy_state(&format!("http://{}", addr), "old", "openapi", "");
+        renew_token(&client).await.unwrap();
+        let state = config::get_proxy_state();
+        assert_eq!(state.token, "new");
+        assert!(state.token_expires_at >= unix_now() + 3500);
+
+        // The server rejects the (now unknown) token
+        config::update_proxy_state(&format!("http://{}", addr), "revoked", "openapi", "");
+        let err = renew_token(&client).await.unwrap_err();
+        assert!(matches!(err, CommandError::Unauthorized(_)), "{}", err);
+        assert!(err.to_string().contains("401"), "{}", err);
+        assert_eq!(config::get_proxy_state().token, "revoked");
+    }
+
+    #[tokio::test]
+    async fn restored_oauth_sessions_can_refresh() {
+        use axum::{Form, Router, routing::post};
+        use std::collections::HashMap;
+
+        let provider = Router::new().route("/token", post(|Form(form): Form<HashMap<String, String>>| async move {
+            let ok = form.get("grant_type").map(String::as_str) == Some("refresh_token")
+                && form.get("refresh_token").map(String::as_str) == Some("saved-refresh")
+                && form.get("client_id").map(String::as_str) == Some("desktop");
+            if ok {
+                (axum::http::StatusCode::OK, r#"{"access_token":"renewed","refresh_token":"rotated"}"#)
+            } else {
+                (axum::http::StatusCode::BAD_REQUEST, r#"{"error":"invalid_grant"}"#)
+            }
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, provider).await.unwrap();
+        });
+
+        let _lock = config::lock_test_state().await;
+        let dir = std::env::temp_dir().join(format!("cui-restore-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("data")).unwrap();
+        session_store::init(&dir.join("data"), &dir.join("local"));
+        session_store::save(&ProxyState {
+            server_url: "https://yao.example.com".into(),
+            token: "saved".into(),
+            auth_mode: "oauth".into(),
+            refresh_token: "saved-refresh".into(),
+            ..ProxyState::default()
+        });
+        let saved_conf = crate::app_conf::get_app_conf();
+        let mut conf = saved_conf.clone();
+        conf.oauth.client_id = "desktop".into();
+        conf.oauth.authorization_endpoint = format!("http://{}/authorize", addr);
+        conf.oauth.token_endpoint = format!("http://{}/token", addr);
+        crate::app_conf::set_app_conf(conf);
+
+        // A new run: no login has happened in this process
+        config::update_proxy_state("", "", "", "");
+        assert!(restore_session("https://yao.example.com", ""));
+        let client = reqwest::Client::builder().no_proxy().build().unwrap();
+        let result = renew_token(&client).await;
+        crate::app_conf::set_app_conf(saved_conf);
+        session_store::wipe();
+        let _ = std::fs::remove_dir_all(&dir);
+        result.unwrap();
+        let state = config::get_proxy_state();
+        assert_eq!((state.token.as_str(), state.refresh_token.as_str()), ("renewed", "rotated"));
+    }
+
+    #[tokio::test]
+    async fn restored_sessions_are_only_dropped_when_rejected() {
+        use axum::{Router, http::HeaderMap, routing::get};
+
+        let upstream = Router::new().route(USER_INFO_PATH, get(|headers: HeaderMap| async move {
+            match headers.get("authorization").and_then(|v| v.to_str().ok()) {
+                Some("Bearer good") => axum::http::StatusCode::OK,
+                Some("Bearer broken") => axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                Some("Bearer forbidden") => axum::http::StatusCode::FORBIDDEN,
+                _ => axum::http::StatusCode::UNAUTHORIZED,
+            }
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, upstream).await.unwrap();
+        });
+
+        let client = reqwest::Client::builder().no_proxy().build().unwrap();
+        let session = |server: &str, token: &str| ProxyState {
+            server_url: server.to_string(),
+            token: token.to_string(),
+            ..ProxyState::default()
+        };
+        let server = format!("http://{}", addr);
+        assert!(session_accepted(&client, &session(&server, "good")).await);
+        assert!(session_accepted(&client, &session(&server, "broken")).await);
+        assert!(!session_accepted(&client, &session(&server, "expired")).await);
+        assert!(!session_accepted(&client, &session(&server, "forbidden")).await);
+        // Offline servers keep the session
+        assert!(session_accepted(&client, &session("http://127.0.0.1:1", "good")).await);
+    }
+
+    #[tokio::test]
+    async fn session_info_reports_the_user() {
+        use axum::{Router, http::HeaderMap, routing::get};
+
+        let upstream = Router::new().route(USER_INFO_PATH, get(|headers: HeaderMap| async move {
+            let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok()).unwrap_or("").to_string();
+            if header("authorization") == "Bearer tok" && header("cookie") == "sid=abc" {
+                (axum::http::StatusCode::OK, r#"{"id":7,"name":"Ada","roles":["admin",{"name":"editor"}]}"#)
+            } else {
+                (axum::http::StatusCode::UNAUTHORIZED, "{}")
+            }
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, upstream).await.unwrap();
+        });
+
+        let _lock = config::lock_test_state().await;
+        config::clear_cookies();
+        config::store_cookie("sid=abc; Path=/");
+        let client = reqwest::Client::builder().no_proxy().build().unwrap();
+        let state = ProxyState {
+            server_url: format!("http://{}", addr),
+            token: "tok".into(),
+            token_expires_at: 1234,
+            ..ProxyState::default()
+        };
+        let info = fetch_session_info(&client, &state).await.unwrap();
+        assert!(info.authenticated);
+        assert_eq!(info.user.unwrap()["name"], "Ada");
+        assert_eq!(info.roles, ["admin", "editor"]);
+        assert_eq!(info.expires_at, 1234);
+
+        let rejected = ProxyState { token: "stale".into(), ..state };
+        let info = fetch_session_info(&client, &rejected).await.unwrap();
+        assert_eq!(info, SessionInfo { expires_at: 1234, ..SessionInfo::default() });
+
+        assert!(fetch_session_info(&client, &ProxyState::default()).await.is_err());
+        config::clear_cookies();
+    }
+
+    #[test]
+    fn build_window_info_main_window() {
+        let info = build_window_info(
+            "main",
+            Some("http://127.0.0.1:15099/__yao_admin_root/".into()),
+            Some("Yao Agents".into()),
+            true,
+            true,
+            false,
+        );
+        assert_eq!(info.label, "main");
+        assert_eq!(info.url, "http://127.0.0.1:15099/__yao_admin_root/");
+        assert_eq!(info.title, "Yao Agents");
+        assert!(info.visible && info.focused && !info.fullscreen);
+        assert!(!info.is_popup);
+    }
+
+    #[test]
+    fn build_window_info_popup_without_url() {
+        let info = build_window_info("popup_3", None, None, false, false, true);
+        assert!(info.is_popup);
+        assert!(info.url.is_empty());
+        assert!(info.title.is_empty());
+        assert!(info.fullscreen);
+    }
+
+    #[test]
+    fn select_proxy_port_reuses_bound_port() {
+        let conf = AppConf { port: 18080, ..AppConf::default() };
+        let state = ProxyState { port: 18081, ..ProxyState::default() };
+        assert_eq!(select_proxy_port(&conf, &state), 18081);
+    }
+
+    #[test]
+    fn theme_preference_is_validated_and_stored() {
+        let _lock = config::TEST_MUTEX.blocking_lock();
+        config::clear_cookies();
+
+        assert_eq!(save_theme_preference("Dark").unwrap().theme, "dark");
+        assert_eq!(config::cookie_value("__theme").as_deref(), Some("dark"));
+        assert_eq!(save_theme_preference("auto").unwrap().theme, "auto");
+        assert_eq!(config::cookie_value("__theme").as_deref(), Some("auto"));
+
+        for theme in ["", "blue", "dark\");alert(1)//"] {
+            assert!(save_theme_preference(theme).is_err(), "{}", theme);
+        }
+        assert_eq!(config::cookie_value("__theme").as_deref(), Some("auto"));
+        config::clear_cookies();
+    }
+
+    #[test]
+    fn preference_cookies_use_cookie_forms() {
+        let prefs = config::UserPreferences { locale: "zh-CN".into(), theme: "dark".into(), ..Default::default() };
+        assert_eq!(preference_cookies(&prefs), [
+            "__locale=zh-cn; Path=/; Max-Age=31536000",
+            "__theme=dark; Path=/; Max-Age=31536000",
+        ]);
+        assert_eq!(preference_cookies(&config::UserPreferences::default()), ["__theme=; Path=/; Max-Age=0"]);
+    }
+
+    #[tokio::test]
+    async fn preference_cookies_are_validated_before_storing() {
+        let _lock = config::lock_test_state().await;
+        config::clear_cookies();
+
+        set_preference_cookies("JA-jp".into(), "Light".into()).await.unwrap();
+        assert_eq!(config::cookie_value("__locale").as_deref(), Some("ja-jp"));
+        assert_eq!(config::cookie_value("__theme").as_deref(), Some("light"));
+
+        assert!(set_preference_cookies("fr-FR".into(), "dark".into()).await.is_err());
+        assert!(set_preference_cookies("en-US".into(), "sepia".into()).await.is_err());
+        assert_eq!(config::cookie_value("__locale").as_deref(), Some("ja-jp"));
+        assert_eq!(config::cookie_value("__theme").as_deref(), Some("light"));
+        config::clear_cookies();
+    }
+}
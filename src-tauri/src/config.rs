@@ -3,7 +3,8 @@ use serde::{Deserialize, Serialize};
 use once_cell::sync::Lazy;
 use std::path::PathBuf;
 use std::sync::OnceLock;
-use tauri::Manager;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use tauri::{Emitter, Manager};
 use tracing::{info, warn};
 
 // ========== Global AppHandle ==========
@@ -32,6 +33,20 @@ pub struct ProxyState {
     /// Used to redirect /{dashboard}/* → /__yao_admin_root/* so that
     /// server-side redirects (login success_url etc.) land on local CUI.
     pub dashboard: String,
+    /// OAuth issuer from .well-known/yao; empty if the server doesn't report one
+    #[serde(default)]
+    pub issuer_url: String,
+    /// Token expiry (Unix seconds) from the JWT `exp` claim or the login
+    /// response; 0 = unknown, never auto-refreshed.
+    pub token_expires_at: u64,
+    /// OAuth refresh token from `login_oauth`; never sent to the webview.
+    /// Cleared whenever the access token is replaced by another session's.
+    #[serde(default, skip_serializing)]
+    pub refresh_token: String,
+    /// Result of the last reachability check; None until the first check
+    /// (or after switching servers)
+    #[serde(default)]
+    pub server_online: Option<bool>,
 }
 
 impl Default for ProxyState {
@@ -43,6 +58,10 @@ impl Default for ProxyState {
             token: String::new(),
             auth_mode: String::from("openapi"),
             dashboard: String::new(),
+            issuer_url: String::new(),
+            token_expires_at: 0,
+            refresh_token: String::new(),
+            server_online: None,
         }
     }
 }
@@ -55,6 +74,12 @@ pub static PROXY_STATE: Lazy<RwLock<ProxyState>> = Lazy::new(|| {
 pub fn update_proxy_state(server_url: &str, token: &str, auth_mode: &str, dashboard: &str) {
     let mut state = PROXY_STATE.write();
     state.server_url = server_url.to_string();
+    if state.token != token || state.token_expires_at == 0 {
+        state.token_expires_at = jwt_expiry(token).unwrap_or(0);
+    }
+    if state.token != token {
+        state.refresh_token.clear();
+    }
     state.token = token.to_string();
     state.auth_mode = auth_mode.to_string();
     // Normalize: ensure leading slash, strip trailing slash
@@ -68,6 +93,38 @@ pub fn update_proxy_state(server_url: &str, token: &str, auth_mode: &str, dashbo
     };
 }
 
+/// Record the server's OAuth issuer (empty clears it)
+pub fn set_issuer_url(issuer_url: &str) {
+    PROXY_STATE.write().issuer_url = issuer_url.trim().trim_end_matches('/').to_string();
+}
+
+/// Record the token expiry reported by the server (for opaque tokens)
+pub fn set_token_expiry(expires_at: u64) {
+    PROXY_STATE.write().token_expires_at = expires_at;
+}
+
+/// Record whether the server answered the last reachability check
+pub fn set_server_online(online: Option<bool>) {
+    PROXY_STATE.write().server_online = online;
+}
+
+/// Store the OAuth refresh token for the current access token
+pub fn set_refresh_token(refresh_token: &str) {
+    PROXY_STATE.write().refresh_token = refresh_token.to_string();
+}
+
+/// Read the `exp` claim of a JWT without verifying it. None for opaque tokens.
+pub fn jwt_expiry(token: &str) -> Option<u64> {
+    use base64::Engine;
+    let mut parts = token.split('.');
+    let (_header, payload, _sig) = (parts.next()?, parts.next()?, parts.next()?);
+    let bytes = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(payload.trim_end_matches('='))
+        .ok()?;
+    let claims: serde_json::Value = serde_json::from_slice(&bytes).ok()?;
+    claims.get("exp")?.as_u64()
+}
+
 pub fn set_proxy_running(running: bool) {
     let mut state = PROXY_STATE.write();
     state.running = running;
@@ -91,6 +148,119 @@ pub fn get_tunnel_manager() -> Option<&'static TunnelManager> {
     TUNNEL_MANAGER.get()
 }
 
+// ========== Startup Timings ==========
+
+/// Cold-start milestones, in the order they normally occur
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StartupMilestone {
+    ConfigLoaded,
+    TrayReady,
+    WindowBuilt,
+    ProxyBound,
+    ProxyReady,
+    /// CUI pinged /__yao_desktop/ready after its first paint
+    FirstPaint,
+}
+
+/// Time a milestone was reached, relative to process start
+#[derive(Debug, Clone, Serialize)]
+pub struct StartupTiming {
+    pub milestone: StartupMilestone,
+    pub elapsed_ms: f64,
+}
+
+static STARTUP_INSTANT: Lazy<std::time::Instant> = Lazy::new(std::time::Instant::now);
+static STARTUP_TIMINGS: Lazy<RwLock<Vec<StartupTiming>>> = Lazy::new(|| RwLock::new(Vec::new()));
+
+/// Pin the startup clock; call as early as possible in `run()`
+pub fn mark_startup() {
+    Lazy::force(&STARTUP_INSTANT);
+}
+
+/// Record a milestone. Only the first occurrence counts, so proxy restarts
+/// or page reloads don't overwrite the cold-start numbers.
+pub fn record_milestone(milestone: StartupMilestone) {
+    let elapsed_ms = STARTUP_INSTANT.elapsed().as_secs_f64() * 1000.0;
+    let mut timings = STARTUP_TIMINGS.write();
+    if timings.iter().any(|t| t.milestone == milestone) {
+        return;
+    }
+    info!("Startup: {:?} at {:.1}ms", milestone, elapsed_ms);
+    timings.push(StartupTiming { milestone, elapsed_ms });
+}
+
+/// Seconds since process start (the clock pinned by `mark_startup`)
+pub fn uptime_secs() -> u64 {
+    STARTUP_INSTANT.elapsed().as_secs()
+}
+
+/// Milestones recorded so far, in the order they were reached
+pub fn get_startup_timings() -> Vec<StartupTiming> {
+    STARTUP_TIMINGS.read().clone()
+}
+
+// ========== Metrics ==========
+
+/// Proxy counters scraped from `/__yao_desktop/metrics`; plain atomics so
+/// recording stays off the lock path
+#[derive(Default)]
+pub struct ProxyMetrics {
+    pub requests: AtomicU64,
+    /// Responses by status class, index 0 = 1xx … 4 = 5xx
+    pub status_classes: [AtomicU64; 5],
+    pub bytes_proxied: AtomicU64,
+    pub active_sse_streams: AtomicU64,
+    pub upstream_errors: AtomicU64,
+}
+
+pub static PROXY_METRICS: Lazy<ProxyMetrics> = Lazy::new(ProxyMetrics::default);
+
+/// Count a proxied request by the status returned to the client
+pub fn record_proxied_response(status: u16) {
+    PROXY_METRICS.requests.fetch_add(1, Ordering::Relaxed);
+    if let Some(class) = (status / 100).checked_sub(1).and_then(|i| PROXY_METRICS.status_classes.get(i as usize)) {
+        class.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+pub fn record_bytes_proxied(len: usize) {
+    PROXY_METRICS.bytes_proxied.fetch_add(len as u64, Ordering::Relaxed);
+}
+
+pub fn record_upstream_error() {
+    PROXY_METRICS.upstream_errors.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Keeps an SSE stream counted as active until dropped
+pub struct SseStreamGuard(());
+
+pub fn sse_stream_started() -> SseStreamGuard {
+    PROXY_METRICS.active_sse_streams.fetch_add(1, Ordering::Relaxed);
+    SseStreamGuard(())
+}
+
+impl Drop for SseStreamGuard {
+    fn drop(&mut self) {
+        PROXY_METRICS.active_sse_streams.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// ========== Deep Links ==========
+
+/// CUI route from a deep link that arrived before CUI was loaded
+static PENDING_DEEP_LINK: Lazy<RwLock<Option<String>>> = Lazy::new(|| RwLock::new(None));
+
+/// Remember a route to open once CUI reports it is ready (latest link wins)
+pub fn set_pending_deep_link(path: String) {
+    *PENDING_DEEP_LINK.write() = Some(path);
+}
+
+/// Take the pending deep link route, if any
+pub fn take_pending_deep_link() -> Option<String> {
+    PENDING_DEEP_LINK.write().take()
+}
+
 // ========== Cookie Jar ==========
 
 /// A single cookie entry
@@ -100,6 +270,10 @@ pub struct CookieEntry {
     pub name: String,
     /// Cookie value
     pub value: String,
+    /// Domain scope (lowercase, no leading dot). Empty for entries saved
+    /// before domains were tracked; those match any host.
+    #[serde(default)]
+    pub domain: String,
     /// Path scope
     pub path: String,
     /// Expiry time (Unix seconds), 0 = session cookie
@@ -114,43 +288,139 @@ static COOKIE_FILE: Lazy<RwLock<Option<PathBuf>>> = Lazy::new(|| RwLock::new(Non
 /// Global cookie jar
 pub static COOKIE_JAR: Lazy<RwLock<Vec<CookieEntry>>> = Lazy::new(|| RwLock::new(Vec::new()));
 
+/// How long jar changes may wait before being written, so a response
+/// setting several cookies (or a burst of responses) costs one write
+const COOKIE_SAVE_DELAY: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// The jar has changes not yet written to the file
+static COOKIES_DIRTY: AtomicBool = AtomicBool::new(false);
+/// A delayed save is pending
+static COOKIE_SAVE_SCHEDULED: AtomicBool = AtomicBool::new(false);
+
 /// Set the cookie persistence file path
 pub fn set_cookie_file(path: PathBuf) {
     *COOKIE_FILE.write() = Some(path);
 }
 
-/// Load cookies from file
+/// Payload of the `cookie-load-error` event
+#[derive(Debug, Clone, Serialize)]
+pub struct CookieLoadError {
+    pub error: String,
+    /// Whether the jar was restored from the `.bak` copy
+    pub recovered: bool,
+}
+
+/// `cookies.json` → `cookies.json.<suffix>`
+fn cookie_sibling(path: &std::path::Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Read and parse a cookie file. `Ok(None)` if it does not exist.
+fn read_cookie_file(path: &std::path::Path) -> Result<Option<Vec<CookieEntry>>, String> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let data = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read cookie file: {}", e))?;
+    serde_json::from_str(&data)
+        .map(Some)
+        .map_err(|e| format!("Failed to parse cookie file: {}", e))
+}
+
+/// Load cookies from file. A corrupt file is kept as
+/// `cookies.json.corrupt.<ts>` and the jar is restored from `cookies.json.bak`
+/// when possible; either way a `cookie-load-error` event is emitted.
 pub fn load_cookies() {
     let path = COOKIE_FILE.read().clone();
     if let Some(path) = path {
-        if path.exists() {
-            match std::fs::read_to_string(&path) {
-                Ok(data) => {
-                    match serde_json::from_str::<Vec<CookieEntry>>(&data) {
-                        Ok(cookies) => {
-                            let count = cookies.len();
-                            *COOKIE_JAR.write() = cookies;
-                            info!("Loaded {} cookies from file", count);
-                        }
-                        Err(e) => warn!("Failed to parse cookie file: {}", e),
+        let backup = cookie_sibling(&path, "bak");
+        match read_cookie_file(&path) {
+            Ok(Some(cookies)) => {
+                let count = cookies.len();
+                *COOKIE_JAR.write() = cookies;
+                info!("Loaded {} cookies from file", count);
+            }
+            Ok(None) => {
+                // Interrupted save: the previous copy is still in .bak
+                if let Ok(Some(cookies)) = read_cookie_file(&backup) {
+                    info!("Cookie file missing, restored {} cookies from backup", cookies.len());
+                    *COOKIE_JAR.write() = cookies;
+                    save_cookies();
+                }
+            }
+            Err(error) => {
+                warn!("{}", error);
+                let ts = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                let corrupt = cookie_sibling(&path, &format!("corrupt.{}", ts));
+                match std::fs::rename(&path, &corrupt) {
+                    Ok(()) => warn!("Corrupt cookie file kept as {:?}", corrupt),
+                    Err(e) => warn!("Failed to move corrupt cookie file: {}", e),
+                }
+
+                let recovered = match read_cookie_file(&backup) {
+                    Ok(Some(cookies)) => {
+                        info!("Restored {} cookies from backup", cookies.len());
+                        *COOKIE_JAR.write() = cookies;
+                        save_cookies();
+                        true
                     }
+                    _ => false,
+                };
+                if let Some(handle) = get_app_handle() {
+                    let _ = handle.emit("cookie-load-error", CookieLoadError { error, recovered });
                 }
-                Err(e) => warn!("Failed to read cookie file: {}", e),
             }
         }
     }
     purge_expired();
 }
 
-/// Save cookies to file
+/// Write the jar within `COOKIE_SAVE_DELAY`. Outside a Tokio runtime the
+/// jar is written right away.
+fn schedule_save() {
+    COOKIES_DIRTY.store(true, Ordering::SeqCst);
+    let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+        save_cookies();
+        return;
+    };
+    if !COOKIE_SAVE_SCHEDULED.swap(true, Ordering::SeqCst) {
+        runtime.spawn(async {
+            tokio::time::sleep(COOKIE_SAVE_DELAY).await;
+            COOKIE_SAVE_SCHEDULED.store(false, Ordering::SeqCst);
+            if COOKIES_DIRTY.load(Ordering::SeqCst) {
+                save_cookies();
+            }
+        });
+    }
+}
+
+/// Save cookies to file atomically: write a temp file, keep the previous
+/// version as `.bak`, then rename the temp file into place.
 fn save_cookies() {
+    COOKIES_DIRTY.store(false, Ordering::SeqCst);
     let path = COOKIE_FILE.read().clone();
     if let Some(path) = path {
         let jar = COOKIE_JAR.read();
         match serde_json::to_string_pretty(&*jar) {
             Ok(data) => {
-                if let Err(e) = std::fs::write(&path, data) {
+                let tmp = cookie_sibling(&path, "tmp");
+                if let Err(e) = std::fs::write(&tmp, data) {
                     warn!("Failed to write cookie file: {}", e);
+                    return;
+                }
+                if path.exists() {
+                    if let Err(e) = std::fs::rename(&path, cookie_sibling(&path, "bak")) {
+                        warn!("Failed to back up cookie file: {}", e);
+                    }
+                }
+                if let Err(e) = std::fs::rename(&tmp, &path) {
+                    warn!("Failed to replace cookie file: {}", e);
                 }
             }
             Err(e) => warn!("Failed to serialize cookies: {}", e),
@@ -178,31 +448,74 @@ pub struct StoreCookieResult {
 
 /// Parse a Set-Cookie header, store it in the jar, and return processing result.
 ///
-/// "Secure" cookies (__Secure-*, __Host-*, or with Secure attribute) are stored
-/// in the jar only. Non-secure cookies are stored in the jar AND a sanitized
-/// version is returned for forwarding to the browser.
+/// The jar copy is merged into every upstream request whatever its
+/// SameSite, so the browser copy serves scripts and the browser's own
+/// requests. Browsers only keep what works on `http://127.0.0.1`:
+///
+/// | Set-Cookie                                | jar     | browser                      |
+/// |-------------------------------------------|---------|------------------------------|
+/// | `Secure`, or `__Secure-`/`__Host-` name   | stored  | not sent (needs HTTPS)       |
+/// | not Secure, HttpOnly or not               | stored  | sanitized copy, HttpOnly kept |
+/// | not Secure, expired or `Max-Age<=0`       | removed | deletion passed on           |
+///
+/// See `browser_set_cookie` for the SameSite rules of the browser copy.
 pub fn store_cookie(set_cookie: &str) -> StoreCookieResult {
-    let parts: Vec<&str> = set_cookie.split(';').collect();
+    store_cookies(&[set_cookie]).pop().unwrap_or(StoreCookieResult { is_secure: false, browser_cookie: None })
+}
+
+/// Store every Set-Cookie header of one response under a single jar lock.
+/// The file is written shortly after (see `schedule_save`). Results are in
+/// input order.
+pub fn store_cookies(set_cookies: &[&str]) -> Vec<StoreCookieResult> {
+    store_cookies_for(&current_cookie_host(), set_cookies)
+}
+
+/// `store_cookies` for a response from `host` (e.g. a route's backend)
+/// rather than the server URL
+pub fn store_cookies_for(host: &str, set_cookies: &[&str]) -> Vec<StoreCookieResult> {
+    let mut jar = COOKIE_JAR.write();
+    let mut changed = false;
+    let results = set_cookies.iter()
+        .map(|set_cookie| {
+            let (result, stored) = apply_set_cookie(&mut jar, set_cookie, host);
+            changed |= stored;
+            result
+        })
+        .collect();
+    drop(jar);
+    if changed {
+        schedule_save();
+    }
+    results
+}
+
+/// Upsert or delete one cookie in `jar`; the flag is false when the header
+/// was ignored
+fn apply_set_cookie(jar: &mut Vec<CookieEntry>, set_cookie: &str, host: &str) -> (StoreCookieResult, bool) {
+    let ignored = || (StoreCookieResult { is_secure: false, browser_cookie: None }, false);
+    let parts = split_cookie_list(set_cookie, true);
     if parts.is_empty() {
-        return StoreCookieResult { is_secure: false, browser_cookie: None };
+        return ignored();
     }
 
-    // Parse name=value
+    // Parse name=value. The value is everything after the first `=`, kept
+    // verbatim (quotes included, RFC 6265 §5.2) and sent back unchanged.
     let name_value = parts[0].trim();
     let (name, value) = match name_value.split_once('=') {
         Some((n, v)) => (n.trim().to_string(), v.trim().to_string()),
-        None => return StoreCookieResult { is_secure: false, browser_cookie: None },
+        None => return ignored(),
     };
 
     if name.is_empty() {
-        return StoreCookieResult { is_secure: false, browser_cookie: None };
+        return ignored();
     }
 
     let mut path = "/".to_string();
-    let mut expires_at: u64 = 0;
+    let mut domain = host.to_string();
+    let mut max_age: Option<i64> = None;
+    let mut expires: Option<u64> = None;
     let mut http_only = false;
     let mut has_secure_flag = false;
-    let mut has_samesite_none = false;
 
     for part in &parts[1..] {
         let trimmed = part.trim();
@@ -210,86 +523,231 @@ pub fn store_cookie(set_cookie: &str) -> StoreCookieResult {
 
         if lower.starts_with("path=") {
             path = trimmed[5..].trim().to_string();
+        } else if let Some(d) = lower.strip_prefix("domain=") {
+            let d = d.trim().trim_start_matches('.');
+            if !d.is_empty() {
+                // A Domain the responding host doesn't domain-match rejects
+                // the cookie (RFC 6265 §5.3 step 6); IP hosts only match exactly
+                let is_ip = host.parse::<std::net::IpAddr>().is_ok();
+                if !domain_matches(d, host) || (is_ip && d != host) {
+                    return ignored();
+                }
+                domain = d.to_string();
+            }
         } else if lower.starts_with("max-age=") {
             if let Ok(secs) = trimmed[8..].trim().parse::<i64>() {
-                if secs > 0 {
-                    let now = std::time::SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap_or_default()
-                        .as_secs();
-                    expires_at = now + secs as u64;
-                } else {
-                    // max-age=0 means delete
-                    remove_cookie(&name);
-                    return StoreCookieResult { is_secure: false, browser_cookie: None };
-                }
+                max_age = Some(secs);
             }
+        } else if lower.starts_with("expires=") {
+            expires = parse_cookie_date(&trimmed[8..]);
         } else if lower == "httponly" {
             http_only = true;
         } else if lower == "secure" {
             has_secure_flag = true;
-        } else if lower == "samesite=none" {
-            has_samesite_none = true;
         }
     }
 
+    // Max-Age takes precedence over Expires (RFC 6265 §5.3 step 3).
+    // A non-positive Max-Age or a past Expires means delete.
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let expires_at = match (max_age, expires) {
+        (Some(secs), _) if secs > 0 => Some(now + secs as u64),
+        (Some(_), _) => None,
+        (None, Some(ts)) if ts > now => Some(ts),
+        (None, Some(_)) => None,
+        (None, None) => Some(0),
+    };
     // Determine if this cookie is "secure-only" (can't work on plain HTTP)
     let is_secure = has_secure_flag
         || name.starts_with("__Secure-")
         || name.starts_with("__Host-");
+    // Deletions reach the browser too, or its stale copy would keep being sent
+    let browser_cookie = (!is_secure).then(|| browser_set_cookie(&name, &value, &parts[1..]));
+
+    let Some(expires_at) = expires_at else {
+        jar.retain(|c| !(c.name == name && c.domain == domain));
+        return (StoreCookieResult { is_secure, browser_cookie }, true);
+    };
 
     let entry = CookieEntry {
         name: name.clone(),
         value: value.clone(),
+        domain: domain.clone(),
         path: path.clone(),
         expires_at,
         http_only,
     };
 
     // Upsert into jar (always)
-    let mut jar = COOKIE_JAR.write();
-    if let Some(existing) = jar.iter_mut().find(|c| c.name == name) {
+    if let Some(existing) = jar.iter_mut().find(|c| c.name == name && c.domain == domain) {
         *existing = entry;
     } else {
         jar.push(entry);
     }
-    drop(jar);
-    save_cookies();
 
-    // Build sanitized Set-Cookie for browser (only if non-secure)
-    let browser_cookie = if !is_secure {
-        // Rebuild Set-Cookie: keep name=value, Path, Max-Age/Expires, HttpOnly
-        // Remove: Domain, Secure, SameSite=None (requires Secure on HTTP)
-        let mut parts_out = vec![format!("{}={}", name, value)];
-        for part in &parts[1..] {
-            let lower = part.trim().to_lowercase();
-            // Skip attributes that don't work on HTTP localhost
-            if lower == "secure"
-                || lower.starts_with("domain=")
-                || lower == "samesite=none"
-            {
+    (StoreCookieResult { is_secure, browser_cookie }, true)
+}
+
+/// Browser copy of a non-secure Set-Cookie. `Domain` is dropped (host-only
+/// on the proxy origin), as are `Secure` and `Partitioned`, which need HTTPS.
+/// `SameSite=None` also needs Secure and becomes `Lax`, which browsers still
+/// send on the top-level GET redirects that bring a sign-in back to the proxy.
+/// `Lax`, `Strict` or no SameSite stay as they are: where the browser
+/// withholds a cookie on a cross-site hop (a `form_post` callback, a Strict
+/// cookie), the proxy sends the jar copy upstream anyway.
+fn browser_set_cookie(name: &str, value: &str, attributes: &[&str]) -> String {
+    let mut parts_out = vec![format!("{}={}", name, value)];
+    for attr in attributes.iter().map(|a| a.trim()).filter(|a| !a.is_empty()) {
+        let (key, val) = match attr.split_once('=') {
+            Some((k, v)) => (k.trim().to_ascii_lowercase(), v.trim()),
+            None => (attr.to_ascii_lowercase(), ""),
+        };
+        match key.as_str() {
+            "domain" | "secure" | "partitioned" => {}
+            "samesite" if val.eq_ignore_ascii_case("none") => parts_out.push("SameSite=Lax".to_string()),
+            _ => parts_out.push(attr.to_string()),
+        }
+    }
+    parts_out.join("; ")
+}
+
+/// Split a Set-Cookie or Cookie header on `;`. A value that starts with a
+/// quote right after `=` runs to its closing quote (`a="x;y"`, which some
+/// servers send despite RFC 6265); any other `"` is an ordinary character.
+/// In a Set-Cookie (`set_cookie`) only the leading name=value can be
+/// quoted, so a stray quote never swallows the attributes (§5.2).
+fn split_cookie_list(header: &str, set_cookie: bool) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut rest = header;
+    loop {
+        let mut end = rest.find(';');
+        if !set_cookie || parts.is_empty() {
+            if let Some(open) = rest.find("=\"").map(|eq| eq + 1) {
+                let is_value = end.is_none_or(|e| open < e) && !rest[..open].contains('"');
+                if let Some(close) = rest[open + 1..].find('"').filter(|_| is_value) {
+                    let after = open + 1 + close + 1;
+                    end = rest[after..].find(';').map(|e| after + e);
+                }
+            }
+        }
+        match end {
+            Some(e) => {
+                parts.push(&rest[..e]);
+                rest = &rest[e + 1..];
+            }
+            None => {
+                parts.push(rest);
+                return parts;
+            }
+        }
+    }
+}
+
+/// Parse a Set-Cookie Expires date into Unix seconds, following the lenient
+/// algorithm of RFC 6265 §5.1.1. Accepts RFC 1123 ("Wed, 21 Oct 2026 07:28:00 GMT"),
+/// RFC 850 ("Wednesday, 21-Oct-26 07:28:00 GMT") and asctime formats.
+fn parse_cookie_date(input: &str) -> Option<u64> {
+    const MONTHS: [&str; 12] = [
+        "jan", "feb", "mar", "apr", "may", "jun",
+        "jul", "aug", "sep", "oct", "nov", "dec",
+    ];
+
+    let mut time: Option<(u64, u64, u64)> = None;
+    let mut day: Option<u64> = None;
+    let mut month: Option<u64> = None;
+    let mut year: Option<u64> = None;
+
+    let is_delimiter = |c: char| !(c.is_ascii_alphanumeric() || c == ':');
+    for token in input.split(is_delimiter).filter(|t| !t.is_empty()) {
+        if time.is_none() && token.contains(':') {
+            let fields: Vec<&str> = token.split(':').collect();
+            if fields.len() == 3 {
+                if let (Ok(h), Ok(m), Ok(s)) = (
+                    fields[0].parse::<u64>(),
+                    fields[1].parse::<u64>(),
+                    fields[2].trim_end_matches(|c: char| !c.is_ascii_digit()).parse::<u64>(),
+                ) {
+                    time = Some((h, m, s));
+                    continue;
+                }
+            }
+        }
+        let digits = token.chars().take_while(|c| c.is_ascii_digit()).count();
+        if day.is_none() && (1..=2).contains(&digits) && digits == token.len() {
+            day = token.parse().ok();
+            continue;
+        }
+        if month.is_none() && token.len() >= 3 {
+            let prefix = token[..3].to_ascii_lowercase();
+            if let Some(idx) = MONTHS.iter().position(|m| *m == prefix) {
+                month = Some(idx as u64 + 1);
                 continue;
             }
-            parts_out.push(part.trim().to_string());
         }
-        // If original had SameSite=None (which requires Secure), replace with Lax
-        if has_samesite_none {
-            parts_out.push("SameSite=Lax".to_string());
+        if year.is_none() && (2..=4).contains(&digits) {
+            year = token[..digits].parse().ok();
+            continue;
         }
-        Some(parts_out.join("; "))
-    } else {
-        None
-    };
+    }
+
+    let (hour, minute, second) = time?;
+    let (day, month, mut year) = (day?, month?, year?);
+    if (70..=99).contains(&year) {
+        year += 1900;
+    } else if year <= 69 {
+        year += 2000;
+    }
+    if !(1..=31).contains(&day) || year < 1601 || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
 
-    StoreCookieResult { is_secure, browser_cookie }
+    // Days since 1970-01-01 (Howard Hinnant's days_from_civil)
+    let (y, m) = if month <= 2 { (year as i64 - 1, month + 9) } else { (year as i64, month - 3) };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * m as i64 + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    let secs = days * 86400 + (hour * 3600 + minute * 60 + second) as i64;
+    u64::try_from(secs).ok()
 }
 
-/// Remove a cookie by name
-fn remove_cookie(name: &str) {
-    let mut jar = COOKIE_JAR.write();
-    jar.retain(|c| c.name != name);
-    drop(jar);
-    save_cookies();
+/// Host of the current upstream server, used as the default cookie domain
+fn current_cookie_host() -> String {
+    let server_url = PROXY_STATE.read().server_url.clone();
+    cookie_host(&server_url)
+}
+
+/// Lowercased host of `url`, as cookies are scoped to it (empty if unparsable)
+pub fn cookie_host(url: &str) -> String {
+    url::Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(|h| h.to_lowercase()))
+        .unwrap_or_default()
+}
+
+/// Whether a cookie scoped to `domain` should be sent to `host`
+/// (exact match or subdomain, RFC 6265 §5.1.3).
+fn domain_matches(domain: &str, host: &str) -> bool {
+    domain.is_empty()
+        || host == domain
+        || (host.ends_with(domain) && host[..host.len() - domain.len()].ends_with('.'))
+}
+
+/// RFC 6265 §5.1.4 path-match: exact match, or `cookie_path` is a prefix
+/// that ends in `/` or is followed by `/` in the request path.
+/// Any query string on `request_path` is ignored.
+fn path_matches(cookie_path: &str, request_path: &str) -> bool {
+    let request_path = request_path.split(['?', '#']).next().unwrap_or("");
+    if request_path == cookie_path {
+        return true;
+    }
+    request_path.starts_with(cookie_path)
+        && (cookie_path.ends_with('/') || request_path[cookie_path.len()..].starts_with('/'))
 }
 
 /// Build a Cookie header value by merging jar cookies with browser cookies.
@@ -298,13 +756,19 @@ fn remove_cookie(name: &str) {
 /// `browser_cookie_header`: the raw Cookie header from the browser (may be empty)
 /// `request_path`: used to filter jar cookies by path scope
 pub fn get_merged_cookies(browser_cookie_header: &str, request_path: &str) -> String {
+    get_merged_cookies_for(&current_cookie_host(), browser_cookie_header, request_path)
+}
+
+/// `get_merged_cookies` for a request to `host` (e.g. a route's backend)
+/// rather than the server URL
+pub fn get_merged_cookies_for(host: &str, browser_cookie_header: &str, request_path: &str) -> String {
     purge_expired();
 
     // Parse browser cookies into a map
     let mut cookie_map: std::collections::HashMap<String, String> = std::collections::HashMap::new();
 
     if !browser_cookie_header.is_empty() {
-        for pair in browser_cookie_header.split(';') {
+        for pair in split_cookie_list(browser_cookie_header, false) {
             let pair = pair.trim();
             if let Some((n, v)) = pair.split_once('=') {
                 cookie_map.insert(n.trim().to_string(), v.trim().to_string());
@@ -315,7 +779,7 @@ pub fn get_merged_cookies(browser_cookie_header: &str, request_path: &str) -> St
     // Merge jar cookies (jar wins on conflict, because it has secure cookies the browser can't store)
     let jar = COOKIE_JAR.read();
     for c in jar.iter() {
-        if request_path.starts_with(&c.path) {
+        if domain_matches(&c.domain, host) && path_matches(&c.path, request_path) {
             cookie_map.insert(c.name.clone(), c.value.clone());
         }
     }
@@ -329,25 +793,299 @@ pub fn get_merged_cookies(browser_cookie_header: &str, request_path: &str) -> St
 /// Build a Cookie header value from jar only (legacy, kept for compatibility)
 pub fn get_cookies_header(request_path: &str) -> String {
     purge_expired();
+    let host = current_cookie_host();
     let jar = COOKIE_JAR.read();
     jar.iter()
-        .filter(|c| request_path.starts_with(&c.path))
+        .filter(|c| domain_matches(&c.domain, &host) && path_matches(&c.path, request_path))
         .map(|c| format!("{}={}", c.name, c.value))
         .collect::<Vec<_>>()
         .join("; ")
 }
 
+/// Scope domain-less cookies (saved before domains were tracked) to `host`,
+/// so they stop matching every server once the user switches away.
+pub fn adopt_legacy_cookies(host: &str) {
+    let mut jar = COOKIE_JAR.write();
+    let mut changed = false;
+    for c in jar.iter_mut().filter(|c| c.domain.is_empty()) {
+        c.domain = host.to_lowercase();
+        changed = true;
+    }
+    drop(jar);
+    if changed {
+        schedule_save();
+    }
+}
+
+/// Drop expired cookies and write the jar to disk (used on quit)
+pub fn flush_cookies() {
+    purge_expired();
+    save_cookies();
+}
+
 /// Clear all cookies
 pub fn clear_cookies() {
     COOKIE_JAR.write().clear();
     save_cookies();
 }
 
+/// Placeholder for redacted values in exports. `<` and `>` can't occur in a
+/// cookie value, so importing never mistakes it for a real one.
+pub const REDACTED_COOKIE_VALUE: &str = "<redacted>";
+
+/// Unexpired jar entries as JSON. `redact` hides the values of HttpOnly and
+/// `__Secure-`/`__Host-` cookies, which usually hold the session.
+pub fn export_cookies(redact: bool) -> Result<String, String> {
+    purge_expired();
+    let mut cookies = COOKIE_JAR.read().clone();
+    if redact {
+        for c in cookies.iter_mut().filter(|c| c.http_only || c.name.starts_with("__Secure-") || c.name.starts_with("__Host-")) {
+            c.value = REDACTED_COOKIE_VALUE.to_string();
+        }
+    }
+    serde_json::to_string_pretty(&cookies).map_err(|e| format!("Failed to serialize cookies: {}", e))
+}
+
+/// Outcome of `import_cookies`
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct CookieImport {
+    pub imported: usize,
+    /// Malformed, expired or redacted entries
+    pub skipped: usize,
+}
+
+/// Merge a JSON array of cookies (as written by `export_cookies`) into the
+/// jar, replacing entries with the same name and domain, then save once
+pub fn import_cookies(json: &str) -> Result<CookieImport, String> {
+    let entries: Vec<serde_json::Value> = serde_json::from_str(json)
+        .map_err(|e| format!("Invalid cookie JSON: {}", e))?;
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let mut jar = COOKIE_JAR.write();
+    let mut imported = 0;
+    for entry in &entries {
+        let Ok(mut cookie) = CookieEntry::deserialize(entry) else {
+            continue;
+        };
+        cookie.domain = cookie.domain.trim_start_matches('.').to_lowercase();
+        let valid_name = !cookie.name.is_empty()
+            && !cookie.name.contains(|ch: char| ch.is_whitespace() || ch.is_control() || "=;,".contains(ch));
+        let valid = valid_name
+            && !cookie.value.contains(|ch: char| ch.is_control() || ch == ';')
+            && cookie.value != REDACTED_COOKIE_VALUE
+            && cookie.path.starts_with('/')
+            && (cookie.expires_at == 0 || cookie.expires_at > now);
+        if !valid {
+            continue;
+        }
+        match jar.iter_mut().find(|c| c.name == cookie.name && c.domain == cookie.domain) {
+            Some(existing) => *existing = cookie,
+            None => jar.push(cookie),
+        }
+        imported += 1;
+    }
+    drop(jar);
+    if imported > 0 {
+        save_cookies();
+    }
+    Ok(CookieImport { imported, skipped: entries.len() - imported })
+}
+
+/// Value of a cookie in the jar by name (e.g. the `__theme` preference)
+pub fn cookie_value(name: &str) -> Option<String> {
+    COOKIE_JAR.read().iter().find(|c| c.name == name).map(|c| c.value.clone())
+}
+
+/// A jar entry as shown in CUI's session details
+#[derive(Debug, Clone, Serialize)]
+pub struct CookieInfo {
+    pub name: String,
+    pub domain: String,
+    pub path: String,
+    pub expires_at: u64,
+    pub http_only: bool,
+    /// Masked unless revealed (see `mask_cookie_value`)
+    pub value: String,
+    pub value_len: usize,
+}
+
+/// Unexpired jar entries, values masked unless `reveal`
+pub fn cookie_infos(reveal: bool) -> Vec<CookieInfo> {
+    purge_expired();
+    COOKIE_JAR.read().iter()
+        .map(|c| CookieInfo {
+            name: c.name.clone(),
+            domain: c.domain.clone(),
+            path: c.path.clone(),
+            expires_at: c.expires_at,
+            http_only: c.http_only,
+            value: if reveal { c.value.clone() } else { mask_cookie_value(&c.value) },
+            value_len: c.value.chars().count(),
+        })
+        .collect()
+}
+
+/// Enough of a value to tell two cookies apart, never enough to reuse it:
+/// the first 4 characters of values longer than 12, nothing of shorter ones
+fn mask_cookie_value(value: &str) -> String {
+    if value.chars().count() > 12 {
+        format!("{}…", value.chars().take(4).collect::<String>())
+    } else if value.is_empty() {
+        String::new()
+    } else {
+        "…".to_string()
+    }
+}
+
+/// A Cookie or Set-Cookie header fit for the log: every value masked (see
+/// `mask_cookie_value`), Set-Cookie attributes kept
+pub fn mask_cookie_header(header: &str, set_cookie: bool) -> String {
+    split_cookie_list(header, set_cookie)
+        .into_iter()
+        .enumerate()
+        .map(|(i, part)| match part.split_once('=') {
+            Some((name, value)) if i == 0 || !set_cookie => format!("{}={}", name, mask_cookie_value(value.trim())),
+            _ => part.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+/// Remove cookies named `name` (only on `domain` when given); returns how
+/// many were removed
+pub fn delete_cookie(name: &str, domain: Option<&str>) -> usize {
+    let mut jar = COOKIE_JAR.write();
+    let before = jar.len();
+    jar.retain(|c| !(c.name == name && domain.is_none_or(|d| c.domain.eq_ignore_ascii_case(d))));
+    let removed = before - jar.len();
+    drop(jar);
+    if removed > 0 {
+        save_cookies();
+    }
+    removed
+}
+
 /// Get the number of stored cookies
 pub fn cookie_count() -> usize {
     COOKIE_JAR.read().len()
 }
 
+// ========== CUI Preferences ==========
+
+/// A supported CUI theme, matched case-insensitively. Preference values
+/// end up in inline scripts, so only known values pass (locales: `locale`).
+pub fn known_theme(theme: &str) -> Option<&'static str> {
+    ["dark", "light", "auto"].into_iter().find(|t| t.eq_ignore_ascii_case(theme.trim()))
+}
+
+/// Whether the OS is in dark mode, as last reported by the main window
+static SYSTEM_DARK: AtomicBool = AtomicBool::new(false);
+/// The theme preference is "auto": CUI follows the OS theme
+static THEME_FOLLOWS_SYSTEM: AtomicBool = AtomicBool::new(false);
+
+pub fn set_system_dark(dark: bool) {
+    SYSTEM_DARK.store(dark, Ordering::Relaxed);
+}
+
+pub fn system_dark() -> bool {
+    SYSTEM_DARK.load(Ordering::Relaxed)
+}
+
+pub fn set_theme_follows_system(follow: bool) {
+    THEME_FOLLOWS_SYSTEM.store(follow, Ordering::Relaxed);
+}
+
+pub fn theme_follows_system() -> bool {
+    THEME_FOLLOWS_SYSTEM.load(Ordering::Relaxed)
+}
+
+/// The theme CUI renders for a preference: "auto" becomes "dark" or
+/// "light" after the OS, unknown values become "" (CUI's default)
+pub fn resolve_theme(preference: &str, system_dark: bool) -> &'static str {
+    match known_theme(preference) {
+        Some("auto") if system_dark => "dark",
+        Some("auto") => "light",
+        Some(theme) => theme,
+        None => "",
+    }
+}
+
+/// User-level CUI preferences persisted to {app_data_dir}/preferences.json,
+/// separate from the developer's read-only config.json. Empty / None = unset.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct UserPreferences {
+    /// umi locale (`zh-CN`)
+    #[serde(default)]
+    pub locale: String,
+    #[serde(default)]
+    pub theme: String,
+    /// Zoom for windows without a level of their own
+    #[serde(default)]
+    pub zoom: Option<f64>,
+    /// Base font size in px, 8–32
+    #[serde(default)]
+    pub font_size: Option<f64>,
+    /// Keep the sign-in across restarts ("remember me"); None = on
+    #[serde(default)]
+    pub remember_session: Option<bool>,
+}
+
+impl UserPreferences {
+    /// Canonicalize values, rejecting anything that isn't a known locale or
+    /// theme (they are injected into CUI pages) or a usable size
+    pub fn validated(self) -> Result<Self, String> {
+        let locale = match self.locale.trim() {
+            "" => "",
+            l => crate::locale::known(l).ok_or_else(|| format!("Unsupported locale: {}", l))?,
+        };
+        let theme = match self.theme.trim() {
+            "" => "",
+            t => known_theme(t).ok_or_else(|| format!("Unsupported theme: {}", t))?,
+        };
+        if let Some(size) = self.font_size.filter(|s| !(8.0..=32.0).contains(s)) {
+            return Err(format!("Font size must be between 8 and 32: {}", size));
+        }
+        Ok(Self {
+            locale: locale.to_string(),
+            theme: theme.to_string(),
+            zoom: self.zoom.map(clamp_zoom),
+            font_size: self.font_size,
+            remember_session: self.remember_session,
+        })
+    }
+
+    pub fn remembers_session(&self) -> bool {
+        self.remember_session.unwrap_or(true)
+    }
+}
+
+fn preferences_file() -> Option<PathBuf> {
+    get_app_handle()?.path().app_data_dir().ok().map(|dir| dir.join("preferences.json"))
+}
+
+/// Saved user preferences; a missing or invalid file yields defaults
+pub fn get_user_preferences() -> UserPreferences {
+    preferences_file()
+        .and_then(|path| std::fs::read_to_string(path).ok())
+        .and_then(|data| serde_json::from_str::<UserPreferences>(&data).ok())
+        .and_then(|prefs| prefs.validated().ok())
+        .unwrap_or_default()
+}
+
+/// Validate and persist user preferences, returning what was saved
+pub fn save_user_preferences(prefs: UserPreferences) -> Result<UserPreferences, String> {
+    let prefs = prefs.validated()?;
+    let path = preferences_file().ok_or("App not ready")?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    let data = serde_json::to_string_pretty(&prefs).map_err(|e| e.to_string())?;
+    std::fs::write(&path, data).map_err(|e| format!("Failed to save preferences: {}", e))?;
+    Ok(prefs)
+}
+
 // ========== UI Language ==========
 
 /// Read the UI language from {app_data_dir}/lang.txt
@@ -375,6 +1113,135 @@ pub fn save_ui_lang(lang: &str) {
     }
 }
 
+/// Whether closing the main window hides it to tray: the user's choice from
+/// {app_data_dir}/close_to_tray.txt, else the config.json default
+pub fn get_close_to_tray() -> bool {
+    let saved = get_app_handle()
+        .and_then(|h| h.path().app_data_dir().ok())
+        .and_then(|dir| std::fs::read_to_string(dir.join("close_to_tray.txt")).ok());
+    parse_close_to_tray(saved.as_deref(), crate::app_conf::get_app_conf().close_to_tray)
+}
+
+/// Persist the user's close-to-tray choice to {app_data_dir}/close_to_tray.txt
+pub fn save_close_to_tray(enabled: bool) {
+    if let Some(handle) = get_app_handle() {
+        if let Ok(dir) = handle.path().app_data_dir() {
+            let _ = std::fs::create_dir_all(&dir);
+            let _ = std::fs::write(dir.join("close_to_tray.txt"), enabled.to_string());
+        }
+    }
+}
+
+/// `AppConf.kiosk` as read at startup, so a config reload can't unlock a
+/// running kiosk
+static KIOSK: AtomicBool = AtomicBool::new(false);
+
+pub fn set_kiosk(kiosk: bool) {
+    KIOSK.store(kiosk, Ordering::Relaxed);
+}
+
+pub fn kiosk() -> bool {
+    KIOSK.load(Ordering::Relaxed)
+}
+
+/// Refuse `action` in kiosk mode, where handing something to another app
+/// (browser, file manager, default app) would let users leave the kiosk
+pub fn check_not_kiosk(action: &str) -> Result<(), String> {
+    if kiosk() {
+        Err(format!("{} is disabled in kiosk mode", action))
+    } else {
+        Ok(())
+    }
+}
+
+fn parse_close_to_tray(saved: Option<&str>, default: bool) -> bool {
+    match saved.map(str::trim) {
+        Some("true") => true,
+        Some("false") => false,
+        _ => default,
+    }
+}
+
+/// Download directory: the user's choice from {app_data_dir}/download_dir.txt,
+/// else config.json. Empty means the system Downloads folder.
+pub fn get_download_dir() -> String {
+    let saved = get_app_handle()
+        .and_then(|h| h.path().app_data_dir().ok())
+        .and_then(|dir| std::fs::read_to_string(dir.join("download_dir.txt")).ok());
+    match saved {
+        Some(dir) => dir.trim().to_string(),
+        None => crate::app_conf::get_app_conf().download_dir,
+    }
+}
+
+/// Persist the download directory to {app_data_dir}/download_dir.txt
+pub fn save_download_dir(dir: &str) -> Result<(), String> {
+    let handle = get_app_handle().ok_or("App not ready")?;
+    let data_dir = handle.path().app_data_dir().map_err(|e| e.to_string())?;
+    std::fs::create_dir_all(&data_dir).map_err(|e| e.to_string())?;
+    std::fs::write(data_dir.join("download_dir.txt"), dir)
+        .map_err(|e| format!("Failed to save download directory: {}", e))
+}
+
+// ========== Zoom ==========
+
+pub const MIN_ZOOM: f64 = 0.5;
+pub const MAX_ZOOM: f64 = 3.0;
+
+/// Zoom currently applied per window label (Tauri has no zoom getter)
+static WINDOW_ZOOM: Lazy<RwLock<std::collections::HashMap<String, f64>>> =
+    Lazy::new(|| RwLock::new(std::collections::HashMap::new()));
+
+/// Clamp a requested zoom factor to the supported range; non-finite values reset to 1.0
+pub fn clamp_zoom(zoom: f64) -> f64 {
+    if zoom.is_finite() { zoom.clamp(MIN_ZOOM, MAX_ZOOM) } else { 1.0 }
+}
+
+fn zoom_file() -> Option<PathBuf> {
+    get_app_handle()?.path().app_data_dir().ok().map(|dir| dir.join("zoom.json"))
+}
+
+fn read_zoom_file() -> std::collections::HashMap<String, f64> {
+    zoom_file()
+        .and_then(|path| std::fs::read_to_string(path).ok())
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default()
+}
+
+/// Zoom factor of a window: the level applied this session, else the saved
+/// one, else the user's preferred zoom, else 1.0
+pub fn get_window_zoom(label: &str) -> f64 {
+    if let Some(zoom) = WINDOW_ZOOM.read().get(label) {
+        return *zoom;
+    }
+    read_zoom_file().get(label).copied()
+        .or_else(|| get_user_preferences().zoom)
+        .map(clamp_zoom)
+        .unwrap_or(1.0)
+}
+
+/// Record a window's zoom and persist it to {app_data_dir}/zoom.json.
+/// Popup labels are per-session, so their levels aren't saved.
+pub fn set_window_zoom(label: &str, zoom: f64) {
+    WINDOW_ZOOM.write().insert(label.to_string(), zoom);
+    if label.starts_with("popup_") {
+        return;
+    }
+    let Some(path) = zoom_file() else {
+        return;
+    };
+    let mut saved = read_zoom_file();
+    saved.insert(label.to_string(), zoom);
+    if let Some(dir) = path.parent() {
+        let _ = std::fs::create_dir_all(dir);
+    }
+    if let Ok(data) = serde_json::to_string_pretty(&saved) {
+        if let Err(e) = std::fs::write(&path, data) {
+            warn!("Failed to save zoom levels: {}", e);
+        }
+    }
+}
+
 /// Return a localized tray menu label based on the current UI language
 pub fn tray_label(key: &str) -> String {
     let lang = get_ui_lang();
@@ -383,22 +1250,90 @@ pub fn tray_label(key: &str) -> String {
         ("servers", "zh") => "切换服务器".into(),
         ("settings", "zh") => "设置".into(),
         ("quit", "zh") => "退出".into(),
+        ("close_to_tray", "zh") => "关闭时最小化到托盘".into(),
         ("switch_confirm", "zh") => "切换服务器将退出当前登录，是否继续？".into(),
+        ("untrusted_navigation", "zh") => "页面正在打开不在信任列表中的站点 {host}，是否在应用中继续打开？".into(),
+        ("untrusted_navigation_title", "zh") => "打开外部站点".into(),
+        ("edit", "zh") => "编辑".into(),
+        ("find", "zh") => "查找".into(),
+        ("view", "zh") => "视图".into(),
+        ("window", "zh") => "窗口".into(),
+        ("reload", "zh") => "重新加载".into(),
+        ("zoom_in", "zh") => "放大".into(),
+        ("zoom_out", "zh") => "缩小".into(),
+        ("zoom_reset", "zh") => "实际大小".into(),
+        ("fullscreen", "zh") => "切换全屏".into(),
         ("show", _) => "Show Window".into(),
         ("servers", _) => "Switch Server".into(),
         ("settings", _) => "Settings".into(),
         ("quit", _) => "Quit".into(),
+        ("close_to_tray", _) => "Close to Tray".into(),
         ("switch_confirm", _) => "Switching server will end your current session. Continue?".into(),
+        ("untrusted_navigation", _) => "This page wants to open {host}, which is not a trusted site. Open it in the app?".into(),
+        ("untrusted_navigation_title", _) => "Open External Site".into(),
+        ("edit", _) => "Edit".into(),
+        ("find", _) => "Find".into(),
+        ("view", _) => "View".into(),
+        ("window", _) => "Window".into(),
+        ("reload", _) => "Reload".into(),
+        ("zoom_in", _) => "Zoom In".into(),
+        ("zoom_out", _) => "Zoom Out".into(),
+        ("zoom_reset", _) => "Actual Size".into(),
+        ("fullscreen", _) => "Toggle Full Screen".into(),
         _ => key.into(),
     }
 }
 
+// ========== Popups ==========
+
+/// Global counter for generating unique popup window labels
+static POPUP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Labels of open popup windows, in the order they were opened
+static POPUPS: Lazy<RwLock<Vec<String>>> = Lazy::new(|| RwLock::new(Vec::new()));
+
+/// A label no popup has used yet (`popup_N`)
+pub fn next_popup_label() -> String {
+    format!("popup_{}", POPUP_COUNTER.fetch_add(1, Ordering::SeqCst))
+}
+
+/// Track a popup once its window is built
+pub fn register_popup(label: &str) {
+    let mut popups = POPUPS.write();
+    if !popups.iter().any(|l| l == label) {
+        popups.push(label.to_string());
+    }
+}
+
+/// Forget a popup whose window was destroyed
+pub fn unregister_popup(label: &str) {
+    POPUPS.write().retain(|l| l != label);
+}
+
+pub fn is_open_popup(label: &str) -> bool {
+    POPUPS.read().iter().any(|l| l == label)
+}
+
+/// Open popup labels, oldest first
+pub fn popup_labels() -> Vec<String> {
+    POPUPS.read().clone()
+}
+
+/// Serializes tests (across modules) that mutate the global proxy state or
+/// cookie jar. Sync tests take it with `blocking_lock()`, async tests with
+/// `lock_test_state().await`.
+#[cfg(test)]
+pub(crate) static TEST_MUTEX: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// Hold `TEST_MUTEX` across `.await` points in an async test
+#[cfg(test)]
+pub(crate) async fn lock_test_state() -> tokio::sync::MutexGuard<'static, ()> {
+    TEST_MUTEX.lock().await
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::sync::Mutex;
-
-    static TEST_MUTEX: Mutex<()> = Mutex::new(());
 
     fn reset_jar() {
         COOKIE_JAR.write().clear();
@@ -406,7 +1341,7 @@ mod tests {
 
     #[test]
     fn store_simple_cookie() {
-        let _lock = TEST_MUTEX.lock().unwrap();
+        let _lock = TEST_MUTEX.blocking_lock();
         reset_jar();
         let result = store_cookie("session=abc123; Path=/; HttpOnly");
         assert!(!result.is_secure);
@@ -424,7 +1359,7 @@ mod tests {
 
     #[test]
     fn store_secure_cookie_not_forwarded() {
-        let _lock = TEST_MUTEX.lock().unwrap();
+        let _lock = TEST_MUTEX.blocking_lock();
         reset_jar();
         let result = store_cookie("__Secure-token=xyz; Path=/; Secure; HttpOnly");
         assert!(result.is_secure);
@@ -437,7 +1372,7 @@ mod tests {
 
     #[test]
     fn store_cookie_with_secure_flag() {
-        let _lock = TEST_MUTEX.lock().unwrap();
+        let _lock = TEST_MUTEX.blocking_lock();
         reset_jar();
         let result = store_cookie("id=42; Path=/; Secure");
         assert!(result.is_secure);
@@ -446,8 +1381,9 @@ mod tests {
 
     #[test]
     fn store_cookie_strips_domain_and_samesite_none() {
-        let _lock = TEST_MUTEX.lock().unwrap();
+        let _lock = TEST_MUTEX.blocking_lock();
         reset_jar();
+        update_proxy_state("https://yao.example.com", "", "openapi", "");
         let result = store_cookie("tok=v; Path=/; Domain=example.com; SameSite=None; Secure");
         assert!(result.is_secure);
 
@@ -460,9 +1396,54 @@ mod tests {
         assert!(bc.contains("SameSite=Lax"));
     }
 
+    #[test]
+    fn jar_and_browser_decisions() {
+        let _lock = TEST_MUTEX.blocking_lock();
+        update_proxy_state("https://yao.example.com", "", "openapi", "");
+        // (Set-Cookie, kept in jar, browser copy)
+        let cases = [
+            ("a=1; Path=/; Secure; HttpOnly; SameSite=None", true, None),
+            ("__Host-a=1; Path=/; Secure", true, None),
+            ("__Secure-a=1; Path=/; Secure; SameSite=Lax", true, None),
+            ("a=1; Path=/; HttpOnly; SameSite=Strict", true, Some("a=1; Path=/; HttpOnly; SameSite=Strict")),
+            ("a=1; Path=/; HttpOnly; samesite = none", true, Some("a=1; Path=/; HttpOnly; SameSite=Lax")),
+            ("a=1; Path=/; SameSite=Lax; Domain=.example.com", true, Some("a=1; Path=/; SameSite=Lax")),
+            ("a=1; Path=/", true, Some("a=1; Path=/")),
+            ("a=; Path=/; Max-Age=0; HttpOnly", false, Some("a=; Path=/; Max-Age=0; HttpOnly")),
+            ("a=; Path=/; Max-Age=0; Secure", false, None),
+        ];
+        for (set_cookie, in_jar, browser) in cases {
+            reset_jar();
+            let result = store_cookie(set_cookie);
+            assert_eq!(cookie_count() == 1, in_jar, "{}", set_cookie);
+            assert_eq!(result.browser_cookie.as_deref(), browser, "{}", set_cookie);
+            assert_eq!(result.is_secure, browser.is_none(), "{}", set_cookie);
+        }
+        reset_jar();
+    }
+
+    #[test]
+    fn store_cookie_partitioned() {
+        let _lock = TEST_MUTEX.blocking_lock();
+        reset_jar();
+        let result = store_cookie("chip=1; Path=/; Partitioned; Secure");
+        assert!(result.is_secure);
+        assert!(result.browser_cookie.is_none());
+        assert_eq!(cookie_count(), 1);
+
+        reset_jar();
+        let result = store_cookie("chip=2; Path=/; Partitioned; HttpOnly");
+        assert!(!result.is_secure);
+        let bc = result.browser_cookie.unwrap();
+        assert!(bc.contains("chip=2"));
+        assert!(bc.contains("HttpOnly"));
+        assert!(!bc.to_lowercase().contains("partitioned"));
+        assert_eq!(COOKIE_JAR.read()[0].value, "2");
+    }
+
     #[test]
     fn store_cookie_upsert() {
-        let _lock = TEST_MUTEX.lock().unwrap();
+        let _lock = TEST_MUTEX.blocking_lock();
         reset_jar();
         store_cookie("key=old; Path=/");
         store_cookie("key=new; Path=/");
@@ -473,7 +1454,7 @@ mod tests {
 
     #[test]
     fn store_cookie_max_age_zero_deletes() {
-        let _lock = TEST_MUTEX.lock().unwrap();
+        let _lock = TEST_MUTEX.blocking_lock();
         reset_jar();
         store_cookie("key=val; Path=/");
         assert_eq!(cookie_count(), 1);
@@ -481,9 +1462,61 @@ mod tests {
         assert_eq!(cookie_count(), 0);
     }
 
+    #[test]
+    fn parse_cookie_date_formats() {
+        // RFC 1123
+        assert_eq!(parse_cookie_date("Wed, 21 Oct 2026 07:28:00 GMT"), Some(1792567680));
+        // RFC 850 (two-digit year)
+        assert_eq!(parse_cookie_date("Sunday, 06-Nov-94 08:49:37 GMT"), Some(784111777));
+        // asctime
+        assert_eq!(parse_cookie_date("Sun Nov  6 08:49:37 1994"), Some(784111777));
+        // Common dashed variant
+        assert_eq!(parse_cookie_date("Wed, 21-Oct-2026 07:28:00 GMT"), Some(1792567680));
+        assert_eq!(parse_cookie_date("not a date"), None);
+        assert_eq!(parse_cookie_date("Wed, 32 Oct 2026 07:28:00 GMT"), None);
+    }
+
+    #[test]
+    fn store_cookie_expires_sets_expiry() {
+        let _lock = TEST_MUTEX.blocking_lock();
+        reset_jar();
+        store_cookie("sid=abc; Path=/; Expires=Thu, 01 Jan 2099 00:00:00 GMT");
+        let jar = COOKIE_JAR.read();
+        assert_eq!(jar.len(), 1);
+        assert_eq!(jar[0].expires_at, 4070908800);
+    }
+
+    #[test]
+    fn store_cookie_past_expires_deletes() {
+        let _lock = TEST_MUTEX.blocking_lock();
+        reset_jar();
+        store_cookie("sid=abc; Path=/");
+        assert_eq!(cookie_count(), 1);
+        store_cookie("sid=; Path=/; Expires=Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(cookie_count(), 0);
+    }
+
+    #[test]
+    fn store_cookie_max_age_overrides_expires() {
+        let _lock = TEST_MUTEX.blocking_lock();
+        reset_jar();
+        // Past Expires but positive Max-Age: cookie is kept for Max-Age
+        store_cookie("sid=abc; Path=/; Expires=Thu, 01 Jan 1970 00:00:00 GMT; Max-Age=3600");
+        let expires_at = COOKIE_JAR.read()[0].expires_at;
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        assert!(expires_at >= now + 3590 && expires_at <= now + 3600);
+
+        // Future Expires but Max-Age=0: cookie is deleted
+        store_cookie("sid=abc; Path=/; Expires=Thu, 01 Jan 2099 00:00:00 GMT; Max-Age=0");
+        assert_eq!(cookie_count(), 0);
+    }
+
     #[test]
     fn store_cookie_empty_name_ignored() {
-        let _lock = TEST_MUTEX.lock().unwrap();
+        let _lock = TEST_MUTEX.blocking_lock();
         reset_jar();
         let result = store_cookie("=value; Path=/");
         assert!(!result.is_secure);
@@ -493,7 +1526,7 @@ mod tests {
 
     #[test]
     fn get_merged_cookies_browser_and_jar() {
-        let _lock = TEST_MUTEX.lock().unwrap();
+        let _lock = TEST_MUTEX.blocking_lock();
         reset_jar();
         store_cookie("jar_only=secret; Path=/");
         let merged = get_merged_cookies("browser_cookie=visible", "/api/test");
@@ -503,7 +1536,7 @@ mod tests {
 
     #[test]
     fn get_merged_cookies_jar_wins_conflict() {
-        let _lock = TEST_MUTEX.lock().unwrap();
+        let _lock = TEST_MUTEX.blocking_lock();
         reset_jar();
         store_cookie("token=from_jar; Path=/");
         let merged = get_merged_cookies("token=from_browser", "/");
@@ -513,7 +1546,7 @@ mod tests {
 
     #[test]
     fn get_merged_cookies_path_filtering() {
-        let _lock = TEST_MUTEX.lock().unwrap();
+        let _lock = TEST_MUTEX.blocking_lock();
         reset_jar();
         store_cookie("a=1; Path=/api");
         store_cookie("b=2; Path=/web");
@@ -522,9 +1555,127 @@ mod tests {
         assert!(!merged.contains("b=2"));
     }
 
+    #[test]
+    fn store_cookie_defaults_domain_to_server_host() {
+        let _lock = TEST_MUTEX.blocking_lock();
+        reset_jar();
+        update_proxy_state("https://Yao.Example.com:8443", "", "openapi", "");
+        store_cookie("a=1; Path=/");
+        store_cookie("b=2; Path=/; Domain=.example.com");
+        let jar = COOKIE_JAR.read();
+        assert_eq!(jar[0].domain, "yao.example.com");
+        assert_eq!(jar[1].domain, "example.com");
+    }
+
+    #[test]
+    fn get_merged_cookies_domain_filtering() {
+        let _lock = TEST_MUTEX.blocking_lock();
+        reset_jar();
+        update_proxy_state("https://a.example.com", "", "openapi", "");
+        store_cookie("host_only=1; Path=/");
+        store_cookie("parent=2; Path=/; Domain=example.com");
+        // Domains the server doesn't belong to are rejected outright
+        let rejected = store_cookie("other=3; Path=/; Domain=other.org");
+        assert!(rejected.browser_cookie.is_none());
+        store_cookie("child=4; Path=/; Domain=b.a.example.com");
+        assert_eq!(cookie_count(), 2);
+
+        let merged = get_merged_cookies("", "/");
+        assert!(merged.contains("host_only=1"));
+        assert!(merged.contains("parent=2"));
+        assert!(!merged.contains("other=3"));
+
+        // Switching servers must not leak the previous host's cookies
+        update_proxy_state("https://b.example.com", "", "openapi", "");
+        let merged = get_merged_cookies("", "/");
+        assert!(!merged.contains("host_only=1"));
+        assert!(merged.contains("parent=2"));
+
+        update_proxy_state("https://notexample.com", "", "openapi", "");
+        assert!(get_merged_cookies("", "/").is_empty());
+
+        // IP hosts only accept their own address as Domain
+        update_proxy_state("http://10.0.0.1:5099", "", "openapi", "");
+        store_cookie("ip=1; Path=/; Domain=0.0.1");
+        store_cookie("ip=2; Path=/; Domain=10.0.0.1");
+        assert_eq!(get_merged_cookies("", "/"), "ip=2");
+    }
+
+    #[test]
+    fn store_cookie_same_name_different_domains() {
+        let _lock = TEST_MUTEX.blocking_lock();
+        reset_jar();
+        update_proxy_state("https://one.test", "", "openapi", "");
+        store_cookie("sid=1; Path=/");
+        update_proxy_state("https://two.test", "", "openapi", "");
+        store_cookie("sid=2; Path=/");
+        assert_eq!(cookie_count(), 2);
+        assert_eq!(get_merged_cookies("", "/"), "sid=2");
+
+        // Deleting on one host leaves the other intact
+        store_cookie("sid=; Path=/; Max-Age=0");
+        assert_eq!(cookie_count(), 1);
+        update_proxy_state("https://one.test", "", "openapi", "");
+        assert_eq!(get_merged_cookies("", "/"), "sid=1");
+    }
+
+    #[test]
+    fn adopt_legacy_cookies_scopes_to_previous_host() {
+        let _lock = TEST_MUTEX.blocking_lock();
+        reset_jar();
+        update_proxy_state("https://old.test", "", "openapi", "");
+        store_cookie("scoped=1; Path=/");
+        COOKIE_JAR.write().push(CookieEntry {
+            name: "legacy".into(),
+            value: "2".into(),
+            domain: String::new(),
+            path: "/".into(),
+            expires_at: 0,
+            http_only: false,
+        });
+
+        adopt_legacy_cookies("Old.Test");
+        update_proxy_state("https://new.test", "", "openapi", "");
+        assert!(get_merged_cookies("", "/").is_empty());
+        update_proxy_state("https://old.test", "", "openapi", "");
+        let merged = get_merged_cookies("", "/");
+        assert!(merged.contains("scoped=1") && merged.contains("legacy=2"));
+    }
+
+    #[test]
+    fn cookie_entry_without_domain_deserializes() {
+        let old = r#"[{"name":"a","value":"1","path":"/","expires_at":0,"http_only":false}]"#;
+        let entries: Vec<CookieEntry> = serde_json::from_str(old).unwrap();
+        assert_eq!(entries[0].domain, "");
+        assert!(domain_matches(&entries[0].domain, "any.host"));
+    }
+
+    #[test]
+    fn path_matches_rfc6265() {
+        assert!(path_matches("/", "/anything"));
+        assert!(path_matches("/api", "/api"));
+        assert!(path_matches("/api", "/api/data"));
+        assert!(path_matches("/api/", "/api/data"));
+        assert!(path_matches("/api", "/api?x=1"));
+        assert!(!path_matches("/api", "/apixyz"));
+        assert!(!path_matches("/api/", "/api"));
+        assert!(!path_matches("/api", "/ap"));
+    }
+
+    #[test]
+    fn get_merged_cookies_no_prefix_false_positive() {
+        let _lock = TEST_MUTEX.blocking_lock();
+        reset_jar();
+        store_cookie("a=1; Path=/api");
+        assert!(get_merged_cookies("", "/apixyz").is_empty());
+        assert_eq!(get_merged_cookies("", "/api/data"), "a=1");
+        assert!(get_cookies_header("/apixyz").is_empty());
+        assert_eq!(get_cookies_header("/api/data"), "a=1");
+    }
+
     #[test]
     fn get_merged_cookies_empty() {
-        let _lock = TEST_MUTEX.lock().unwrap();
+        let _lock = TEST_MUTEX.blocking_lock();
         reset_jar();
         let merged = get_merged_cookies("", "/");
         assert!(merged.is_empty());
@@ -532,7 +1683,7 @@ mod tests {
 
     #[test]
     fn clear_cookies_empties_jar() {
-        let _lock = TEST_MUTEX.lock().unwrap();
+        let _lock = TEST_MUTEX.blocking_lock();
         reset_jar();
         store_cookie("a=1; Path=/");
         store_cookie("b=2; Path=/");
@@ -541,9 +1692,306 @@ mod tests {
         assert_eq!(cookie_count(), 0);
     }
 
+    #[test]
+    fn startup_milestones_recorded_in_order() {
+        let _lock = TEST_MUTEX.blocking_lock();
+        STARTUP_TIMINGS.write().clear();
+        mark_startup();
+        record_milestone(StartupMilestone::ConfigLoaded);
+        record_milestone(StartupMilestone::TrayReady);
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        record_milestone(StartupMilestone::ProxyBound);
+        // A repeat (e.g. proxy restart) keeps the original timestamp
+        let first = get_startup_timings()[2].elapsed_ms;
+        record_milestone(StartupMilestone::ProxyBound);
+
+        let timings = get_startup_timings();
+        let order: Vec<_> = timings.iter().map(|t| t.milestone).collect();
+        assert_eq!(
+            order,
+            vec![StartupMilestone::ConfigLoaded, StartupMilestone::TrayReady, StartupMilestone::ProxyBound]
+        );
+        assert!(timings.windows(2).all(|w| w[0].elapsed_ms <= w[1].elapsed_ms));
+        assert!(timings[2].elapsed_ms > timings[1].elapsed_ms);
+        assert_eq!(timings[2].elapsed_ms, first);
+    }
+
+    fn cookie_test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cui-cookies-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn corrupt_copies(dir: &std::path::Path) -> Vec<String> {
+        std::fs::read_dir(dir).unwrap()
+            .filter_map(|e| e.ok())
+            .map(|e| e.file_name().to_string_lossy().to_string())
+            .filter(|n| n.starts_with("cookies.json.corrupt."))
+            .collect()
+    }
+
+    #[test]
+    fn save_cookies_keeps_previous_version_as_backup() {
+        let _lock = TEST_MUTEX.blocking_lock();
+        reset_jar();
+        let dir = cookie_test_dir("save");
+        let file = dir.join("cookies.json");
+        set_cookie_file(file.clone());
+        store_cookie("a=1; Path=/");
+        store_cookie("b=2; Path=/");
+        *COOKIE_FILE.write() = None;
+
+        let current: Vec<CookieEntry> = serde_json::from_str(&std::fs::read_to_string(&file).unwrap()).unwrap();
+        let backup: Vec<CookieEntry> =
+            serde_json::from_str(&std::fs::read_to_string(dir.join("cookies.json.bak")).unwrap()).unwrap();
+        assert_eq!(current.len(), 2);
+        assert_eq!(backup.len(), 1);
+        assert!(!dir.join("cookies.json.tmp").exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn store_cookies_saves_once_per_response() {
+        let _lock = TEST_MUTEX.blocking_lock();
+        reset_jar();
+        let dir = cookie_test_dir("batch");
+        let file = dir.join("cookies.json");
+        set_cookie_file(file.clone());
+        let results = store_cookies(&["a=1; Path=/", "__Secure-s=2; Path=/; Secure", "bad", "c=3; Path=/; HttpOnly"]);
+        *COOKIE_FILE.write() = None;
+
+        let forwarded: Vec<_> = results.iter().map(|r| r.browser_cookie.as_deref()).collect();
+        assert_eq!(forwarded, [Some("a=1; Path=/"), None, None, Some("c=3; Path=/; HttpOnly")]);
+        assert!(results[1].is_secure);
+        let saved: Vec<CookieEntry> = serde_json::from_str(&std::fs::read_to_string(&file).unwrap()).unwrap();
+        assert_eq!(saved.len(), 3);
+        // A single save leaves no backup of an intermediate jar
+        assert!(!dir.join("cookies.json.bak").exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn cookie_saves_are_debounced() {
+        let _lock = lock_test_state().await;
+        reset_jar();
+        let dir = cookie_test_dir("debounce");
+        let file = dir.join("cookies.json");
+        set_cookie_file(file.clone());
+        for i in 0..5 {
+            store_cookie(&format!("c{}={}; Path=/", i, i));
+        }
+        assert_eq!(COOKIE_JAR.read().len(), 5);
+        assert!(!file.exists());
+
+        tokio::time::sleep(COOKIE_SAVE_DELAY * 2).await;
+        let saved: Vec<CookieEntry> = serde_json::from_str(&std::fs::read_to_string(&file).unwrap()).unwrap();
+        assert_eq!(saved.len(), 5);
+        // Written once: no backup of an earlier version
+        assert!(!dir.join("cookies.json.bak").exists());
+
+        // clear_cookies writes right away
+        clear_cookies();
+        *COOKIE_FILE.write() = None;
+        assert_eq!(std::fs::read_to_string(&file).unwrap().trim(), "[]");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn cookies_round_trip_through_export_and_import() {
+        let _lock = TEST_MUTEX.blocking_lock();
+        reset_jar();
+        update_proxy_state("https://yao.example.com", "", "openapi", "");
+        store_cookie("theme=dark; Path=/; Max-Age=3600");
+        store_cookie("sid=s3cret; Path=/api; HttpOnly");
+        store_cookie("__Host-csrf=t0k; Path=/; Secure");
+        let exported = export_cookies(false).unwrap();
+        let redacted = export_cookies(true).unwrap();
+        assert!(exported.contains("s3cret") && !redacted.contains("s3cret") && !redacted.contains("t0k"));
+
+        let sorted_jar = || {
+            let mut jar = COOKIE_JAR.read().clone();
+            jar.sort_by(|a, b| a.name.cmp(&b.name));
+            serde_json::to_value(jar).unwrap()
+        };
+        let before = sorted_jar();
+        reset_jar();
+        store_cookie("sid=old; Path=/");
+        assert_eq!(import_cookies(&exported).unwrap(), CookieImport { imported: 3, skipped: 0 });
+        assert_eq!(sorted_jar(), before);
+
+        // Only the non-secret cookie of a redacted export comes back
+        reset_jar();
+        assert_eq!(import_cookies(&redacted).unwrap(), CookieImport { imported: 1, skipped: 2 });
+        assert_eq!(cookie_value("theme").as_deref(), Some("dark"));
+
+        let bad = r#"[
+            {"name": "ok", "value": "1", "domain": ".Example.COM", "path": "/", "expires_at": 0, "http_only": false},
+            {"name": "old", "value": "1", "path": "/", "expires_at": 1, "http_only": false},
+            {"name": "a b", "value": "1", "path": "/", "expires_at": 0, "http_only": false},
+            {"name": "nopath", "value": "1", "path": "api", "expires_at": 0, "http_only": false},
+            {"value": "missing name"},
+            42
+        ]"#;
+        assert_eq!(import_cookies(bad).unwrap(), CookieImport { imported: 1, skipped: 5 });
+        assert!(COOKIE_JAR.read().iter().any(|c| c.name == "ok" && c.domain == "example.com"));
+        assert!(import_cookies("{").is_err());
+        reset_jar();
+        update_proxy_state("", "", "openapi", "");
+    }
+
+    #[test]
+    fn cookie_values_are_masked() {
+        assert_eq!(mask_cookie_value(""), "");
+        assert_eq!(mask_cookie_value("dark"), "…");
+        assert_eq!(mask_cookie_value("123456789012"), "…");
+        assert_eq!(mask_cookie_value("eyJhbGciOiJIUzI1NiJ9.payload.sig"), "eyJh…");
+        assert_eq!(mask_cookie_value("ключ-значение-сессии"), "ключ…");
+        assert_eq!(
+            mask_cookie_header("sid=eyJhbGciOiJIUzI1NiJ9.payload.sig; Path=/; Secure; HttpOnly", true),
+            "sid=eyJh…; Path=/; Secure; HttpOnly"
+        );
+        assert_eq!(mask_cookie_header("a=eyJhbGciOiJIUzI1NiJ9; theme=dark; flag", false), "a=eyJh…; theme=…; flag");
+
+        let _lock = TEST_MUTEX.blocking_lock();
+        reset_jar();
+        store_cookie("sid=eyJhbGciOiJIUzI1NiJ9.payload.sig; Path=/; HttpOnly");
+        store_cookie("theme=dark; Path=/");
+        let masked = cookie_infos(false);
+        assert_eq!(masked.len(), 2);
+        assert!(masked.iter().all(|c| !c.value.contains("payload") && c.value != "dark"));
+        assert_eq!((masked[0].value_len, masked[0].http_only), (32, true));
+        assert_eq!(cookie_infos(true)[1].value, "dark");
+
+        assert_eq!(delete_cookie("sid", Some("other.example")), 0);
+        assert_eq!(delete_cookie("sid", None), 1);
+        assert_eq!(cookie_count(), 1);
+        reset_jar();
+    }
+
+    #[test]
+    fn cookie_values_are_kept_verbatim() {
+        let _lock = TEST_MUTEX.blocking_lock();
+        reset_jar();
+        let quoted = store_cookie(r#"q="a=b; c"; Path=/; HttpOnly"#);
+        assert_eq!(quoted.browser_cookie.as_deref(), Some(r#"q="a=b; c"; Path=/; HttpOnly"#));
+        assert_eq!(cookie_value("q").as_deref(), Some(r#""a=b; c""#));
+        assert!(COOKIE_JAR.read()[0].http_only);
+
+        store_cookie("eq=a=b=c; Path=/");
+        assert_eq!(cookie_value("eq").as_deref(), Some("a=b=c"));
+        let token = store_cookie("tok=dG9rZW4gdmFsdWU==; Path=/");
+        assert_eq!(token.browser_cookie.as_deref(), Some("tok=dG9rZW4gdmFsdWU==; Path=/"));
+
+        // Browser and jar pairs go out unchanged (order is unspecified)
+        let merged = get_merged_cookies(r#"b64=YWJj==; bq="x;y""#, "/");
+        for pair in ["b64=YWJj==", r#"bq="x;y""#, r#"q="a=b; c""#, "eq=a=b=c", "tok=dG9rZW4gdmFsdWU=="] {
+            assert!(merged.contains(pair), "{} missing from {}", pair, merged);
+        }
+        reset_jar();
+
+        // A quote inside a value doesn't open a quoted string, so the
+        // attributes after it still apply
+        let stray = store_cookie(r#"a=x"y; Path=/; Secure; HttpOnly"#);
+        assert!(stray.is_secure);
+        assert_eq!(cookie_value("a").as_deref(), Some(r#"x"y"#));
+        assert!(COOKIE_JAR.read()[0].http_only);
+        assert_eq!(split_cookie_list(r#"a="x; Path=/; Secure"#, true), vec![r#"a="x"#, " Path=/", " Secure"]);
+        assert_eq!(split_cookie_list(r#"a="x;y"; Path="/p;q"; Secure"#, true), vec![r#"a="x;y""#, r#" Path="/p"#, r#"q""#, " Secure"]);
+        assert_eq!(split_cookie_list(r#"c=1"; d="2;3""#, false), vec![r#"c=1""#, r#" d="2;3""#]);
+        reset_jar();
+    }
+
+    #[test]
+    fn load_cookies_recovers_corrupt_file_from_backup() {
+        let _lock = TEST_MUTEX.blocking_lock();
+        reset_jar();
+        let dir = cookie_test_dir("recover");
+        let file = dir.join("cookies.json");
+        std::fs::write(&file, "{ not json").unwrap();
+        std::fs::write(
+            dir.join("cookies.json.bak"),
+            r#"[{"name":"sid","value":"ok","path":"/","expires_at":0,"http_only":true}]"#,
+        ).unwrap();
+
+        set_cookie_file(file.clone());
+        load_cookies();
+        *COOKIE_FILE.write() = None;
+
+        assert_eq!(cookie_count(), 1);
+        assert_eq!(COOKIE_JAR.read()[0].value, "ok");
+        // Corrupt original kept for diagnosis, main file rewritten from backup
+        let corrupt = corrupt_copies(&dir);
+        assert_eq!(corrupt.len(), 1);
+        assert_eq!(std::fs::read_to_string(dir.join(&corrupt[0])).unwrap(), "{ not json");
+        assert!(read_cookie_file(&file).unwrap().is_some());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn load_cookies_corrupt_without_backup_starts_empty() {
+        let _lock = TEST_MUTEX.blocking_lock();
+        reset_jar();
+        let dir = cookie_test_dir("nobak");
+        let file = dir.join("cookies.json");
+        std::fs::write(&file, "garbage").unwrap();
+
+        set_cookie_file(file.clone());
+        load_cookies();
+        *COOKIE_FILE.write() = None;
+
+        assert_eq!(cookie_count(), 0);
+        assert_eq!(corrupt_copies(&dir).len(), 1);
+        assert!(!file.exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn jwt_expiry_reads_exp_claim() {
+        // {"alg":"HS256"}.{"sub":"u1","exp":1792567680}.sig
+        let jwt = "eyJhbGciOiJIUzI1NiJ9.eyJzdWIiOiJ1MSIsImV4cCI6MTc5MjU2NzY4MH0.c2ln";
+        assert_eq!(jwt_expiry(jwt), Some(1792567680));
+        assert_eq!(jwt_expiry("opaque-token"), None);
+        assert_eq!(jwt_expiry("a.b.c"), None);
+        assert_eq!(jwt_expiry(""), None);
+    }
+
+    #[test]
+    fn update_proxy_state_tracks_token_expiry() {
+        let _lock = TEST_MUTEX.blocking_lock();
+        let jwt = "eyJhbGciOiJIUzI1NiJ9.eyJzdWIiOiJ1MSIsImV4cCI6MTc5MjU2NzY4MH0.c2ln";
+        update_proxy_state("https://a.test", jwt, "openapi", "");
+        assert_eq!(get_proxy_state().token_expires_at, 1792567680);
+
+        // Expiry reported by login for an opaque token survives re-applying it
+        update_proxy_state("https://a.test", "opaque", "openapi", "");
+        assert_eq!(get_proxy_state().token_expires_at, 0);
+        set_token_expiry(1700000000);
+        update_proxy_state("https://a.test", "opaque", "openapi", "/d");
+        assert_eq!(get_proxy_state().token_expires_at, 1700000000);
+    }
+
+    #[test]
+    fn clamp_zoom_limits_range() {
+        assert_eq!(clamp_zoom(1.25), 1.25);
+        assert_eq!(clamp_zoom(0.1), MIN_ZOOM);
+        assert_eq!(clamp_zoom(10.0), MAX_ZOOM);
+        assert_eq!(clamp_zoom(f64::NAN), 1.0);
+        assert_eq!(clamp_zoom(f64::INFINITY), 1.0);
+    }
+
+    #[test]
+    fn parse_close_to_tray_prefers_saved_choice() {
+        assert!(parse_close_to_tray(None, true));
+        assert!(!parse_close_to_tray(None, false));
+        assert!(!parse_close_to_tray(Some("false\n"), true));
+        assert!(parse_close_to_tray(Some("true"), false));
+        assert!(parse_close_to_tray(Some("garbage"), true));
+    }
+
     #[test]
     fn update_proxy_state_normalizes_dashboard() {
-        let _lock = TEST_MUTEX.lock().unwrap();
+        let _lock = TEST_MUTEX.blocking_lock();
         update_proxy_state("http://example.com", "tok", "openapi", "dashboard/");
         let s = get_proxy_state();
         assert_eq!(s.dashboard, "/dashboard");
@@ -556,4 +2004,79 @@ mod tests {
         let s = get_proxy_state();
         assert_eq!(s.dashboard, "");
     }
+
+    #[test]
+    fn known_preferences_are_whitelisted() {
+        assert_eq!(crate::locale::known("zh-cn"), Some("zh-CN"));
+        assert_eq!(crate::locale::known("EN-US"), Some("en-US"));
+        assert_eq!(crate::locale::known("en\");alert(1)//"), None);
+        assert_eq!(known_theme("Dark"), Some("dark"));
+        assert_eq!(known_theme("auto"), Some("auto"));
+        assert_eq!(known_theme("\"});alert(1)//"), None);
+        assert_eq!(known_theme(""), None);
+    }
+
+    #[test]
+    fn auto_theme_resolves_after_the_os() {
+        assert_eq!(resolve_theme("auto", true), "dark");
+        assert_eq!(resolve_theme("Auto", false), "light");
+        assert_eq!(resolve_theme("light", true), "light");
+        assert_eq!(resolve_theme("dark", false), "dark");
+        assert_eq!(resolve_theme("", true), "");
+        assert_eq!(resolve_theme("\"});alert(1)//", true), "");
+    }
+
+    #[test]
+    fn user_preferences_are_validated() {
+        let prefs = UserPreferences {
+            locale: "zh-cn".into(),
+            theme: " Dark ".into(),
+            zoom: Some(9.0),
+            font_size: Some(15.0),
+            remember_session: Some(false),
+        };
+        assert_eq!(prefs.validated().unwrap(), UserPreferences {
+            locale: "zh-CN".into(),
+            theme: "dark".into(),
+            zoom: Some(MAX_ZOOM),
+            font_size: Some(15.0),
+            remember_session: Some(false),
+        });
+        assert_eq!(UserPreferences::default().validated().unwrap(), UserPreferences::default());
+
+        let bad = |prefs: UserPreferences| prefs.validated().is_err();
+        assert!(bad(UserPreferences { locale: "en\");alert(1)//".into(), ..Default::default() }));
+        assert!(bad(UserPreferences { theme: "neon".into(), ..Default::default() }));
+        assert!(bad(UserPreferences { font_size: Some(64.0), ..Default::default() }));
+        assert!(bad(UserPreferences { font_size: Some(f64::NAN), ..Default::default() }));
+    }
+
+    #[test]
+    fn set_issuer_url_trims_trailing_slash() {
+        let _lock = TEST_MUTEX.blocking_lock();
+        set_issuer_url(" https://auth.example.com/ ");
+        assert_eq!(get_proxy_state().issuer_url, "https://auth.example.com");
+        set_issuer_url("");
+        assert_eq!(get_proxy_state().issuer_url, "");
+    }
+
+    #[test]
+    fn popup_registry_tracks_open_windows() {
+        let first = next_popup_label();
+        let second = next_popup_label();
+        assert!(first.starts_with("popup_") && first != second);
+
+        register_popup(&second);
+        register_popup(&first);
+        register_popup(&second);
+        let open = popup_labels();
+        let pos = |l: &str| open.iter().position(|o| o == l).unwrap();
+        assert!(pos(&second) < pos(&first));
+        assert_eq!(open.iter().filter(|l| **l == second).count(), 1);
+
+        unregister_popup(&second);
+        assert!(!is_open_popup(&second) && is_open_popup(&first));
+        unregister_popup(&first);
+        assert!(!is_open_popup(&first));
+    }
 }
@@ -0,0 +1,205 @@
+use serde::Serialize;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+
+use crate::commands::{fetch_well_known, WellKnownInfo};
+use crate::config::ProxyState;
+use crate::error::CommandError;
+
+/// Endpoint CUI loads first after sign-in
+const USER_ENTRY_PATH: &str = "/v1/user/entry";
+
+/// Per-request limit for the loopback round trip
+const ROUND_TRIP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Outcome of one diagnostic step
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepStatus {
+    Pass,
+    Fail,
+    /// Not applicable (e.g. TLS for an http server)
+    Skip,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiagnosticStep {
+    /// `well_known`, `tls`, `user_entry`, `local_port` or `proxy_round_trip`
+    pub name: &'static str,
+    pub status: StepStatus,
+    /// What was found, or why the step failed
+    pub detail: String,
+    pub millis: u64,
+}
+
+/// Result of `diagnose`, in the order the steps ran
+#[derive(Debug, Clone, Serialize)]
+pub struct DiagnosticReport {
+    pub server_url: String,
+    /// No step failed
+    pub ok: bool,
+    pub steps: Vec<DiagnosticStep>,
+}
+
+/// Check every hop between the app and `server_url` (already normalized).
+/// `port` is the port the proxy would bind; `state` is only read.
+pub async fn run(client: &reqwest::Client, server_url: &str, port: u16, state: &ProxyState) -> DiagnosticReport {
+    let steps = vec![
+        step("well_known", well_known(client, server_url)).await,
+        step("tls", tls(client, server_url)).await,
+        step("user_entry", user_entry(client, server_url)).await,
+        step("local_port", local_port(port, state)).await,
+        step("proxy_round_trip", proxy_round_trip(server_url, state)).await,
+    ];
+    DiagnosticReport {
+        server_url: server_url.to_string(),
+        ok: steps.iter().all(|s| s.status != StepStatus::Fail),
+        steps,
+    }
+}
+
+async fn step(name: &'static str, check: impl Future<Output = (StepStatus, String)>) -> DiagnosticStep {
+    let started = Instant::now();
+    let (status, detail) = check.await;
+    DiagnosticStep { name, status, detail, millis: started.elapsed().as_millis() as u64 }
+}
+
+fn describe(info: &WellKnownInfo) -> String {
+    let name = info.name.as_deref().unwrap_or("Yao server");
+    match &info.version {
+        Some(version) => format!("{} {}", name, version),
+        None => name.to_string(),
+    }
+}
+
+async fn well_known(client: &reqwest::Client, server_url: &str) -> (StepStatus, String) {
+    match fetch_well_known(client, server_url).await {
+        Ok(info) => (StepStatus::Pass, describe(&info)),
+        Err(e) => (StepStatus::Fail, e.to_string()),
+    }
+}
+
+async fn tls(client: &reqwest::Client, server_url: &str) -> (StepStatus, String) {
+    if !server_url.starts_with("https://") {
+        return (StepStatus::Skip, "Plain http, no TLS".to_string());
+    }
+    match client.head(server_url).send().await {
+        Ok(_) => (StepStatus::Pass, "TLS handshake and certificate OK".to_string()),
+        Err(e) => (StepStatus::Fail, CommandError::from(e).to_string()),
+    }
+}
+
+async fn user_entry(client: &reqwest::Client, server_url: &str) -> (StepStatus, String) {
+    let url = format!("{}{}", server_url, USER_ENTRY_PATH);
+    match client.get(&url).send().await {
+        Ok(resp) if resp.status().is_success() => (StepStatus::Pass, format!("{} returned {}", USER_ENTRY_PATH, resp.status())),
+        Ok(resp) => (StepStatus::Fail, format!("{} returned {}", USER_ENTRY_PATH, resp.status())),
+        Err(e) => (StepStatus::Fail, CommandError::from(e).to_string()),
+    }
+}
+
+async fn local_port(port: u16, state: &ProxyState) -> (StepStatus, String) {
+    if state.running && state.port == port {
+        return (StepStatus::Pass, format!("Port {} is in use by the running proxy", port));
+    }
+    if port == 0 {
+        return (StepStatus::Pass, "The system picks a free port".to_string());
+    }
+    let ip = crate::proxy::loopback_order(crate::app_conf::get_app_conf().proxy.local_host).0;
+    match TcpListener::bind((ip, port)).await {
+        Ok(_) => (StepStatus::Pass, format!("Port {} on {} is free", port, ip)),
+        Err(e) => (StepStatus::Fail, format!("Port {} on {} is unavailable ({}); the proxy will try the next ports", port, ip, e)),
+    }
+}
+
+/// `.well-known/yao` through the running proxy, when it is connected to this server
+async fn proxy_round_trip(server_url: &str, state: &ProxyState) -> (StepStatus, String) {
+    if !state.running {
+        return (StepStatus::Skip, "Proxy is not running".to_string());
+    }
+    if state.server_url.trim_end_matches('/') != server_url {
+        return (StepStatus::Skip, format!("Proxy is connected to {}", state.server_url));
+    }
+    let client = match reqwest::Client::builder().no_proxy().timeout(ROUND_TRIP_TIMEOUT).build() {
+        Ok(c) => c,
+        Err(e) => return (StepStatus::Fail, e.to_string()),
+    };
+    let local_base = crate::proxy::local_base(state.port);
+    match fetch_well_known(&client, &local_base).await {
+        Ok(info) => (StepStatus::Pass, format!("{} via {}", describe(&info), local_base)),
+        Err(e) => (StepStatus::Fail, format!("Via {}: {}", local_base, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Yao-like server; `/v1/user/entry` answers `entry_status`
+    async fn spawn_server(entry_status: u16) -> String {
+        use axum::{Router, http::StatusCode, routing::get};
+
+        let entry = StatusCode::from_u16(entry_status).unwrap();
+        let app = Router::new()
+            .route("/.well-known/yao", get(|| async { r#"{"name":"Acme","version":"1.2.0"}"# }))
+            .route(USER_ENTRY_PATH, get(move || async move { (entry, "{}") }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+        format!("http://{}", addr)
+    }
+
+    fn statuses(report: &DiagnosticReport) -> Vec<(&'static str, StepStatus)> {
+        report.steps.iter().map(|s| (s.name, s.status)).collect()
+    }
+
+    #[tokio::test]
+    async fn healthy_server_passes() {
+        // local_port reads proxy.localHost
+        let _lock = crate::config::lock_test_state().await;
+        let server = spawn_server(200).await;
+        let client = reqwest::Client::builder().no_proxy().build().unwrap();
+        let free_port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+
+        let report = run(&client, &server, free_port, &ProxyState::default()).await;
+        assert!(report.ok);
+        assert_eq!(statuses(&report), [
+            ("well_known", StepStatus::Pass),
+            ("tls", StepStatus::Skip),
+            ("user_entry", StepStatus::Pass),
+            ("local_port", StepStatus::Pass),
+            ("proxy_round_trip", StepStatus::Skip),
+        ]);
+        assert_eq!(report.steps[0].detail, "Acme 1.2.0");
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["server_url"], server.as_str());
+        assert_eq!(json["steps"][1]["status"], "skip");
+        assert!(json["steps"][0]["millis"].is_u64());
+    }
+
+    #[tokio::test]
+    async fn failures_are_reported_per_step() {
+        // local_port reads proxy.localHost
+        let _lock = crate::config::lock_test_state().await;
+        let server = spawn_server(404).await;
+        let client = reqwest::Client::builder().no_proxy().build().unwrap();
+        let busy = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let busy_port = busy.local_addr().unwrap().port();
+
+        let report = run(&client, &server, busy_port, &ProxyState::default()).await;
+        assert!(!report.ok);
+        assert_eq!(report.steps[2].status, StepStatus::Fail);
+        assert!(report.steps[2].detail.contains("404"), "{}", report.steps[2].detail);
+        assert_eq!(report.steps[3].status, StepStatus::Fail);
+
+        // Nothing listening at all
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let report = run(&client, &format!("http://127.0.0.1:{}", closed), 0, &ProxyState::default()).await;
+        assert_eq!(report.steps[0].status, StepStatus::Fail);
+        assert_eq!(report.steps[2].status, StepStatus::Fail);
+    }
+}
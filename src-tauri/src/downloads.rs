@@ -0,0 +1,365 @@
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tauri::async_runtime::JoinHandle;
+use tauri::{AppHandle, Emitter, Manager};
+use tracing::{info, warn};
+
+use crate::config;
+
+/// Lifecycle of a download started by the app (native webview downloads aren't tracked)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DownloadStatus {
+    Pending,
+    Downloading,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl DownloadStatus {
+    fn is_active(self) -> bool {
+        matches!(self, Self::Pending | Self::Downloading)
+    }
+}
+
+/// A download as reported to the frontend (`get_downloads`, `download://updated`)
+#[derive(Debug, Clone, Serialize)]
+pub struct DownloadInfo {
+    pub id: u64,
+    pub url: String,
+    pub filename: String,
+    /// Destination on disk, once the response headers are in
+    pub path: Option<String>,
+    pub status: DownloadStatus,
+    pub downloaded: u64,
+    /// Content-Length, 0 if unknown
+    pub total: u64,
+    pub error: Option<String>,
+}
+
+/// Registry entry: the reported state plus the task doing the transfer
+pub struct DownloadHandle {
+    pub info: DownloadInfo,
+    task: Option<JoinHandle<()>>,
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+static DOWNLOADS: Lazy<RwLock<HashMap<u64, DownloadHandle>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// Directory downloads are saved to: the configured one, else system Downloads
+pub fn target_dir(app: &AppHandle) -> Result<PathBuf, String> {
+    let configured = config::get_download_dir();
+    if configured.is_empty() {
+        app.path().download_dir().map_err(|e| e.to_string())
+    } else {
+        Ok(PathBuf::from(configured))
+    }
+}
+
+/// Validate a directory for `set_download_dir`: empty (system Downloads) or absolute
+pub fn validate_dir(dir: &str) -> Result<String, String> {
+    let dir = dir.trim();
+    if !dir.is_empty() && !Path::new(dir).is_absolute() {
+        return Err(format!("Download directory must be an absolute path: {}", dir));
+    }
+    Ok(dir.to_string())
+}
+
+/// Whether `path` is where a tracked download was saved (e.g. a "save as" target)
+pub fn is_download_path(path: &Path) -> bool {
+    DOWNLOADS.read().values()
+        .any(|d| d.info.path.as_deref().map(Path::new) == Some(path))
+}
+
+/// Whether a file may be revealed/opened through the desktop API: it must lie
+/// in the download directory (configured or system) or be a tracked download.
+/// Paths are canonicalized so `..` can't escape the directory.
+pub fn is_allowed_path(app: &AppHandle, path: &Path) -> bool {
+    if !path.is_absolute() {
+        return false;
+    }
+    if is_download_path(path) {
+        return true;
+    }
+    let Ok(target) = std::fs::canonicalize(path) else {
+        return false;
+    };
+    let dirs = target_dir(app).ok().into_iter().chain(app.path().download_dir().ok());
+    in_any_dir(&target, dirs)
+}
+
+fn in_any_dir(target: &Path, dirs: impl IntoIterator<Item = PathBuf>) -> bool {
+    dirs.into_iter().any(|dir| {
+        let dir = std::fs::canonicalize(&dir).unwrap_or(dir);
+        target.starts_with(dir)
+    })
+}
+
+/// Extensions that run code (or install software) when the OS opens them
+const EXECUTABLE_EXTENSIONS: &[&str] = &[
+    // Windows
+    "exe", "com", "scr", "pif", "msi", "msp", "bat", "cmd", "ps1", "psm1", "vbs", "vbe",
+    "js", "jse", "wsf", "wsh", "hta", "cpl", "msc", "lnk", "reg", "inf", "appx", "msix",
+    // macOS
+    "app", "command", "tool", "pkg", "mpkg", "scpt", "applescript", "workflow", "terminal",
+    // Linux and cross-platform
+    "sh", "bash", "zsh", "csh", "run", "bin", "desktop", "appimage", "deb", "rpm",
+    "jar", "py", "pl", "rb", "php",
+];
+
+/// Whether a file may be handed to the OS default application. Nothing is
+/// in kiosk mode; otherwise executables, scripts and installers are refused,
+/// as is anything the download extension policy refuses and, on Unix, any
+/// file with an execute bit.
+pub fn check_openable(path: &Path) -> Result<(), String> {
+    config::check_not_kiosk("Opening files")?;
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let ext = Path::new(name.trim_end_matches(['.', ' ']))
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase());
+    if let Some(ext) = ext.filter(|e| EXECUTABLE_EXTENSIONS.contains(&e.as_str())) {
+        return Err(format!("Opening .{} files is not allowed", ext));
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if std::fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0) {
+            return Err("Opening executable files is not allowed".to_string());
+        }
+    }
+    crate::check_download_allowed(&name)
+}
+
+/// Open a file with the OS default application, unless `check_openable`
+/// refuses it
+pub fn open_file(path: &Path) {
+    if let Err(e) = check_openable(path) {
+        warn!("Not opening {:?}: {}", path, e);
+        return;
+    }
+    info!("Open file: {:?}", path);
+    #[cfg(target_os = "macos")]
+    let result = std::process::Command::new("open").arg(path).spawn();
+    #[cfg(target_os = "windows")]
+    let result = std::process::Command::new("explorer").arg(path).spawn();
+    #[cfg(target_os = "linux")]
+    let result = std::process::Command::new("xdg-open").arg(path).spawn();
+    if let Err(e) = result {
+        warn!("Failed to open {:?}: {}", path, e);
+    }
+}
+
+/// Show a file selected in the OS file manager (its folder on Linux); not
+/// in kiosk mode
+pub fn reveal_file(path: &Path) {
+    if let Err(e) = config::check_not_kiosk("Showing files in the file manager") {
+        warn!("Not revealing {:?}: {}", path, e);
+        return;
+    }
+    info!("Reveal file: {:?}", path);
+    #[cfg(target_os = "macos")]
+    let result = std::process::Command::new("open").arg("-R").arg(path).spawn();
+    #[cfg(target_os = "windows")]
+    let result = std::process::Command::new("explorer").arg(format!("/select,\"{}\"", path.display())).spawn();
+    #[cfg(target_os = "linux")]
+    let result = std::process::Command::new("xdg-open").arg(path.parent().unwrap_or(Path::new("."))).spawn();
+    if let Err(e) = result {
+        warn!("Failed to reveal {:?}: {}", path, e);
+    }
+}
+
+/// Open a finished download when `openAfterDownload` is set
+pub fn open_if_configured(path: &Path) {
+    if crate::app_conf::get_app_conf().open_after_download {
+        open_file(path);
+    }
+}
+
+/// Track a new download and return its id
+pub fn register(url: &str) -> u64 {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let info = DownloadInfo {
+        id,
+        url: url.to_string(),
+        filename: String::new(),
+        path: None,
+        status: DownloadStatus::Pending,
+        downloaded: 0,
+        total: 0,
+        error: None,
+    };
+    DOWNLOADS.write().insert(id, DownloadHandle { info, task: None });
+    id
+}
+
+/// Attach the task running a download so it can be aborted
+pub fn set_task(id: u64, task: JoinHandle<()>) {
+    if let Some(entry) = DOWNLOADS.write().get_mut(&id) {
+        entry.task = Some(task);
+    }
+}
+
+/// Apply a change to a download's state. Cancelled downloads are frozen so a
+/// task that hasn't noticed its abort yet can't overwrite the status.
+fn apply(id: u64, change: impl FnOnce(&mut DownloadInfo)) -> Option<DownloadInfo> {
+    let mut downloads = DOWNLOADS.write();
+    let entry = downloads.get_mut(&id)?;
+    if entry.info.status == DownloadStatus::Cancelled {
+        return None;
+    }
+    change(&mut entry.info);
+    Some(entry.info.clone())
+}
+
+/// Update a download and emit `download://updated`
+pub fn update(app: &AppHandle, id: u64, change: impl FnOnce(&mut DownloadInfo)) {
+    if let Some(info) = apply(id, change) {
+        let _ = app.emit("download://updated", &info);
+    }
+}
+
+/// All tracked downloads, oldest first
+pub fn list() -> Vec<DownloadInfo> {
+    let mut all: Vec<DownloadInfo> = DOWNLOADS.read().values().map(|d| d.info.clone()).collect();
+    all.sort_by_key(|d| d.id);
+    all
+}
+
+/// Mark an active download cancelled, returning its task and partial file
+fn mark_cancelled(id: u64) -> Result<(DownloadInfo, Option<JoinHandle<()>>), String> {
+    let mut downloads = DOWNLOADS.write();
+    let entry = downloads.get_mut(&id).ok_or_else(|| format!("Download {} not found", id))?;
+    if !entry.info.status.is_active() {
+        return Err(format!("Download {} is not in progress", id));
+    }
+    entry.info.status = DownloadStatus::Cancelled;
+    Ok((entry.info.clone(), entry.task.take()))
+}
+
+/// Abort an in-flight download and delete its partial file
+pub async fn cancel(app: &AppHandle, id: u64) -> Result<DownloadInfo, String> {
+    let (info, task) = mark_cancelled(id)?;
+    if let Some(task) = task {
+        task.abort();
+        // Wait for the task to drop its file handle before deleting
+        let _ = task.await;
+    }
+    if let Some(path) = &info.path {
+        let _ = std::fs::remove_file(PathBuf::from(path));
+    }
+    let _ = app.emit("download://updated", &info);
+    Ok(info)
+}
+
+/// Reset a failed or cancelled download so it can run again; returns its URL
+pub fn reset_for_retry(id: u64) -> Result<String, String> {
+    let mut downloads = DOWNLOADS.write();
+    let entry = downloads.get_mut(&id).ok_or_else(|| format!("Download {} not found", id))?;
+    if !matches!(entry.info.status, DownloadStatus::Failed | DownloadStatus::Cancelled) {
+        return Err(format!("Download {} can only be retried after it failed or was cancelled", id));
+    }
+    entry.info.status = DownloadStatus::Pending;
+    entry.info.path = None;
+    entry.info.downloaded = 0;
+    entry.info.total = 0;
+    entry.info.error = None;
+    entry.task = None;
+    Ok(entry.info.url.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(id: u64) -> DownloadStatus {
+        DOWNLOADS.read()[&id].info.status
+    }
+
+    #[test]
+    fn cancelled_downloads_ignore_late_updates() {
+        let id = register("http://127.0.0.1:15099/v1/file/a.pdf");
+        apply(id, |d| d.status = DownloadStatus::Downloading);
+        assert!(mark_cancelled(id).is_ok());
+        assert!(apply(id, |d| d.status = DownloadStatus::Completed).is_none());
+        assert_eq!(status(id), DownloadStatus::Cancelled);
+        // Already cancelled
+        assert!(mark_cancelled(id).is_err());
+    }
+
+    #[test]
+    fn validate_dir_requires_absolute_path() {
+        assert_eq!(validate_dir("  ").unwrap(), "");
+        assert!(validate_dir("exports").is_err());
+        let abs = std::env::temp_dir().join("exports");
+        assert_eq!(validate_dir(&abs.to_string_lossy()).unwrap(), abs.to_string_lossy());
+    }
+
+    #[test]
+    fn in_any_dir_rejects_traversal() {
+        let root = std::env::temp_dir().join(format!("cui-dl-{}", std::process::id()));
+        let dl = root.join("Downloads");
+        std::fs::create_dir_all(&dl).unwrap();
+        std::fs::write(dl.join("report.pdf"), b"%PDF").unwrap();
+        std::fs::write(root.join("secret.txt"), b"x").unwrap();
+
+        let inside = std::fs::canonicalize(dl.join("report.pdf")).unwrap();
+        assert!(in_any_dir(&inside, [dl.clone()]));
+        let escaped = std::fs::canonicalize(dl.join("../secret.txt")).unwrap();
+        assert!(!in_any_dir(&escaped, [dl.clone()]));
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn executables_and_scripts_are_never_opened() {
+        let _lock = config::TEST_MUTEX.blocking_lock();
+        let dir = std::env::temp_dir().join(format!("cui-open-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["report.pdf", "setup.EXE", "run.sh", "evil.exe. ", "invoice.pdf.js", "tool"] {
+            std::fs::write(dir.join(name), b"x").unwrap();
+        }
+
+        assert!(check_openable(&dir.join("report.pdf")).is_ok());
+        assert_eq!(check_openable(&dir.join("setup.EXE")), Err("Opening .exe files is not allowed".to_string()));
+        for name in ["run.sh", "evil.exe. ", "invoice.pdf.js"] {
+            assert!(check_openable(&dir.join(name)).is_err(), "{}", name);
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert!(check_openable(&dir.join("tool")).is_ok());
+            std::fs::set_permissions(dir.join("tool"), std::fs::Permissions::from_mode(0o755)).unwrap();
+            assert!(check_openable(&dir.join("tool")).is_err());
+        }
+
+        config::set_kiosk(true);
+        let kiosk = check_openable(&dir.join("report.pdf"));
+        config::set_kiosk(false);
+        assert_eq!(kiosk, Err("Opening files is disabled in kiosk mode".to_string()));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn retry_only_after_failure_or_cancel() {
+        let id = register("http://127.0.0.1:15099/v1/file/b.zip");
+        assert!(reset_for_retry(id).is_err());
+
+        apply(id, |d| {
+            d.status = DownloadStatus::Failed;
+            d.downloaded = 512;
+            d.error = Some("Stream error".into());
+        });
+        assert_eq!(reset_for_retry(id).unwrap(), "http://127.0.0.1:15099/v1/file/b.zip");
+        let info = list().into_iter().find(|d| d.id == id).unwrap();
+        assert_eq!(info.status, DownloadStatus::Pending);
+        assert_eq!((info.downloaded, info.error), (0, None));
+
+        assert!(reset_for_retry(u64::MAX).is_err());
+    }
+}
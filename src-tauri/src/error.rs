@@ -0,0 +1,294 @@
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use std::error::Error as StdError;
+use std::fmt;
+
+/// Error returned by commands, serialized as `{kind, message}` so the
+/// frontend can branch on `kind` and show `message` as is
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandError {
+    /// The server couldn't be reached
+    Network(String),
+    /// Connecting to the server failed; serialized with kind `network` plus
+    /// `cause` and `http_may_help`
+    Connect(ConnectFailure),
+    /// The server answered with an unexpected status
+    HttpStatus(u16),
+    /// The server's response wasn't what we expected
+    Parse(String),
+    /// Credentials were rejected or sign-in was not completed
+    Unauthorized(String),
+    /// Bad input or config.json settings
+    Config(String),
+    Internal(String),
+}
+
+impl CommandError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Network(_) | Self::Connect(_) => "network",
+            Self::HttpStatus(_) => "http_status",
+            Self::Parse(_) => "parse",
+            Self::Unauthorized(_) => "unauthorized",
+            Self::Config(_) => "config",
+            Self::Internal(_) => "internal",
+        }
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::HttpStatus(code) => match reqwest::StatusCode::from_u16(*code) {
+                Ok(status) => write!(f, "Server returned {}", status),
+                Err(_) => write!(f, "Server returned {}", code),
+            },
+            Self::Connect(failure) => f.write_str(&failure.message),
+            Self::Network(msg) | Self::Parse(msg) | Self::Unauthorized(msg)
+            | Self::Config(msg) | Self::Internal(msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+impl Serialize for CommandError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("CommandError", 4)?;
+        s.serialize_field("kind", self.kind())?;
+        s.serialize_field("message", &self.to_string())?;
+        if let Self::Connect(failure) = self {
+            s.serialize_field("cause", &failure.cause)?;
+            s.serialize_field("http_may_help", &failure.http_may_help)?;
+        }
+        s.end()
+    }
+}
+
+/// 401/403 are `Unauthorized`, anything else `HttpStatus`
+impl From<reqwest::StatusCode> for CommandError {
+    fn from(status: reqwest::StatusCode) -> Self {
+        match status {
+            reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN => {
+                Self::Unauthorized(format!("Server returned {}", status))
+            }
+            _ => Self::HttpStatus(status.as_u16()),
+        }
+    }
+}
+
+impl From<reqwest::Error> for CommandError {
+    fn from(e: reqwest::Error) -> Self {
+        if let Some(status) = e.status() {
+            return status.into();
+        }
+        if e.is_builder() {
+            Self::Config(format!("Invalid request: {}", e))
+        } else if e.is_connect() || e.is_timeout() {
+            // Skip reqwest's own message: it includes the URL, which could
+            // contain any of the words we look for
+            Self::Connect(ConnectFailure::new(e.url(), e.is_timeout(), e.source()))
+        } else if e.is_decode() {
+            Self::Parse(format!("Failed to parse server response: {}", e))
+        } else {
+            Self::Network(format!("Cannot connect to server: {}", e))
+        }
+    }
+}
+
+/// What went wrong while connecting
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectCause {
+    Dns,
+    Refused,
+    Tls,
+    Timeout,
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectFailure {
+    pub cause: ConnectCause,
+    pub message: String,
+    /// The URL is https and plain http may work (TLS failed, or a local
+    /// server refused the connection)
+    pub http_may_help: bool,
+}
+
+impl ConnectFailure {
+    /// Diagnose a failed connection from the error's source chain
+    pub fn new(url: Option<&url::Url>, timed_out: bool, source: Option<&(dyn StdError + 'static)>) -> Self {
+        let cause = if timed_out { ConnectCause::Timeout } else { classify(source) };
+        let host = url.and_then(|u| u.host_str()).unwrap_or("the server");
+        let detail = source.map(root_message).unwrap_or_default();
+        let message = match cause {
+            ConnectCause::Dns => format!("Cannot resolve {}: check the address and your DNS or proxy settings", host),
+            ConnectCause::Refused => format!("{} refused the connection: is the server running on that port?", host),
+            ConnectCause::Tls => format!("Secure connection to {} failed ({})", host, detail),
+            ConnectCause::Timeout => format!("Connection to {} timed out", host),
+            ConnectCause::Other => format!("Cannot connect to server: {}", detail),
+        };
+        let https = url.is_some_and(|u| u.scheme() == "https");
+        let http_may_help = https
+            && (cause == ConnectCause::Tls
+                || (cause == ConnectCause::Refused && url.is_some_and(is_private_host)));
+        Self { cause, message, http_may_help }
+    }
+}
+
+fn classify(source: Option<&(dyn StdError + 'static)>) -> ConnectCause {
+    let mut current = source;
+    while let Some(e) = current {
+        if let Some(io) = e.downcast_ref::<std::io::Error>() {
+            match io.kind() {
+                std::io::ErrorKind::ConnectionRefused => return ConnectCause::Refused,
+                std::io::ErrorKind::TimedOut => return ConnectCause::Timeout,
+                _ => {}
+            }
+        }
+        let msg = e.to_string().to_lowercase();
+        if msg.contains("dns error") || msg.contains("failed to lookup address") {
+            return ConnectCause::Dns;
+        }
+        if msg.contains("certificate") || msg.contains("tls") || msg.contains("handshake") {
+            return ConnectCause::Tls;
+        }
+        current = e.source();
+    }
+    ConnectCause::Other
+}
+
+/// Message of the innermost error, which names the actual problem
+fn root_message(e: &(dyn StdError + 'static)) -> String {
+    let mut e = e;
+    while let Some(next) = e.source() {
+        e = next;
+    }
+    e.to_string()
+}
+
+/// localhost, loopback or a private-network address
+pub fn is_private_host(url: &url::Url) -> bool {
+    match url.host() {
+        Some(url::Host::Domain(d)) => d.eq_ignore_ascii_case("localhost"),
+        Some(url::Host::Ipv4(ip)) => ip.is_loopback() || ip.is_private() || ip.is_link_local(),
+        Some(url::Host::Ipv6(ip)) => {
+            // fc00::/7 unique local, fe80::/10 link local
+            ip.is_loopback() || (ip.segments()[0] & 0xfe00) == 0xfc00 || (ip.segments()[0] & 0xffc0) == 0xfe80
+        }
+        None => false,
+    }
+}
+
+impl From<serde_json::Error> for CommandError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Parse(format!("Failed to parse server response: {}", e))
+    }
+}
+
+impl From<url::ParseError> for CommandError {
+    fn from(e: url::ParseError) -> Self {
+        Self::Config(format!("Invalid URL: {}", e))
+    }
+}
+
+/// Errors from helpers that still report plain strings
+impl From<String> for CommandError {
+    fn from(msg: String) -> Self {
+        Self::Internal(msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serializes_kind_and_message() {
+        assert_eq!(
+            serde_json::to_value(CommandError::HttpStatus(502)).unwrap(),
+            serde_json::json!({"kind": "http_status", "message": "Server returned 502 Bad Gateway"})
+        );
+        assert_eq!(
+            serde_json::to_value(CommandError::Network("Cannot connect to server: refused".into())).unwrap(),
+            serde_json::json!({"kind": "network", "message": "Cannot connect to server: refused"})
+        );
+    }
+
+    #[test]
+    fn conversions_pick_the_kind() {
+        assert!(matches!(CommandError::from(reqwest::StatusCode::UNAUTHORIZED), CommandError::Unauthorized(_)));
+        assert!(matches!(CommandError::from(reqwest::StatusCode::FORBIDDEN), CommandError::Unauthorized(_)));
+        assert_eq!(CommandError::from(reqwest::StatusCode::NOT_FOUND), CommandError::HttpStatus(404));
+
+        let builder = reqwest::Client::new().get("not a url").build().unwrap_err();
+        assert_eq!(CommandError::from(builder).kind(), "config");
+
+        let json = serde_json::from_str::<serde_json::Value>("{").unwrap_err();
+        assert_eq!(CommandError::from(json).kind(), "parse");
+        assert_eq!(CommandError::from(url::Url::parse("::").unwrap_err()).kind(), "config");
+        assert_eq!(CommandError::from("boom".to_string()), CommandError::Internal("boom".into()));
+    }
+
+    /// Stand-in for hyper's wrapper errors
+    #[derive(Debug)]
+    struct Wrapped(&'static str, Box<dyn StdError + Send + Sync>);
+
+    impl fmt::Display for Wrapped {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(self.0)
+        }
+    }
+
+    impl StdError for Wrapped {
+        fn source(&self) -> Option<&(dyn StdError + 'static)> {
+            Some(self.1.as_ref())
+        }
+    }
+
+    fn diagnose(url: &str, timed_out: bool, err: Wrapped) -> ConnectFailure {
+        ConnectFailure::new(Some(&url::Url::parse(url).unwrap()), timed_out, Some(&err))
+    }
+
+    #[test]
+    fn connect_failures_are_diagnosed() {
+        use std::io::{Error, ErrorKind};
+
+        let dns = diagnose("https://nope.example", false,
+            Wrapped("client error (Connect)", Box::new(Wrapped("dns error", Box::new(Error::other("failed to lookup address information"))))));
+        assert_eq!((dns.cause, dns.http_may_help), (ConnectCause::Dns, false));
+        assert!(dns.message.contains("nope.example"), "{}", dns.message);
+
+        let refused = || Wrapped("tcp connect error", Box::new(Error::from(ErrorKind::ConnectionRefused)));
+        let local = diagnose("https://192.168.1.20:5099", false, refused());
+        assert_eq!((local.cause, local.http_may_help), (ConnectCause::Refused, true));
+        assert!(!diagnose("https://yao.example.com", false, refused()).http_may_help);
+        assert!(!diagnose("http://localhost:5099", false, refused()).http_may_help);
+
+        let tls = diagnose("https://yao.example.com", false,
+            Wrapped("client error (Connect)", Box::new(Error::new(ErrorKind::InvalidData, "invalid peer certificate: UnknownIssuer"))));
+        assert_eq!((tls.cause, tls.http_may_help), (ConnectCause::Tls, true));
+        assert!(tls.message.ends_with("(invalid peer certificate: UnknownIssuer)"), "{}", tls.message);
+
+        let timeout = diagnose("https://yao.example.com", true, refused());
+        assert_eq!(timeout.cause, ConnectCause::Timeout);
+        let other = diagnose("https://yao.example.com", false, Wrapped("client error", Box::new(Error::other("broken pipe"))));
+        assert_eq!(other.cause, ConnectCause::Other);
+
+        assert_eq!(
+            serde_json::to_value(CommandError::Connect(tls)).unwrap()["cause"],
+            serde_json::json!("tls")
+        );
+    }
+
+    #[test]
+    fn private_hosts() {
+        let private = |u: &str| is_private_host(&url::Url::parse(u).unwrap());
+        assert!(private("https://localhost:5099"));
+        assert!(private("https://127.0.0.1"));
+        assert!(private("https://10.0.0.8"));
+        assert!(private("https://[fd12::1]"));
+        assert!(!private("https://8.8.8.8"));
+        assert!(!private("https://yao.example.com"));
+    }
+}
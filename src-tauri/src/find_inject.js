@@ -0,0 +1,173 @@
+(function(){
+if(window.__yaoFind)return;
+var BAR_ID="__yao_find_bar";
+var zh=/^zh/i.test(localStorage.getItem("umi_locale")||navigator.language||"");
+var T=zh?{find:"查找",none:"无匹配",matchCase:"区分大小写",prev:"上一个",next:"下一个",close:"关闭"}
+:{find:"Find",none:"No matches",matchCase:"Match case",prev:"Previous",next:"Next",close:"Close"};
+var canHighlight=!!(window.CSS&&CSS.highlights&&window.Highlight);
+var state={query:"",matchCase:false,ranges:[],active:-1};
+var bar=null,input=null,count=null,caseBtn=null;
+function collect(query,matchCase){
+var ranges=[];
+var needle=matchCase?query:query.toLowerCase();
+var walker=document.createTreeWalker(document.body,NodeFilter.SHOW_TEXT,{acceptNode:function(n){
+var p=n.parentElement;
+if(!p||p.closest("script,style,noscript,#"+BAR_ID)||!p.getClientRects().length)return NodeFilter.FILTER_REJECT;
+return NodeFilter.FILTER_ACCEPT;
+}});
+for(var n=walker.nextNode();n;n=walker.nextNode()){
+var text=matchCase?n.data:n.data.toLowerCase();
+for(var i=text.indexOf(needle);i!==-1;i=text.indexOf(needle,i+needle.length)){
+var r=document.createRange();
+r.setStart(n,i);
+r.setEnd(n,i+needle.length);
+ranges.push(r);
+}
+}
+return ranges;
+}
+function paint(){
+var cur=state.ranges[state.active];
+if(canHighlight){
+var all=new Highlight(),active=new Highlight();
+state.ranges.forEach(function(r){all.add(r);});
+if(cur)active.add(cur);
+CSS.highlights.set("yao-find",all);
+CSS.highlights.set("yao-find-active",active);
+}else{
+var sel=window.getSelection();
+sel.removeAllRanges();
+if(cur)sel.addRange(cur);
+}
+if(cur){
+var el=cur.startContainer.parentElement;
+if(el)el.scrollIntoView({block:"center",inline:"nearest"});
+}
+}
+function clear(){
+state={query:"",matchCase:state.matchCase,ranges:[],active:-1};
+if(canHighlight){
+CSS.highlights.delete("yao-find");
+CSS.highlights.delete("yao-find-active");
+}else{
+window.getSelection().removeAllRanges();
+}
+}
+function find(query,forward,matchCase){
+query=String(query||"");
+matchCase=!!matchCase;
+if(!query){
+clear();
+update(0,0);
+return {query:"",active:0,total:0};
+}
+var again=query===state.query&&matchCase===state.matchCase&&state.active>=0;
+var prev=again?state.ranges[state.active]:null;
+state.ranges=collect(query,matchCase);
+state.query=query;
+state.matchCase=matchCase;
+var total=state.ranges.length;
+if(!total){
+state.active=-1;
+}else if(again){
+// The page may have changed since the last step: continue from the same match
+var at=state.ranges.findIndex(function(r){return r.startContainer===prev.startContainer&&r.startOffset===prev.startOffset;});
+if(at<0)at=Math.min(state.active,total-1);
+state.active=(at+(forward===false?-1:1)+total)%total;
+}else{
+state.active=forward===false?total-1:0;
+}
+paint();
+update(state.active+1,total);
+return {query:query,active:state.active+1,total:total};
+}
+function update(active,total){
+if(!bar)return;
+count.textContent=!state.query?"":total?active+"/"+total:T.none;
+count.className=state.query&&!total?"none":"";
+}
+function styles(){
+var dark=localStorage.getItem("__theme")==="dark";
+var css="::highlight(yao-find){background-color:rgba(255,200,0,.45);color:inherit}"
++"::highlight(yao-find-active){background-color:#ff9632;color:#000}"
++"#"+BAR_ID+"{position:fixed;top:10px;right:16px;z-index:2147483647;display:flex;align-items:center;gap:4px;padding:6px 8px;border-radius:8px;font:13px -apple-system,BlinkMacSystemFont,'Segoe UI',sans-serif;"
++(dark?"background:#2a2a2e;color:#eee;border:1px solid #444;":"background:#fff;color:#222;border:1px solid #ddd;")
++"box-shadow:0 4px 16px rgba(0,0,0,.18)}"
++"#"+BAR_ID+" input{width:200px;padding:4px 6px;border:1px solid "+(dark?"#555":"#ccc")+";border-radius:4px;background:transparent;color:inherit;font:inherit;outline:none}"
++"#"+BAR_ID+" span{min-width:64px;text-align:center;opacity:.75}"
++"#"+BAR_ID+" span.none{color:#e5484d;opacity:1}"
++"#"+BAR_ID+" button{border:none;background:transparent;color:inherit;font:inherit;padding:2px 6px;border-radius:4px;cursor:pointer}"
++"#"+BAR_ID+" button:hover,#"+BAR_ID+" button[aria-pressed=true]{background:"+(dark?"#444":"#eee")+"}";
+// Constructed sheets are not subject to the page's style-src
+if(document.adoptedStyleSheets&&window.CSSStyleSheet&&CSSStyleSheet.prototype.replaceSync){
+var sheet=new CSSStyleSheet();
+sheet.replaceSync(css);
+document.adoptedStyleSheets=document.adoptedStyleSheets.concat([sheet]);
+}else{
+var s=document.createElement("style");
+s.textContent=css;
+document.head.appendChild(s);
+}
+}
+function button(text,title,onclick){
+var b=document.createElement("button");
+b.type="button";
+b.textContent=text;
+b.title=title;
+b.addEventListener("click",onclick);
+bar.appendChild(b);
+return b;
+}
+function build(){
+styles();
+bar=document.createElement("div");
+bar.id=BAR_ID;
+input=document.createElement("input");
+input.placeholder=T.find;
+input.addEventListener("input",function(){find(input.value,true,state.matchCase);});
+input.addEventListener("keydown",function(e){
+if(e.key==="Enter"){e.preventDefault();find(input.value,!e.shiftKey,state.matchCase);}
+else if(e.key==="Escape"){e.preventDefault();close();}
+});
+bar.appendChild(input);
+count=document.createElement("span");
+bar.appendChild(count);
+caseBtn=button("Aa",T.matchCase,function(){
+var on=!state.matchCase;
+caseBtn.setAttribute("aria-pressed",String(on));
+state.query="";
+find(input.value,true,on);
+});
+caseBtn.setAttribute("aria-pressed","false");
+button("↑",T.prev,function(){find(input.value,false,state.matchCase);});
+button("↓",T.next,function(){find(input.value,true,state.matchCase);});
+button("×",T.close,close);
+document.body.appendChild(bar);
+}
+function open(){
+if(!bar)build();
+bar.style.display="flex";
+input.focus();
+input.select();
+if(input.value){
+state.query="";
+find(input.value,true,state.matchCase);
+}
+}
+function close(){
+if(bar)bar.style.display="none";
+clear();
+}
+function isOpen(){
+return !!bar&&bar.style.display!=="none";
+}
+document.addEventListener("keydown",function(e){
+if(!(e.metaKey||e.ctrlKey)||e.altKey)return;
+var k=e.key.toLowerCase();
+if(k==="f")open();
+else if(k==="g"&&isOpen())find(input.value,!e.shiftKey,state.matchCase);
+else return;
+e.preventDefault();
+},true);
+window.__yaoFind={find:find,open:open,close:close};
+})();
@@ -1,16 +1,30 @@
 mod app_conf;
 mod commands;
 mod config;
+mod diagnostics;
+mod downloads;
+mod error;
+mod locale;
+mod logging;
+mod oauth;
+mod print;
 mod proxy;
+mod request_log;
+mod session_store;
+mod static_cache;
+mod tls;
 mod tunnel;
+mod upstream;
+mod updates;
+mod web_storage;
+mod window_state;
 
-use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Mutex;
 use std::collections::HashMap;
 use std::path::PathBuf;
 use tauri::{
-    Manager, WebviewUrl, WebviewWindowBuilder,
-    menu::{Menu, MenuItem},
+    Emitter, Manager, WebviewUrl, WebviewWindowBuilder,
+    menu::{AboutMetadata, CheckMenuItem, Menu, MenuItem, PredefinedMenuItem, Submenu},
     tray::TrayIconBuilder,
     image::Image,
     WindowEvent,
@@ -18,10 +32,8 @@ use tauri::{
 use tauri::webview::{DownloadEvent, NewWindowResponse};
 use futures_util::StreamExt;
 use tracing::{info, debug, warn};
-use tracing_subscriber::EnvFilter;
-
-/// Global counter for generating unique popup window labels
-static POPUP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+use downloads::DownloadStatus;
+use app_conf::{DownloadExtensionPolicy, DownloadPolicyMode, LinkBehavior, TrayAction, TrayMenuItem, UntrustedNavigation};
 
 /// Tracks download destinations set during DownloadEvent::Requested,
 /// so we can retrieve the file path in DownloadEvent::Finished
@@ -232,14 +244,112 @@ fn eval_on_main(handle: &tauri::AppHandle, js: &str) {
     }
 }
 
-fn open_in_system_browser(url: &str) {
-    #[cfg(target_os = "macos")]
-    { let _ = std::process::Command::new("open").arg(url).spawn(); }
-    #[cfg(target_os = "windows")]
-    { let _ = std::process::Command::new("cmd")
-        .args(["/C", "start", "", url]).spawn(); }
-    #[cfg(target_os = "linux")]
-    { let _ = std::process::Command::new("xdg-open").arg(url).spawn(); }
+/// Whether `url` may be handed to the OS: web pages and mail links only,
+/// never `file:` or schemes that launch other apps, and nothing in kiosk mode
+fn check_handoff(url: &str) -> Result<(), String> {
+    config::check_not_kiosk("Opening links outside the app")?;
+    if url::Url::parse(url).is_ok_and(|u| matches!(u.scheme(), "http" | "https" | "mailto")) {
+        Ok(())
+    } else {
+        Err("only http(s) and mailto links are opened outside the app".to_string())
+    }
+}
+
+/// Open a web page or mail link with the system default app; other URLs
+/// are dropped (see `check_handoff`)
+pub(crate) fn open_in_system_browser(url: &str) {
+    if let Err(e) = check_handoff(url) {
+        warn!("Not handing off {}: {}", url, e);
+        return;
+    }
+    if let Err(e) = tauri_plugin_opener::open_url(url, None::<&str>) {
+        warn!("Failed to open {}: {}", url, e);
+    }
+}
+
+/// What the main window does with a navigation request
+#[derive(Debug, PartialEq, Eq)]
+enum Navigation {
+    Allow,
+    /// A page on the server: load it through the proxy instead
+    ViaProxy(String),
+    /// A host outside `navigationAllowlist`, or a mail link
+    Untrusted,
+    /// Any other scheme (`file:`, `data:`, `blob:`, app schemes): neither
+    /// loaded nor handed off
+    Deny,
+}
+
+/// Decide a main-window navigation. The app shell, the local proxy, the
+/// server, its issuer and the OAuth endpoints are always trusted.
+fn classify_navigation(url: &url::Url, state: &config::ProxyState, conf: &app_conf::AppConf) -> Navigation {
+    match url.scheme() {
+        "tauri" | "about" => return Navigation::Allow,
+        "http" | "https" => {}
+        "mailto" => return Navigation::Untrusted,
+        _ => return Navigation::Deny,
+    }
+    let url_str = url.as_str();
+    let host = url.host_str().unwrap_or("");
+    // Dev server and the app shell on Windows (http://tauri.localhost)
+    if (host == "localhost" && url.scheme() == "http") || host == "tauri.localhost" {
+        return Navigation::Allow;
+    }
+    let local_base = proxy::local_base(state.port);
+    if url_str.starts_with(&format!("{}/", local_base)) || url_str == local_base {
+        return Navigation::Allow;
+    }
+    if state.running && !state.server_url.is_empty() {
+        let remote = state.server_url.trim_end_matches('/');
+        if url_str.starts_with(remote) {
+            return Navigation::ViaProxy(url_str.replacen(remote, &local_base, 1));
+        }
+    }
+    if is_trusted_auth_url(url, state, conf) {
+        Navigation::Allow
+    } else {
+        Navigation::Untrusted
+    }
+}
+
+/// A sign-in page that must stay in the app for cookie continuity: the
+/// server, its issuer, the OAuth endpoints or a `navigationAllowlist` entry
+fn is_trusted_auth_url(url: &url::Url, state: &config::ProxyState, conf: &app_conf::AppConf) -> bool {
+    let Some(host) = url.host_str().map(str::to_ascii_lowercase) else {
+        return false;
+    };
+    let host_of = |u: &str| url::Url::parse(u).ok().and_then(|u| u.host_str().map(str::to_ascii_lowercase));
+    [&state.server_url, &state.issuer_url, &conf.oauth.authorization_endpoint, &conf.oauth.token_endpoint]
+        .into_iter()
+        .filter_map(|u| host_of(u))
+        .any(|h| h == host)
+        || conf.navigation_allowlist.iter().any(|entry| app_conf::allowlist_matches(entry, url))
+}
+
+/// URL the user agreed to open in the main window (see `confirm_navigation`)
+static CONFIRMED_NAVIGATION: Mutex<Option<String>> = Mutex::new(None);
+
+/// Ask before opening an untrusted page in the main window; on OK the
+/// navigation is repeated and let through once
+fn confirm_navigation(handle: &tauri::AppHandle, url: url::Url) {
+    use tauri_plugin_dialog::{DialogExt, MessageDialogButtons};
+    let msg = config::tray_label("untrusted_navigation").replace("{host}", url.host_str().unwrap_or(url.as_str()));
+    let h = handle.clone();
+    handle.dialog()
+        .message(msg)
+        .title(config::tray_label("untrusted_navigation_title"))
+        .buttons(MessageDialogButtons::OkCancel)
+        .show(move |confirmed| {
+            if !confirmed {
+                return;
+            }
+            if let Ok(mut pending) = CONFIRMED_NAVIGATION.lock() {
+                *pending = Some(url.to_string());
+            }
+            if let Some(w) = h.get_webview_window("main") {
+                let _ = w.navigate(url);
+            }
+        });
 }
 
 fn is_external_url(url: &str) -> bool {
@@ -249,7 +359,7 @@ fn is_external_url(url: &str) -> bool {
             return false;
         }
         let host = parsed.host_str().unwrap_or("");
-        if host == "127.0.0.1" || host == "localhost" || host == "::1" {
+        if host == "127.0.0.1" || host == "localhost" || host == "[::1]" {
             return false;
         }
         let state = config::get_proxy_state();
@@ -266,7 +376,7 @@ fn is_external_url(url: &str) -> bool {
 }
 
 /// Detect sandbox service port URLs that should open in the system browser.
-/// Case 1: 127.0.0.1/localhost + port != proxy_port (CUI uses location.hostname)
+/// Case 1: 127.0.0.1/localhost/[::1] + port != proxy_port (CUI uses location.hostname)
 /// Case 2: remote_host + port != main_port (direct remote address)
 fn should_open_in_browser(url: &str) -> bool {
     let parsed = match url::Url::parse(url) {
@@ -285,7 +395,7 @@ fn should_open_in_browser(url: &str) -> bool {
     let port = parsed.port();
 
     // Case 1: localhost with non-proxy port
-    if host == "127.0.0.1" || host == "localhost" {
+    if host == "127.0.0.1" || host == "localhost" || host == "[::1]" {
         if let Some(p) = port {
             return p != state.port;
         }
@@ -344,36 +454,331 @@ fn build_token_login_url(target_url: &str) -> String {
     )
 }
 
+/// What to do with a window.open / target="_blank" request
+#[derive(Debug, PartialEq)]
+enum NewWindowAction {
+    /// File API URL: download it to disk
+    Download(String),
+    /// Open in the system browser (external host, sandbox port, or configured)
+    OpenInBrowser(String),
+    /// Navigate the originating window
+    SameWindow(String),
+    /// Open a new popup window
+    Popup(String),
+}
+
+/// Decide how to handle a new-window request. Remote server URLs are first
+/// rewritten to the local proxy so auth cookies keep working; other hosts
+/// open in the system browser unless they are trusted sign-in pages.
+fn resolve_new_window(url: &str, behavior: LinkBehavior) -> NewWindowAction {
+    let state = config::get_proxy_state();
+    let final_url = if state.running && !state.server_url.is_empty() {
+        let remote = state.server_url.trim_end_matches('/');
+        let local_base = proxy::local_base(state.port);
+        if url.starts_with(remote) {
+            url.replacen(remote, &local_base, 1)
+        } else {
+            url.to_string()
+        }
+    } else {
+        url.to_string()
+    };
+
+    let conf = app_conf::get_app_conf();
+    if is_file_download_url(&final_url, &conf.download_paths) {
+        return NewWindowAction::Download(final_url);
+    }
+
+    if should_open_in_browser(&final_url) {
+        let auth_url = build_token_login_url(&final_url);
+        info!("Sandbox URL: {} -> {}", final_url, auth_url);
+        return NewWindowAction::OpenInBrowser(auth_url);
+    }
+
+    // Ordinary external links go to the system browser; sign-in pages stay
+    // in the app so the flow can return to the proxy with its cookies
+    let trusted = url::Url::parse(&final_url).is_ok_and(|u| is_trusted_auth_url(&u, &state, &conf));
+    if is_external_url(&final_url) && !trusted {
+        return NewWindowAction::OpenInBrowser(final_url);
+    }
+
+    match behavior {
+        LinkBehavior::Popup => NewWindowAction::Popup(final_url),
+        LinkBehavior::SameWindow => NewWindowAction::SameWindow(final_url),
+        LinkBehavior::ExternalBrowser => NewWindowAction::OpenInBrowser(final_url),
+    }
+}
+
+/// Navigate an existing window to the given URL
+fn navigate_window(handle: &tauri::AppHandle, label: &str, url: &str) {
+    let parsed = match url::Url::parse(url) {
+        Ok(u) => u,
+        Err(e) => {
+            warn!("Failed to parse navigation URL: {} — {}", url, e);
+            return;
+        }
+    };
+    if let Some(win) = handle.get_webview_window(label) {
+        info!("Navigating '{}' in place: {}", label, url);
+        let _ = win.navigate(parsed);
+    }
+}
+
+/// Where a popup starts. Pages on the proxy first pass through the bridge so
+/// they get the main window's locale and theme; other origins (OAuth
+/// providers and the like) are left alone.
+fn popup_start_url(url: &str) -> String {
+    let state = config::get_proxy_state();
+    let local_base = proxy::local_base(state.port);
+    let path = match url.strip_prefix(&local_base) {
+        Some(path) if state.running && path.starts_with('/') && !path.starts_with("/__yao_bridge") => path,
+        _ => return url.to_string(),
+    };
+    let mut query = url::form_urlencoded::Serializer::new(String::new());
+    // The cookies track what the main window currently shows
+    if let Some(locale) = config::cookie_value("__locale").as_deref().and_then(locale::known) {
+        query.append_pair("locale", locale);
+    }
+    if let Some(theme) = config::cookie_value("__theme").as_deref().and_then(config::known_theme) {
+        query.append_pair("theme", theme);
+    }
+    query.append_pair("next", path);
+    format!("{}/__yao_bridge?{}", local_base, query.finish())
+}
+
+/// Initial popup title, until the page sets its own: the site's host, with
+/// proxied pages named after the connected server, and the app's `name`
+fn popup_title(url: &url::Url, name: &str) -> String {
+    let state = config::get_proxy_state();
+    let host = if url.as_str().starts_with(&format!("{}/", proxy::local_base(state.port))) {
+        url::Url::parse(&state.server_url).ok().and_then(|u| u.host_str().map(str::to_string))
+    } else {
+        url.host_str().map(str::to_string)
+    };
+    match host {
+        Some(host) => format!("{} — {}", host, name),
+        None => name.to_string(),
+    }
+}
+
+/// Parse the URL a popup opens at (see `popup_start_url`)
+fn parse_popup_url(url: &str) -> Option<url::Url> {
+    let start = popup_start_url(url);
+    url::Url::parse(&start)
+        .map_err(|e| warn!("Failed to parse popup URL: {} — {}", start, e))
+        .ok()
+}
+
+/// Act on a new-window request from the window `parent`: download, hand
+/// off, navigate `parent` or open a popup, per `resolve_new_window`
+fn handle_new_window(handle: tauri::AppHandle, parent: &str, url: &str) {
+    let behavior = app_conf::get_app_conf().link_behavior;
+    match resolve_new_window(url, behavior) {
+        NewWindowAction::Download(u) => spawn_file_download(handle, u),
+        NewWindowAction::OpenInBrowser(u) => {
+            info!("Opening in system browser: {}", u);
+            open_in_system_browser(&u);
+        }
+        NewWindowAction::SameWindow(u) => navigate_window(&handle, parent, &u),
+        NewWindowAction::Popup(u) if is_attachment_url(&u) => spawn_file_download(handle, u),
+        NewWindowAction::Popup(u) => build_popup(&handle, &u),
+    }
+}
+
+/// Open `url` in a new popup window. Popups handle their own new-window
+/// requests (`handle_new_window`) and downloads like the main window.
+fn build_popup(handle: &tauri::AppHandle, url: &str) {
+    let Some(parsed) = parse_popup_url(url) else {
+        return;
+    };
+
+    let label = config::next_popup_label();
+    let title = popup_title(&parsed, &app_conf::get_app_conf().display_name());
+    info!("Creating popup window: {} -> {}", label, url);
+    let handle_dl = handle.clone();
+    let handle_nw = handle.clone();
+    let label_nw = label.clone();
+    let popup = WebviewWindowBuilder::new(handle, &label, WebviewUrl::External(parsed))
+        .title(&title)
+        .inner_size(1100.0, 780.0)
+        .min_inner_size(600.0, 400.0)
+        .center()
+        .resizable(true)
+        .disable_drag_drop_handler()
+        .on_document_title_changed(|wv, title| {
+            let _ = wv.set_title(&title);
+        })
+        .on_new_window(move |url, _features| {
+            info!("Popup new window request: {}", url);
+            let handle = handle_nw.clone();
+            let parent = label_nw.clone();
+            let url = url.to_string();
+            std::thread::spawn(move || handle_new_window(handle, &parent, &url));
+            NewWindowResponse::Deny
+        })
+        .on_download(move |wv, event| {
+            match event {
+                DownloadEvent::Requested { url, destination } => {
+                    if let Ok(dl_dir) = downloads::target_dir(&handle_dl) {
+                        let _ = std::fs::create_dir_all(&dl_dir);
+                        let fname = destination.file_name()
+                            .map(|f| f.to_string_lossy().to_string())
+                            .unwrap_or_else(|| "download".to_string());
+                        *destination = dl_dir.join(&fname);
+                    }
+                    let fname = destination.file_name()
+                        .map(|f| f.to_string_lossy().to_string())
+                        .unwrap_or_else(|| "download".to_string());
+                    if let Err(reason) = check_download_allowed(&fname) {
+                        let _ = wv.eval(refuse_download(&handle_dl, url.as_str(), &fname, &reason));
+                        return false;
+                    }
+                    if let Ok(mut map) = DOWNLOAD_PATHS.lock() {
+                        map.insert(url.as_str().to_string(), destination.clone());
+                    }
+                    info!("Popup download: {} -> {:?}", url.as_str(), destination);
+                    let _ = wv.eval(&toast_eval(&format!(
+                        r#"window.__yaoDownloadToast.start("{}","{}",0)"#,
+                        js_escape(url.as_str()), js_escape(&fname)
+                    )));
+                }
+                DownloadEvent::Finished { url, path, success } => {
+                    let saved = DOWNLOAD_PATHS.lock().ok()
+                        .and_then(|mut m| m.remove(url.as_str()));
+                    let resolved = path.as_ref().cloned().or(saved);
+                    info!("Popup download done: {} success={} path={:?}", url.as_str(), success, resolved);
+                    if success {
+                        let fname = resolved.as_ref()
+                            .and_then(|p| p.file_name())
+                            .map(|f| f.to_string_lossy().to_string())
+                            .unwrap_or_else(|| "download".to_string());
+                        let fpath = resolved.as_ref()
+                            .map(|p| js_escape(&p.to_string_lossy()))
+                            .unwrap_or_default();
+                        let _ = wv.eval(&toast_eval(&format!(
+                            r#"window.__yaoDownloadToast.complete("{}","{}","{}")"#,
+                            js_escape(url.as_str()), js_escape(&fname), fpath
+                        )));
+                        if let Some(p) = &resolved {
+                            downloads::open_if_configured(p);
+                        }
+                    } else {
+                        let _ = wv.eval(&toast_eval(&format!(
+                            r#"window.__yaoDownloadToast.fail("{}","")"#,
+                            js_escape(url.as_str())
+                        )));
+                    }
+                }
+                _ => {}
+            }
+            true
+        })
+        .build();
+    match popup {
+        Ok(win) => {
+            config::register_popup(&label);
+            apply_window_zoom(&win);
+            info!("Popup window created: {}", label);
+        }
+        Err(e) => warn!("Failed to create popup window: {}", e),
+    }
+}
+
+/// Map a `yao://` deep link to the CUI route it opens,
+/// e.g. yao://agent/abc?tab=chat → /__yao_admin_root/agent/abc?tab=chat
+fn deep_link_path(url: &url::Url) -> Option<String> {
+    if url.scheme() != "yao" {
+        return None;
+    }
+    let host = url.host_str().filter(|h| !h.is_empty())?;
+    let mut path = format!("/__yao_admin_root/{}{}", host, url.path());
+    if let Some(query) = url.query() {
+        path.push('?');
+        path.push_str(query);
+    }
+    Some(path)
+}
+
+/// Focus the main window and open a deep link in CUI. If CUI isn't loaded
+/// yet (cold start, server picker) the route opens once CUI reports ready.
+fn open_deep_link(app: &tauri::AppHandle, url: &url::Url) {
+    let Some(path) = deep_link_path(url) else {
+        warn!("Ignoring unsupported deep link: {}", url);
+        return;
+    };
+    info!("Deep link: {} -> {}", url, path);
+    show_main_window(app);
+
+    let state = config::get_proxy_state();
+    let local_base = proxy::local_base(state.port);
+    let on_cui = app.get_webview_window("main")
+        .and_then(|w| w.url().ok())
+        .map(|u| u.as_str().starts_with(&local_base))
+        .unwrap_or(false);
+    if state.running && on_cui {
+        navigate_window(app, "main", &format!("{}{}", local_base, path));
+    } else {
+        config::set_pending_deep_link(path);
+    }
+}
+
+/// Open a deep link that arrived before CUI was loaded; called when CUI
+/// hits /__yao_desktop/ready
+pub(crate) fn open_pending_deep_link(app: &tauri::AppHandle) {
+    if let Some(path) = config::take_pending_deep_link() {
+        let port = config::get_proxy_state().port;
+        navigate_window(app, "main", &format!("{}{}", proxy::local_base(port), path));
+    }
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| EnvFilter::new("cui_desktop_lib=info"))
-        )
-        .init();
+    config::mark_startup();
+
+    logging::init();
 
     tauri::Builder::default()
-        .plugin(tauri_plugin_single_instance::init(|app, _args, _cwd| {
-            if let Some(win) = app.get_webview_window("main") {
-                let _ = win.show();
-                let _ = win.unminimize();
-                let _ = win.set_focus();
-            }
+        // A second launch exits and hands its argv to this instance instead of
+        // racing for the proxy port. With the deep-link feature, yao:// URLs
+        // among the args are delivered through on_open_url below.
+        .plugin(tauri_plugin_single_instance::init(|app, args, _cwd| {
+            info!("Second instance launched with {:?}; focusing the main window", args);
+            let handle = app.clone();
+            let _ = app.run_on_main_thread(move || show_main_window(&handle));
         }))
+        .plugin(tauri_plugin_deep_link::init())
         .plugin(tauri_plugin_store::Builder::default().build())
         .plugin(tauri_plugin_updater::Builder::default().build())
         .plugin(tauri_plugin_process::init())
         .plugin(tauri_plugin_dialog::init())
+        .plugin(tauri_plugin_notification::init())
+        .plugin(tauri_plugin_global_shortcut::Builder::new().build())
         .setup(|app| {
+            // Keep a log on disk too, now that the app data dir is known
+            if let Ok(dir) = app.path().app_data_dir() {
+                logging::start_file(dir.join("logs"));
+            }
+
             // Load developer config.json at startup
             load_app_conf_from_resources(app.handle());
+            config::record_milestone(config::StartupMilestone::ConfigLoaded);
+            let kiosk = app_conf::get_app_conf().kiosk;
+            if kiosk {
+                info!("Kiosk mode: fullscreen main window, quit disabled");
+            }
+            config::set_kiosk(kiosk);
+            apply_global_shortcut(app.handle());
 
             // Store AppHandle globally so the proxy can call native APIs
             config::set_app_handle(app.handle().clone());
 
+            // Refresh the auth token shortly before it expires
+            commands::spawn_token_refresher(app.handle().clone());
+
             // ── System Tray ──
             setup_tray(app)?;
+            config::record_milestone(config::StartupMilestone::TrayReady);
+            setup_app_menu(app)?;
 
             // Channel for navigation redirect requests (main window)
             let (tx, rx) = std::sync::mpsc::channel::<String>();
@@ -381,6 +786,14 @@ pub fn run() {
             // Clone AppHandle for use in closures
             let app_handle = app.handle().clone();
             let app_handle_dl = app.handle().clone();
+            let app_handle_nav = app.handle().clone();
+
+            // Configured size, shrunk to fit the primary monitor's work area
+            let work_area = app.handle().primary_monitor().ok().flatten().map(|m| {
+                let size = m.work_area().size.to_logical::<f64>(m.scale_factor());
+                (size.width, size.height)
+            });
+            let size = window_state::fit_to_work_area(app_conf::get_app_conf().window, work_area);
 
             // Create the main window manually so we can attach on_navigation + on_new_window
             let window = WebviewWindowBuilder::new(
@@ -388,294 +801,83 @@ pub fn run() {
                     "main",
                     WebviewUrl::App("index.html".into()),
                 )
-                .title("Yao Agents")
-                .inner_size(1280.0, 860.0)
-                .min_inner_size(900.0, 600.0)
+                .title(app_conf::get_app_conf().display_name())
+                .inner_size(size.width, size.height)
+                .min_inner_size(size.min_width, size.min_height)
                 .center()
                 .resizable(true)
-                .decorations(true)
+                .decorations(!kiosk)
+                .fullscreen(kiosk)
                 .disable_drag_drop_handler()
                 .on_navigation(move |url| {
-                    let url_str = url.as_str();
-
-                    // Always allow: tauri://, localhost
-                    if url_str.starts_with("tauri://")
-                        || url_str.starts_with("http://localhost")
-                    {
-                        return true;
-                    }
-
-                    // If navigating to the remote server, intercept and redirect through proxy
-                    let state = config::get_proxy_state();
-                    let local_base = format!("http://127.0.0.1:{}", state.port);
-
-                    // Allow our own proxy
-                    if url_str.starts_with(&local_base) {
-                        return true;
-                    }
-
-                    if state.running && !state.server_url.is_empty() {
-                        let remote = state.server_url.trim_end_matches('/');
-                        if url_str.starts_with(remote) {
-                            let proxy_url = url_str.replacen(remote, &local_base, 1);
-                            info!("OAuth intercept: {} -> {}", url_str, proxy_url);
+                    let conf = app_conf::get_app_conf();
+                    match classify_navigation(url, &config::get_proxy_state(), &conf) {
+                        Navigation::Allow => {
+                            debug!("Navigation: {}", url);
+                            true
+                        }
+                        // Block direct navigation to the server; load it through the proxy
+                        Navigation::ViaProxy(proxy_url) => {
+                            info!("OAuth intercept: {} -> {}", url, proxy_url);
                             let _ = tx.send(proxy_url);
-                            return false; // Block direct navigation to server
+                            false
+                        }
+                        Navigation::Deny => {
+                            warn!("Blocked navigation: {}", url);
+                            false
+                        }
+                        Navigation::Untrusted => {
+                            let confirmed = CONFIRMED_NAVIGATION.lock().ok()
+                                .and_then(|mut pending| pending.take_if(|u| u == url.as_str()))
+                                .is_some();
+                            if confirmed {
+                                return true;
+                            }
+                            match conf.untrusted_navigation {
+                                UntrustedNavigation::OpenInBrowser => {
+                                    info!("Untrusted host, opening in system browser: {}", url);
+                                    open_in_system_browser(url.as_str());
+                                }
+                                UntrustedNavigation::Confirm => {
+                                    info!("Untrusted host, asking before navigating: {}", url);
+                                    confirm_navigation(&app_handle_nav, url.clone());
+                                }
+                            }
+                            false
                         }
                     }
-
-                    // Allow all other navigation (Google OAuth, GitHub, etc.)
-                    debug!("External navigation: {}", url_str);
-                    true
                 })
                 // Intercept window.open / target="_blank":
                 // Return Deny immediately (avoids crash inside WebKit's createNewPage
                 // callback), then spawn a new Tauri window asynchronously.
                 // Authentication still works because the proxy manages cookies server-side.
                 .on_new_window(move |url, _features| {
-                    let url_str = url.to_string();
+                    info!("New window request: {}", url);
                     let handle = app_handle.clone();
-                    info!("New window request: {}", url_str);
-
-                    std::thread::spawn(move || {
-                        let state = config::get_proxy_state();
-                        let final_url = if state.running && !state.server_url.is_empty() {
-                            let remote = state.server_url.trim_end_matches('/');
-                            let local_base = format!("http://127.0.0.1:{}", state.port);
-                            if url_str.starts_with(remote) {
-                                url_str.replacen(remote, &local_base, 1)
-                            } else {
-                                url_str.clone()
-                            }
-                        } else {
-                            url_str.clone()
-                        };
-
-                        if is_file_download_url(&final_url) {
-                            spawn_file_download(handle, final_url);
-                            return;
-                        }
-
-                        if should_open_in_browser(&final_url) {
-                            let auth_url = build_token_login_url(&final_url);
-                            info!("Opening sandbox in browser: {} -> {}", final_url, auth_url);
-                            open_in_system_browser(&auth_url);
-                            return;
-                        }
-
-                        if is_external_url(&final_url) {
-                            info!("Opening in system browser: {}", final_url);
-                            open_in_system_browser(&final_url);
-                            return;
-                        }
-
-                        let parsed = match url::Url::parse(&final_url) {
-                            Ok(u) => u,
-                            Err(e) => {
-                                warn!("Failed to parse popup URL: {} — {}", final_url, e);
-                                return;
-                            }
-                        };
-
-                        let n = POPUP_COUNTER.fetch_add(1, Ordering::SeqCst);
-                        let label = format!("popup_{}", n);
-                        info!("Creating popup window: {} -> {}", label, final_url);
-                        let handle_dl = handle.clone();
-                        let handle_nw = handle.clone();
-                        match WebviewWindowBuilder::new(
-                            &handle,
-                            &label,
-                            WebviewUrl::External(parsed),
-                        )
-                        .title("Yao Agents")
-                        .inner_size(1100.0, 780.0)
-                        .min_inner_size(600.0, 400.0)
-                        .center()
-                        .resizable(true)
-                        .disable_drag_drop_handler()
-                        .on_document_title_changed(|wv, title| {
-                            let _ = wv.set_title(&title);
-                        })
-                        .on_new_window(move |url, _features| {
-                            let url_str = url.to_string();
-                            let h = handle_nw.clone();
-                            info!("Popup new window request: {}", url_str);
-
-                            std::thread::spawn(move || {
-                                let state = config::get_proxy_state();
-                                let popup_url = if state.running && !state.server_url.is_empty() {
-                                    let remote = state.server_url.trim_end_matches('/');
-                                    let local_base = format!("http://127.0.0.1:{}", state.port);
-                                    if url_str.starts_with(remote) {
-                                        url_str.replacen(remote, &local_base, 1)
-                                    } else {
-                                        url_str.clone()
-                                    }
-                                } else {
-                                    url_str.clone()
-                                };
-
-                                if is_file_download_url(&popup_url) {
-                                    spawn_file_download(h, popup_url);
-                                    return;
-                                }
-
-                                if should_open_in_browser(&popup_url) {
-                                    let auth_url = build_token_login_url(&popup_url);
-                                    info!("Opening sandbox in browser: {} -> {}", popup_url, auth_url);
-                                    open_in_system_browser(&auth_url);
-                                    return;
-                                }
-
-                                if is_external_url(&popup_url) {
-                                    info!("Opening in system browser: {}", popup_url);
-                                    open_in_system_browser(&popup_url);
-                                    return;
-                                }
-
-                                let p = match url::Url::parse(&popup_url) {
-                                    Ok(u) => u,
-                                    Err(_) => return,
-                                };
-                                let m = POPUP_COUNTER.fetch_add(1, Ordering::SeqCst);
-                                let lbl = format!("popup_{}", m);
-                                let h_dl2 = h.clone();
-                                let _ = WebviewWindowBuilder::new(&h, &lbl, WebviewUrl::External(p))
-                                    .title("Yao Agents")
-                                    .inner_size(1100.0, 780.0)
-                                    .min_inner_size(600.0, 400.0)
-                                    .center()
-                                    .resizable(true)
-                                    .on_download(move |wv, event| {
-                                        match event {
-                                            DownloadEvent::Requested { url, destination } => {
-                                                if let Ok(dl) = h_dl2.path().download_dir() {
-                                                    let _ = std::fs::create_dir_all(&dl);
-                                                    let f = destination.file_name()
-                                                        .map(|f| f.to_string_lossy().to_string())
-                                                        .unwrap_or_else(|| "download".to_string());
-                                                    *destination = dl.join(&f);
-                                                }
-                                                if let Ok(mut map) = DOWNLOAD_PATHS.lock() {
-                                                    map.insert(url.as_str().to_string(), destination.clone());
-                                                }
-                                                let fname = destination.file_name()
-                                                    .map(|f| f.to_string_lossy().to_string())
-                                                    .unwrap_or_else(|| "download".to_string());
-                                                info!("Nested popup download: {} -> {:?}", url.as_str(), destination);
-                                                let _ = wv.eval(&toast_eval(&format!(
-                                                    r#"window.__yaoDownloadToast.start("{}","{}",0)"#,
-                                                    js_escape(url.as_str()), js_escape(&fname)
-                                                )));
-                                            }
-                                            DownloadEvent::Finished { url, path, success } => {
-                                                let saved = DOWNLOAD_PATHS.lock().ok()
-                                                    .and_then(|mut m| m.remove(url.as_str()));
-                                                let resolved = path.as_ref().cloned().or(saved);
-                                                info!("Nested popup download done: {} success={} path={:?}", url.as_str(), success, resolved);
-                                                if success {
-                                                    let fname = resolved.as_ref()
-                                                        .and_then(|p| p.file_name())
-                                                        .map(|f| f.to_string_lossy().to_string())
-                                                        .unwrap_or_else(|| "download".to_string());
-                                                    let fpath = resolved.as_ref()
-                                                        .map(|p| js_escape(&p.to_string_lossy()))
-                                                        .unwrap_or_default();
-                                                    let _ = wv.eval(&toast_eval(&format!(
-                                                        r#"window.__yaoDownloadToast.complete("{}","{}","{}")"#,
-                                                        js_escape(url.as_str()), js_escape(&fname), fpath
-                                                    )));
-                                                } else {
-                                                    let _ = wv.eval(&toast_eval(&format!(
-                                                        r#"window.__yaoDownloadToast.fail("{}","")"#,
-                                                        js_escape(url.as_str())
-                                                    )));
-                                                }
-                                            }
-                                            _ => {}
-                                        }
-                                        true
-                                    })
-                                    .build();
-                            });
-
-                            NewWindowResponse::Deny
-                        })
-                        .on_download(move |wv, event| {
-                            match event {
-                                DownloadEvent::Requested { url, destination } => {
-                                    if let Ok(dl_dir) = handle_dl.path().download_dir() {
-                                        let _ = std::fs::create_dir_all(&dl_dir);
-                                        let fname = destination.file_name()
-                                            .map(|f| f.to_string_lossy().to_string())
-                                            .unwrap_or_else(|| "download".to_string());
-                                        *destination = dl_dir.join(&fname);
-                                    }
-                                    if let Ok(mut map) = DOWNLOAD_PATHS.lock() {
-                                        map.insert(url.as_str().to_string(), destination.clone());
-                                    }
-                                    let fname = destination.file_name()
-                                        .map(|f| f.to_string_lossy().to_string())
-                                        .unwrap_or_else(|| "download".to_string());
-                                    info!("Popup download: {} -> {:?}", url.as_str(), destination);
-                                    let _ = wv.eval(&toast_eval(&format!(
-                                        r#"window.__yaoDownloadToast.start("{}","{}",0)"#,
-                                        js_escape(url.as_str()), js_escape(&fname)
-                                    )));
-                                }
-                                DownloadEvent::Finished { url, path, success } => {
-                                    let saved = DOWNLOAD_PATHS.lock().ok()
-                                        .and_then(|mut m| m.remove(url.as_str()));
-                                    let resolved = path.as_ref().cloned().or(saved);
-                                    info!("Popup download done: {} success={} path={:?}", url.as_str(), success, resolved);
-                                    if success {
-                                        let fname = resolved.as_ref()
-                                            .and_then(|p| p.file_name())
-                                            .map(|f| f.to_string_lossy().to_string())
-                                            .unwrap_or_else(|| "download".to_string());
-                                        let fpath = resolved.as_ref()
-                                            .map(|p| js_escape(&p.to_string_lossy()))
-                                            .unwrap_or_default();
-                                        let _ = wv.eval(&toast_eval(&format!(
-                                            r#"window.__yaoDownloadToast.complete("{}","{}","{}")"#,
-                                            js_escape(url.as_str()), js_escape(&fname), fpath
-                                        )));
-                                    } else {
-                                        let _ = wv.eval(&toast_eval(&format!(
-                                            r#"window.__yaoDownloadToast.fail("{}","")"#,
-                                            js_escape(url.as_str())
-                                        )));
-                                    }
-                                }
-                                _ => {}
-                            }
-                            true
-                        })
-                        .build()
-                        {
-                            Ok(_) => info!("Popup window created: {}", label),
-                            Err(e) => warn!("Failed to create popup window: {}", e),
-                        }
-                    });
-
+                    let url = url.to_string();
+                    std::thread::spawn(move || handle_new_window(handle, "main", &url));
                     NewWindowResponse::Deny
                 })
                 .on_download(move |webview, event| {
                     match event {
                         DownloadEvent::Requested { url, destination } => {
-                            if let Ok(download_dir) = app_handle_dl.path().download_dir() {
+                            if let Ok(download_dir) = downloads::target_dir(&app_handle_dl) {
                                 let _ = std::fs::create_dir_all(&download_dir);
                                 let filename = destination.file_name()
                                     .map(|f| f.to_string_lossy().to_string())
                                     .unwrap_or_else(|| "download".to_string());
                                 *destination = download_dir.join(&filename);
                             }
-                            if let Ok(mut map) = DOWNLOAD_PATHS.lock() {
-                                map.insert(url.as_str().to_string(), destination.clone());
-                            }
                             let fname = destination.file_name()
                                 .map(|f| f.to_string_lossy().to_string())
                                 .unwrap_or_else(|| "download".to_string());
+                            if let Err(reason) = check_download_allowed(&fname) {
+                                let _ = webview.eval(refuse_download(&app_handle_dl, url.as_str(), &fname, &reason));
+                                return false;
+                            }
+                            if let Ok(mut map) = DOWNLOAD_PATHS.lock() {
+                                map.insert(url.as_str().to_string(), destination.clone());
+                            }
                             info!("Download started: {} -> {:?}", url.as_str(), destination);
                             let _ = webview.eval(&toast_eval(&format!(
                                 r#"window.__yaoDownloadToast.start("{}","{}",0)"#,
@@ -699,6 +901,9 @@ pub fn run() {
                                     r#"window.__yaoDownloadToast.complete("{}","{}","{}")"#,
                                     js_escape(url.as_str()), js_escape(&fname), fpath
                                 )));
+                                if let Some(p) = &resolved {
+                                    downloads::open_if_configured(p);
+                                }
                             } else {
                                 warn!("Download failed: {}", url.as_str());
                                 let _ = webview.eval(&toast_eval(&format!(
@@ -712,6 +917,12 @@ pub fn run() {
                     true
                 })
                 .build()?;
+            window_state::restore_main(&window);
+            apply_window_zoom(&window);
+            if let Ok(theme) = window.theme() {
+                config::set_system_dark(theme == tauri::Theme::Dark);
+            }
+            config::record_milestone(config::StartupMilestone::WindowBuilt);
 
             // Background thread: process redirect requests
             let webview = window.clone();
@@ -724,44 +935,416 @@ pub fn run() {
                 }
             });
 
+            // Deep links: yao://agent/<id> from a cold start, or forwarded
+            // by a second instance while running
+            {
+                use tauri_plugin_deep_link::DeepLinkExt;
+                #[cfg(any(windows, target_os = "linux"))]
+                if let Err(e) = app.deep_link().register_all() {
+                    warn!("Failed to register deep link schemes: {}", e);
+                }
+                let handle = app.handle().clone();
+                app.deep_link().on_open_url(move |event| {
+                    for url in event.urls() {
+                        open_deep_link(&handle, &url);
+                    }
+                });
+                if let Ok(Some(urls)) = app.deep_link().get_current() {
+                    for url in urls {
+                        open_deep_link(app.handle(), &url);
+                    }
+                }
+            }
+
             Ok(())
         })
         // Intercept main window close: hide to tray instead of quitting.
-        // Popup windows close normally.
+        // Popup windows close normally and leave the popup registry.
         .on_window_event(|window, event| {
+            if let WindowEvent::Destroyed = event {
+                config::unregister_popup(window.label());
+            }
+            if let WindowEvent::ThemeChanged(theme) = event {
+                // Every window reports the switch; the main window speaks for all
+                if window.label() == "main" {
+                    commands::system_theme_changed(window.app_handle(), *theme == tauri::Theme::Dark);
+                }
+            }
             if let WindowEvent::CloseRequested { api, .. } = event {
                 if window.label() == "main" {
-                    // Hide window instead of closing
-                    let _ = window.hide();
                     api.prevent_close();
-                    info!("Main window hidden to tray");
+                    if config::kiosk() {
+                        info!("Main window close ignored in kiosk mode");
+                        return;
+                    }
+                    window_state::save_main(window.app_handle());
+                    if config::get_close_to_tray() {
+                        // Hide window instead of closing
+                        let _ = window.hide();
+                        info!("Main window hidden to tray");
+                    } else {
+                        // Quit through the same sequence as tray Quit
+                        info!("Main window closed, quitting");
+                        let app = window.app_handle().clone();
+                        tauri::async_runtime::spawn(async move {
+                            commands::graceful_quit(&app, None).await;
+                        });
+                    }
                 }
                 // Popup windows close normally (no prevent_close)
             }
         })
         .invoke_handler(tauri::generate_handler![
             commands::get_app_conf,
+            commands::reload_app_conf,
+            commands::get_log_level,
+            commands::set_log_level,
+            commands::get_recent_logs,
+            commands::open_log_dir,
+            commands::get_config_errors,
+            commands::check_for_update,
+            commands::install_update,
+            commands::get_downloads,
+            commands::set_download_dir,
+            commands::cancel_download,
+            commands::retry_download,
             commands::check_server,
             commands::start_proxy,
             commands::get_proxy_status,
             commands::update_proxy_token,
             commands::clear_cookies,
+            commands::export_cookies,
+            commands::import_cookies,
+            commands::get_cookies,
+            commands::delete_cookie,
             commands::set_preference_cookies,
+            commands::get_user_preferences,
+            commands::set_user_preferences,
             commands::set_window_theme,
             commands::set_ui_language,
             commands::sync_preferences,
+            commands::get_windows,
+            commands::list_windows,
+            commands::print_to_pdf,
+            commands::close_window,
+            commands::get_startup_timings,
+            commands::logout,
+            commands::refresh_token,
+            commands::session_info,
+            commands::diagnose,
+            commands::login_oauth,
+            commands::switch_server,
+            commands::quit_app,
+            commands::get_web_storage_usage,
+            commands::clear_web_storage,
         ])
-        .run(tauri::generate_context!())
-        .expect("Failed to start Tauri application");
+        .build(tauri::generate_context!())
+        .expect("Failed to start Tauri application")
+        .run(|app, event| {
+            // macOS: clicking the Dock icon while the main window is hidden to
+            // tray reshows it, like a tray left-click. Windows/Linux have no
+            // equivalent and stay tray-only.
+            #[cfg(target_os = "macos")]
+            if let tauri::RunEvent::Reopen { .. } = event {
+                if let Some(win) = app.get_webview_window("main") {
+                    let visible = win.is_visible().unwrap_or(false);
+                    let minimized = win.is_minimized().unwrap_or(false);
+                    if should_reshow_main(visible, minimized) {
+                        info!("Dock reopen: showing main window");
+                        show_main_window(app);
+                    }
+                }
+            }
+            #[cfg(not(target_os = "macos"))]
+            let _ = (app, event);
+        });
+}
+
+/// Whether a Dock reopen should bring the main window back
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+fn should_reshow_main(visible: bool, minimized: bool) -> bool {
+    !visible || minimized
+}
+
+/// Show, unminimize and focus the main window (tray click / Dock reopen)
+fn show_main_window(app: &tauri::AppHandle) {
+    if let Some(win) = app.get_webview_window("main") {
+        let _ = win.show();
+        let _ = win.unminimize();
+        let _ = win.set_focus();
+        restore_if_stale(&win);
+    }
+}
+
+/// Reapply the zoom level saved for this window; popups start at the main
+/// window's level
+fn apply_window_zoom(win: &tauri::WebviewWindow) {
+    let label = win.label();
+    let zoom = if label.starts_with("popup_") {
+        let zoom = config::get_window_zoom("main");
+        config::set_window_zoom(label, zoom);
+        zoom
+    } else {
+        config::get_window_zoom(label)
+    };
+    if zoom != 1.0 {
+        let _ = win.set_zoom(zoom);
+    }
+}
+
+/// Hide the main window if it is in front, otherwise bring it back like tray "show"
+fn toggle_main_window(app: &tauri::AppHandle) {
+    let Some(win) = app.get_webview_window("main") else {
+        return;
+    };
+    let in_front = win.is_visible().unwrap_or(false)
+        && win.is_focused().unwrap_or(false)
+        && !win.is_minimized().unwrap_or(false);
+    if in_front {
+        let _ = win.hide();
+    } else {
+        show_main_window(app);
+    }
+}
+
+/// (Re-)register `AppConf.shortcut` as the global show/hide shortcut.
+/// A rejected accelerator (invalid or taken by another app) is logged and skipped.
+fn apply_global_shortcut(app: &tauri::AppHandle) {
+    use tauri_plugin_global_shortcut::{GlobalShortcutExt, ShortcutState};
+
+    let shortcuts = app.global_shortcut();
+    let _ = shortcuts.unregister_all();
+    let accelerator = app_conf::get_app_conf().shortcut;
+    // Hiding the main window would leave the kiosk
+    if accelerator.trim().is_empty() || config::kiosk() {
+        return;
+    }
+    let result = shortcuts.on_shortcut(accelerator.as_str(), |app, _shortcut, event| {
+        if event.state == ShortcutState::Pressed {
+            toggle_main_window(app);
+        }
+    });
+    match result {
+        Ok(()) => info!("Global shortcut registered: {}", accelerator),
+        Err(e) => warn!("Global shortcut {} unavailable: {}", accelerator, e),
+    }
+}
+
+/// Reload config.json and re-apply the settings that depend on it
+pub fn reload_app_conf(app: &tauri::AppHandle) {
+    load_app_conf_from_resources(app);
+    apply_global_shortcut(app);
+    rebuild_tray(app);
+    rebuild_app_menu(app);
 }
 
-/// Build the tray menu with localized labels
+/// Build the tray menu with localized labels, plus any entries from config.json
 fn build_tray_menu<R: tauri::Runtime>(app: &impl Manager<R>) -> Result<Menu<R>, Box<dyn std::error::Error>> {
     let show = MenuItem::with_id(app, "show", config::tray_label("show"), true, None::<&str>)?;
     let servers = MenuItem::with_id(app, "servers", config::tray_label("servers"), true, None::<&str>)?;
     let settings = MenuItem::with_id(app, "settings", config::tray_label("settings"), true, None::<&str>)?;
-    let quit = MenuItem::with_id(app, "quit", config::tray_label("quit"), true, None::<&str>)?;
-    Ok(Menu::with_items(app, &[&show, &servers, &settings, &quit])?)
+    // A kiosk can't be quit, so closing the window never hides it either
+    let unlocked = !config::kiosk();
+    let close_to_tray = CheckMenuItem::with_id(
+        app, "close_to_tray", config::tray_label("close_to_tray"), unlocked, config::get_close_to_tray(), None::<&str>,
+    )?;
+    let quit = MenuItem::with_id(app, "quit", config::tray_label("quit"), unlocked, None::<&str>)?;
+    let menu = Menu::with_items(app, &[&show, &servers, &settings, &close_to_tray])?;
+
+    let custom = custom_tray_entries(&app_conf::get_app_conf().tray_menu);
+    if !custom.is_empty() {
+        menu.append(&PredefinedMenuItem::separator(app)?)?;
+        for (id, label) in custom {
+            menu.append(&MenuItem::with_id(app, id, label, true, None::<&str>)?)?;
+        }
+        menu.append(&PredefinedMenuItem::separator(app)?)?;
+    }
+
+    menu.append(&quit)?;
+    Ok(menu)
+}
+
+/// Menu id prefix for tray entries defined in config.json
+const CUSTOM_TRAY_PREFIX: &str = "custom:";
+
+/// Built-in tray commands a configured entry may invoke
+const TRAY_COMMANDS: &[&str] = &["show", "servers", "settings", "reload", "quit"];
+
+/// Validated click handler for a configured tray entry
+#[derive(Debug, PartialEq)]
+enum TrayDispatch {
+    /// Open the URL in the system browser
+    OpenUrl(String),
+    /// Navigate the main window to this app path on the local proxy
+    Navigate(String),
+    /// Run a built-in tray command
+    Command(String),
+}
+
+/// Validate a configured tray entry and map it to its click handler
+fn resolve_tray_action(item: &TrayMenuItem) -> Result<TrayDispatch, String> {
+    let target = item.target.trim();
+    match item.action {
+        TrayAction::OpenUrl => {
+            let parsed = url::Url::parse(target)
+                .map_err(|e| format!("invalid URL {:?}: {}", target, e))?;
+            match parsed.scheme() {
+                "http" | "https" | "mailto" => Ok(TrayDispatch::OpenUrl(parsed.to_string())),
+                scheme => Err(format!("unsupported URL scheme: {}", scheme)),
+            }
+        }
+        TrayAction::Navigate => {
+            if target.starts_with('/') && !target.starts_with("//") {
+                Ok(TrayDispatch::Navigate(target.to_string()))
+            } else {
+                Err(format!("navigate target must be a path starting with '/': {:?}", target))
+            }
+        }
+        TrayAction::Command => {
+            if TRAY_COMMANDS.contains(&target) {
+                Ok(TrayDispatch::Command(target.to_string()))
+            } else {
+                Err(format!("command not allowed: {:?}", target))
+            }
+        }
+        TrayAction::Unknown => Err("unknown action".to_string()),
+    }
+}
+
+/// Key of a configured tray entry: its `id`, else `#<position>`
+fn tray_entry_key(index: usize, item: &TrayMenuItem) -> String {
+    item.id.clone().unwrap_or_else(|| format!("#{}", index))
+}
+
+/// Menu (id, label) pairs for the valid configured tray entries, in order.
+/// Invalid entries and duplicate ids are skipped with a warning.
+fn custom_tray_entries(items: &[TrayMenuItem]) -> Vec<(String, String)> {
+    let mut seen = std::collections::HashSet::new();
+    let mut entries = Vec::new();
+    for (index, item) in items.iter().enumerate() {
+        let key = tray_entry_key(index, item);
+        if key.is_empty() || item.label.is_empty() {
+            warn!("Skipping tray entry with an empty id or label: {:?}", item);
+            continue;
+        }
+        if let Err(e) = resolve_tray_action(item) {
+            warn!("Skipping tray entry '{}': {}", key, e);
+            continue;
+        }
+        if !seen.insert(key.clone()) {
+            warn!("Skipping duplicate tray entry id '{}'", key);
+            continue;
+        }
+        entries.push((format!("{}{}", CUSTOM_TRAY_PREFIX, key), item.label.clone()));
+    }
+    entries
+}
+
+/// Handle a click on a configured tray entry
+fn dispatch_custom_tray(app: &tauri::AppHandle, id: &str) {
+    let conf = app_conf::get_app_conf();
+    let Some(item) = conf.tray_menu.iter().enumerate()
+        .find(|(index, item)| tray_entry_key(*index, item) == id)
+        .map(|(_, item)| item)
+    else {
+        return;
+    };
+    match resolve_tray_action(item) {
+        Ok(TrayDispatch::OpenUrl(url)) => {
+            info!("Tray '{}': opening {}", id, url);
+            open_in_system_browser(&url);
+        }
+        Ok(TrayDispatch::Navigate(path)) => {
+            let state = config::get_proxy_state();
+            if !state.running {
+                warn!("Tray '{}': proxy not running, cannot navigate to {}", id, path);
+                return;
+            }
+            if let Some(win) = app.get_webview_window("main") {
+                let _ = win.show();
+                let _ = win.set_focus();
+            }
+            navigate_window(app, "main", &format!("{}{}", proxy::local_base(state.port), path));
+        }
+        Ok(TrayDispatch::Command(cmd)) => handle_tray_builtin(app, &cmd),
+        Err(e) => warn!("Tray '{}': {}", id, e),
+    }
+}
+
+/// Handle a built-in tray menu command
+fn handle_tray_builtin(app: &tauri::AppHandle, id: &str) {
+    match id {
+        "show" => {
+            if let Some(win) = app.get_webview_window("main") {
+                let _ = win.show();
+                let _ = win.set_focus();
+                restore_if_stale(&win);
+            }
+        }
+        "servers" => {
+            let handle = app.clone();
+            if let Some(win) = handle.get_webview_window("main") {
+                let _ = win.show();
+                let _ = win.set_focus();
+                let state = config::get_proxy_state();
+                if state.running {
+                    use tauri_plugin_dialog::{DialogExt, MessageDialogButtons};
+                    let msg = config::tray_label("switch_confirm");
+                    let title = config::tray_label("servers");
+                    handle.dialog()
+                        .message(msg)
+                        .title(title)
+                        .buttons(MessageDialogButtons::OkCancel)
+                        .show(move |confirmed| {
+                            if confirmed {
+                                if let Some(w) = handle.get_webview_window("main") {
+                                    let _ = w.navigate("tauri://localhost".parse().unwrap());
+                                }
+                            }
+                        });
+                } else {
+                    let _ = win.navigate("tauri://localhost".parse().unwrap());
+                }
+            }
+        }
+        "settings" => {
+            if let Some(win) = app.get_webview_window("settings") {
+                let _ = win.show();
+                let _ = win.set_focus();
+            } else {
+                let _ = WebviewWindowBuilder::new(
+                    app,
+                    "settings",
+                    WebviewUrl::App("/settings".into()),
+                )
+                .title(config::tray_label("settings"))
+                .inner_size(500.0, 520.0)
+                .resizable(true)
+                .build()
+                .map(|win| apply_window_zoom(&win));
+            }
+        }
+        "close_to_tray" => {
+            let enabled = !config::get_close_to_tray();
+            config::save_close_to_tray(enabled);
+            info!("Close to tray: {}", enabled);
+            rebuild_tray(app);
+        }
+        "reload" => {
+            if let Some(win) = app.get_webview_window("main") {
+                reload_window(&win);
+            }
+        }
+        "quit" if config::kiosk() => info!("Quit ignored in kiosk mode"),
+        "quit" => {
+            info!("Quit from tray");
+            let app = app.clone();
+            tauri::async_runtime::spawn(async move {
+                commands::graceful_quit(&app, None).await;
+            });
+        }
+        _ => {}
+    }
 }
 
 /// When the window is restored from tray, check if it's showing a stale proxy page.
@@ -771,22 +1354,21 @@ fn restore_if_stale(win: &tauri::WebviewWindow) {
     if let Ok(u) = url {
         let url_str = u.as_str();
         // If on proxy URL but proxy is not running → go back to shell
-        if url_str.starts_with("http://127.0.0.1") {
-            let state = config::get_proxy_state();
-            if !state.running {
-                info!("Proxy not running, navigating back to shell UI");
-                let _ = win.navigate("tauri://localhost".parse().unwrap());
-            }
+        let state = config::get_proxy_state();
+        if url_str.starts_with(&proxy::local_base(state.port)) && !state.running {
+            info!("Proxy not running, navigating back to shell UI");
+            let _ = win.navigate("tauri://localhost".parse().unwrap());
         }
     }
 }
 
-/// Rebuild the tray menu (called when language changes)
+/// Rebuild the tray menu (called when language or config changes)
 pub fn rebuild_tray(app: &tauri::AppHandle) {
     if let Some(tray) = app.tray_by_id("main-tray") {
         if let Ok(menu) = build_tray_menu(app) {
             let _ = tray.set_menu(Some(menu));
         }
+        let _ = tray.set_tooltip(Some(app_conf::get_app_conf().display_name()));
     }
 }
 
@@ -800,65 +1382,14 @@ fn setup_tray(app: &mut tauri::App) -> Result<(), Box<dyn std::error::Error>> {
     let _tray = TrayIconBuilder::with_id("main-tray")
         .icon(icon)
         .icon_as_template(cfg!(target_os = "macos"))
-        .tooltip("Yao Agents")
+        .tooltip(app_conf::get_app_conf().display_name())
         .menu(&menu)
         .show_menu_on_left_click(false)
         .on_menu_event(|app, event| {
-            match event.id().as_ref() {
-                "show" => {
-                    if let Some(win) = app.get_webview_window("main") {
-                        let _ = win.show();
-                        let _ = win.set_focus();
-                        restore_if_stale(&win);
-                    }
-                }
-                "servers" => {
-                    let handle = app.clone();
-                    if let Some(win) = handle.get_webview_window("main") {
-                        let _ = win.show();
-                        let _ = win.set_focus();
-                        let state = config::get_proxy_state();
-                        if state.running {
-                            use tauri_plugin_dialog::{DialogExt, MessageDialogButtons};
-                            let msg = config::tray_label("switch_confirm");
-                            let title = config::tray_label("servers");
-                            handle.dialog()
-                                .message(msg)
-                                .title(title)
-                                .buttons(MessageDialogButtons::OkCancel)
-                                .show(move |confirmed| {
-                                    if confirmed {
-                                        if let Some(w) = handle.get_webview_window("main") {
-                                            let _ = w.navigate("tauri://localhost".parse().unwrap());
-                                        }
-                                    }
-                                });
-                        } else {
-                            let _ = win.navigate("tauri://localhost".parse().unwrap());
-                        }
-                    }
-                }
-                "settings" => {
-                    if let Some(win) = app.get_webview_window("settings") {
-                        let _ = win.show();
-                        let _ = win.set_focus();
-                    } else {
-                        let _ = WebviewWindowBuilder::new(
-                            app,
-                            "settings",
-                            WebviewUrl::App("/settings".into()),
-                        )
-                        .title(config::tray_label("settings"))
-                        .inner_size(500.0, 520.0)
-                        .resizable(true)
-                        .build();
-                    }
-                }
-                "quit" => {
-                    info!("Quit from tray");
-                    app.exit(0);
-                }
-                _ => {}
+            let id = event.id().as_ref();
+            match id.strip_prefix(CUSTOM_TRAY_PREFIX) {
+                Some(custom) => dispatch_custom_tray(app, custom),
+                None => handle_tray_builtin(app, id),
             }
         })
         .on_tray_icon_event(|tray, event| {
@@ -867,12 +1398,7 @@ fn setup_tray(app: &mut tauri::App) -> Result<(), Box<dyn std::error::Error>> {
                 button_state: tauri::tray::MouseButtonState::Up,
                 ..
             } = event {
-                if let Some(win) = tray.app_handle().get_webview_window("main") {
-                    let _ = win.show();
-                    let _ = win.unminimize();
-                    let _ = win.set_focus();
-                    restore_if_stale(&win);
-                }
+                show_main_window(tray.app_handle());
             }
         })
         .build(app)?;
@@ -884,6 +1410,24 @@ fn setup_tray(app: &mut tauri::App) -> Result<(), Box<dyn std::error::Error>> {
 /// Load the tray icon PNG, trying multiple paths (bundled resources, dev icons/).
 /// macOS: monochrome template icons; Windows/Linux: colored icons.
 fn load_tray_icon(app: &tauri::App) -> Image<'static> {
+    // 0. Configured icon (theme.trayIcon), inside the resource dir or, in dev
+    //    mode, the working directory
+    let configured = app_conf::get_app_conf().theme.tray_icon;
+    if !configured.is_empty() {
+        let bases = app.handle().path().resource_dir().ok().into_iter()
+            .chain(std::env::current_dir().ok());
+        match bases.filter_map(|base| app_conf::resolve_resource(&base, &configured)).next() {
+            Some(path) => match Image::from_path(&path) {
+                Ok(img) => {
+                    info!("Tray icon loaded from config: {:?}", path);
+                    return img;
+                }
+                Err(e) => warn!("Configured tray icon {:?} is not a valid image: {}", path, e),
+            },
+            None => warn!("Configured tray icon {:?} not found inside the resource dir", configured),
+        }
+    }
+
     // Choose icon set based on platform:
     //   macOS: monochrome template (system auto-inverts for dark/light menu bar)
     //   Windows/Linux: colored icon for better visual identification
@@ -922,141 +1466,412 @@ fn load_tray_icon(app: &tauri::App) -> Image<'static> {
     }
 }
 
-// ========== File Download Helpers ==========
+// ========== App Menu ==========
 
-/// Check if a URL looks like a file download (Yao file API)
-fn is_file_download_url(url: &str) -> bool {
-    if let Ok(parsed) = url::Url::parse(url) {
-        let path = parsed.path();
-        // Yao file API: /v1/file/{namespace}/{hash}/content
-        return path.starts_with("/v1/file/");
+/// Menu id prefix for application menu items (tray ids have none)
+const APP_MENU_PREFIX: &str = "menu:";
+
+/// Zoom change per View > Zoom In / Zoom Out, matching the in-page shortcuts
+const ZOOM_STEP: f64 = 0.1;
+
+/// Application menu: App (named after `AppConf.name`), Edit, View and Window.
+/// Edit items are native so copy/paste work in every webview. A kiosk gets
+/// no Hide or Minimize and a disabled Quit.
+fn build_app_menu<R: tauri::Runtime>(app: &impl Manager<R>) -> Result<Menu<R>, Box<dyn std::error::Error>> {
+    let name = app_conf::get_app_conf().name;
+    let kiosk = config::kiosk();
+    let item = |id: &str, accelerator: &str| {
+        let enabled = !(kiosk && id == "quit");
+        MenuItem::with_id(app, format!("{}{}", APP_MENU_PREFIX, id), config::tray_label(id), enabled, Some(accelerator))
+    };
+    let about = AboutMetadata { name: Some(name.clone()), ..Default::default() };
+
+    let app_menu = Submenu::with_items(app, &name, true, &[
+        &PredefinedMenuItem::about(app, None, Some(about))?,
+        &PredefinedMenuItem::separator(app)?,
+        &PredefinedMenuItem::services(app, None)?,
+        &PredefinedMenuItem::separator(app)?,
+    ])?;
+    if !kiosk {
+        app_menu.append(&PredefinedMenuItem::hide(app, None)?)?;
+        app_menu.append(&PredefinedMenuItem::hide_others(app, None)?)?;
+        app_menu.append(&PredefinedMenuItem::show_all(app, None)?)?;
+        app_menu.append(&PredefinedMenuItem::separator(app)?)?;
     }
-    false
+    // Not the predefined quit: this one honors logoutOnQuit
+    app_menu.append(&item("quit", "CmdOrCtrl+Q")?)?;
+    let edit = Submenu::with_items(app, config::tray_label("edit"), true, &[
+        &PredefinedMenuItem::undo(app, None)?,
+        &PredefinedMenuItem::redo(app, None)?,
+        &PredefinedMenuItem::separator(app)?,
+        &PredefinedMenuItem::cut(app, None)?,
+        &PredefinedMenuItem::copy(app, None)?,
+        &PredefinedMenuItem::paste(app, None)?,
+        &PredefinedMenuItem::select_all(app, None)?,
+        &PredefinedMenuItem::separator(app)?,
+        &item("find", "CmdOrCtrl+F")?,
+    ])?;
+    let fullscreen_key = if cfg!(target_os = "macos") { "Ctrl+Cmd+F" } else { "F11" };
+    let view = Submenu::with_items(app, config::tray_label("view"), true, &[
+        &item("reload", "CmdOrCtrl+R")?,
+        &PredefinedMenuItem::separator(app)?,
+        &item("zoom_in", "CmdOrCtrl+=")?,
+        &item("zoom_out", "CmdOrCtrl+-")?,
+        &item("zoom_reset", "CmdOrCtrl+0")?,
+        &PredefinedMenuItem::separator(app)?,
+        &item("fullscreen", fullscreen_key)?,
+    ])?;
+    let window = Submenu::with_items(app, config::tray_label("window"), true, &[
+        &PredefinedMenuItem::maximize(app, None)?,
+        &PredefinedMenuItem::separator(app)?,
+        &PredefinedMenuItem::close_window(app, None)?,
+    ])?;
+    if !kiosk {
+        window.insert(&PredefinedMenuItem::minimize(app, None)?, 0)?;
+    }
+    Ok(Menu::with_items(app, &[&app_menu, &edit, &view, &window])?)
 }
 
-/// Spawn an async task to download a file from the proxy and save to Downloads folder.
-/// Uses streaming to report progress via Toast UI injected into the main window.
-fn spawn_file_download(handle: tauri::AppHandle, url: String) {
-    info!("File download: {}", url);
-    let url_id = js_escape(&url);
-    tauri::async_runtime::spawn(async move {
-        let download_dir = match handle.path().download_dir() {
-            Ok(d) => d,
-            Err(e) => {
-                warn!("Cannot resolve Downloads directory: {}", e);
-                eval_on_main(&handle, &toast_eval(&format!(
-                    r#"window.__yaoDownloadToast.fail("{}","Cannot resolve Downloads directory")"#,
-                    url_id
-                )));
-                return;
+/// Install the application menu. Only macOS gets a menu bar; on Windows and
+/// Linux it would be drawn inside every window.
+fn setup_app_menu(app: &tauri::App) -> Result<(), Box<dyn std::error::Error>> {
+    if !cfg!(target_os = "macos") {
+        return Ok(());
+    }
+    app.set_menu(build_app_menu(app)?)?;
+    app.on_menu_event(|app, event| {
+        if let Some(id) = event.id().as_ref().strip_prefix(APP_MENU_PREFIX) {
+            handle_app_menu(app, id);
+        }
+    });
+    Ok(())
+}
+
+/// Rebuild the application menu (called when language or config changes)
+pub fn rebuild_app_menu(app: &tauri::AppHandle) {
+    if app.menu().is_some() {
+        if let Ok(menu) = build_app_menu(app) {
+            let _ = app.set_menu(menu);
+        }
+    }
+}
+
+/// Handle a click on an application menu item (id without the prefix)
+fn handle_app_menu(app: &tauri::AppHandle, id: &str) {
+    if id == "quit" {
+        handle_tray_builtin(app, "quit");
+        return;
+    }
+    let Some(win) = focused_or_main_window(app) else {
+        return;
+    };
+    match id {
+        "reload" => reload_window(&win),
+        // The find bar is injected into proxied pages; elsewhere this is a no-op
+        "find" => {
+            let _ = win.eval("window.__yaoFind&&window.__yaoFind.open()");
+        }
+        "zoom_in" | "zoom_out" | "zoom_reset" => {
+            let current = config::get_window_zoom(win.label());
+            let zoom = match id {
+                "zoom_in" => current + ZOOM_STEP,
+                "zoom_out" => current - ZOOM_STEP,
+                _ => 1.0,
+            };
+            if let Err(e) = set_window_zoom(&win, zoom) {
+                warn!("{}", e);
+            }
+        }
+        "fullscreen" => {
+            let enable = proxy::fullscreen_target(win.label(), !win.is_fullscreen().unwrap_or(false));
+            info!("Window fullscreen: {} -> {}", win.label(), enable);
+            let _ = win.set_fullscreen(enable);
+        }
+        _ => {}
+    }
+}
+
+/// The focused window, else main
+pub(crate) fn focused_or_main_window(app: &tauri::AppHandle) -> Option<tauri::WebviewWindow> {
+    app.webview_windows().into_values()
+        .find(|w| w.is_focused().unwrap_or(false))
+        .or_else(|| app.get_webview_window("main"))
+}
+
+/// Apply a zoom factor (clamped to 0.5–3.0) and remember it for the window
+pub(crate) fn set_window_zoom(win: &tauri::WebviewWindow, zoom: f64) -> Result<f64, String> {
+    let zoom = (config::clamp_zoom(zoom) * 100.0).round() / 100.0;
+    win.set_zoom(zoom).map_err(|e| format!("failed to set zoom: {}", e))?;
+    info!("Window zoom: {} -> {}", win.label(), zoom);
+    config::set_window_zoom(win.label(), zoom);
+    Ok(zoom)
+}
+
+/// Reload a window without losing the proxy origin: the main window on the
+/// proxy goes back to CUI's root, other proxied windows re-open their URL,
+/// and the app's own pages reload in place.
+fn reload_window(win: &tauri::WebviewWindow) {
+    let state = config::get_proxy_state();
+    let proxy_origin = proxy::local_base(state.port);
+    match win.url() {
+        Ok(url) if state.running && url.as_str().starts_with(&proxy_origin) => {
+            let target = if win.label() == "main" {
+                url::Url::parse(&format!("{}/__yao_admin_root/", proxy_origin)).ok()
+            } else {
+                Some(url)
+            };
+            if let Some(target) = target {
+                info!("Reloading '{}' via proxy: {}", win.label(), target);
+                let _ = win.navigate(target);
             }
-        };
-        if let Err(e) = std::fs::create_dir_all(&download_dir) {
-            warn!("Cannot create Downloads directory: {:?} — {}", download_dir, e);
-            eval_on_main(&handle, &toast_eval(&format!(
-                r#"window.__yaoDownloadToast.fail("{}","Cannot create Downloads directory")"#,
-                url_id
-            )));
-            return;
         }
+        _ => {
+            let _ = win.eval("window.location.reload()");
+        }
+    }
+}
+
+// ========== File Download Helpers ==========
+
+/// Check if a URL is under one of the configured download path prefixes
+/// (by default the Yao file API: /v1/file/{namespace}/{hash}/content)
+fn is_file_download_url(url: &str, prefixes: &[String]) -> bool {
+    match url::Url::parse(url) {
+        Ok(parsed) => prefixes.iter().any(|p| !p.is_empty() && parsed.path().starts_with(p.as_str())),
+        Err(_) => false,
+    }
+}
 
-        let client = match reqwest::Client::builder()
-            .redirect(reqwest::redirect::Policy::limited(10))
+/// How long the popup flow waits for a HEAD response before opening a window
+const ATTACHMENT_PROBE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(3);
+
+/// Whether a Content-Disposition value asks for a download
+fn is_attachment_disposition(value: &str) -> bool {
+    value.split(';').next().is_some_and(|kind| kind.trim().eq_ignore_ascii_case("attachment"))
+}
+
+/// For a URL about to open as a popup, ask the local proxy (HEAD) whether the
+/// response is an attachment. Blocking; call from a worker thread. Other
+/// hosts are never probed.
+fn is_attachment_url(url: &str) -> bool {
+    let state = config::get_proxy_state();
+    if !state.running || !url.starts_with(&format!("{}/", proxy::local_base(state.port))) {
+        return false;
+    }
+    let probe = async {
+        let client = reqwest::Client::builder()
             .no_proxy()
+            .timeout(ATTACHMENT_PROBE_TIMEOUT)
             .build()
-        {
-            Ok(c) => c,
-            Err(e) => {
-                warn!("Download client error: {}", e);
+            .ok()?;
+        let resp = client.head(url).send().await.ok()?;
+        let disposition = resp.headers().get(reqwest::header::CONTENT_DISPOSITION)?;
+        Some(is_attachment_disposition(disposition.to_str().ok()?))
+    };
+    tauri::async_runtime::block_on(probe).unwrap_or(false)
+}
+
+/// Spawn an async task to download a file from the proxy and save to Downloads folder.
+/// Uses streaming to report progress via Toast UI injected into the main window.
+fn spawn_file_download(handle: tauri::AppHandle, url: String) {
+    let id = downloads::register(&url);
+    start_download(handle, id, url);
+}
+
+/// Why a download stopped before completing
+enum DownloadError {
+    /// Refused by the extension policy (already reported to the user)
+    Refused(String),
+    /// Short reason shown in the toast
+    Failed(String),
+    /// The user closed the "save as" dialog
+    Dismissed,
+}
+
+/// Ask where to save a download; None if the dialog was dismissed
+async fn prompt_save_path(handle: &tauri::AppHandle, dir: &std::path::Path, filename: &str) -> Option<PathBuf> {
+    use tauri_plugin_dialog::DialogExt;
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    handle.dialog()
+        .file()
+        .set_directory(dir)
+        .set_file_name(filename)
+        .save_file(move |path| {
+            let _ = tx.send(path);
+        });
+    rx.await.ok().flatten()?.into_path().ok()
+}
+
+/// Run a registered download in the background (also used by `retry_download`)
+fn start_download(handle: tauri::AppHandle, id: u64, url: String) {
+    info!("File download #{}: {}", id, url);
+    let task = tauri::async_runtime::spawn(async move {
+        let url_id = js_escape(&url);
+        match download_file(&handle, id, &url).await {
+            Ok((dest, filename)) => {
+                downloads::update(&handle, id, |d| d.status = DownloadStatus::Completed);
+                downloads::open_if_configured(&dest);
+                let dest_escaped = js_escape(&dest.to_string_lossy());
                 eval_on_main(&handle, &toast_eval(&format!(
-                    r#"window.__yaoDownloadToast.fail("{}","Client error")"#, url_id
+                    r#"window.__yaoDownloadToast.complete("{}","{}","{}")"#,
+                    url_id, js_escape(&filename), dest_escaped
                 )));
-                return;
             }
-        };
-
-        let resp = match client.get(&url).send().await {
-            Ok(r) => r,
-            Err(e) => {
-                warn!("Download request failed: {} — {}", url, e);
+            Err(DownloadError::Refused(reason)) => {
+                downloads::update(&handle, id, |d| {
+                    d.status = DownloadStatus::Failed;
+                    d.error = Some(reason);
+                });
+            }
+            Err(DownloadError::Dismissed) => {
+                info!("Download #{} dismissed at save dialog", id);
+                downloads::update(&handle, id, |d| d.status = DownloadStatus::Cancelled);
+            }
+            Err(DownloadError::Failed(reason)) => {
                 eval_on_main(&handle, &toast_eval(&format!(
-                    r#"window.__yaoDownloadToast.fail("{}","Request failed")"#, url_id
+                    r#"window.__yaoDownloadToast.fail("{}","{}")"#, url_id, js_escape(&reason)
                 )));
-                return;
+                downloads::update(&handle, id, |d| {
+                    d.status = DownloadStatus::Failed;
+                    d.error = Some(reason);
+                });
             }
-        };
-
-        if !resp.status().is_success() {
-            warn!("Download HTTP {}: {}", resp.status(), url);
-            eval_on_main(&handle, &toast_eval(&format!(
-                r#"window.__yaoDownloadToast.fail("{}","HTTP {}")"#, url_id, resp.status().as_u16()
-            )));
-            return;
         }
+    });
+    downloads::set_task(id, task);
+}
 
-        let filename = extract_download_filename(&resp, &url);
-        let dest = ensure_unique_path(download_dir.join(&filename));
-        let total = resp.content_length().unwrap_or(0);
-        let fname_escaped = js_escape(&filename);
-
-        eval_on_main(&handle, &toast_eval(&format!(
-            r#"window.__yaoDownloadToast.start("{}","{}",{})"#,
-            url_id, fname_escaped, total
-        )));
-
-        let mut stream = resp.bytes_stream();
-        let mut buffer = Vec::with_capacity(if total > 0 { total as usize } else { 64 * 1024 });
-        let mut downloaded: u64 = 0;
-        let mut last_notified: u64 = 0;
-        let mut last_time = std::time::Instant::now();
-        let mut error: Option<String> = None;
-
-        while let Some(chunk_result) = stream.next().await {
-            match chunk_result {
-                Ok(chunk) => {
-                    downloaded += chunk.len() as u64;
-                    buffer.extend_from_slice(&chunk);
-                    if downloaded - last_notified >= 200_000
-                        || last_time.elapsed().as_millis() >= 200
-                    {
-                        eval_on_main(&handle, &toast_eval(&format!(
-                            r#"window.__yaoDownloadToast.progress("{}",{},{})"#,
-                            url_id, downloaded, total
-                        )));
-                        last_notified = downloaded;
-                        last_time = std::time::Instant::now();
-                    }
-                }
-                Err(e) => {
-                    error = Some(e.to_string());
-                    break;
-                }
+/// Stream `url` into the Downloads folder, keeping the registry entry current.
+/// Returns the saved path and the server-provided filename.
+async fn download_file(
+    handle: &tauri::AppHandle,
+    id: u64,
+    url: &str,
+) -> Result<(PathBuf, String), DownloadError> {
+    use tokio::io::AsyncWriteExt;
+
+    let url_id = js_escape(url);
+    let download_dir = downloads::target_dir(handle).map_err(|e| {
+        warn!("Cannot resolve Downloads directory: {}", e);
+        DownloadError::Failed("Cannot resolve Downloads directory".into())
+    })?;
+    if let Err(e) = std::fs::create_dir_all(&download_dir) {
+        warn!("Cannot create Downloads directory: {:?} — {}", download_dir, e);
+        return Err(DownloadError::Failed("Cannot create Downloads directory".into()));
+    }
+
+    let client = crate::upstream::client_builder()
+        .redirect(reqwest::redirect::Policy::limited(10))
+        .build()
+        .map_err(|e| {
+            warn!("Download client error: {}", e);
+            DownloadError::Failed("Client error".into())
+        })?;
+
+    let resp = client.get(url).send().await.map_err(|e| {
+        warn!("Download request failed: {} — {}", url, e);
+        DownloadError::Failed("Request failed".into())
+    })?;
+
+    if !resp.status().is_success() {
+        warn!("Download HTTP {}: {}", resp.status(), url);
+        return Err(DownloadError::Failed(format!("HTTP {}", resp.status().as_u16())));
+    }
+
+    let filename = extract_download_filename(&resp, url);
+    if let Err(reason) = check_download_allowed(&filename) {
+        eval_on_main(handle, &refuse_download(handle, url, &filename, &reason));
+        return Err(DownloadError::Refused(reason));
+    }
+    let dest = if app_conf::get_app_conf().prompt_save_as {
+        prompt_save_path(handle, &download_dir, &filename).await.ok_or(DownloadError::Dismissed)?
+    } else {
+        ensure_unique_path(download_dir.join(&filename))
+    };
+    let total = resp.content_length().unwrap_or(0);
+    downloads::update(handle, id, |d| {
+        d.filename = filename.clone();
+        d.path = Some(dest.to_string_lossy().to_string());
+        d.total = total;
+        d.status = DownloadStatus::Downloading;
+    });
+
+    eval_on_main(handle, &toast_eval(&format!(
+        r#"window.__yaoDownloadToast.start("{}","{}",{})"#,
+        url_id, js_escape(&filename), total
+    )));
+
+    let mut file = tokio::fs::File::create(&dest).await.map_err(|e| {
+        warn!("Failed to create file: {:?} — {}", dest, e);
+        DownloadError::Failed("Save failed".into())
+    })?;
+    let mut stream = resp.bytes_stream();
+    let mut downloaded: u64 = 0;
+    let mut last_notified: u64 = 0;
+    let mut last_time = std::time::Instant::now();
+    let mut error: Option<DownloadError> = None;
+
+    while let Some(chunk_result) = stream.next().await {
+        let chunk = match chunk_result {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                warn!("Download stream error: {} — {}", url, e);
+                error = Some(DownloadError::Failed("Stream error".into()));
+                break;
             }
+        };
+        if let Err(e) = file.write_all(&chunk).await {
+            warn!("Failed to save file: {:?} — {}", dest, e);
+            error = Some(DownloadError::Failed("Save failed".into()));
+            break;
         }
-
-        if let Some(err) = error {
-            warn!("Download stream error: {} — {}", url, err);
-            eval_on_main(&handle, &toast_eval(&format!(
-                r#"window.__yaoDownloadToast.fail("{}","Stream error")"#, url_id
+        downloaded += chunk.len() as u64;
+        if downloaded - last_notified >= 200_000
+            || last_time.elapsed().as_millis() >= 200
+        {
+            eval_on_main(handle, &toast_eval(&format!(
+                r#"window.__yaoDownloadToast.progress("{}",{},{})"#,
+                url_id, downloaded, total
             )));
-            return;
+            downloads::update(handle, id, |d| d.downloaded = downloaded);
+            last_notified = downloaded;
+            last_time = std::time::Instant::now();
         }
+    }
 
-        if let Err(e) = std::fs::write(&dest, &buffer) {
+    if error.is_none() {
+        if let Err(e) = file.flush().await {
             warn!("Failed to save file: {:?} — {}", dest, e);
-            eval_on_main(&handle, &toast_eval(&format!(
-                r#"window.__yaoDownloadToast.fail("{}","Save failed")"#, url_id
-            )));
-            return;
+            error = Some(DownloadError::Failed("Save failed".into()));
         }
+    }
+    drop(file);
+    if let Some(err) = error {
+        // Don't leave a truncated file behind
+        let _ = std::fs::remove_file(&dest);
+        return Err(err);
+    }
 
-        info!("Downloaded {} bytes → {:?}", buffer.len(), dest);
-        let dest_escaped = js_escape(&dest.to_string_lossy());
-        eval_on_main(&handle, &toast_eval(&format!(
-            r#"window.__yaoDownloadToast.complete("{}","{}","{}")"#,
-            url_id, fname_escaped, dest_escaped
-        )));
-    });
+    info!("Downloaded {} bytes → {:?}", downloaded, dest);
+    downloads::update(handle, id, |d| d.downloaded = downloaded);
+    Ok((dest, filename))
+}
+
+/// Abort an app-managed download, delete its partial file and close its toast
+pub(crate) async fn cancel_file_download(app: &tauri::AppHandle, id: u64) -> Result<(), String> {
+    let info = downloads::cancel(app, id).await?;
+    info!("Download #{} cancelled", id);
+    eval_on_main(app, &toast_eval(&format!(
+        r#"window.__yaoDownloadToast.fail("{}","Cancelled")"#, js_escape(&info.url)
+    )));
+    Ok(())
+}
+
+/// Restart a failed or cancelled download under the same id
+pub(crate) fn retry_file_download(app: &tauri::AppHandle, id: u64) -> Result<(), String> {
+    let url = downloads::reset_for_retry(id)?;
+    start_download(app.clone(), id, url);
+    Ok(())
 }
 
 /// Extract a filename from the response Content-Disposition header, falling back to the URL path.
@@ -1098,8 +1913,9 @@ fn extract_download_filename(resp: &reqwest::Response, url: &str) -> String {
                 after[..end].trim()
             };
             if !name.is_empty() {
+                let name = decode_encoded_words(name);
                 info!("Filename from Content-Disposition: {}", name);
-                return sanitize_filename(name);
+                return sanitize_filename(&name);
             }
         }
     }
@@ -1117,6 +1933,80 @@ fn extract_download_filename(resp: &reqwest::Response, url: &str) -> String {
     "download".to_string()
 }
 
+/// Decode RFC 2047 encoded-words (`=?UTF-8?B?…?=` / `=?UTF-8?Q?…?=`) that
+/// some servers put in `filename=`. Whitespace between adjacent words is
+/// dropped; words in unsupported charsets are kept as-is.
+fn decode_encoded_words(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    let mut rest = input;
+    let mut after_word = false;
+    while let Some(start) = rest.find("=?") {
+        let decoded = rest[start + 2..].find("?=").and_then(|len| {
+            let word = &rest[start + 2..start + 2 + len];
+            decode_encoded_word(word).map(|text| (text, start + 2 + len + 2))
+        });
+        match decoded {
+            Some((text, end)) => {
+                let between = &rest[..start];
+                if !(after_word && between.trim().is_empty()) {
+                    out.push_str(between);
+                }
+                out.push_str(&text);
+                rest = &rest[end..];
+                after_word = true;
+            }
+            None => {
+                out.push_str(&rest[..start + 2]);
+                rest = &rest[start + 2..];
+                after_word = false;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Decode the `charset?encoding?text` part of one encoded-word
+fn decode_encoded_word(word: &str) -> Option<String> {
+    use base64::Engine;
+
+    let mut parts = word.splitn(3, '?');
+    let (charset, encoding, text) = (parts.next()?, parts.next()?, parts.next()?);
+    // Drop an RFC 2231 language suffix ("UTF-8*en")
+    let charset = charset.split('*').next().unwrap_or(charset).to_ascii_lowercase();
+    let bytes = match encoding {
+        "B" | "b" => {
+            let engine = base64::engine::general_purpose::STANDARD;
+            let unpadded = base64::engine::general_purpose::STANDARD_NO_PAD;
+            engine.decode(text).or_else(|_| unpadded.decode(text.trim_end_matches('='))).ok()?
+        }
+        "Q" | "q" => {
+            let mut out = Vec::with_capacity(text.len());
+            let raw = text.as_bytes();
+            let mut i = 0;
+            while i < raw.len() {
+                match raw[i] {
+                    b'_' => out.push(b' '),
+                    b'=' => {
+                        let hex = std::str::from_utf8(raw.get(i + 1..i + 3)?).ok()?;
+                        out.push(u8::from_str_radix(hex, 16).ok()?);
+                        i += 2;
+                    }
+                    b => out.push(b),
+                }
+                i += 1;
+            }
+            out
+        }
+        _ => return None,
+    };
+    match charset.as_str() {
+        "utf-8" | "utf8" | "us-ascii" => String::from_utf8(bytes).ok(),
+        "iso-8859-1" | "latin1" => Some(bytes.iter().map(|&b| b as char).collect()),
+        _ => None,
+    }
+}
+
 /// Simple percent-decoding (for Content-Disposition filenames)
 fn percent_decode(input: &str) -> String {
     let mut out = Vec::with_capacity(input.len());
@@ -1139,11 +2029,94 @@ fn percent_decode(input: &str) -> String {
     String::from_utf8_lossy(&out).to_string()
 }
 
-/// Remove characters that are illegal in filenames
+/// Longest filename (bytes) we save under; leaves room for the " (N)" that
+/// `ensure_unique_path` may add within the usual 255-byte limit
+const MAX_FILENAME_BYTES: usize = 200;
+
+/// Make a server-provided name safe to save on every OS: replace illegal
+/// characters, drop trailing dots/spaces (Windows strips them), shorten long
+/// names while keeping the extension, and prefix reserved device names
+/// (`CON.txt` → `_CON.txt`).
 fn sanitize_filename(name: &str) -> String {
-    name.chars()
-        .map(|c| if "/\\:*?\"<>|".contains(c) { '_' } else { c })
-        .collect()
+    let replaced: String = name.chars()
+        .map(|c| if "/\\:*?\"<>|".contains(c) || c.is_control() { '_' } else { c })
+        .collect();
+    let trimmed = replaced.trim().trim_end_matches(['.', ' ']);
+    if trimmed.is_empty() {
+        return "download".to_string();
+    }
+
+    // A leading dot (".env") is part of the name, not an extension
+    let (stem, ext) = match trimmed.rfind('.') {
+        Some(i) if i > 0 && trimmed.len() - i <= 16 => trimmed.split_at(i),
+        _ => (trimmed, ""),
+    };
+    let mut max_stem = MAX_FILENAME_BYTES - ext.len();
+    while !stem.is_char_boundary(max_stem.min(stem.len())) {
+        max_stem -= 1;
+    }
+    let stem = stem[..max_stem.min(stem.len())].trim_end_matches(['.', ' ']);
+
+    let device = stem.split('.').next().unwrap_or("").trim_end().to_ascii_uppercase();
+    let reserved = matches!(device.as_str(), "CON" | "PRN" | "AUX" | "NUL")
+        || ((device.starts_with("COM") || device.starts_with("LPT"))
+            && device.len() == 4
+            && device.as_bytes()[3].is_ascii_digit());
+    format!("{}{}{}", if reserved { "_" } else { "" }, stem, ext)
+}
+
+/// Payload of the `download://blocked` event
+#[derive(Debug, Clone, serde::Serialize)]
+struct DownloadBlocked {
+    url: String,
+    filename: String,
+    reason: String,
+}
+
+/// Check a resolved download filename against the configured extension policy
+pub(crate) fn check_download_allowed(filename: &str) -> Result<(), String> {
+    download_allowed_by(&app_conf::get_app_conf().download_extension_policy, filename)
+}
+
+/// Allow/block decision for `filename` under `policy`. The extension is the
+/// part after the last dot, ignoring trailing dots/spaces (`evil.exe.`).
+fn download_allowed_by(policy: &DownloadExtensionPolicy, filename: &str) -> Result<(), String> {
+    if policy.mode == DownloadPolicyMode::AllowAll {
+        return Ok(());
+    }
+    let ext = std::path::Path::new(filename.trim_end_matches(['.', ' ']))
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase());
+    let listed = ext.as_deref().is_some_and(|ext| {
+        policy.extensions.iter()
+            .any(|e| e.trim().trim_start_matches('.').eq_ignore_ascii_case(ext))
+    });
+    let allowed = match policy.mode {
+        DownloadPolicyMode::Allowlist => listed,
+        _ => !listed,
+    };
+    if allowed {
+        Ok(())
+    } else {
+        Err(match ext {
+            Some(ext) => format!("Downloading .{} files is not allowed", ext),
+            None => "Downloading files without an extension is not allowed".to_string(),
+        })
+    }
+}
+
+/// Emit `download://blocked` and return the toast JS for the refusal
+fn refuse_download(handle: &tauri::AppHandle, url: &str, filename: &str, reason: &str) -> String {
+    warn!("Download blocked: {} ({}) — {}", url, filename, reason);
+    let _ = handle.emit("download://blocked", DownloadBlocked {
+        url: url.to_string(),
+        filename: filename.to_string(),
+        reason: reason.to_string(),
+    });
+    toast_eval(&format!(
+        r#"window.__yaoDownloadToast.fail("{}","{}")"#,
+        js_escape(url), js_escape(reason)
+    ))
 }
 
 /// If the path already exists, append (1), (2), … until unique
@@ -1191,3 +2164,398 @@ fn load_app_conf_from_resources(app: &tauri::AppHandle) {
 
     info!("config.json not found, using defaults");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_reshow_main_when_hidden_or_minimized() {
+        assert!(should_reshow_main(false, false));
+        assert!(should_reshow_main(true, true));
+        assert!(should_reshow_main(false, true));
+        assert!(!should_reshow_main(true, false));
+    }
+
+    #[test]
+    fn default_shortcut_is_a_valid_accelerator() {
+        use tauri_plugin_global_shortcut::Shortcut;
+        assert!(app_conf::AppConf::default().shortcut.parse::<Shortcut>().is_ok());
+        assert!("Ctrl+Nope".parse::<Shortcut>().is_err());
+    }
+
+    #[test]
+    fn deep_link_path_maps_to_cui_routes() {
+        let path = |s: &str| deep_link_path(&url::Url::parse(s).unwrap());
+        assert_eq!(path("yao://agent/abc123").as_deref(), Some("/__yao_admin_root/agent/abc123"));
+        assert_eq!(path("yao://agent/abc?tab=chat").as_deref(), Some("/__yao_admin_root/agent/abc?tab=chat"));
+        assert_eq!(path("yao://agent/../../etc").as_deref(), Some("/__yao_admin_root/agent/etc"));
+        assert_eq!(path("https://agent/abc"), None);
+        assert_eq!(path("yao:agent"), None);
+    }
+
+    fn tray_item(id: &str, action: TrayAction, target: &str) -> TrayMenuItem {
+        TrayMenuItem {
+            id: Some(id.into()),
+            label: format!("Label {}", id),
+            action,
+            target: target.into(),
+        }
+    }
+
+    #[test]
+    fn resolve_tray_action_maps_and_validates() {
+        assert_eq!(
+            resolve_tray_action(&tray_item("docs", TrayAction::OpenUrl, "https://yaoapps.com/docs")),
+            Ok(TrayDispatch::OpenUrl("https://yaoapps.com/docs".into()))
+        );
+        assert_eq!(
+            resolve_tray_action(&tray_item("chat", TrayAction::Navigate, "/chat")),
+            Ok(TrayDispatch::Navigate("/chat".into()))
+        );
+        assert_eq!(
+            resolve_tray_action(&tray_item("r", TrayAction::Command, "reload")),
+            Ok(TrayDispatch::Command("reload".into()))
+        );
+        assert!(resolve_tray_action(&tray_item("x", TrayAction::OpenUrl, "file:///etc/passwd")).is_err());
+        assert!(resolve_tray_action(&tray_item("x", TrayAction::OpenUrl, "not a url")).is_err());
+        assert!(resolve_tray_action(&tray_item("x", TrayAction::Navigate, "https://evil.com")).is_err());
+        assert!(resolve_tray_action(&tray_item("x", TrayAction::Navigate, "//evil.com")).is_err());
+        assert!(resolve_tray_action(&tray_item("x", TrayAction::Command, "rm")).is_err());
+        assert!(resolve_tray_action(&tray_item("x", TrayAction::Unknown, "")).is_err());
+    }
+
+    #[test]
+    fn custom_tray_entries_skip_invalid_and_duplicates() {
+        let items: Vec<TrayMenuItem> = serde_json::from_str(r#"[
+            {"id": "docs", "label": "Open Docs", "action": "open_url", "target": "https://yaoapps.com"},
+            {"id": "bad", "label": "Bad", "action": "launch_rockets", "target": "x"},
+            {"id": "docs", "label": "Dup", "action": "command", "target": "show"},
+            {"id": "", "label": "No id", "action": "command", "target": "show"},
+            {"id": "reload", "label": "Reload", "action": "command", "target": "reload"},
+            {"label": "New Chat", "action": "navigate", "target": "/chat/new"}
+        ]"#).unwrap();
+        assert_eq!(items[1].action, TrayAction::Unknown);
+        assert_eq!(
+            custom_tray_entries(&items),
+            vec![
+                ("custom:docs".to_string(), "Open Docs".to_string()),
+                ("custom:reload".to_string(), "Reload".to_string()),
+                ("custom:#5".to_string(), "New Chat".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn download_policy_allow_and_block_by_extension() {
+        let allow_all = DownloadExtensionPolicy::default();
+        assert!(download_allowed_by(&allow_all, "setup.exe").is_ok());
+
+        let block = DownloadExtensionPolicy {
+            mode: DownloadPolicyMode::Blocklist,
+            extensions: vec!["exe".into(), ".BAT".into(), "scr".into()],
+        };
+        assert!(download_allowed_by(&block, "setup.exe").is_err());
+        assert!(download_allowed_by(&block, "SETUP.EXE").is_err());
+        assert!(download_allowed_by(&block, "run.bat").is_err());
+        assert!(download_allowed_by(&block, "evil.exe. ").is_err());
+        assert!(download_allowed_by(&block, "report.pdf").is_ok());
+        assert!(download_allowed_by(&block, "exe").is_ok());
+        assert!(download_allowed_by(&block, "Makefile").is_ok());
+
+        let allow = DownloadExtensionPolicy {
+            mode: DownloadPolicyMode::Allowlist,
+            extensions: vec!["pdf".into(), "png".into()],
+        };
+        assert!(download_allowed_by(&allow, "report.pdf").is_ok());
+        assert!(download_allowed_by(&allow, "shot.PNG").is_ok());
+        assert!(download_allowed_by(&allow, "report.pdf.exe").is_err());
+        assert_eq!(
+            download_allowed_by(&allow, "Makefile"),
+            Err("Downloading files without an extension is not allowed".to_string())
+        );
+        assert_eq!(
+            download_allowed_by(&allow, "data.csv"),
+            Err("Downloading .csv files is not allowed".to_string())
+        );
+    }
+
+    fn set_running_proxy() {
+        config::update_proxy_state("http://example.com", "", "openapi", "");
+        let mut state = config::PROXY_STATE.write();
+        state.running = true;
+        state.port = 15099;
+    }
+
+    #[test]
+    fn resolve_new_window_downloads_file_urls() {
+        let _lock = config::TEST_MUTEX.blocking_lock();
+        set_running_proxy();
+        for behavior in [LinkBehavior::Popup, LinkBehavior::SameWindow, LinkBehavior::ExternalBrowser] {
+            assert_eq!(
+                resolve_new_window("http://example.com/v1/file/ns/abc/content", behavior),
+                NewWindowAction::Download("http://127.0.0.1:15099/v1/file/ns/abc/content".into())
+            );
+        }
+    }
+
+    #[test]
+    fn download_detection_uses_configured_prefixes() {
+        let paths = vec!["/v1/file/".to_string(), "/api/download/".to_string()];
+        assert!(is_file_download_url("http://127.0.0.1:15099/v1/file/ns/abc/content", &paths));
+        assert!(is_file_download_url("http://127.0.0.1:15099/api/download/report?id=1", &paths));
+        assert!(!is_file_download_url("http://127.0.0.1:15099/v1/export/report", &paths));
+        assert!(!is_file_download_url("http://127.0.0.1:15099/v1/file/x", &[String::new()]));
+        assert!(!is_file_download_url("not a url", &paths));
+
+        assert!(is_attachment_disposition(r#"attachment; filename="a.pdf""#));
+        assert!(is_attachment_disposition("Attachment"));
+        assert!(!is_attachment_disposition(r#"inline; filename="attachment.pdf""#));
+        assert!(!is_attachment_disposition(""));
+    }
+
+    #[test]
+    fn sanitize_filename_keeps_extension_within_limits() {
+        let long = format!("{}.pdf", "a".repeat(300));
+        let name = sanitize_filename(&long);
+        assert_eq!(name.len(), MAX_FILENAME_BYTES);
+        assert!(name.ends_with("aaa.pdf"));
+        // Multi-byte names are cut on a char boundary
+        let name = sanitize_filename(&format!("{}.docx", "报".repeat(100)));
+        assert!(name.len() <= MAX_FILENAME_BYTES && name.ends_with("报.docx"));
+
+        assert_eq!(sanitize_filename("CON.txt"), "_CON.txt");
+        assert_eq!(sanitize_filename("nul"), "_nul");
+        assert_eq!(sanitize_filename("lpt1.tar.gz"), "_lpt1.tar.gz");
+        assert_eq!(sanitize_filename("CONTRACT.txt"), "CONTRACT.txt");
+        assert_eq!(sanitize_filename("report.pdf. . "), "report.pdf");
+        assert_eq!(sanitize_filename("a/b:c.txt"), "a_b_c.txt");
+        assert_eq!(sanitize_filename(".env"), ".env");
+        assert_eq!(sanitize_filename(" ..."), "download");
+    }
+
+    #[test]
+    fn decode_encoded_words_base64_and_quoted_printable() {
+        assert_eq!(decode_encoded_words("=?UTF-8?B?5a2j5bqm5oql5ZGKLnBkZg==?="), "季度报告.pdf");
+        assert_eq!(decode_encoded_words("=?utf-8?Q?caf=C3=A9_menu.pdf?="), "café menu.pdf");
+        // Adjacent words join without the whitespace between them
+        assert_eq!(decode_encoded_words("=?UTF-8?B?5a2j5bqm?= =?UTF-8?B?5oql5ZGKLnBkZg?="), "季度报告.pdf");
+        assert_eq!(decode_encoded_words("=?ISO-8859-1?Q?r=E9sum=E9?=.txt"), "résumé.txt");
+        // Not encoded-words, or an unsupported charset / bad payload
+        assert_eq!(decode_encoded_words("report=?.pdf"), "report=?.pdf");
+        assert_eq!(decode_encoded_words("=?GB2312?B?sai45g==?="), "=?GB2312?B?sai45g==?=");
+        assert_eq!(decode_encoded_words("=?UTF-8?Q?bad=ZZ?="), "=?UTF-8?Q?bad=ZZ?=");
+    }
+
+    #[test]
+    fn extract_download_filename_decodes_encoded_words() {
+        let resp = |cd: &str| reqwest::Response::from(
+            http::Response::builder().header("content-disposition", cd).body("").unwrap()
+        );
+        let url = "http://127.0.0.1:15099/v1/file/ns/abc/content";
+        assert_eq!(
+            extract_download_filename(&resp(r#"attachment; filename="=?UTF-8?B?5a2j5bqm5oql5ZGKLnBkZg==?=""#), url),
+            "季度报告.pdf"
+        );
+        assert_eq!(
+            extract_download_filename(&resp("attachment; filename==?UTF-8?Q?a/b_c.txt?="), url),
+            "a_b c.txt"
+        );
+    }
+
+    #[test]
+    fn proxy_popups_start_at_the_bridge() {
+        let _lock = config::TEST_MUTEX.blocking_lock();
+        set_running_proxy();
+        config::COOKIE_JAR.write().clear();
+        config::store_cookie("__locale=zh-cn; Path=/");
+        config::store_cookie("__theme=dark; Path=/");
+        assert_eq!(
+            popup_start_url("http://127.0.0.1:15099/__yao_admin_root/agent/a?tab=chat"),
+            "http://127.0.0.1:15099/__yao_bridge?locale=zh-CN&theme=dark\
+             &next=%2F__yao_admin_root%2Fagent%2Fa%3Ftab%3Dchat"
+        );
+        config::COOKIE_JAR.write().clear();
+        assert_eq!(
+            popup_start_url("http://127.0.0.1:15099/docs"),
+            "http://127.0.0.1:15099/__yao_bridge?next=%2Fdocs"
+        );
+        // External origins and the bridge itself are opened as is
+        for url in ["https://accounts.google.com/o/oauth2/auth", "http://127.0.0.1:150990/x", "http://127.0.0.1:15099/__yao_bridge?next=%2F"] {
+            assert_eq!(popup_start_url(url), url);
+        }
+        config::PROXY_STATE.write().running = false;
+        assert_eq!(popup_start_url("http://127.0.0.1:15099/docs"), "http://127.0.0.1:15099/docs");
+        config::update_proxy_state("", "", "openapi", "");
+    }
+
+    #[test]
+    fn popup_titles_name_the_site() {
+        let _lock = config::TEST_MUTEX.blocking_lock();
+        set_running_proxy();
+        let title = |u: &str| popup_title(&url::Url::parse(u).unwrap(), "Yao Agents");
+        assert_eq!(title("http://127.0.0.1:15099/__yao_bridge?next=%2F"), "example.com — Yao Agents");
+        assert_eq!(title("https://accounts.google.com/o/oauth2/auth"), "accounts.google.com — Yao Agents");
+        assert_eq!(title("data:text/plain,hi"), "Yao Agents");
+    }
+
+    #[test]
+    fn window_titles_use_the_configured_name() {
+        let _lock = config::TEST_MUTEX.blocking_lock();
+        set_running_proxy();
+        let conf: app_conf::AppConf = serde_json::from_str(r#"{"name": " Acme Desk "}"#).unwrap();
+        // Main window title and tray tooltip
+        assert_eq!(conf.display_name(), "Acme Desk");
+        let url = url::Url::parse("https://accounts.google.com/o/oauth2/auth").unwrap();
+        assert_eq!(popup_title(&url, &conf.display_name()), "accounts.google.com — Acme Desk");
+        let unnamed = app_conf::AppConf { name: "  ".into(), ..app_conf::AppConf::default() };
+        assert_eq!(unnamed.display_name(), "Yao Agents");
+        config::update_proxy_state("", "", "openapi", "");
+    }
+
+    #[test]
+    fn navigation_is_limited_to_trusted_hosts() {
+        // local_base reads proxy.localHost
+        let _lock = config::TEST_MUTEX.blocking_lock();
+        let state = config::ProxyState {
+            server_url: "https://yao.acme.test".into(),
+            issuer_url: "https://sso.acme.test".into(),
+            running: true,
+            port: 15099,
+            ..Default::default()
+        };
+        let conf = app_conf::AppConf {
+            navigation_allowlist: vec!["*.okta.com".into()],
+            oauth: app_conf::OAuthConf { authorization_endpoint: "https://idp.acme.test/authorize".into(), ..Default::default() },
+            ..Default::default()
+        };
+        let decide = |u: &str| classify_navigation(&url::Url::parse(u).unwrap(), &state, &conf);
+        for url in [
+            "tauri://localhost/index.html",
+            "http://tauri.localhost/",
+            "http://localhost:1420/",
+            "http://127.0.0.1:15099/__yao_admin_root/",
+            "https://sso.acme.test/login",
+            "https://idp.acme.test/authorize?x=1",
+            "https://acme.okta.com/oauth2",
+            "about:blank",
+        ] {
+            assert_eq!(decide(url), Navigation::Allow, "{}", url);
+        }
+        assert_eq!(
+            decide("https://yao.acme.test/api/__yao/oauth/callback?code=1"),
+            Navigation::ViaProxy("http://127.0.0.1:15099/api/__yao/oauth/callback?code=1".into())
+        );
+        for url in [
+            "https://evil.test/",
+            "https://accounts.google.com/o/oauth2",
+            "http://localhost.evil.test/",
+            "http://127.0.0.1:15100/",
+            "https://okta.com.evil.test/",
+            "mailto:a@acme.test",
+        ] {
+            assert_eq!(decide(url), Navigation::Untrusted, "{}", url);
+        }
+        for url in [
+            "file:///etc/passwd",
+            "vscode://file/etc/hosts",
+            "ms-settings:privacy",
+            "data:text/html,<script>alert(1)</script>",
+            "blob:http://127.0.0.1:15099/0b6c1f2e",
+        ] {
+            assert_eq!(decide(url), Navigation::Deny, "{}", url);
+        }
+    }
+
+    #[test]
+    fn only_web_and_mail_links_are_handed_off() {
+        let _lock = config::TEST_MUTEX.blocking_lock();
+        for url in ["https://github.com/YaoApp", "http://example.com/a", "mailto:a@acme.test"] {
+            assert!(check_handoff(url).is_ok(), "{}", url);
+        }
+        for url in ["file:///C:/Windows/System32/calc.exe", "vscode://file/etc/hosts", "ms-settings:privacy", "javascript:alert(1)", "not a url"] {
+            assert!(check_handoff(url).is_err(), "{}", url);
+        }
+
+        // A kiosk keeps every link in the app
+        config::set_kiosk(true);
+        let kiosk = check_handoff("https://github.com/YaoApp");
+        config::set_kiosk(false);
+        assert_eq!(kiosk, Err("Opening links outside the app is disabled in kiosk mode".to_string()));
+    }
+
+    #[test]
+    fn resolve_new_window_external_host_opens_browser() {
+        let _lock = config::TEST_MUTEX.blocking_lock();
+        set_running_proxy();
+        assert_eq!(
+            resolve_new_window("https://github.com/YaoApp", LinkBehavior::SameWindow),
+            NewWindowAction::OpenInBrowser("https://github.com/YaoApp".into())
+        );
+    }
+
+    #[test]
+    fn resolve_new_window_keeps_sign_in_pages_in_app() {
+        let _lock = config::TEST_MUTEX.blocking_lock();
+        set_running_proxy();
+        config::set_issuer_url("https://sso.acme.test");
+        for url in [
+            "https://github.com/login/oauth/authorize?client_id=x",
+            "https://accounts.google.com/o/oauth2/v2/auth",
+            "https://sso.acme.test/authorize",
+        ] {
+            assert_eq!(resolve_new_window(url, LinkBehavior::Popup), NewWindowAction::Popup(url.into()), "{}", url);
+        }
+        for url in ["https://github.com/YaoApp/yao", "https://docs.rs/tauri", "https://google.com/"] {
+            assert_eq!(resolve_new_window(url, LinkBehavior::Popup), NewWindowAction::OpenInBrowser(url.into()), "{}", url);
+        }
+        config::set_issuer_url("");
+    }
+
+    #[test]
+    fn resolve_new_window_follows_link_behavior() {
+        let _lock = config::TEST_MUTEX.blocking_lock();
+        set_running_proxy();
+        let app_url = "http://127.0.0.1:15099/__yao_admin_root/chat";
+        assert_eq!(
+            resolve_new_window(app_url, LinkBehavior::Popup),
+            NewWindowAction::Popup(app_url.into())
+        );
+        assert_eq!(
+            resolve_new_window(app_url, LinkBehavior::SameWindow),
+            NewWindowAction::SameWindow(app_url.into())
+        );
+        assert_eq!(
+            resolve_new_window(app_url, LinkBehavior::ExternalBrowser),
+            NewWindowAction::OpenInBrowser(app_url.into())
+        );
+    }
+
+    #[test]
+    fn resolve_new_window_rewrites_remote_to_proxy() {
+        let _lock = config::TEST_MUTEX.blocking_lock();
+        set_running_proxy();
+        assert_eq!(
+            resolve_new_window("http://example.com/__yao_admin_root/x", LinkBehavior::SameWindow),
+            NewWindowAction::SameWindow("http://127.0.0.1:15099/__yao_admin_root/x".into())
+        );
+
+        crate::app_conf::set_app_conf(crate::app_conf::AppConf {
+            proxy: crate::app_conf::ProxyConf { local_host: crate::app_conf::LocalHost::Localhost, ..Default::default() },
+            ..Default::default()
+        });
+        assert_eq!(
+            resolve_new_window("http://example.com/chat", LinkBehavior::SameWindow),
+            NewWindowAction::SameWindow("http://localhost:15099/chat".into())
+        );
+        crate::app_conf::set_app_conf(crate::app_conf::AppConf {
+            proxy: crate::app_conf::ProxyConf { local_host: crate::app_conf::LocalHost::Ipv6, ..Default::default() },
+            ..Default::default()
+        });
+        assert_eq!(
+            resolve_new_window("http://example.com/chat", LinkBehavior::Popup),
+            NewWindowAction::Popup("http://[::1]:15099/chat".into())
+        );
+        crate::app_conf::set_app_conf(crate::app_conf::AppConf::default());
+    }
+}
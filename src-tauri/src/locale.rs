@@ -0,0 +1,53 @@
+/// Locales CUI supports, in umi form. The `__locale` cookie carries the
+/// lowercase form (`zh-cn`). Locales end up in inline scripts, so only
+/// these values ever pass.
+pub const SUPPORTED: [&str; 5] = ["zh-CN", "en-US", "ja-JP", "zh-TW", "ko-KR"];
+
+/// Stands in for unsupported locales
+pub const FALLBACK: &str = "en-US";
+
+/// A supported locale in umi form, matched case-insensitively (`zh_cn` too)
+pub fn known(locale: &str) -> Option<&'static str> {
+    let locale = locale.trim().replace('_', "-");
+    SUPPORTED.into_iter().find(|l| l.eq_ignore_ascii_case(&locale))
+}
+
+/// umi form of a locale in any form (`zh-cn` → `zh-CN`). Unsupported
+/// locales fall back to `en-US`; empty (no preference) stays empty.
+pub fn to_umi(locale: &str) -> &'static str {
+    if locale.trim().is_empty() {
+        return "";
+    }
+    known(locale).unwrap_or(FALLBACK)
+}
+
+/// Cookie form of a locale in any form (`zh-CN` → `zh-cn`), with the
+/// same fallback as `to_umi`
+pub fn to_cookie(locale: &str) -> String {
+    to_umi(locale).to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locales_map_between_umi_and_cookie_forms() {
+        for (input, umi, cookie) in [
+            ("zh-cn", "zh-CN", "zh-cn"),
+            ("en-US", "en-US", "en-us"),
+            ("JA-JP", "ja-JP", "ja-jp"),
+            ("zh_TW", "zh-TW", "zh-tw"),
+            (" ko-kr ", "ko-KR", "ko-kr"),
+            ("fr-FR", "en-US", "en-us"),
+            ("en\");alert(1)//", "en-US", "en-us"),
+            ("", "", ""),
+        ] {
+            assert_eq!(to_umi(input), umi, "{}", input);
+            assert_eq!(to_cookie(input), cookie, "{}", input);
+        }
+        assert_eq!(known("fr-FR"), None);
+        assert_eq!(known(""), None);
+        assert!(SUPPORTED.iter().all(|l| known(&l.to_ascii_lowercase()) == Some(*l)));
+    }
+}
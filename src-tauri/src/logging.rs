@@ -0,0 +1,292 @@
+use once_cell::sync::{Lazy, OnceCell};
+use parking_lot::{Mutex, RwLock};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::{Event, Subscriber};
+use tracing_appender::non_blocking::{NonBlocking, WorkerGuard};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::fmt::writer::OptionalWriter;
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{reload, EnvFilter, Layer, Registry};
+
+/// Target whose level `set_level` changes; other crates stay at their defaults
+const TARGET: &str = "cui_desktop_lib";
+
+/// Log lines kept in memory for `recent`
+const RECENT_CAPACITY: usize = 1000;
+
+static FILTER: OnceCell<reload::Handle<EnvFilter, Registry>> = OnceCell::new();
+
+/// Current filter directives, as set at startup or by `set_level`
+static DIRECTIVES: Lazy<RwLock<String>> = Lazy::new(|| RwLock::new(format!("{}=info", TARGET)));
+
+/// Daily log files kept, today's included
+const MAX_LOG_DAYS: usize = 7;
+
+/// Log files are named `app.YYYY-MM-DD.log` (UTC days)
+const LOG_PREFIX: &str = "app";
+const LOG_SUFFIX: &str = "log";
+
+/// Set once the app data dir is known (see `start_file`)
+static LOG_FILE: OnceCell<NonBlocking> = OnceCell::new();
+static LOG_DIR: OnceCell<PathBuf> = OnceCell::new();
+
+/// Keeps the background writer running; dropped by `flush`
+static LOG_GUARD: Mutex<Option<WorkerGuard>> = Mutex::new(None);
+
+static RECENT: Lazy<Mutex<VecDeque<LogEntry>>> = Lazy::new(|| Mutex::new(VecDeque::with_capacity(RECENT_CAPACITY)));
+
+/// One log line, as kept for bug reports
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LogEntry {
+    /// Unix time in milliseconds
+    pub time: u64,
+    pub level: String,
+    pub target: String,
+    pub message: String,
+}
+
+/// Install the global subscriber, logging to stdout (and to files once
+/// `start_file` is called): `RUST_LOG` if set, else info for this crate.
+/// The filter can be changed later with `set_level`.
+pub fn init() {
+    let directives = std::env::var(EnvFilter::DEFAULT_ENV)
+        .ok()
+        .filter(|d| EnvFilter::try_new(d).is_ok())
+        .unwrap_or_else(|| format!("{}=info", TARGET));
+    let (filter, handle) = reload::Layer::new(EnvFilter::new(&directives));
+    *DIRECTIVES.write() = directives;
+    let _ = FILTER.set(handle);
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer())
+        .with(tracing_subscriber::fmt::layer().with_ansi(false).with_writer(|| OptionalWriter::from(LOG_FILE.get().cloned())))
+        .with(RecentLogs)
+        .init();
+}
+
+/// Also write the log to daily files in `dir`, keeping `MAX_LOG_DAYS` of
+/// them. Lines logged before this are written first. Only the first call
+/// has an effect; if `dir` can't be created the app logs to stdout only.
+pub fn start_file(dir: PathBuf) {
+    if LOG_FILE.get().is_some() {
+        return;
+    }
+    let (mut writer, guard) = match daily_files(&dir, MAX_LOG_DAYS) {
+        Ok(files) => files,
+        Err(e) => {
+            tracing::warn!("Logging to stdout only, cannot write to {:?}: {}", dir, e);
+            return;
+        }
+    };
+    for entry in recent(None) {
+        let line = format!("{} {:>5} {}: {}\n", crate::request_log::rfc3339(entry.time_as_system()), entry.level, entry.target, entry.message);
+        let _ = writer.write_all(line.as_bytes());
+    }
+    *LOG_GUARD.lock() = Some(guard);
+    let _ = LOG_DIR.set(dir);
+    let _ = LOG_FILE.set(writer);
+}
+
+/// Daily files in `dir` (created if missing), keeping the newest `keep`,
+/// written from a background thread
+fn daily_files(dir: &Path, keep: usize) -> Result<(NonBlocking, WorkerGuard), String> {
+    let appender = RollingFileAppender::builder()
+        .rotation(Rotation::DAILY)
+        .filename_prefix(LOG_PREFIX)
+        .filename_suffix(LOG_SUFFIX)
+        .max_log_files(keep)
+        .build(dir)
+        .map_err(|e| e.to_string())?;
+    Ok(tracing_appender::non_blocking(appender))
+}
+
+/// Write out buffered log lines (on quit); later lines only go to stdout
+pub fn flush() {
+    LOG_GUARD.lock().take();
+}
+
+/// Today's log file, or the log directory before anything was written;
+/// None when not logging to files
+pub fn log_path() -> Option<PathBuf> {
+    let dir = LOG_DIR.get()?;
+    let now = crate::request_log::rfc3339(std::time::SystemTime::now());
+    let today = dir.join(format!("{}.{}.{}", LOG_PREFIX, &now[..10], LOG_SUFFIX));
+    Some(if today.exists() { today } else { dir.clone() })
+}
+
+/// A level name (`error`, `warn`, `info`, `debug`, `trace`), any case
+pub fn parse_level(level: &str) -> Result<LevelFilter, String> {
+    match level.trim().to_ascii_lowercase().as_str() {
+        "error" => Ok(LevelFilter::ERROR),
+        "warn" | "warning" => Ok(LevelFilter::WARN),
+        "info" => Ok(LevelFilter::INFO),
+        "debug" => Ok(LevelFilter::DEBUG),
+        "trace" => Ok(LevelFilter::TRACE),
+        _ => Err(format!("Unknown log level: {} (expected error, warn, info, debug or trace)", level)),
+    }
+}
+
+/// Log this crate at `level` from now on, replacing any `RUST_LOG` filter.
+/// Returns the level as set (lowercase).
+pub fn set_level(level: &str) -> Result<String, String> {
+    let level = parse_level(level)?.to_string().to_ascii_lowercase();
+    let directives = format!("{}={}", TARGET, level);
+    let handle = FILTER.get().ok_or("Logging is not initialized")?;
+    handle
+        .reload(EnvFilter::new(&directives))
+        .map_err(|e| format!("Failed to change the log level: {}", e))?;
+    *DIRECTIVES.write() = directives;
+    Ok(level)
+}
+
+/// This crate's log level, or the raw filter when `RUST_LOG` set something
+/// more specific
+pub fn level() -> String {
+    level_of(&DIRECTIVES.read())
+}
+
+fn level_of(directives: &str) -> String {
+    directives
+        .strip_prefix(TARGET)
+        .and_then(|rest| rest.strip_prefix('='))
+        .filter(|level| parse_level(level).is_ok())
+        .unwrap_or(directives)
+        .to_string()
+}
+
+/// Keep this thread's debug and higher lines for `recent` until the guard
+/// is dropped (tests run without the global subscriber)
+#[cfg(test)]
+pub(crate) fn capture_for_test() -> tracing::subscriber::DefaultGuard {
+    tracing::subscriber::set_default(Registry::default().with(LevelFilter::DEBUG).with(RecentLogs))
+}
+
+/// The last `limit` log lines (all that are kept when None), oldest first
+pub fn recent(limit: Option<usize>) -> Vec<LogEntry> {
+    let recent = RECENT.lock();
+    let skip = limit.map_or(0, |n| recent.len().saturating_sub(n));
+    recent.iter().skip(skip).cloned().collect()
+}
+
+fn remember(entry: LogEntry) {
+    let mut recent = RECENT.lock();
+    if recent.len() == RECENT_CAPACITY {
+        recent.pop_front();
+    }
+    recent.push_back(entry);
+}
+
+impl LogEntry {
+    fn time_as_system(&self) -> std::time::SystemTime {
+        std::time::UNIX_EPOCH + std::time::Duration::from_millis(self.time)
+    }
+}
+
+/// Keeps the lines that pass the filter for `recent`
+struct RecentLogs;
+
+impl<S: Subscriber> Layer<S> for RecentLogs {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let meta = event.metadata();
+        let mut message = String::new();
+        event.record(&mut MessageVisitor(&mut message));
+        remember(LogEntry {
+            time: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+            level: meta.level().to_string(),
+            target: meta.target().to_string(),
+            message,
+        });
+    }
+}
+
+/// The message followed by ` name=value` for the other fields
+struct MessageVisitor<'a>(&'a mut String);
+
+impl Visit for MessageVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        let sep = if self.0.is_empty() { "" } else { " " };
+        if field.name() == "message" {
+            let _ = write!(self.0, "{}{:?}", sep, value);
+        } else {
+            let _ = write!(self.0, "{}{}={:?}", sep, field.name(), value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_parse_from_simple_names() {
+        for (name, level) in [
+            ("error", LevelFilter::ERROR),
+            ("WARN", LevelFilter::WARN),
+            ("warning", LevelFilter::WARN),
+            (" info ", LevelFilter::INFO),
+            ("Debug", LevelFilter::DEBUG),
+            ("trace", LevelFilter::TRACE),
+        ] {
+            assert_eq!(parse_level(name), Ok(level), "{}", name);
+        }
+        for name in ["", "verbose", "off", "cui_desktop_lib=debug"] {
+            assert!(parse_level(name).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn level_reports_the_simple_form_when_it_can() {
+        assert_eq!(level_of("cui_desktop_lib=debug"), "debug");
+        assert_eq!(level_of("cui_desktop_lib=debug,hyper=warn"), "cui_desktop_lib=debug,hyper=warn");
+        assert_eq!(level_of("warn"), "warn");
+    }
+
+    #[test]
+    fn recent_logs_keep_the_newest_lines() {
+        for i in 0..RECENT_CAPACITY + 5 {
+            remember(LogEntry { time: 0, level: "INFO".into(), target: TARGET.into(), message: i.to_string() });
+        }
+        let all = recent(None);
+        assert_eq!(all.len(), RECENT_CAPACITY);
+        assert_eq!(all[0].message, "5");
+        let last: Vec<String> = recent(Some(2)).into_iter().map(|e| e.message).collect();
+        assert_eq!(last, [(RECENT_CAPACITY + 3).to_string(), (RECENT_CAPACITY + 4).to_string()]);
+    }
+
+    #[test]
+    fn log_files_are_daily_and_old_days_are_deleted() {
+        let dir = std::env::temp_dir().join(format!("cui-applog-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("requests.log"), "{}\n").unwrap();
+        for day in ["2026-01-01", "2026-01-02", "2026-01-03"] {
+            std::fs::write(dir.join(format!("app.{}.log", day)), "old\n").unwrap();
+        }
+
+        let (mut writer, guard) = daily_files(&dir, 2).unwrap();
+        writer.write_all(b"line\n").unwrap();
+        // Dropping the guard waits for the background writer
+        drop(guard);
+
+        let now = crate::request_log::rfc3339(std::time::SystemTime::now());
+        let today = format!("app.{}.log", &now[..10]);
+        let names: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        assert_eq!(names.iter().filter(|n| n.starts_with("app.")).count(), 2, "{:?}", names);
+        assert!(names.contains(&"requests.log".to_string()), "{:?}", names);
+        assert_eq!(std::fs::read_to_string(dir.join(today)).unwrap(), "line\n");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,368 @@
+use base64::Engine;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::time::Duration;
+use tauri::{AppHandle, WebviewUrl, WebviewWindow, WebviewWindowBuilder};
+use tokio::sync::oneshot;
+use tracing::{info, warn};
+
+use crate::app_conf::OAuthConf;
+use crate::config;
+use crate::error::CommandError;
+
+/// Served by the local proxy; `http://127.0.0.1:<port>` + this path is the
+/// redirect_uri the authorization server must allow
+pub const CALLBACK_PATH: &str = "/__yao_desktop/oauth/callback";
+
+/// How long the user has to finish signing in
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Token endpoint response (RFC 6749 §5.1). `token` is accepted for the
+/// server's own refresh endpoint.
+#[derive(Debug, Deserialize)]
+pub struct TokenResponse {
+    #[serde(alias = "token")]
+    pub access_token: String,
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+    /// Lifetime in seconds (for opaque tokens)
+    #[serde(default)]
+    pub expires_in: Option<u64>,
+}
+
+/// Error body of a token endpoint (RFC 6749 §5.2)
+#[derive(Debug, Deserialize)]
+struct TokenError {
+    error: String,
+    #[serde(default)]
+    error_description: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct Endpoints {
+    authorization_endpoint: String,
+    token_endpoint: String,
+}
+
+/// Client used for the last login, needed to refresh its tokens
+#[derive(Debug, Clone)]
+struct Session {
+    token_endpoint: String,
+    client_id: String,
+}
+
+static SESSION: Lazy<Mutex<Option<Session>>> = Lazy::new(|| Mutex::new(None));
+
+/// Delivers a redirect's authorization code, or the provider's error
+type CodeSender = oneshot::Sender<Result<String, String>>;
+
+/// Logins waiting for their redirect, by `state` parameter
+static PENDING: Lazy<Mutex<HashMap<String, CodeSender>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// PKCE verifier and its S256 challenge (RFC 7636)
+#[derive(Debug)]
+struct Pkce {
+    verifier: String,
+    challenge: String,
+}
+
+impl Pkce {
+    fn generate() -> Result<Self, CommandError> {
+        Ok(Self::from_verifier(random_string()?))
+    }
+
+    fn from_verifier(verifier: String) -> Self {
+        let challenge = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(Sha256::digest(verifier.as_bytes()));
+        Self { verifier, challenge }
+    }
+}
+
+/// 32 random bytes, base64url (43 characters)
+fn random_string() -> Result<String, CommandError> {
+    let mut bytes = [0u8; 32];
+    getrandom::getrandom(&mut bytes)
+        .map_err(|e| CommandError::Internal(format!("No secure random source: {}", e)))?;
+    Ok(base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes))
+}
+
+pub fn redirect_uri(port: u16) -> String {
+    format!("{}{}", crate::proxy::local_base(port), CALLBACK_PATH)
+}
+
+fn authorize_url(
+    endpoint: &str,
+    client_id: &str,
+    redirect_uri: &str,
+    scope: &str,
+    state: &str,
+    challenge: &str,
+) -> Result<url::Url, CommandError> {
+    let mut url = url::Url::parse(endpoint)
+        .map_err(|e| CommandError::Config(format!("Invalid authorization endpoint {}: {}", endpoint, e)))?;
+    {
+        let mut query = url.query_pairs_mut();
+        query
+            .append_pair("response_type", "code")
+            .append_pair("client_id", client_id)
+            .append_pair("redirect_uri", redirect_uri);
+        if !scope.is_empty() {
+            query.append_pair("scope", scope);
+        }
+        query
+            .append_pair("state", state)
+            .append_pair("code_challenge", challenge)
+            .append_pair("code_challenge_method", "S256");
+    }
+    Ok(url)
+}
+
+/// Configured endpoints, or the issuer's metadata when either is missing
+async fn resolve_endpoints(client: &reqwest::Client, conf: &OAuthConf, issuer: &str) -> Result<Endpoints, CommandError> {
+    if !conf.authorization_endpoint.is_empty() && !conf.token_endpoint.is_empty() {
+        return Ok(Endpoints {
+            authorization_endpoint: conf.authorization_endpoint.clone(),
+            token_endpoint: conf.token_endpoint.clone(),
+        });
+    }
+    if issuer.is_empty() {
+        return Err(CommandError::Config(
+            "The server reports no OAuth issuer; set oauth.authorizationEndpoint and oauth.tokenEndpoint in config.json".to_string(),
+        ));
+    }
+    let mut last_err = CommandError::Internal(String::new());
+    for doc in ["openid-configuration", "oauth-authorization-server"] {
+        let url = format!("{}/.well-known/{}", issuer.trim_end_matches('/'), doc);
+        match client.get(&url).send().await.and_then(|r| r.error_for_status()) {
+            Ok(resp) => {
+                let found: Endpoints = serde_json::from_slice(&resp.bytes().await?)
+                    .map_err(|e| CommandError::Parse(format!("Failed to parse {}: {}", url, e)))?;
+                return Ok(Endpoints {
+                    authorization_endpoint: non_empty_or(&conf.authorization_endpoint, found.authorization_endpoint),
+                    token_endpoint: non_empty_or(&conf.token_endpoint, found.token_endpoint),
+                });
+            }
+            Err(e) => {
+                warn!("OAuth discovery failed ({}): {}", url, e);
+                last_err = e.into();
+            }
+        }
+    }
+    Err(last_err)
+}
+
+fn non_empty_or(configured: &str, discovered: String) -> String {
+    if configured.is_empty() { discovered } else { configured.to_string() }
+}
+
+/// Deliver a redirect to the login waiting for its `state`. Errors mean the
+/// redirect was unexpected or carried no code.
+pub fn complete_callback(query: &str) -> Result<(), String> {
+    let params: HashMap<String, String> = url::form_urlencoded::parse(query.as_bytes()).into_owned().collect();
+    let state = params.get("state").ok_or("Missing state parameter")?;
+    let sender = PENDING.lock().remove(state).ok_or("Unknown or expired login")?;
+    let result = match (params.get("code"), params.get("error")) {
+        (_, Some(error)) => Err(match params.get("error_description") {
+            Some(desc) => format!("{}: {}", error, desc),
+            None => error.clone(),
+        }),
+        (Some(code), None) if !code.is_empty() => Ok(code.clone()),
+        _ => Err("Redirect carried no authorization code".to_string()),
+    };
+    let delivered = result.clone().map(|_| ());
+    let _ = sender.send(result);
+    delivered
+}
+
+fn expect_callback(state: &str) -> oneshot::Receiver<Result<String, String>> {
+    let (tx, rx) = oneshot::channel();
+    PENDING.lock().insert(state.to_string(), tx);
+    rx
+}
+
+/// Give up on a pending login (its receiver sees the sender dropped)
+fn cancel(state: &str) {
+    PENDING.lock().remove(state);
+}
+
+fn open_popup(app: &AppHandle, url: url::Url, state: &str) -> Result<WebviewWindow, String> {
+    let label = format!("oauth-{}", &state[..8]);
+    let window = WebviewWindowBuilder::new(app, label, WebviewUrl::External(url))
+        .title("Sign in")
+        .inner_size(520.0, 680.0)
+        .center()
+        .build()
+        .map_err(|e| format!("Failed to open sign-in window: {}", e))?;
+    let state = state.to_string();
+    window.on_window_event(move |event| {
+        if let tauri::WindowEvent::Destroyed = event {
+            cancel(&state);
+        }
+    });
+    Ok(window)
+}
+
+/// Run the authorization-code flow with PKCE against the proxied server and
+/// return its tokens. The caller stores them.
+pub async fn login(app: &AppHandle) -> Result<TokenResponse, CommandError> {
+    let conf = crate::app_conf::get_app_conf().oauth;
+    if conf.client_id.is_empty() {
+        return Err(CommandError::Config("oauth.clientId is not set in config.json".to_string()));
+    }
+    let proxy = config::get_proxy_state();
+    if !proxy.running {
+        return Err(CommandError::Internal("Proxy is not running".to_string()));
+    }
+    let client = crate::upstream::client_builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .map_err(|e| CommandError::Internal(format!("Failed to create HTTP client: {}", e)))?;
+
+    let endpoints = resolve_endpoints(&client, &conf, &proxy.issuer_url).await?;
+    let pkce = Pkce::generate()?;
+    let state = random_string()?;
+    let redirect = redirect_uri(proxy.port);
+    let url = authorize_url(&endpoints.authorization_endpoint, &conf.client_id, &redirect, &conf.scope, &state, &pkce.challenge)?;
+    info!("OAuth login via {}", endpoints.authorization_endpoint);
+
+    let code = expect_callback(&state);
+    let popup = if conf.system_browser {
+        crate::open_in_system_browser(url.as_str());
+        None
+    } else {
+        match open_popup(app, url, &state) {
+            Ok(window) => Some(window),
+            Err(e) => {
+                cancel(&state);
+                return Err(CommandError::Internal(e));
+            }
+        }
+    };
+    let result = tokio::time::timeout(LOGIN_TIMEOUT, code).await;
+    cancel(&state);
+    if let Some(window) = popup {
+        let _ = window.close();
+    }
+    let code = match result {
+        Ok(Ok(code)) => code.map_err(CommandError::Unauthorized)?,
+        Ok(Err(_)) => return Err(CommandError::Unauthorized("Sign-in was cancelled".to_string())),
+        Err(_) => return Err(CommandError::Unauthorized("Sign-in timed out".to_string())),
+    };
+
+    let tokens = request_token(&client, &endpoints.token_endpoint, &[
+        ("grant_type", "authorization_code"),
+        ("code", &code),
+        ("redirect_uri", &redirect),
+        ("client_id", &conf.client_id),
+        ("code_verifier", &pkce.verifier),
+    ]).await?;
+    *SESSION.lock() = Some(Session {
+        token_endpoint: endpoints.token_endpoint,
+        client_id: conf.client_id,
+    });
+    Ok(tokens)
+}
+
+/// Exchange a refresh token at the token endpoint of the last login
+pub async fn refresh(client: &reqwest::Client, refresh_token: &str) -> Result<TokenResponse, CommandError> {
+    let last = SESSION.lock().clone();
+    let session = match last {
+        Some(session) => session,
+        None => resolve_session(client).await?,
+    };
+    request_token(client, &session.token_endpoint, &[
+        ("grant_type", "refresh_token"),
+        ("refresh_token", refresh_token),
+        ("client_id", &session.client_id),
+    ]).await
+}
+
+/// Client of a session restored from the last run, resolved again from
+/// config.json and the server's issuer
+async fn resolve_session(client: &reqwest::Client) -> Result<Session, CommandError> {
+    let conf = crate::app_conf::get_app_conf().oauth;
+    if conf.client_id.is_empty() {
+        return Err(CommandError::Config("oauth.clientId is not set in config.json".to_string()));
+    }
+    let endpoints = resolve_endpoints(client, &conf, &config::get_proxy_state().issuer_url).await?;
+    let session = Session {
+        token_endpoint: endpoints.token_endpoint,
+        client_id: conf.client_id,
+    };
+    *SESSION.lock() = Some(session.clone());
+    Ok(session)
+}
+
+async fn request_token(client: &reqwest::Client, endpoint: &str, form: &[(&str, &str)]) -> Result<TokenResponse, CommandError> {
+    let resp = client.post(endpoint)
+        .header("Accept", "application/json")
+        .form(form)
+        .send().await?;
+    let status = resp.status();
+    let body = resp.bytes().await?;
+    if !status.is_success() {
+        warn!("Token endpoint returned {}", status);
+        // An OAuth error body (invalid_grant…) means the grant was refused
+        return Err(match serde_json::from_slice::<TokenError>(&body) {
+            Ok(TokenError { error, error_description: Some(desc) }) => {
+                CommandError::Unauthorized(format!("Token request rejected ({}: {})", error, desc))
+            }
+            Ok(TokenError { error, .. }) => CommandError::Unauthorized(format!("Token request rejected ({})", error)),
+            Err(_) => status.into(),
+        });
+    }
+    let tokens: TokenResponse = serde_json::from_slice(&body)
+        .map_err(|e| CommandError::Parse(format!("Failed to parse token response: {}", e)))?;
+    if tokens.access_token.is_empty() {
+        return Err(CommandError::Parse("Token endpoint returned an empty token".to_string()));
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pkce_s256_challenge() {
+        // RFC 7636 appendix B
+        let pkce = Pkce::from_verifier("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk".into());
+        assert_eq!(pkce.challenge, "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM");
+        let random = Pkce::generate().unwrap();
+        assert_eq!(random.verifier.len(), 43);
+        assert_ne!(random.verifier, Pkce::generate().unwrap().verifier);
+    }
+
+    #[test]
+    fn authorize_url_carries_pkce_and_redirect() {
+        let _lock = crate::config::TEST_MUTEX.blocking_lock();
+        let url = authorize_url(
+            "https://yao.example.com/oauth/authorize?prompt=login",
+            "desktop", &redirect_uri(15099), "openid profile", "st", "ch",
+        ).unwrap();
+        let query: HashMap<_, _> = url.query_pairs().into_owned().collect();
+        assert_eq!(query["prompt"], "login");
+        assert_eq!(query["response_type"], "code");
+        assert_eq!(query["redirect_uri"], "http://127.0.0.1:15099/__yao_desktop/oauth/callback");
+        assert_eq!(query["scope"], "openid profile");
+        assert_eq!((query["state"].as_str(), query["code_challenge"].as_str()), ("st", "ch"));
+        assert_eq!(query["code_challenge_method"], "S256");
+    }
+
+    #[test]
+    fn callback_delivers_code_to_matching_state() {
+        let mut rx = expect_callback("s1");
+        assert!(complete_callback("state=other&code=x").is_err());
+        complete_callback("code=abc%2F1&state=s1").unwrap();
+        assert_eq!(rx.try_recv().unwrap(), Ok("abc/1".to_string()));
+        // A state is only accepted once
+        assert!(complete_callback("code=abc&state=s1").is_err());
+
+        let mut rx = expect_callback("s2");
+        assert!(complete_callback("state=s2&error=access_denied&error_description=no").is_err());
+        assert_eq!(rx.try_recv().unwrap(), Err("access_denied: no".to_string()));
+    }
+}
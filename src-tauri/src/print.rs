@@ -0,0 +1,184 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tauri::WebviewWindow;
+
+/// Paper sizes offered by `print_to_pdf`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PageSize {
+    #[default]
+    A4,
+    A3,
+    Letter,
+    Legal,
+}
+
+impl PageSize {
+    /// PWG paper name, as used by GTK
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    fn pwg_name(self) -> &'static str {
+        match self {
+            PageSize::A4 => "iso_a4",
+            PageSize::A3 => "iso_a3",
+            PageSize::Letter => "na_letter",
+            PageSize::Legal => "na_legal",
+        }
+    }
+}
+
+/// Options of `print_to_pdf`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PdfOptions {
+    #[serde(default)]
+    pub page_size: PageSize,
+    #[serde(default)]
+    pub landscape: bool,
+    /// Margin on every side in millimetres, 0–50
+    #[serde(default = "default_margin_mm")]
+    pub margin_mm: f64,
+}
+
+fn default_margin_mm() -> f64 {
+    10.0
+}
+
+impl Default for PdfOptions {
+    fn default() -> Self {
+        Self { page_size: PageSize::default(), landscape: false, margin_mm: default_margin_mm() }
+    }
+}
+
+impl PdfOptions {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..=50.0).contains(&self.margin_mm) {
+            return Err(format!("Margin must be between 0 and 50 mm, got {}", self.margin_mm));
+        }
+        Ok(())
+    }
+}
+
+/// Result of `print_to_pdf`
+#[derive(Debug, Clone, Serialize)]
+pub struct PrintResult {
+    /// Where the PDF was saved; None when the print dialog was shown instead
+    pub path: Option<String>,
+    /// The native print dialog was opened (no silent PDF export here)
+    pub dialog: bool,
+}
+
+/// File name for a PDF of a page titled `title`
+pub fn pdf_file_name(title: &str) -> String {
+    let title = title.trim();
+    let stem = if title.is_empty() { "page" } else { title };
+    crate::sanitize_filename(&format!("{}.pdf", stem))
+}
+
+/// Render the window's page to a PDF at `path` without a dialog. Only
+/// WebKitGTK can do this; elsewhere (and on failure) this returns Err and
+/// the caller falls back to the print dialog.
+pub async fn save_pdf(win: &WebviewWindow, path: &Path, options: &PdfOptions) -> Result<(), String> {
+    #[cfg(target_os = "linux")]
+    {
+        linux::save_pdf(win, path, options).await
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (win, path, options);
+        Err("Silent PDF export is not supported on this platform".to_string())
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::PdfOptions;
+    use std::cell::RefCell;
+    use std::path::Path;
+    use std::rc::Rc;
+    use std::time::Duration;
+    use tauri::WebviewWindow;
+    use tokio::sync::oneshot;
+    use webkit2gtk::PrintOperationExt;
+
+    /// Long pages can take a while to lay out
+    const PRINT_TIMEOUT: Duration = Duration::from_secs(120);
+
+    pub async fn save_pdf(win: &WebviewWindow, path: &Path, options: &PdfOptions) -> Result<(), String> {
+        let uri = url::Url::from_file_path(path)
+            .map_err(|_| format!("Invalid PDF path: {:?}", path))?
+            .to_string();
+        let options = options.clone();
+        let (tx, rx) = oneshot::channel::<Result<(), String>>();
+
+        win.with_webview(move |webview| {
+            let op = webkit2gtk::PrintOperation::new(&webview.inner());
+
+            let settings = gtk::PrintSettings::new();
+            settings.set_printer("Print to File");
+            settings.set(gtk::PRINT_SETTINGS_OUTPUT_FILE_FORMAT.as_str(), Some("pdf"));
+            settings.set(gtk::PRINT_SETTINGS_OUTPUT_URI.as_str(), Some(&uri));
+            op.set_print_settings(&settings);
+
+            let page = gtk::PageSetup::new();
+            page.set_paper_size(&gtk::PaperSize::new(Some(options.page_size.pwg_name())));
+            page.set_orientation(if options.landscape {
+                gtk::PageOrientation::Landscape
+            } else {
+                gtk::PageOrientation::Portrait
+            });
+            let margin = options.margin_mm;
+            page.set_top_margin(margin, gtk::Unit::Mm);
+            page.set_bottom_margin(margin, gtk::Unit::Mm);
+            page.set_left_margin(margin, gtk::Unit::Mm);
+            page.set_right_margin(margin, gtk::Unit::Mm);
+            op.set_page_setup(&page);
+
+            // Keeps the operation alive until it reports back; "failed" is
+            // followed by "finished", so the first report wins
+            let pending = Rc::new(RefCell::new(Some((tx, op.clone()))));
+            let on_failed = pending.clone();
+            op.connect_failed(move |_, err| {
+                if let Some((tx, _op)) = on_failed.borrow_mut().take() {
+                    let _ = tx.send(Err(err.to_string()));
+                }
+            });
+            op.connect_finished(move |_| {
+                if let Some((tx, _op)) = pending.borrow_mut().take() {
+                    let _ = tx.send(Ok(()));
+                }
+            });
+            op.print();
+        })
+        .map_err(|e| format!("Failed to reach the webview: {}", e))?;
+
+        match tokio::time::timeout(PRINT_TIMEOUT, rx).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err("Print operation was dropped".to_string()),
+            Err(_) => Err("Timed out printing to PDF".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn options_default_and_validate() {
+        let options: PdfOptions = serde_json::from_str("{}").unwrap();
+        assert_eq!(options, PdfOptions::default());
+        assert_eq!((options.page_size, options.margin_mm), (PageSize::A4, 10.0));
+
+        let options: PdfOptions = serde_json::from_str(r#"{"page_size":"letter","landscape":true,"margin_mm":0}"#).unwrap();
+        assert_eq!((options.page_size, options.landscape), (PageSize::Letter, true));
+        assert!(options.validate().is_ok());
+        assert!(PdfOptions { margin_mm: 80.0, ..Default::default() }.validate().is_err());
+        assert!(PdfOptions { margin_mm: f64::NAN, ..Default::default() }.validate().is_err());
+        assert!(serde_json::from_str::<PdfOptions>(r#"{"page_size":"tabloid"}"#).is_err());
+    }
+
+    #[test]
+    fn pdf_names_come_from_the_title() {
+        assert_eq!(pdf_file_name("Q3 Report: Sales"), "Q3 Report_ Sales.pdf");
+        assert_eq!(pdf_file_name("  "), "page.pdf");
+    }
+}
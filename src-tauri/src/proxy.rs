@@ -6,28 +6,60 @@ use axum::{
 };
 use axum::extract::ws::{WebSocket, WebSocketUpgrade, Message as AxumMessage};
 use axum::extract::FromRequest;
-use http::{header, HeaderValue, StatusCode};
+use http::{header, HeaderName, HeaderValue, StatusCode};
+use std::collections::HashMap;
 use reqwest::Client;
-use tower_http::cors::CorsLayer;
+use serde::Deserialize;
+use tower_http::cors::{AllowHeaders, AllowOrigin, CorsLayer};
 use tokio::net::TcpListener;
-use tauri::Manager;
 use tracing::{info, error, warn, debug};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::path::PathBuf;
 use std::time::Duration;
 use futures_util::{SinkExt, StreamExt};
 use tokio_tungstenite::tungstenite::Message as TungsteniteMessage;
 
+use crate::app_conf::{is_hex_color, LocalHost, PathRewrite, UpstreamRoute};
 use crate::config::{self, get_proxy_state};
-
-/// Max request body size: 512 MB
-const MAX_BODY_SIZE: usize = 512 * 1024 * 1024;
+use crate::focused_or_main_window;
+use crate::locale;
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Request bodies of known size up to this many bytes are buffered (so
+/// retries can resend them); others stream to the server
+const BUFFERED_BODY_MAX: u64 = 64 * 1024;
+
+/// Largest body the proxy inflates; bigger ones (or decompression bombs)
+/// are forwarded still encoded
+const DECODED_BODY_MAX: u64 = 32 * 1024 * 1024;
+
+/// How many ports above the preferred one to try when it is already in use
+const PORT_FALLBACK_RANGE: u16 = 20;
+
+/// Signals the running proxy server to stop accepting connections
+static PROXY_SHUTDOWN: tokio::sync::Notify = tokio::sync::Notify::const_new();
+
+/// Slots for in-flight upstream requests (`proxy.maxConcurrentUpstream`);
+/// None when unlimited
+static UPSTREAM_LIMIT: Lazy<RwLock<Option<UpstreamLimit>>> = Lazy::new(|| RwLock::new(None));
+
+#[derive(Clone)]
+struct UpstreamLimit {
+    slots: Arc<Semaphore>,
+    queue_timeout: Duration,
+}
 
 /// Start the local proxy server on the given port
 pub async fn start_proxy_server(cui_dist_path: PathBuf, port: u16) -> Result<u16, String> {
+    let proxy_conf = crate::app_conf::get_app_conf().proxy;
+    set_upstream_limit(proxy_conf.max_concurrent_upstream, Duration::from_secs(proxy_conf.upstream_queue_secs));
 
-    let client = Client::builder()
+    let client = crate::upstream::client_builder()
         .redirect(reqwest::redirect::Policy::none())
-        .no_proxy()
         .pool_idle_timeout(Duration::from_secs(90))
         .pool_max_idle_per_host(10)
         .connect_timeout(Duration::from_secs(10))
@@ -37,29 +69,24 @@ pub async fn start_proxy_server(cui_dist_path: PathBuf, port: u16) -> Result<u16
     // Initialize the global TunnelManager with a shared client
     config::init_tunnel_manager(client.clone());
 
-    let cui_dist = cui_dist_path.clone();
-
-    let app = Router::new()
-        .fallback(move |req: Request| {
-            let client = client.clone();
-            let cui_dist = cui_dist.clone();
-            async move {
-                handle_request(req, client, cui_dist).await
-            }
-        })
-        .layer(
-            CorsLayer::very_permissive()
-        );
-
-    let listener = TcpListener::bind(format!("127.0.0.1:{}", port))
-        .await
-        .map_err(|e| format!("Failed to bind port {}: {}", port, e))?;
+    let (primary_ip, other_ip) = loopback_order(proxy_conf.local_host);
+    let listener = bind_with_fallback(primary_ip, port).await?;
+    config::record_milestone(config::StartupMilestone::ProxyBound);
 
     let actual_port = listener.local_addr()
         .map_err(|e| format!("Failed to get local addr: {}", e))?
         .port();
+    // Same port on the other loopback, so `localhost` works whichever address it resolves to
+    let other = match TcpListener::bind((other_ip, actual_port)).await {
+        Ok(l) => Some(l),
+        Err(e) => {
+            warn!("Proxy listening on {} only ({} port {} unavailable: {})", primary_ip, other_ip, actual_port, e);
+            None
+        }
+    };
+    let app = router(client, cui_dist_path, actual_port);
 
-    info!("Proxy server started at http://127.0.0.1:{}", actual_port);
+    info!("Proxy server started at {}", proxy_conf.local_host.base(actual_port));
     {
         let mut state = config::PROXY_STATE.write();
         state.running = true;
@@ -67,15 +94,150 @@ pub async fn start_proxy_server(cui_dist_path: PathBuf, port: u16) -> Result<u16
     }
 
     tokio::spawn(async move {
-        if let Err(e) = axum::serve(listener, app).await {
+        let (stop_tx, mut stop_rx) = tokio::sync::watch::channel(());
+        let other = other.map(|listener| {
+            let app = app.clone();
+            tokio::spawn(async move {
+                axum::serve(listener, app)
+                    .with_graceful_shutdown(async move { let _ = stop_rx.changed().await; })
+                    .await
+            })
+        });
+        let result = axum::serve(listener, app)
+            .with_graceful_shutdown(async move {
+                PROXY_SHUTDOWN.notified().await;
+                let _ = stop_tx.send(());
+            })
+            .await;
+        if let Err(e) = result {
             error!("Proxy server error: {}", e);
-            config::set_proxy_running(false);
         }
+        if let Some(other) = other {
+            if let Ok(Err(e)) = other.await {
+                error!("Proxy server error: {}", e);
+            }
+        }
+        info!("Proxy server stopped");
+        config::set_proxy_running(false);
     });
+    config::record_milestone(config::StartupMilestone::ProxyReady);
 
     Ok(actual_port)
 }
 
+/// Stop the proxy server, waiting up to `timeout` for open connections
+/// (e.g. SSE streams) to finish before giving up on them.
+pub async fn stop_proxy_server(timeout: Duration) {
+    if !get_proxy_state().running {
+        return;
+    }
+    PROXY_SHUTDOWN.notify_one();
+    let deadline = tokio::time::Instant::now() + timeout;
+    while get_proxy_state().running && tokio::time::Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+/// Whether a lowercase header name is in a `proxy.strip*Headers` list
+fn is_listed(name: &str, list: &[String]) -> bool {
+    list.iter().any(|h| h.trim().eq_ignore_ascii_case(name))
+}
+
+/// Proxy origin for `port` with the configured `proxy.localHost`
+pub fn local_base(port: u16) -> String {
+    crate::app_conf::get_app_conf().proxy.local_host.base(port)
+}
+
+/// Loopback addresses to listen on: the one `host` names first (it must
+/// bind), then the other
+pub(crate) fn loopback_order(host: LocalHost) -> (IpAddr, IpAddr) {
+    let (v4, v6) = (IpAddr::V4(Ipv4Addr::LOCALHOST), IpAddr::V6(Ipv6Addr::LOCALHOST));
+    match host {
+        LocalHost::Ipv6 => (v6, v4),
+        LocalHost::Ipv4 | LocalHost::Localhost => (v4, v6),
+    }
+}
+
+/// Bind the preferred port, scanning upward (port+1 … port+N) if it is taken.
+/// Port 0 lets the OS pick a free port and never falls back.
+async fn bind_with_fallback(ip: IpAddr, port: u16) -> Result<TcpListener, String> {
+    if port == 0 {
+        return TcpListener::bind((ip, 0))
+            .await
+            .map_err(|e| format!("Failed to bind port 0: {}", e));
+    }
+
+    let mut last_err = String::new();
+    for offset in 0..=PORT_FALLBACK_RANGE {
+        let candidate = match port.checked_add(offset) {
+            Some(p) => p,
+            None => break,
+        };
+        match TcpListener::bind((ip, candidate)).await {
+            Ok(listener) => {
+                if offset > 0 {
+                    info!("Preferred port {} unavailable ({}), using port {}", port, last_err, candidate);
+                }
+                return Ok(listener);
+            }
+            Err(e) => {
+                warn!("Port {} unavailable: {}", candidate, e);
+                last_err = e.to_string();
+            }
+        }
+    }
+
+    Err(format!(
+        "Failed to bind ports {}-{}: {}",
+        port,
+        port.saturating_add(PORT_FALLBACK_RANGE),
+        last_err
+    ))
+}
+
+/// All proxy routes. CORS preflights (OPTIONS with
+/// Access-Control-Request-Method) are answered here and never reach the
+/// server; other OPTIONS requests are forwarded like any method.
+fn router(client: Client, cui_dist: PathBuf, port: u16) -> Router {
+    let permissive = crate::app_conf::get_app_conf().proxy.permissive_cors;
+    Router::new()
+        .fallback(move |req: Request| {
+            let client = client.clone();
+            let cui_dist = cui_dist.clone();
+            async move {
+                handle_request(req, client, cui_dist).await
+            }
+        })
+        .layer(cors_layer(port, permissive))
+}
+
+/// CUI is served same-origin, so cross-origin requests are only allowed
+/// from the proxy's own origins (127.0.0.1, localhost or [::1] on `port`).
+/// `permissive` (`proxy.permissiveCors`) reflects any origin instead.
+fn cors_layer(port: u16, permissive: bool) -> CorsLayer {
+    if permissive {
+        warn!("proxy.permissiveCors is on: any web page can call the proxy");
+        return CorsLayer::very_permissive();
+    }
+    let origins = [LocalHost::Ipv4, LocalHost::Localhost, LocalHost::Ipv6]
+        .into_iter()
+        .map(|host| host.base(port))
+        .filter_map(|origin| HeaderValue::from_str(&origin).ok());
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_methods([
+            http::Method::GET,
+            http::Method::HEAD,
+            http::Method::POST,
+            http::Method::PUT,
+            http::Method::PATCH,
+            http::Method::DELETE,
+            http::Method::OPTIONS,
+        ])
+        .allow_headers(AllowHeaders::mirror_request())
+        .allow_credentials(true)
+}
+
 /// Route handler:
 ///   /__yao_admin_root/* -> local CUI static files
 ///   Everything else     -> proxy to remote server (same-origin guarantee)
@@ -100,11 +262,14 @@ async fn handle_request(
 
     // CUI static assets -- served locally
     if path.starts_with("/__yao_admin_root/") {
-        let if_none_match = req.headers()
-            .get(header::IF_NONE_MATCH)
-            .and_then(|v| v.to_str().ok())
-            .map(|s| s.to_string());
-        return serve_cui_static(path, &cui_dist, if_none_match.as_deref()).await;
+        let headers = req.headers();
+        let header_str = |name| headers.get(name).and_then(|v| v.to_str().ok());
+        let opts = StaticRequest {
+            if_none_match: header_str(header::IF_NONE_MATCH),
+            range: header_str(header::RANGE),
+            gzip: crate::app_conf::get_app_conf().proxy.gzip_static && accepts_gzip(headers),
+        };
+        return serve_cui_static(path, &cui_dist, opts).await;
     }
 
     // Redirect /__yao_admin_root (no trailing slash)
@@ -119,29 +284,13 @@ async fn handle_request(
     // Redirect /{dashboard}/* → /__yao_admin_root/* so that server-side
     // redirects (e.g. login success_url="/dashboard/chat") land on local CUI
     // instead of being proxied to the remote server.
-    {
-        let state = get_proxy_state();
-        let dash = &state.dashboard; // e.g. "/dashboard"
-        if !dash.is_empty() {
-            let dash_slash = format!("{}/", dash); // "/dashboard/"
-            if path.starts_with(&dash_slash) {
-                let new_path = format!("/__yao_admin_root/{}", &path[dash_slash.len()..]);
-                info!("Dashboard redirect: {} -> {}", path, new_path);
-                return Response::builder()
-                    .status(StatusCode::TEMPORARY_REDIRECT)
-                    .header(header::LOCATION, new_path)
-                    .body(Body::empty())
-                    .unwrap();
-            }
-            if path == dash.as_str() {
-                info!("Dashboard redirect: {} -> /__yao_admin_root/", path);
-                return Response::builder()
-                    .status(StatusCode::TEMPORARY_REDIRECT)
-                    .header(header::LOCATION, "/__yao_admin_root/")
-                    .body(Body::empty())
-                    .unwrap();
-            }
-        }
+    if let Some(location) = dashboard_redirect(req.uri(), &get_proxy_state().dashboard) {
+        info!("Dashboard redirect: {} -> {}", path, location);
+        return Response::builder()
+            .status(StatusCode::TEMPORARY_REDIRECT)
+            .header(header::LOCATION, location)
+            .body(Body::empty())
+            .unwrap();
     }
 
     // Root -> redirect to CUI
@@ -162,8 +311,53 @@ async fn handle_request(
     proxy_request(req, client).await
 }
 
-/// Forward a request to the remote Yao server
+/// Forward a request to the remote Yao server, counting it in the metrics
 async fn proxy_request(req: Request, client: Client) -> Response {
+    let started = std::time::Instant::now();
+    let logged = crate::request_log::enabled()
+        .then(|| (req.method().to_string(), req.uri().path().to_string()));
+    let response = forward_request(req, client).await;
+    let status = response.status().as_u16();
+    config::record_proxied_response(status);
+    match logged {
+        Some((method, path)) => log_response(response, method, path, status, started),
+        None => response,
+    }
+}
+
+/// Write an access log entry for a proxied response. Bodies of known size are
+/// logged right away; streamed ones once the stream ends, with the bytes sent.
+fn log_response(response: Response, method: String, path: String, status: u16, started: std::time::Instant) -> Response {
+    use axum::body::HttpBody;
+    use crate::request_log::{record, LogEntry};
+
+    if let Some(len) = response.body().size_hint().exact() {
+        record(LogEntry::new(&method, &path, status, started.elapsed(), len));
+        return response;
+    }
+
+    struct Pending { method: String, path: String, status: u16, started: std::time::Instant, bytes: u64 }
+    impl Pending {
+        fn add(&mut self, len: usize) {
+            self.bytes += len as u64;
+        }
+    }
+    impl Drop for Pending {
+        fn drop(&mut self) {
+            record(LogEntry::new(&self.method, &self.path, self.status, self.started.elapsed(), self.bytes));
+        }
+    }
+    let mut pending = Pending { method, path, status, started, bytes: 0 };
+    let (parts, body) = response.into_parts();
+    let stream = body.into_data_stream().inspect(move |chunk| {
+        if let Ok(bytes) = chunk {
+            pending.add(bytes.len());
+        }
+    });
+    Response::from_parts(parts, Body::from_stream(stream))
+}
+
+async fn forward_request(req: Request, client: Client) -> Response {
     let state = get_proxy_state();
 
     if state.server_url.is_empty() {
@@ -175,19 +369,47 @@ async fn proxy_request(req: Request, client: Client) -> Response {
 
     let method = req.method().clone();
     let uri = req.uri().clone();
-    let path_and_query = uri.path_and_query()
+    let request_path_and_query = uri.path_and_query()
         .map(|pq| pq.as_str())
         .unwrap_or("/");
+    let app_conf = crate::app_conf::get_app_conf();
+    let rewritten = rewrite_path(request_path_and_query, &app_conf.path_rewrites, false);
+    let path_and_query = rewritten.as_deref().unwrap_or(request_path_and_query);
+    if rewritten.is_some() {
+        debug!("Path rewrite: {} -> {}", request_path_and_query, path_and_query);
+    }
 
-    let remote_base = state.server_url.trim_end_matches('/').to_string();
+    let route = upstream_route(request_path_and_query, &app_conf.routes);
+    let remote_base = upstream_base(request_path_and_query, &app_conf.routes, &state.server_url).to_string();
     let target_url = format!("{}{}", remote_base, path_and_query);
+    // Cookies are scoped to the backend that set them; the session token
+    // only goes to the server unless the route opts in
+    let cookie_host = config::cookie_host(&remote_base);
+    let token = if route.is_none_or(|r| r.forward_auth) { state.token.as_str() } else { "" };
 
-    let local_base = format!("http://127.0.0.1:{}", state.port);
+    let local_base = app_conf.proxy.local_host.base(state.port);
     debug!("Proxy: {} {}", method, target_url);
 
+    let is_head = method == http::Method::HEAD;
+    // Only idempotent, non-streaming requests are safe to resend
+    let accepts_sse = req.headers()
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.contains("text/event-stream"))
+        .unwrap_or(false);
+    let retries = if (is_head || method == http::Method::GET) && !accepts_sse {
+        app_conf.proxy.max_retries
+    } else {
+        0
+    };
+
     // Build upstream request
     let mut builder = client.request(method, &target_url);
 
+    // Configured headers win over anything the proxy would set for the same name
+    let custom = custom_headers(&app_conf.headers, token);
+    let is_custom = |name: &str| custom.iter().any(|(n, _)| n.as_str() == name);
+
     // Collect browser Cookie header before iterating
     let browser_cookie_header = req.headers()
         .get("cookie")
@@ -195,13 +417,17 @@ async fn proxy_request(req: Request, client: Client) -> Response {
         .unwrap_or("")
         .to_string();
 
-    // Copy headers (skip hop-by-hop; cookie is handled separately below)
+    // Copy headers (skip hop-by-hop; cookie is handled separately below).
+    // Conditional headers (If-None-Match, If-Modified-Since) pass through so
+    // the server can answer 304 to the webview's cache.
     for (name, value) in req.headers() {
         let name_str = name.as_str().to_lowercase();
         if name_str == "host"
             || name_str == "connection"
             || name_str == "transfer-encoding"
             || name_str == "cookie"  // Handled separately: merge browser + jar
+            || name_str == "accept-encoding"  // Always request identity, see below
+            || is_custom(&name_str)
         {
             continue;
         }
@@ -219,48 +445,92 @@ async fn proxy_request(req: Request, client: Client) -> Response {
                 continue;
             }
         }
+        if is_listed(&name_str, &app_conf.proxy.strip_request_headers) {
+            continue;
+        }
         if let Ok(v) = value.to_str() {
             builder = builder.header(name.as_str(), v);
         }
     }
 
+    // Compression policy: ask upstream for an uncompressed body. The proxy
+    // forwards bodies verbatim (and may rewrite them), so passing the browser's
+    // Accept-Encoding through would risk a Content-Encoding / body mismatch.
+    // Loopback to the webview doesn't benefit from compression anyway.
+    if !is_custom("accept-encoding") {
+        builder = builder.header("Accept-Encoding", "identity");
+    }
+
     // Merge browser cookies with jar cookies. Jar wins on conflict.
-    let merged_cookies = config::get_merged_cookies(&browser_cookie_header, path_and_query);
-    if !merged_cookies.is_empty() {
-        debug!("Sending cookies: {}", &merged_cookies[..merged_cookies.len().min(120)]);
+    let merged_cookies = config::get_merged_cookies_for(&cookie_host, &browser_cookie_header, path_and_query);
+    if !merged_cookies.is_empty() && !is_custom("cookie") {
+        debug!("Sending cookies: {}", config::mask_cookie_header(&merged_cookies, false));
         builder = builder.header("Cookie", &merged_cookies);
     }
 
     // Inject auth token (if obtained via client-side login)
-    if !state.token.is_empty() {
-        builder = builder.header("Authorization", format!("Bearer {}", state.token));
+    if !token.is_empty() && !is_custom("authorization") {
+        builder = builder.header("Authorization", format!("Bearer {}", token));
     }
 
-    // Read request body
-    let body_bytes = match axum::body::to_bytes(req.into_body(), MAX_BODY_SIZE).await {
-        Ok(b) => b,
-        Err(e) => {
-            error!("Failed to read request body: {}", e);
-            return Response::builder()
-                .status(StatusCode::BAD_REQUEST)
-                .body(Body::from(format!("Failed to read request body: {}", e)))
-                .unwrap();
-        }
-    };
+    for (name, value) in custom {
+        builder = builder.header(name, value);
+    }
 
-    if !body_bytes.is_empty() {
-        builder = builder.body(body_bytes);
+    // Request body: small ones are read here, larger or unknown-length
+    // uploads stream through with `proxy.maxBodyMb` enforced on the way
+    let max_body = app_conf.proxy.max_body_mb.saturating_mul(1024 * 1024);
+    let body_len = axum::body::HttpBody::size_hint(req.body()).exact();
+    if max_body > 0 && body_len.is_some_and(|len| len > max_body) {
+        return body_too_large(max_body);
+    }
+    let body_overflow = Arc::new(AtomicBool::new(false));
+    if body_len.is_some_and(|len| len <= BUFFERED_BODY_MAX) {
+        let body_bytes = match axum::body::to_bytes(req.into_body(), BUFFERED_BODY_MAX as usize).await {
+            Ok(b) => b,
+            Err(e) => {
+                error!("Failed to read request body: {}", e);
+                return Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from(format!("Failed to read request body: {}", e)))
+                    .unwrap();
+            }
+        };
+        if !body_bytes.is_empty() {
+            builder = builder.body(body_bytes);
+        }
+    } else {
+        let stream = limit_body(req.into_body(), max_body, body_overflow.clone());
+        builder = builder.body(reqwest::Body::wrap_stream(stream));
     }
 
+    // SSE requests stay open indefinitely, so they don't take a slot
+    let slot = if accepts_sse {
+        None
+    } else {
+        match acquire_upstream_slot().await {
+            Ok(slot) => slot,
+            Err(waited) => {
+                warn!("No upstream slot after {:?}: {}", waited, target_url);
+                return json_error(StatusCode::SERVICE_UNAVAILABLE, "too many concurrent upstream requests");
+            }
+        }
+    };
+
     // Send request to upstream
-    let upstream_resp = match builder.send().await {
+    let timeout = Some(app_conf.proxy.request_timeout_secs)
+        .filter(|s| *s > 0)
+        .map(Duration::from_secs);
+    let upstream_resp = match send_with_retry(builder, timeout, retries).await {
         Ok(r) => r,
+        Err(_) if body_overflow.load(Ordering::Relaxed) => {
+            warn!("Request body over {} bytes: {}", max_body, target_url);
+            return body_too_large(max_body);
+        }
         Err(e) => {
             error!("Proxy request failed: {} -> {}", target_url, e);
-            return Response::builder()
-                .status(StatusCode::BAD_GATEWAY)
-                .body(Body::from(format!("Proxy request failed: {}", e)))
-                .unwrap();
+            config::record_upstream_error();
+            return json_error(StatusCode::GATEWAY_TIMEOUT, &format!("upstream request failed: {}", e));
         }
     };
 
@@ -273,10 +543,74 @@ async fn proxy_request(req: Request, client: Client) -> Response {
         .and_then(|v| v.to_str().ok())
         .map(|v| v.contains("text/event-stream"))
         .unwrap_or(false);
+    // Free the slot once an unrequested SSE stream is identified
+    let slot = if is_sse { None } else { slot };
 
     let is_redirect = status.is_redirection();
 
-    // Copy response headers; intercept Set-Cookie into jar, rewrite Location
+    // Compressed non-SSE bodies up to the buffer threshold are decoded locally
+    // (see decode_content) so the browser gets plaintext with matching
+    // headers; larger ones stream through still encoded. 206 responses are a
+    // byte slice of the encoded representation and can't be decoded on their
+    // own, so they pass through with Content-Range / Content-Encoding intact.
+    let content_encoding = upstream_resp.headers()
+        .get("content-encoding")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim().to_lowercase())
+        .unwrap_or_default();
+    let has_body = !is_head
+        && status != reqwest::StatusCode::NO_CONTENT
+        && status != reqwest::StatusCode::NOT_MODIFIED;
+    let decode = has_body
+        && !is_sse
+        && status != reqwest::StatusCode::PARTIAL_CONTENT
+        && is_decodable_encoding(&content_encoding)
+        && upstream_resp.content_length().is_none_or(|len| len <= app_conf.proxy.buffer_threshold);
+
+    // Small and HTML responses are buffered (exact Content-Length, room for
+    // rewriting); large or unknown-length binary bodies stream through.
+    let content_type = upstream_resp.headers()
+        .get("content-type")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .to_string();
+    // Optional: point absolute server URLs in text bodies at the proxy
+    // (needs the whole body, so these responses are always buffered)
+    let rewrite_urls = app_conf.proxy.rewrite_body_urls
+        && has_body
+        && !is_sse
+        && status != reqwest::StatusCode::PARTIAL_CONTENT
+        && (decode || content_encoding.is_empty())
+        && is_url_rewritable_type(&content_type);
+    let buffer = !is_sse
+        && !decode
+        && has_body
+        && (rewrite_urls || should_buffer_response(
+            upstream_resp.content_length(),
+            &content_type,
+            app_conf.proxy.buffer_threshold,
+        ));
+
+    // Set-Cookie headers go into the jar together (one save); the non-secure
+    // ones are also passed on to the browser, each as its own header
+    let set_cookies: Vec<&str> = upstream_resp.headers()
+        .get_all(header::SET_COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .collect();
+    let stored = config::store_cookies_for(&cookie_host, &set_cookies);
+    for (cookie_str, result) in set_cookies.iter().zip(stored) {
+        if result.is_secure {
+            debug!("Secure cookie -> jar only: {}", config::mask_cookie_header(cookie_str, true));
+        } else if let Some(ref sanitized) = result.browser_cookie {
+            debug!("Cookie -> jar + browser: {}", config::mask_cookie_header(sanitized, true));
+            if let Ok(hv) = HeaderValue::from_str(sanitized) {
+                response_builder = response_builder.header("set-cookie", hv);
+            }
+        }
+    }
+
+    // Copy response headers; rewrite Location
     for (name, value) in upstream_resp.headers() {
         let name_str = name.as_str().to_lowercase();
 
@@ -284,18 +618,15 @@ async fn proxy_request(req: Request, client: Client) -> Response {
             continue;
         }
 
+        if decode && (name_str == "content-encoding" || name_str == "content-length") {
+            continue;
+        }
+
+        if buffer && name_str == "content-length" {
+            continue;
+        }
+
         if name_str == "set-cookie" {
-            if let Ok(cookie_str) = value.to_str() {
-                let result = config::store_cookie(cookie_str);
-                if result.is_secure {
-                    debug!("Secure cookie -> jar only: {}", &cookie_str[..cookie_str.len().min(80)]);
-                } else if let Some(ref sanitized) = result.browser_cookie {
-                    debug!("Cookie -> jar + browser: {}", &sanitized[..sanitized.len().min(80)]);
-                    if let Ok(hv) = HeaderValue::from_str(sanitized) {
-                        response_builder = response_builder.header("set-cookie", hv);
-                    }
-                }
-            }
             continue;
         }
 
@@ -303,9 +634,11 @@ async fn proxy_request(req: Request, client: Client) -> Response {
             if let Ok(loc) = value.to_str() {
                 let mut final_loc = loc.to_string();
 
-                // Rewrite absolute remote URL → local proxy
-                if final_loc.starts_with(&remote_base) {
-                    final_loc = final_loc.replacen(&remote_base, &local_base, 1);
+                // Rewrite absolute remote URL → local proxy (a route's
+                // backend may also redirect to the main server)
+                let server_base = state.server_url.trim_end_matches('/');
+                if let Some(base) = [remote_base.as_str(), server_base].into_iter().find(|b| final_loc.starts_with(b)) {
+                    final_loc = final_loc.replacen(base, &local_base, 1);
                 }
 
                 // Rewrite /{dashboard}/ paths → /__yao_admin_root/
@@ -324,6 +657,19 @@ async fn proxy_request(req: Request, client: Client) -> Response {
                     let _ = local_admin; // suppress unused warning
                 }
 
+                // Map upstream paths back through the rewrite table, unless
+                // that would send the client straight back to this request.
+                if let Some(local) = final_loc.strip_prefix(&local_base).map(str::to_string)
+                    .or_else(|| final_loc.starts_with('/').then(|| final_loc.clone()))
+                {
+                    if let Some(reversed) = rewrite_path(&local, &app_conf.path_rewrites, true) {
+                        if reversed != request_path_and_query {
+                            let abs = final_loc.starts_with(&local_base);
+                            final_loc = if abs { format!("{}{}", local_base, reversed) } else { reversed };
+                        }
+                    }
+                }
+
                 if final_loc != loc {
                     response_builder = response_builder.header("location", final_loc);
                     continue;
@@ -331,6 +677,9 @@ async fn proxy_request(req: Request, client: Client) -> Response {
             }
         }
 
+        if is_listed(&name_str, &app_conf.proxy.strip_response_headers) {
+            continue;
+        }
         response_builder = response_builder.header(name.as_str(), value.clone());
     }
 
@@ -340,8 +689,123 @@ async fn proxy_request(req: Request, client: Client) -> Response {
             .header("X-Accel-Buffering", "no");
     }
 
-    let stream = upstream_resp.bytes_stream();
-    let body = Body::from_stream(stream);
+    // 304 (the browser's cached copy is current), 204 and HEAD: headers only
+    if !has_body {
+        return response_builder.body(Body::empty()).unwrap_or_else(|e| {
+            error!("Failed to build empty response: {}", e);
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from("Failed to build response"))
+                .unwrap()
+        });
+    }
+
+    if decode {
+        let raw = match read_body_within(upstream_resp, app_conf.proxy.buffer_threshold).await {
+            Ok(Ok(b)) => b,
+            Ok(Err(rest)) => {
+                // No Content-Length and larger than it looked: stream it encoded
+                debug!("{} body from {} is too large to decode; passing it through", content_encoding, target_url);
+                response_builder = response_builder.header("content-encoding", content_encoding.as_str());
+                let body = Body::from_stream(rest.inspect(move |chunk| {
+                    let _ = &slot;
+                    if let Ok(bytes) = chunk {
+                        config::record_bytes_proxied(bytes.len());
+                    }
+                }));
+                return response_builder.body(body).unwrap_or_else(|e| {
+                    error!("Failed to build streaming response: {}", e);
+                    Response::builder()
+                        .status(StatusCode::INTERNAL_SERVER_ERROR)
+                        .body(Body::from("Failed to build response"))
+                        .unwrap()
+                });
+            }
+            Err(e) => {
+                error!("Failed to read upstream body: {} -> {}", target_url, e);
+                config::record_upstream_error();
+                return Response::builder()
+                    .status(StatusCode::BAD_GATEWAY)
+                    .body(Body::from(format!("Failed to read upstream body: {}", e)))
+                    .unwrap();
+            }
+        };
+        let decoded = {
+            let (encoding, raw) = (content_encoding.clone(), raw.clone());
+            tokio::task::spawn_blocking(move || decode_content(&encoding, &raw, DECODED_BODY_MAX))
+                .await
+                .unwrap_or_else(|e| Err(std::io::Error::other(e)))
+        };
+        let body = match decoded {
+            Ok(plain) => {
+                debug!("Decoded {} body: {} -> {} bytes", content_encoding, raw.len(), plain.len());
+                let plain = match rewrite_urls.then(|| rewrite_body_urls(&plain, &remote_base, &local_base)).flatten() {
+                    Some(rewritten) => rewritten,
+                    None => plain,
+                };
+                response_builder = response_builder.header("content-length", plain.len());
+                config::record_bytes_proxied(plain.len());
+                Body::from(plain)
+            }
+            Err(e) => {
+                // Forward the body untouched with its original encoding
+                warn!("Failed to decode {} body from {}: {}", content_encoding, target_url, e);
+                response_builder = response_builder
+                    .header("content-encoding", content_encoding.as_str())
+                    .header("content-length", raw.len());
+                config::record_bytes_proxied(raw.len());
+                Body::from(raw)
+            }
+        };
+        return response_builder.body(body).unwrap_or_else(|e| {
+            error!("Failed to build decoded response: {}", e);
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from("Failed to build response"))
+                .unwrap()
+        });
+    }
+
+    if buffer {
+        let data = match upstream_resp.bytes().await {
+            Ok(b) => b,
+            Err(e) => {
+                error!("Failed to read upstream body: {} -> {}", target_url, e);
+                config::record_upstream_error();
+                return Response::builder()
+                    .status(StatusCode::BAD_GATEWAY)
+                    .body(Body::from(format!("Failed to read upstream body: {}", e)))
+                    .unwrap();
+            }
+        };
+        let data = match rewrite_urls.then(|| rewrite_body_urls(&data, &remote_base, &local_base)).flatten() {
+            Some(rewritten) => bytes::Bytes::from(rewritten),
+            None => data,
+        };
+        response_builder = response_builder.header("content-length", data.len());
+        config::record_bytes_proxied(data.len());
+        return response_builder.body(Body::from(data)).unwrap_or_else(|e| {
+            error!("Failed to build buffered response: {}", e);
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from("Failed to build response"))
+                .unwrap()
+        });
+    }
+
+    // SSE streams count as active until the client or upstream drops them;
+    // other streamed bodies keep their upstream slot until they end
+    let body = if is_sse {
+        let heartbeat = Some(app_conf.proxy.sse_heartbeat_secs).filter(|&s| s > 0).map(Duration::from_secs);
+        Body::from_stream(sse_body(upstream_resp.bytes_stream(), config::sse_stream_started(), heartbeat))
+    } else {
+        Body::from_stream(upstream_resp.bytes_stream().inspect(move |chunk| {
+            let _ = &slot;
+            if let Ok(bytes) = chunk {
+                config::record_bytes_proxied(bytes.len());
+            }
+        }))
+    };
     response_builder.body(body).unwrap_or_else(|e| {
         error!("Failed to build streaming response: {}", e);
         Response::builder()
@@ -351,13 +815,388 @@ async fn proxy_request(req: Request, client: Client) -> Response {
     })
 }
 
+/// Sent when upstream ends an SSE stream cleanly; a comment, so parsers skip it
+const SSE_CLOSED: &[u8] = b": upstream closed\n\n";
+
+/// Sent down idle SSE streams; a comment line between events, so parsers skip it
+const SSE_HEARTBEAT: &[u8] = b": keep-alive\n";
+
+/// Length of `buf` up to the end of its last complete SSE event (the blank
+/// line after it), 0 if none is complete yet. Lines end in CRLF, LF or CR.
+fn sse_complete_len(buf: &[u8]) -> usize {
+    let (mut complete, mut line_start, mut i) = (0, 0, 0);
+    while i < buf.len() {
+        let term = match buf[i] {
+            b'\r' if buf.get(i + 1) == Some(&b'\n') => 2,
+            b'\r' | b'\n' => 1,
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+        if i == line_start {
+            complete = i + term;
+        }
+        i += term;
+        line_start = i;
+    }
+    complete
+}
+
+/// Pass an upstream SSE stream through, one complete event at a time, so
+/// the client never sees half an event. After `heartbeat` without output a
+/// keep-alive comment is sent. Instead of dropping the connection
+/// mid-stream, an upstream error ends it with an `event: error` carrying
+/// the reason (an unfinished event is dropped); a clean EOF ends with the
+/// `SSE_CLOSED` comment. `guard` is released as soon as upstream is done.
+fn sse_body<S, E>(
+    upstream: S,
+    guard: config::SseStreamGuard,
+    heartbeat: Option<Duration>,
+) -> impl futures_util::Stream<Item = Result<bytes::Bytes, std::io::Error>> + Send + 'static
+where
+    S: futures_util::Stream<Item = Result<bytes::Bytes, E>> + Send + 'static,
+    E: std::fmt::Display,
+{
+    // (upstream, guard while streaming, bytes of the unfinished event)
+    let state = (Box::pin(upstream), Some(guard), bytes::BytesMut::new());
+    futures_util::stream::unfold(state, move |(mut upstream, guard, mut pending)| async move {
+        let guard = guard?;
+        loop {
+            let next = match heartbeat {
+                Some(idle) => match tokio::time::timeout(idle, upstream.next()).await {
+                    Ok(next) => next,
+                    Err(_) => {
+                        let ping = bytes::Bytes::from_static(SSE_HEARTBEAT);
+                        return Some((Ok(ping), (upstream, Some(guard), pending)));
+                    }
+                },
+                None => upstream.next().await,
+            };
+            match next {
+                Some(Ok(chunk)) => {
+                    config::record_bytes_proxied(chunk.len());
+                    pending.extend_from_slice(&chunk);
+                    let complete = sse_complete_len(&pending);
+                    if complete > 0 {
+                        let events = pending.split_to(complete).freeze();
+                        return Some((Ok(events), (upstream, Some(guard), pending)));
+                    }
+                }
+                Some(Err(e)) => {
+                    warn!("SSE stream from upstream failed: {}", e);
+                    config::record_upstream_error();
+                    drop(guard);
+                    if !pending.is_empty() {
+                        debug!("Dropping {} bytes of an unfinished SSE event", pending.len());
+                    }
+                    let data = serde_json::json!({ "message": e.to_string() });
+                    let event = format!("event: error\ndata: {}\n\n", data);
+                    return Some((Ok(bytes::Bytes::from(event)), (upstream, None, bytes::BytesMut::new())));
+                }
+                None => {
+                    drop(guard);
+                    if !pending.is_empty() {
+                        debug!("Dropping {} bytes of an unfinished SSE event", pending.len());
+                    }
+                    let closed = bytes::Bytes::from_static(SSE_CLOSED);
+                    return Some((Ok(closed), (upstream, None, bytes::BytesMut::new())));
+                }
+            }
+        }
+    })
+}
+
+/// Pass a request body through, failing once it exceeds `max` bytes (0 = no
+/// limit) and setting `overflow` so the caller can answer 413
+fn limit_body(
+    body: Body,
+    max: u64,
+    overflow: Arc<AtomicBool>,
+) -> impl futures_util::Stream<Item = Result<bytes::Bytes, std::io::Error>> + Send + 'static {
+    let mut total = 0u64;
+    body.into_data_stream().map(move |chunk| {
+        let chunk = chunk.map_err(std::io::Error::other)?;
+        total += chunk.len() as u64;
+        if max > 0 && total > max {
+            overflow.store(true, Ordering::Relaxed);
+            return Err(std::io::Error::other(format!("request body over {} bytes", max)));
+        }
+        Ok(chunk)
+    })
+}
+
+fn body_too_large(max: u64) -> Response {
+    json_error(
+        StatusCode::PAYLOAD_TOO_LARGE,
+        &format!("request body larger than {} MB", max / (1024 * 1024)),
+    )
+}
+
+/// The longest route matching a request path (None = the server URL)
+fn upstream_route<'a>(path_and_query: &str, routes: &'a [UpstreamRoute]) -> Option<&'a UpstreamRoute> {
+    let path = path_and_query.split('?').next().unwrap_or("");
+    routes.iter()
+        .filter(|r| prefix_matches(&r.prefix, path))
+        .max_by_key(|r| r.prefix.len())
+}
+
+/// Upstream base URL for a request path: the target of the longest matching
+/// route, or the server URL
+fn upstream_base<'a>(path_and_query: &str, routes: &'a [UpstreamRoute], server_url: &'a str) -> &'a str {
+    upstream_route(path_and_query, routes)
+        .map_or(server_url, |r| r.target.as_str())
+        .trim_end_matches('/')
+}
+
+/// Apply the longest matching rewrite rule to a path (with optional query).
+/// `reverse` maps `to_prefix` back to `from_prefix` (for Location headers).
+/// Rules apply once — the result is never re-matched — so cyclic tables
+/// can't loop. Returns None when no rule matches.
+fn rewrite_path(path_and_query: &str, rules: &[PathRewrite], reverse: bool) -> Option<String> {
+    let path = path_and_query.split('?').next().unwrap_or("");
+    rules.iter()
+        .map(|r| if reverse { (&r.to_prefix, &r.from_prefix) } else { (&r.from_prefix, &r.to_prefix) })
+        .filter(|(from, to)| from.starts_with('/') && to.starts_with('/') && from != to)
+        .filter(|(from, _)| prefix_matches(from, path))
+        .max_by_key(|(from, _)| from.len())
+        .map(|(from, to)| {
+            let rest = &path_and_query[from.len()..];
+            // "/old/" → "/new" must not glue segments together
+            if to.ends_with('/') && rest.starts_with('/') {
+                format!("{}{}", to, &rest[1..])
+            } else if !to.ends_with('/') && from.ends_with('/') && !rest.is_empty() && !rest.starts_with('?') {
+                format!("{}/{}", to, rest)
+            } else {
+                format!("{}{}", to, rest)
+            }
+        })
+}
+
+/// Whether `prefix` matches `path` on a segment boundary (`/api` matches
+/// `/api` and `/api/x`, not `/apix`)
+fn prefix_matches(prefix: &str, path: &str) -> bool {
+    path == prefix
+        || (path.starts_with(prefix) && (prefix.ends_with('/') || path[prefix.len()..].starts_with('/')))
+}
+
+/// Decide whether a proxied response body should be buffered in full.
+/// Known lengths are compared to `threshold`; without a length only HTML
+/// is buffered, everything else (downloads, NDJSON, media) is streamed.
+fn should_buffer_response(content_length: Option<u64>, content_type: &str, threshold: u64) -> bool {
+    match content_length {
+        Some(len) => len <= threshold,
+        None => content_type.starts_with("text/html"),
+    }
+}
+
+/// Upstream body stream handed back by `read_body_within`
+type BodyStream = futures_util::stream::BoxStream<'static, Result<bytes::Bytes, reqwest::Error>>;
+
+/// Read a response body while it stays within `limit` bytes. A longer one
+/// comes back as a stream of the chunks read so far followed by the rest.
+async fn read_body_within(
+    resp: reqwest::Response,
+    limit: u64,
+) -> Result<Result<bytes::Bytes, BodyStream>, reqwest::Error> {
+    let mut stream = resp.bytes_stream();
+    let (mut chunks, mut len) = (Vec::new(), 0u64);
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        len += chunk.len() as u64;
+        chunks.push(chunk);
+        if len > limit {
+            let read = futures_util::stream::iter(chunks.into_iter().map(Ok));
+            return Ok(Err(read.chain(stream).boxed()));
+        }
+    }
+    Ok(Ok(chunks.concat().into()))
+}
+
+/// Content-Encoding values the proxy knows how to decode
+fn is_decodable_encoding(encoding: &str) -> bool {
+    matches!(encoding, "gzip" | "x-gzip" | "deflate" | "br")
+}
+
+/// Decode a compressed response body (gzip / deflate / br). Bodies that
+/// inflate to more than `limit` bytes fail with `FileTooLarge`.
+fn decode_content(encoding: &str, data: &[u8], limit: u64) -> std::io::Result<Vec<u8>> {
+    use std::io::{ErrorKind, Read};
+
+    let read_limited = |reader: &mut dyn Read| {
+        let mut out = Vec::new();
+        reader.take(limit.saturating_add(1)).read_to_end(&mut out)?;
+        if out.len() as u64 > limit {
+            return Err(std::io::Error::new(
+                ErrorKind::FileTooLarge,
+                format!("decoded body is larger than {} bytes", limit),
+            ));
+        }
+        Ok(out)
+    };
+    match encoding {
+        "gzip" | "x-gzip" => read_limited(&mut flate2::read::MultiGzDecoder::new(data)),
+        // RFC 9110 deflate is zlib-wrapped, but some servers send raw deflate
+        "deflate" => match read_limited(&mut flate2::read::ZlibDecoder::new(data)) {
+            Err(e) if e.kind() != ErrorKind::FileTooLarge => {
+                read_limited(&mut flate2::read::DeflateDecoder::new(data))
+            }
+            result => result,
+        },
+        "br" => read_limited(&mut brotli::Decompressor::new(data, 4096)),
+        _ => Err(std::io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("unsupported content-encoding: {}", encoding),
+        )),
+    }
+}
+
+/// Local CUI location for a request under the server's dashboard path
+/// (`/dashboard/chat?x=1` → `/__yao_admin_root/chat?x=1`). None when no
+/// dashboard is set or the path is outside it (`/dashboards` doesn't match).
+fn dashboard_redirect(uri: &http::Uri, dashboard: &str) -> Option<String> {
+    if dashboard.is_empty() {
+        return None;
+    }
+    let rest = uri.path().strip_prefix(dashboard)?;
+    let rest = if rest.is_empty() { "/" } else { rest };
+    if !rest.starts_with('/') {
+        return None;
+    }
+    Some(match uri.query() {
+        Some(q) => format!("/__yao_admin_root{}?{}", rest, q),
+        None => format!("/__yao_admin_root{}", rest),
+    })
+}
+
+/// Content types whose bodies may carry links back to the server
+fn is_url_rewritable_type(content_type: &str) -> bool {
+    let mime = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+    matches!(
+        mime.as_str(),
+        "text/html" | "application/javascript" | "text/javascript" | "application/json"
+    ) || mime.ends_with("+json")
+}
+
+/// Replace `from` with `to` where it is a whole origin, i.e. not followed by
+/// more host or port characters (`https://a.com` must not match `https://a.com.evil`
+/// or `https://a.com:8443`). None if nothing was replaced.
+fn replace_origin(text: &str, from: &str, to: &str) -> Option<String> {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    let mut changed = false;
+    while let Some(i) = rest.find(from) {
+        let after = &rest[i + from.len()..];
+        let whole = after.chars().next()
+            .is_none_or(|c| !(c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_' | ':' | '@')));
+        out.push_str(&rest[..i]);
+        out.push_str(if whole { to } else { from });
+        changed |= whole;
+        rest = after;
+    }
+    if !changed {
+        return None;
+    }
+    out.push_str(rest);
+    Some(out)
+}
+
+/// Rewrite absolute `remote_base` URLs in a text body to `local_base`,
+/// including the `http:\/\/host` form some JSON encoders emit. Bodies that
+/// aren't UTF-8 are left alone. None if nothing changed.
+fn rewrite_body_urls(body: &[u8], remote_base: &str, local_base: &str) -> Option<Vec<u8>> {
+    let text = std::str::from_utf8(body).ok()?;
+    let plain = replace_origin(text, remote_base, local_base);
+    let escaped = replace_origin(
+        plain.as_deref().unwrap_or(text),
+        &remote_base.replace('/', "\\/"),
+        &local_base.replace('/', "\\/"),
+    );
+    escaped.or(plain).map(String::into_bytes)
+}
+
+/// First delay between upstream retries; doubles on each attempt
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(200);
+
+/// Send an upstream request, waiting at most `timeout` for the response
+/// headers (the body may keep streaming afterwards). Connection failures are
+/// retried up to `retries` times with exponential backoff; HTTP error
+/// statuses are returned as responses, never retried.
+async fn send_with_retry(
+    mut builder: reqwest::RequestBuilder,
+    timeout: Option<Duration>,
+    retries: u32,
+) -> Result<reqwest::Response, String> {
+    let mut attempt = 0;
+    loop {
+        let retry = if attempt < retries { builder.try_clone() } else { None };
+        let sent = match timeout {
+            Some(t) => match tokio::time::timeout(t, builder.send()).await {
+                Ok(sent) => sent,
+                Err(_) => return Err(format!("no response within {:?}", t)),
+            },
+            None => builder.send().await,
+        };
+        match (sent, retry) {
+            (Ok(resp), _) => return Ok(resp),
+            (Err(e), Some(retry)) if e.is_connect() => {
+                let delay = RETRY_BASE_DELAY * 2u32.pow(attempt);
+                warn!("Upstream connect failed ({}), retrying in {:?}", e, delay);
+                tokio::time::sleep(delay).await;
+                builder = retry;
+                attempt += 1;
+            }
+            (Err(e), _) => return Err(e.to_string()),
+        }
+    }
+}
+
+/// Parse `AppConf.headers` for an upstream request, expanding `${token}`.
+/// Invalid names/values are skipped, as are token headers while logged out.
+fn custom_headers(headers: &HashMap<String, String>, token: &str) -> Vec<(HeaderName, HeaderValue)> {
+    let mut out = Vec::new();
+    for (name, value) in headers {
+        if value.contains("${token}") && token.is_empty() {
+            continue;
+        }
+        let value = value.replace("${token}", token);
+        match (HeaderName::from_bytes(name.trim().as_bytes()), HeaderValue::from_str(&value)) {
+            (Ok(n), Ok(v)) => out.push((n, v)),
+            _ => warn!("Ignoring invalid custom header: {}", name),
+        }
+    }
+    out
+}
+
 /// Handle desktop native API requests (window management)
 async fn handle_desktop_api(req: Request) -> Response {
     let path = req.uri().path();
     match path {
         "/__yao_desktop/window/fullscreen" => handle_window_fullscreen(req).await,
+        "/__yao_desktop/window/zoom" => handle_window_zoom(req).await,
+        "/__yao_desktop/window/find" => handle_window_find(req).await,
+        "/__yao_desktop/window/minimize"
+        | "/__yao_desktop/window/maximize"
+        | "/__yao_desktop/window/close"
+        | "/__yao_desktop/window/state" => handle_window_control(req).await,
         "/__yao_desktop/reveal" => handle_reveal_file(req).await,
+        "/__yao_desktop/file/open" => handle_open_file(req).await,
         "/__yao_desktop/tunnel" => handle_tunnel_create(req).await,
+        "/__yao_desktop/storage/result" => handle_storage_result(req).await,
+        "/__yao_desktop/notification" => handle_notification(req).await,
+        "/__yao_desktop/status" => handle_status(&req),
+        "/__yao_desktop/metrics" => handle_metrics(&req),
+        crate::oauth::CALLBACK_PATH => handle_oauth_callback(&req),
+        "/__yao_desktop/ready" => {
+            config::record_milestone(config::StartupMilestone::FirstPaint);
+            if let Some(app) = config::get_app_handle() {
+                crate::open_pending_deep_link(app);
+            }
+            Response::builder()
+                .status(StatusCode::NO_CONTENT)
+                .body(Body::empty())
+                .unwrap()
+        }
         _ => Response::builder()
             .status(StatusCode::NOT_FOUND)
             .header("Content-Type", "application/json")
@@ -366,6 +1205,211 @@ async fn handle_desktop_api(req: Request) -> Response {
     }
 }
 
+/// Proxy health for diagnostics; the session token is never included
+fn desktop_status() -> serde_json::Value {
+    let state = config::get_proxy_state();
+    serde_json::json!({
+        "running": state.running,
+        "port": state.port,
+        "server_url": state.server_url,
+        "auth_mode": state.auth_mode,
+        "dashboard": state.dashboard,
+        "cookie_count": config::cookie_count(),
+        "uptime_secs": config::uptime_secs(),
+        "version": env!("CARGO_PKG_VERSION"),
+    })
+}
+
+/// GET /__yao_desktop/status  → {"running": true, "port": 15099, "server_url": "…", …}
+fn handle_status(req: &Request) -> Response {
+    if req.method() != http::Method::GET {
+        return json_error(StatusCode::METHOD_NOT_ALLOWED, "method not allowed");
+    }
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .header("Cache-Control", "no-store")
+        .body(Body::from(desktop_status().to_string()))
+        .unwrap()
+}
+
+/// Proxy counters in the Prometheus text exposition format
+fn metrics_text() -> String {
+    use std::fmt::Write;
+    use std::sync::atomic::Ordering::Relaxed;
+    let m = &*config::PROXY_METRICS;
+    let single = [
+        ("requests_total", "counter", "Requests forwarded to the Yao server", &m.requests),
+        ("bytes_total", "counter", "Response body bytes sent to the client", &m.bytes_proxied),
+        ("sse_streams_active", "gauge", "Server-sent event streams currently open", &m.active_sse_streams),
+        ("upstream_errors_total", "counter", "Failed upstream requests or body reads", &m.upstream_errors),
+    ];
+    let mut out = String::new();
+    for (name, kind, help, value) in single {
+        let name = format!("cui_desktop_proxy_{}", name);
+        let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}\n{} {}", name, help, name, kind, name, value.load(Relaxed));
+    }
+    let name = "cui_desktop_proxy_responses_total";
+    let _ = writeln!(out, "# HELP {} Proxied responses by status class\n# TYPE {} counter", name, name);
+    for (i, count) in m.status_classes.iter().enumerate() {
+        let _ = writeln!(out, "{}{{class=\"{}xx\"}} {}", name, i + 1, count.load(Relaxed));
+    }
+    out
+}
+
+/// GET /__yao_desktop/oauth/callback?code=…&state=…  (redirect_uri of `login_oauth`)
+fn handle_oauth_callback(req: &Request) -> Response {
+    let (status, message) = match crate::oauth::complete_callback(req.uri().query().unwrap_or("")) {
+        Ok(()) => (StatusCode::OK, "Signed in. You can close this window."),
+        Err(e) => {
+            warn!("OAuth callback rejected: {}", e);
+            (StatusCode::BAD_REQUEST, "Sign-in failed. Return to the app and try again.")
+        }
+    };
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "text/html; charset=utf-8")
+        .header("Cache-Control", "no-store")
+        .body(Body::from(format!("<!DOCTYPE html><meta charset=\"utf-8\"><title>Sign in</title><p>{}</p>", message)))
+        .unwrap()
+}
+
+/// GET /__yao_desktop/metrics  → Prometheus text format
+fn handle_metrics(req: &Request) -> Response {
+    if req.method() != http::Method::GET {
+        return json_error(StatusCode::METHOD_NOT_ALLOWED, "method not allowed");
+    }
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "text/plain; version=0.0.4; charset=utf-8")
+        .header("Cache-Control", "no-store")
+        .body(Body::from(metrics_text()))
+        .unwrap()
+}
+
+/// Result of a page script run by `get_web_storage_usage` / `clear_web_storage`
+/// or the find endpoint.
+/// POST /__yao_desktop/storage/result  body: {"id": 1, "result": …} or {"id": 1, "error": "…"}
+async fn handle_storage_result(req: Request) -> Response {
+    if req.method() != http::Method::POST {
+        return Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .body(Body::empty())
+            .unwrap();
+    }
+    let body = axum::body::to_bytes(req.into_body(), 64 * 1024)
+        .await
+        .unwrap_or_default();
+    let status = match serde_json::from_slice::<crate::web_storage::StorageReport>(&body) {
+        Ok(report) => {
+            if report.deliver() { StatusCode::NO_CONTENT } else { StatusCode::NOT_FOUND }
+        }
+        Err(_) => StatusCode::BAD_REQUEST,
+    };
+    Response::builder().status(status).body(Body::empty()).unwrap()
+}
+
+/// Body of POST /__yao_desktop/notification
+#[derive(Debug, Deserialize, PartialEq)]
+struct NotificationRequest {
+    title: String,
+    #[serde(default)]
+    body: String,
+    #[serde(default)]
+    icon: Option<String>,
+}
+
+fn parse_notification(body: &[u8]) -> Result<NotificationRequest, String> {
+    let req: NotificationRequest = serde_json::from_slice(body)
+        .map_err(|e| format!("invalid notification: {}", e))?;
+    if req.title.trim().is_empty() {
+        return Err("title is required".to_string());
+    }
+    Ok(req)
+}
+
+/// Allow `max` upstream requests at once (0 = no limit); others wait up to
+/// `queue_timeout` for a slot
+fn set_upstream_limit(max: usize, queue_timeout: Duration) {
+    *UPSTREAM_LIMIT.write() = (max > 0).then(|| UpstreamLimit {
+        slots: Arc::new(Semaphore::new(max)),
+        queue_timeout,
+    });
+}
+
+/// Wait for an upstream slot: None when unlimited, Err with the time waited
+/// when none frees up within the queue timeout
+async fn acquire_upstream_slot() -> Result<Option<OwnedSemaphorePermit>, Duration> {
+    let Some(limit) = UPSTREAM_LIMIT.read().clone() else {
+        return Ok(None);
+    };
+    match tokio::time::timeout(limit.queue_timeout, limit.slots.acquire_owned()).await {
+        Ok(Ok(permit)) => Ok(Some(permit)),
+        // The semaphore is never closed, so this is the timeout
+        _ => Err(limit.queue_timeout),
+    }
+}
+
+fn json_error(status: StatusCode, message: &str) -> Response {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(serde_json::json!({ "error": message }).to_string()))
+        .unwrap()
+}
+
+/// Show a native OS notification.
+/// POST /__yao_desktop/notification  body: {"title": "…", "body": "…", "icon": "…"}  → {"shown": true}
+async fn handle_notification(req: Request) -> Response {
+    use tauri_plugin_notification::{NotificationExt, PermissionState};
+
+    if req.method() != http::Method::POST {
+        return Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .body(Body::empty())
+            .unwrap();
+    }
+    let app_handle = match config::get_app_handle() {
+        Some(h) => h,
+        None => return json_error(StatusCode::SERVICE_UNAVAILABLE, "app not ready"),
+    };
+    if !crate::app_conf::get_app_conf().notifications_enabled {
+        return json_error(StatusCode::FORBIDDEN, "notifications disabled");
+    }
+
+    let body = axum::body::to_bytes(req.into_body(), 64 * 1024)
+        .await
+        .unwrap_or_default();
+    let notification = match parse_notification(&body) {
+        Ok(n) => n,
+        Err(e) => return json_error(StatusCode::BAD_REQUEST, &e),
+    };
+
+    let notifier = app_handle.notification();
+    let permission = match notifier.permission_state() {
+        Ok(PermissionState::Prompt | PermissionState::PromptWithRationale) => notifier.request_permission(),
+        other => other,
+    };
+    if !matches!(permission, Ok(PermissionState::Granted)) {
+        warn!("Notification permission not granted: {:?}", permission);
+        return json_error(StatusCode::FORBIDDEN, "notification permission denied");
+    }
+
+    let mut builder = notifier.builder().title(&notification.title).body(&notification.body);
+    if let Some(icon) = &notification.icon {
+        builder = builder.icon(icon);
+    }
+    if let Err(e) = builder.show() {
+        warn!("Failed to show notification: {}", e);
+        return json_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("failed to show notification: {}", e));
+    }
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(Body::from(r#"{"shown":true}"#))
+        .unwrap()
+}
+
 /// Create or retrieve a tunnel for a remote port.
 /// POST /__yao_desktop/tunnel  body: {"port": 15123}  → {"local_port": 18001}
 async fn handle_tunnel_create(req: Request) -> Response {
@@ -427,7 +1471,8 @@ async fn handle_tunnel_create(req: Request) -> Response {
 }
 
 /// Reveal a downloaded file in the system file manager.
-/// Only allows paths inside the user's Downloads directory.
+/// Only allows paths inside the user's Downloads directory; refused with a
+/// 403 in kiosk mode.
 async fn handle_reveal_file(req: Request) -> Response {
     if req.method() != http::Method::POST {
         return Response::builder()
@@ -435,6 +1480,9 @@ async fn handle_reveal_file(req: Request) -> Response {
             .body(Body::empty())
             .unwrap();
     }
+    if let Err(e) = config::check_not_kiosk("Showing files in the file manager") {
+        return json_error(StatusCode::FORBIDDEN, &e);
+    }
 
     let body = axum::body::to_bytes(req.into_body(), 4096)
         .await
@@ -463,25 +1511,15 @@ async fn handle_reveal_file(req: Request) -> Response {
             .unwrap(),
     };
 
-    if let Ok(dl_dir) = app_handle.path().download_dir() {
-        if !target.starts_with(&dl_dir) {
-            return Response::builder()
-                .status(StatusCode::FORBIDDEN)
-                .header("Content-Type", "application/json")
-                .body(Body::from(r#"{"error":"path not in Downloads"}"#))
-                .unwrap();
-        }
+    if !crate::downloads::is_allowed_path(app_handle, target) {
+        return Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .header("Content-Type", "application/json")
+            .body(Body::from(r#"{"error":"path not in Downloads"}"#))
+            .unwrap();
     }
 
-    info!("Reveal file: {}", file_path);
-    #[cfg(target_os = "macos")]
-    { let _ = std::process::Command::new("open").arg("-R").arg(&file_path).spawn(); }
-    #[cfg(target_os = "windows")]
-    { let _ = std::process::Command::new("explorer")
-        .arg(format!("/select,\"{}\"", file_path)).spawn(); }
-    #[cfg(target_os = "linux")]
-    { let _ = std::process::Command::new("xdg-open")
-        .arg(target.parent().unwrap_or(std::path::Path::new("."))).spawn(); }
+    crate::downloads::reveal_file(target);
 
     Response::builder()
         .status(StatusCode::OK)
@@ -490,29 +1528,238 @@ async fn handle_reveal_file(req: Request) -> Response {
         .unwrap()
 }
 
-/// Toggle or query window fullscreen state.
-async fn handle_window_fullscreen(req: Request) -> Response {
+/// Open a completed download with the OS default application.
+/// POST /__yao_desktop/file/open  body: {"path": "/Users/me/Downloads/report.pdf"}
+/// Only absolute paths inside the download directory are accepted, and
+/// executables and scripts (or anything, in kiosk mode) are refused with a 403.
+async fn handle_open_file(req: Request) -> Response {
+    if req.method() != http::Method::POST {
+        return Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .body(Body::empty())
+            .unwrap();
+    }
+    let body = axum::body::to_bytes(req.into_body(), 4096)
+        .await
+        .unwrap_or_default();
+    let file_path = match serde_json::from_slice::<serde_json::Value>(&body)
+        .ok()
+        .and_then(|v| v.get("path")?.as_str().map(|s| s.to_string()))
+    {
+        Some(p) => p,
+        None => return json_error(StatusCode::BAD_REQUEST, "missing path"),
+    };
     let app_handle = match config::get_app_handle() {
         Some(h) => h,
-        None => return Response::builder()
-            .status(StatusCode::SERVICE_UNAVAILABLE)
+        None => return json_error(StatusCode::SERVICE_UNAVAILABLE, "app not ready"),
+    };
+
+    let target = std::path::Path::new(&file_path);
+    if !crate::downloads::is_allowed_path(app_handle, target) {
+        return json_error(StatusCode::FORBIDDEN, "path not in Downloads");
+    }
+    if !target.is_file() {
+        return json_error(StatusCode::NOT_FOUND, "file not found");
+    }
+    if let Err(e) = crate::downloads::check_openable(target) {
+        return json_error(StatusCode::FORBIDDEN, &e);
+    }
+    crate::downloads::open_file(target);
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(Body::from(r#"{"ok":true}"#))
+        .unwrap()
+}
+
+/// Set or query the webview zoom factor (clamped to 0.5–3.0, saved per window).
+/// GET → {"zoom": 1.0};  POST {"zoom": 1.25} → {"zoom": 1.25}
+async fn handle_window_zoom(req: Request) -> Response {
+    let app_handle = match config::get_app_handle() {
+        Some(h) => h,
+        None => return json_error(StatusCode::SERVICE_UNAVAILABLE, "app not ready"),
+    };
+    let win = match focused_or_main_window(app_handle) {
+        Some(w) => w,
+        None => return json_error(StatusCode::NOT_FOUND, "window not found"),
+    };
+
+    let zoom = if req.method() == http::Method::POST {
+        let body = axum::body::to_bytes(req.into_body(), 256)
+            .await
+            .unwrap_or_default();
+        let requested = serde_json::from_slice::<serde_json::Value>(&body)
+            .ok()
+            .and_then(|v| v["zoom"].as_f64());
+        let Some(zoom) = requested else {
+            return json_error(StatusCode::BAD_REQUEST, "zoom must be a number");
+        };
+        match crate::set_window_zoom(&win, zoom) {
+            Ok(zoom) => zoom,
+            Err(e) => return json_error(StatusCode::INTERNAL_SERVER_ERROR, &e),
+        }
+    } else {
+        config::get_window_zoom(win.label())
+    };
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(Body::from(format!(r#"{{"zoom":{}}}"#, zoom)))
+        .unwrap()
+}
+
+/// How long the page gets to run a search and report its matches
+const FIND_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Body of POST /__yao_desktop/window/find
+#[derive(Debug, Deserialize, PartialEq)]
+struct FindRequest {
+    /// Text to find; empty clears the search
+    #[serde(default)]
+    query: String,
+    /// Step to the next match (default) or the previous one
+    #[serde(default = "default_find_forward")]
+    forward: bool,
+    #[serde(default, alias = "matchCase")]
+    match_case: bool,
+}
+
+fn default_find_forward() -> bool {
+    true
+}
+
+/// Page expression running a search through the injected find script
+fn find_expr(find: &FindRequest) -> String {
+    format!(
+        r#"window.__yaoFind?window.__yaoFind.find({},{},{}):Promise.reject("find is not available on this page")"#,
+        serde_json::Value::from(find.query.as_str()),
+        find.forward,
+        find.match_case,
+    )
+}
+
+/// Find text in the focused (or main) window, highlighting matches and
+/// scrolling to the active one. Repeating a query steps through its matches.
+/// POST {"query": "agent", "forward": true, "match_case": false}
+///   → {"query": "agent", "active": 2, "total": 7} (active is 1-based, 0 = no match);
+/// an empty query clears the highlights. Only pages served by the proxy can search.
+async fn handle_window_find(req: Request) -> Response {
+    if req.method() != http::Method::POST {
+        return json_error(StatusCode::METHOD_NOT_ALLOWED, "method not allowed");
+    }
+    let Some(app_handle) = config::get_app_handle() else {
+        return json_error(StatusCode::SERVICE_UNAVAILABLE, "app not ready");
+    };
+    let Some(win) = focused_or_main_window(app_handle) else {
+        return json_error(StatusCode::NOT_FOUND, "window not found");
+    };
+    let body = axum::body::to_bytes(req.into_body(), 16 * 1024).await.unwrap_or_default();
+    let find = match serde_json::from_slice::<FindRequest>(&body) {
+        Ok(find) => find,
+        Err(e) => return json_error(StatusCode::BAD_REQUEST, &format!("invalid find request: {}", e)),
+    };
+    match crate::web_storage::eval_and_wait(&win, &find_expr(&find), FIND_TIMEOUT).await {
+        Ok(result) => Response::builder()
+            .status(StatusCode::OK)
             .header("Content-Type", "application/json")
-            .body(Body::from(r#"{"error":"app not ready"}"#))
+            .body(Body::from(result.to_string()))
             .unwrap(),
+        // The page has no find script, failed or didn't answer in time
+        Err(e) => json_error(StatusCode::SERVICE_UNAVAILABLE, &e),
+    }
+}
+
+/// Window state reported by the window control endpoints
+fn window_status(win: &tauri::WebviewWindow) -> serde_json::Value {
+    serde_json::json!({
+        "minimized": win.is_minimized().unwrap_or(false),
+        "maximized": win.is_maximized().unwrap_or(false),
+        "fullscreen": win.is_fullscreen().unwrap_or(false),
+        "focused": win.is_focused().unwrap_or(false),
+    })
+}
+
+/// Title bar controls for the focused (or main) window.
+/// GET /window/state → {"minimized", "maximized", "fullscreen", "focused"};
+/// POST /window/minimize, /window/maximize (toggle) → the same state;
+/// POST /window/close → {"closed": true}. Closing goes through the window's
+/// close handler, so the main window hides to tray or quits like the close button.
+/// In kiosk mode the main window refuses minimize and close with a 403.
+async fn handle_window_control(req: Request) -> Response {
+    let app_handle = match config::get_app_handle() {
+        Some(h) => h,
+        None => return json_error(StatusCode::SERVICE_UNAVAILABLE, "app not ready"),
+    };
+    let win = match focused_or_main_window(app_handle) {
+        Some(w) => w,
+        None => return json_error(StatusCode::NOT_FOUND, "window not found"),
     };
+    let action = req.uri().path().trim_start_matches("/__yao_desktop/window/");
+    let is_post = req.method() == http::Method::POST;
 
-    let win = {
-        let mut focused: Option<tauri::WebviewWindow> = None;
-        for w in app_handle.webview_windows().values() {
-            if w.is_focused().unwrap_or(false) {
-                focused = Some(w.clone());
-                break;
+    let result = match (action, is_post) {
+        ("state", false) => Ok(()),
+        ("minimize" | "close", true) if kiosk_locked(win.label()) => {
+            return json_error(StatusCode::FORBIDDEN, &format!("can't {} the window in kiosk mode", action));
+        }
+        ("minimize", true) => win.minimize(),
+        ("maximize", true) => {
+            if win.is_maximized().unwrap_or(false) {
+                win.unmaximize()
+            } else {
+                win.maximize()
             }
         }
-        focused.or_else(|| app_handle.get_webview_window("main"))
+        ("close", true) => {
+            info!("Window close: {}", win.label());
+            return match win.close() {
+                Ok(()) => Response::builder()
+                    .status(StatusCode::OK)
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(r#"{"closed":true}"#))
+                    .unwrap(),
+                Err(e) => json_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("failed to close window: {}", e)),
+            };
+        }
+        _ => return json_error(StatusCode::METHOD_NOT_ALLOWED, "method not allowed"),
+    };
+    if let Err(e) = result {
+        return json_error(StatusCode::INTERNAL_SERVER_ERROR, &format!("failed to {} window: {}", action, e));
+    }
+    if is_post {
+        info!("Window {}: {}", action, win.label());
+    }
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(Body::from(window_status(&win).to_string()))
+        .unwrap()
+}
+
+/// Whether kiosk mode pins this window: the main window can't leave
+/// fullscreen, minimize or close
+fn kiosk_locked(label: &str) -> bool {
+    config::kiosk() && label == "main"
+}
+
+/// Fullscreen state to apply when `requested`: a kiosk stays fullscreen
+pub(crate) fn fullscreen_target(label: &str, requested: bool) -> bool {
+    requested || kiosk_locked(label)
+}
+
+/// Toggle or query window fullscreen state. In kiosk mode exiting is
+/// refused and the reply carries the unchanged state.
+async fn handle_window_fullscreen(req: Request) -> Response {
+    let app_handle = match config::get_app_handle() {
+        Some(h) => h,
+        None => return Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .header("Content-Type", "application/json")
+            .body(Body::from(r#"{"error":"app not ready"}"#))
+            .unwrap(),
     };
 
-    let win = match win {
+    let win = match focused_or_main_window(app_handle) {
         Some(w) => w,
         None => return Response::builder()
             .status(StatusCode::NOT_FOUND)
@@ -531,6 +1778,7 @@ async fn handle_window_fullscreen(req: Request) -> Response {
             .ok()
             .and_then(|v| v["fullscreen"].as_bool())
             .unwrap_or(false);
+        let enable = fullscreen_target(win.label(), enable);
 
         info!("Window fullscreen: {} -> {}", win.label(), enable);
         let _ = win.set_fullscreen(enable);
@@ -551,45 +1799,46 @@ async fn handle_window_fullscreen(req: Request) -> Response {
 }
 
 /// Serve a tiny bridge page that writes preferences into localStorage
-/// on the proxy origin, then immediately redirects to CUI.
+/// on the proxy origin, then immediately redirects to CUI (or `next`).
 fn serve_bridge_page(req: &Request) -> Response {
-    let query = req.uri().query().unwrap_or("");
-    let mut locale = String::new();
-    let mut theme = String::new();
-    for pair in query.split('&') {
-        if let Some(v) = pair.strip_prefix("locale=") {
-            locale = v.to_string();
-        } else if let Some(v) = pair.strip_prefix("theme=") {
-            theme = v.to_string();
-        }
-    }
+    let prefs = parse_bridge_query(req.uri().query().unwrap_or(""))
+        .or_saved(&config::get_user_preferences());
+    let BridgePrefs { locale, theme, primary_color, font_size, next } = &prefs;
 
     let html = format!(
         r#"<!DOCTYPE html>
 <html><head><meta charset="utf-8"><title>Loading...</title>
 <script>
 try {{
+  var exp = "max-age=31536000;path=/;SameSite=Lax";
   if ("{locale}") {{
     localStorage.setItem("umi_locale", "{locale}");
   }}
-  if ("{theme}") {{
-    localStorage.setItem("__theme", "{theme}");
-    localStorage.setItem("xgen:xgen_theme", JSON.stringify({{type:"String",value:"{theme}"}}));
+  if ("{resolved}") {{
+    localStorage.setItem("__theme", "{resolved}");
+    localStorage.setItem("xgen:xgen_theme", JSON.stringify({{type:"String",value:"{resolved}"}}));
   }} else {{
     localStorage.removeItem("__theme");
     localStorage.removeItem("xgen:xgen_theme");
   }}
-  var exp = "max-age=31536000;path=/;SameSite=Lax";
+  if ("{primary_color}") {{
+    localStorage.setItem("xgen:xgen_primary_color", JSON.stringify({{type:"String",value:"{primary_color}"}}));
+    document.cookie = "__primary_color={primary_color};" + exp;
+  }}
+  if ("{font_size}") {{
+    localStorage.setItem("xgen:xgen_font_size", JSON.stringify({{type:"Number",value:Number("{font_size}")}}));
+    document.cookie = "__font_size={font_size};" + exp;
+  }}
   if ("{locale_cookie}") document.cookie = "__locale={locale_cookie};" + exp;
   if ("{theme}") document.cookie = "__theme={theme};" + exp;
   else document.cookie = "__theme=;max-age=0;path=/";
 }} catch(e) {{}}
-location.replace("/__yao_admin_root/auth/connect");
+location.replace("{next}");
 </script>
 </head><body></body></html>"#,
-        locale = locale,
-        theme = theme,
-        locale_cookie = if locale == "zh-CN" { "zh-cn" } else if locale == "en-US" { "en-us" } else { &locale },
+        locale_cookie = crate::locale::to_cookie(locale),
+        resolved = config::resolve_theme(theme, config::system_dark()),
+        next = if next.is_empty() { "/__yao_admin_root/auth/connect" } else { next },
     );
 
     Response::builder()
@@ -600,17 +1849,95 @@ location.replace("/__yao_admin_root/auth/connect");
         .unwrap()
 }
 
+/// Preferences carried by the bridge page query. Values are written into an
+/// inline script, so anything that isn't a known locale/theme, a hex color or
+/// a number is dropped (left empty).
+#[derive(Debug, Default, PartialEq)]
+struct BridgePrefs {
+    locale: String,
+    theme: String,
+    /// `#rgb` / `#rrggbb` (optionally with alpha)
+    primary_color: String,
+    /// Base font size in px, 8–32
+    font_size: String,
+    /// Same-origin path to continue to instead of CUI's connect page
+    next: String,
+}
+
+fn parse_bridge_query(query: &str) -> BridgePrefs {
+    let mut prefs = BridgePrefs::default();
+    for (key, value) in url::form_urlencoded::parse(query.as_bytes()) {
+        let value = value.trim();
+        match key.as_ref() {
+            "locale" => prefs.locale = locale::known(value).unwrap_or_default().to_string(),
+            "theme" => prefs.theme = config::known_theme(value).unwrap_or_default().to_string(),
+            "primaryColor" if is_hex_color(value) => prefs.primary_color = value.to_string(),
+            "next" => prefs.next = bridge_next(value).unwrap_or_default(),
+            "fontSize" => {
+                if let Some(size) = value.parse::<f64>().ok().filter(|s| (8.0..=32.0).contains(s)) {
+                    prefs.font_size = size.to_string();
+                }
+            }
+            _ => {}
+        }
+    }
+    prefs
+}
+
+/// `value` as a path on the proxy origin, or None if it could lead elsewhere
+/// (`//host`, `/\host`), back to the bridge, or out of the inline script
+fn bridge_next(value: &str) -> Option<String> {
+    if !value.starts_with('/') || value.starts_with("//") || value.contains('\\') {
+        return None;
+    }
+    let base = url::Url::parse("http://proxy.invalid").ok()?;
+    let url = base.join(value).ok()?;
+    if url.origin() != base.origin() || url.path() == "/__yao_bridge" {
+        return None;
+    }
+    let next = &url[url::Position::BeforePath..];
+    (!next.contains(['"', '<', '>', '\\'])).then(|| next.to_string())
+}
+
+impl BridgePrefs {
+    /// Fill in values the query left empty from the saved user preferences
+    /// (already validated when loaded)
+    fn or_saved(mut self, saved: &config::UserPreferences) -> Self {
+        if self.locale.is_empty() {
+            self.locale = saved.locale.clone();
+        }
+        if self.theme.is_empty() {
+            self.theme = saved.theme.clone();
+        }
+        if self.font_size.is_empty() {
+            if let Some(size) = saved.font_size {
+                self.font_size = size.to_string();
+            }
+        }
+        self
+    }
+}
+
+/// Request headers that shape a static file response
+#[derive(Debug, Default, Clone, Copy)]
+struct StaticRequest<'a> {
+    if_none_match: Option<&'a str>,
+    range: Option<&'a str>,
+    /// Client accepts gzip and `proxy.gzipStatic` is on
+    gzip: bool,
+}
+
 /// Serve CUI static files from the build output directory
-async fn serve_cui_static(path: &str, cui_dist: &PathBuf, if_none_match: Option<&str>) -> Response {
+async fn serve_cui_static(path: &str, cui_dist: &PathBuf, opts: StaticRequest<'_>) -> Response {
+    let StaticRequest { if_none_match, range, gzip } = opts;
     let relative = path.strip_prefix("/__yao_admin_root/").unwrap_or("");
     let relative = if relative.is_empty() { "index.html" } else { relative };
 
     let file_path = cui_dist.join(relative);
 
-    // Check if this looks like a static asset (has a file extension)
+    // Check if this looks like a static asset (has a file extension other than .html)
     let has_extension = relative.rsplit('/').next()
-        .map(|f| f.contains('.'))
-        .unwrap_or(false);
+        .is_some_and(|f| f.contains('.') && !f.to_ascii_lowercase().ends_with(".html"));
 
     // Path traversal protection + file lookup
     let canonical = match file_path.canonicalize() {
@@ -658,9 +1985,17 @@ async fn serve_cui_static(path: &str, cui_dist: &PathBuf, if_none_match: Option<
         index
     };
 
-    match tokio::fs::read(&file_path).await {
+    // Ranges are served straight from disk; HTML and CSS are rewritten, so
+    // they are always sent whole
+    let mime = guess_mime(&file_path);
+    if let Some(range) = range.filter(|_| !mime.starts_with("text/html") && !mime.starts_with("text/css")) {
+        if let Some(resp) = serve_static_range(&file_path, mime, range).await {
+            return resp;
+        }
+    }
+
+    match crate::static_cache::read(&file_path).await {
         Ok(contents) => {
-            let mime = guess_mime(&file_path);
             let is_html = mime.starts_with("text/html");
             let is_font = mime.starts_with("font/") || mime.contains("font");
             let mut builder = Response::builder()
@@ -668,6 +2003,10 @@ async fn serve_cui_static(path: &str, cui_dist: &PathBuf, if_none_match: Option<
                 .header("Content-Type", mime)
                 .header("Cache-Control", if is_html { "no-store" } else { "no-cache" });
 
+            if !is_html && !mime.starts_with("text/css") {
+                builder = builder.header(header::ACCEPT_RANGES, "bytes");
+            }
+
             // Font files: add explicit CORS headers for WebKitGTK compatibility.
             if is_font {
                 builder = builder
@@ -685,7 +2024,7 @@ async fn serve_cui_static(path: &str, cui_dist: &PathBuf, if_none_match: Option<
                     if modified.len() != css_str.len() {
                         info!("Stripped local() from CSS @font-face ({} bytes)", contents.len());
                     }
-                    return build_static_response(builder, modified.into_bytes(), if_none_match);
+                    return build_static_response(builder, modified.into_bytes(), if_none_match, gzip);
                 }
             }
 
@@ -709,20 +2048,23 @@ async fn serve_cui_static(path: &str, cui_dist: &PathBuf, if_none_match: Option<
                 }
                 drop(jar);
 
-                let umi_locale = match locale_value.as_str() {
-                    "zh-cn" => "zh-CN",
-                    "en-us" => "en-US",
-                    "ja-jp" => "ja-JP",
-                    _ if !locale_value.is_empty() => "en-US",
-                    _ => "",
+                // Cookie values go into inline JS: only known values pass
+                let umi_locale = locale::to_umi(&locale_value);
+                // Following the OS, the stored theme may be stale: always overwrite it
+                let follow = config::theme_follows_system();
+                let theme_value = if follow {
+                    config::resolve_theme("auto", config::system_dark())
+                } else {
+                    config::resolve_theme(&theme_value, config::system_dark())
                 };
                 // Inject scripts: localStorage sync, Fullscreen API bridge,
                 // and FontFace API loader (loads icon fonts via fetch+ArrayBuffer,
                 // bypassing CSS @font-face which may fail on WebKitGTK).
                 let inject_script = format!(
-                    r#"<script>try{{if("{umi}"&&!localStorage.getItem("umi_locale"))localStorage.setItem("umi_locale","{umi}");if("{theme}"&&!localStorage.getItem("__theme")){{localStorage.setItem("__theme","{theme}");localStorage.setItem("xgen:xgen_theme",JSON.stringify({{type:"String",value:"{theme}"}}))}}}}catch(e){{}}</script><script>(function(){{var _fs=false,_ep="/__yao_desktop/window/fullscreen";function _set(v){{return fetch(_ep,{{method:"POST",headers:{{"Content-Type":"application/json"}},body:JSON.stringify({{fullscreen:v}})}}).then(function(r){{return r.json()}}).then(function(d){{_fs=d.fullscreen;document.dispatchEvent(new Event("fullscreenchange"))}})}}Object.defineProperty(document,"fullscreenElement",{{configurable:true,get:function(){{return _fs?document.documentElement:null}}}});Object.defineProperty(document,"webkitFullscreenElement",{{configurable:true,get:function(){{return _fs?document.documentElement:null}}}});Element.prototype.requestFullscreen=function(){{return _set(true)}};document.exitFullscreen=function(){{return _set(false)}};Element.prototype.webkitRequestFullscreen=Element.prototype.requestFullscreen;document.webkitExitFullscreen=document.exitFullscreen}})();</script><script>(function(){{var F=[["md_icon_outline","/__yao_admin_root/icon/md_icon_outline.otf"],["md_icon_filled","/__yao_admin_root/icon/md_icon_filled.ttf"],["fa_icon","/__yao_admin_root/icon/fa_icon.woff"],["material_symbols_icon","/__yao_admin_root/icon/material_symbols.woff2"]];F.forEach(function(f){{fetch(f[1]).then(function(r){{return r.arrayBuffer()}}).then(function(b){{var ff=new FontFace(f[0],b);return ff.load()}}).then(function(ff){{document.fonts.add(ff)}}).catch(function(e){{console.warn("FontFace load failed:",f[0],e)}})}})}})()</script>"#,
+                    r#"<script>try{{if("{umi}"&&!localStorage.getItem("umi_locale"))localStorage.setItem("umi_locale","{umi}");if("{theme}"&&({follow}||!localStorage.getItem("__theme"))){{localStorage.setItem("__theme","{theme}");localStorage.setItem("xgen:xgen_theme",JSON.stringify({{type:"String",value:"{theme}"}}))}}}}catch(e){{}}</script><script>(function(){{var _fs=false,_ep="/__yao_desktop/window/fullscreen";function _set(v){{return fetch(_ep,{{method:"POST",headers:{{"Content-Type":"application/json"}},body:JSON.stringify({{fullscreen:v}})}}).then(function(r){{return r.json()}}).then(function(d){{_fs=d.fullscreen;document.dispatchEvent(new Event("fullscreenchange"))}})}}Object.defineProperty(document,"fullscreenElement",{{configurable:true,get:function(){{return _fs?document.documentElement:null}}}});Object.defineProperty(document,"webkitFullscreenElement",{{configurable:true,get:function(){{return _fs?document.documentElement:null}}}});Element.prototype.requestFullscreen=function(){{return _set(true)}};document.exitFullscreen=function(){{return _set(false)}};Element.prototype.webkitRequestFullscreen=Element.prototype.requestFullscreen;document.webkitExitFullscreen=document.exitFullscreen;fetch(_ep).then(function(r){{return r.json()}}).then(function(d){{if(d.fullscreen!==_fs){{_fs=d.fullscreen;document.dispatchEvent(new Event("fullscreenchange"))}}}}).catch(function(){{}})}})();</script><script>(function(){{var F=[["md_icon_outline","/__yao_admin_root/icon/md_icon_outline.otf"],["md_icon_filled","/__yao_admin_root/icon/md_icon_filled.ttf"],["fa_icon","/__yao_admin_root/icon/fa_icon.woff"],["material_symbols_icon","/__yao_admin_root/icon/material_symbols.woff2"]];F.forEach(function(f){{fetch(f[1]).then(function(r){{return r.arrayBuffer()}}).then(function(b){{var ff=new FontFace(f[0],b);return ff.load()}}).then(function(ff){{document.fonts.add(ff)}}).catch(function(e){{console.warn("FontFace load failed:",f[0],e)}})}})}})()</script>"#,
                     umi = umi_locale,
                     theme = theme_value,
+                    follow = follow,
                 );
 
                 // Tunnel iframe rewrite script: intercepts iframes pointing to
@@ -750,22 +2092,35 @@ async fn serve_cui_static(path: &str, cui_dist: &PathBuf, if_none_match: Option<
                     }
                 };
 
-                let full_inject = format!("{}{}", inject_script, tunnel_script);
-                let html = String::from_utf8_lossy(&contents);
-                let modified = if let Some(head_start) = html.find("<head") {
-                    if let Some(gt) = html[head_start..].find('>') {
-                        let insert_pos = head_start + gt + 1;
-                        format!("{}{}{}", &html[..insert_pos], full_inject, &html[insert_pos..])
-                    } else {
-                        format!("{}{}", html, full_inject)
-                    }
-                } else {
-                    format!("{}{}", full_inject, html)
+                // First-paint ping for startup timings (two frames after DOM ready)
+                let ready_script = r#"<script>document.addEventListener("DOMContentLoaded",function(){requestAnimationFrame(function(){requestAnimationFrame(function(){fetch("/__yao_desktop/ready",{method:"POST"}).catch(function(){})})})})</script>"#;
+
+                // CmdOrCtrl+= / - / 0 zoom in, out and reset via the zoom endpoint
+                let zoom_script = r#"<script>(function(){var ep="/__yao_desktop/window/zoom",z=1;function upd(p){p.then(function(r){return r.json()}).then(function(d){if(d.zoom)z=d.zoom}).catch(function(){})}function set(v){upd(fetch(ep,{method:"POST",headers:{"Content-Type":"application/json"},body:JSON.stringify({zoom:Math.round(v*100)/100})}))}upd(fetch(ep));document.addEventListener("keydown",function(e){if(!(e.metaKey||e.ctrlKey)||e.altKey)return;var k=e.key;if(k==="="||k==="+")set(z+0.1);else if(k==="-"||k==="_")set(z-0.1);else if(k==="0")set(1);else return;e.preventDefault()},true)})()</script>"#;
+
+                // CmdOrCtrl+F find bar; also backs /__yao_desktop/window/find
+                let find_script = concat!("<script>", include_str!("find_inject.js"), "</script>");
+
+                let full_inject = format!("{}{}{}{}{}", inject_script, ready_script, zoom_script, find_script, tunnel_script);
+                let modified = crate::static_cache::injected_html(&file_path, &full_inject, || {
+                    inject_into_html(&String::from_utf8_lossy(&contents), &full_inject)
+                });
+                // Cached HTML carries a placeholder; every response gets a fresh nonce
+                let modified = match std::str::from_utf8(&modified) {
+                    Ok(html) if html.contains(NONCE_PLACEHOLDER) => match csp_nonce() {
+                        Ok(nonce) => bytes::Bytes::from(html.replace(NONCE_PLACEHOLDER, &nonce)),
+                        Err(e) => {
+                            warn!("{}", e);
+                            return json_error(StatusCode::INTERNAL_SERVER_ERROR, &e);
+                        }
+                    },
+                    _ => modified,
                 };
                 return builder.body(Body::from(modified)).unwrap();
             }
 
-            build_static_response(builder, contents, if_none_match)
+            let gzip = gzip && is_compressible_mime(mime);
+            build_static_response(builder, contents, if_none_match, gzip)
         }
         Err(e) => {
             warn!("Failed to read file: {:?} -> {}", file_path, e);
@@ -777,29 +2132,142 @@ async fn serve_cui_static(path: &str, cui_dist: &PathBuf, if_none_match: Option<
     }
 }
 
-/// Placeholder page when CUI has not been built yet
-fn serve_cui_not_built() -> Response {
-    Response::builder()
-        .status(StatusCode::NOT_FOUND)
-        .header("Content-Type", "text/html; charset=utf-8")
-        .body(Body::from(
-            r#"<!DOCTYPE html>
-<html><head><meta charset="utf-8"></head>
-<body style="font-family:system-ui;display:flex;justify-content:center;align-items:center;height:100vh;margin:0;background:#f5f5f5">
-<div style="text-align:center">
-<h2>CUI Not Built</h2>
-<p>Please run <code>npm run build-cui</code> to build CUI static assets first.</p>
-</div>
-</body></html>"#
-        ))
-        .unwrap()
+/// Stands in for the CSP nonce in (cached) injected HTML
+const NONCE_PLACEHOLDER: &str = "__YAO_CSP_NONCE__";
+
+/// Insert the desktop scripts right after `<head>` so they run before any CUI
+/// script. If a CSP meta tag blocks inline scripts, the scripts get a nonce
+/// (left as `NONCE_PLACEHOLDER`) that is also added to the policy.
+fn inject_into_html(html: &str, inject: &str) -> String {
+    let mut html = html.to_string();
+    let mut inject = inject.to_string();
+    if let Some((start, end, policy)) = find_csp_meta(&html) {
+        if let Some(policy) = csp_with_nonce(&policy, NONCE_PLACEHOLDER) {
+            let meta = format!(
+                r#"<meta http-equiv="Content-Security-Policy" content="{}">"#,
+                policy.replace('"', "&quot;")
+            );
+            html.replace_range(start..end, &meta);
+            inject = inject.replace("<script>", &format!(r#"<script nonce="{}">"#, NONCE_PLACEHOLDER));
+        }
+    }
+    match find_head(&html) {
+        Some(h) => match html[h..].find('>') {
+            Some(gt) => format!("{}{}{}", &html[..h + gt + 1], inject, &html[h + gt + 1..]),
+            None => format!("{}{}", html, inject),
+        },
+        None => format!("{}{}", inject, html),
+    }
 }
 
-/// Remove all local() entries from CSS @font-face src declarations.
-/// Prevents fontconfig fuzzy matching on Linux from resolving to wrong fonts.
-/// e.g. "src: local('md_icon'), url(...)" becomes "src: url(...)"
-fn strip_css_local_refs(css: &str) -> String {
-    let mut result = css.to_string();
+/// Start of the `<head>` tag, any case (`<header>` doesn't count)
+fn find_head(html: &str) -> Option<usize> {
+    let lower = html.to_ascii_lowercase();
+    let mut from = 0;
+    while let Some(i) = lower[from..].find("<head") {
+        let start = from + i;
+        match lower.as_bytes().get(start + 5) {
+            Some(b'>') | Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') | None => return Some(start),
+            _ => from = start + 5,
+        }
+    }
+    None
+}
+
+/// Byte range and policy of the first `<meta http-equiv="Content-Security-Policy">`
+fn find_csp_meta(html: &str) -> Option<(usize, usize, String)> {
+    // ASCII lowercasing keeps byte offsets, so indices map back to `html`
+    let lower = html.to_ascii_lowercase();
+    let mut from = 0;
+    while let Some(i) = lower[from..].find("<meta") {
+        let start = from + i;
+        let end = start + lower[start..].find('>')? + 1;
+        let tag = &lower[start..end];
+        if tag.contains("content-security-policy") {
+            let attr = tag.find("content=")? + "content=".len();
+            let quote = tag[attr..].chars().next().filter(|c| *c == '"' || *c == '\'')?;
+            let value_start = start + attr + 1;
+            let value_end = value_start + html[value_start..end].find(quote)?;
+            let policy = html[value_start..value_end].replace("&quot;", "\"").replace("&#39;", "'");
+            return Some((start, end, policy));
+        }
+        from = end;
+    }
+    None
+}
+
+/// Add `'nonce-…'` to the policy's script sources. None when inline scripts
+/// are already allowed (no script restriction, or `'unsafe-inline'` without
+/// nonces/hashes, which adding a nonce would switch off).
+fn csp_with_nonce(policy: &str, nonce: &str) -> Option<String> {
+    let directives: Vec<&str> = policy.split(';').map(str::trim).filter(|d| !d.is_empty()).collect();
+    let find = |name: &str| directives.iter().position(|d| {
+        d.split_whitespace().next().is_some_and(|n| n.eq_ignore_ascii_case(name))
+    });
+    let source = format!("'nonce-{}'", nonce);
+    let allows_inline = |d: &str| {
+        let d = d.to_ascii_lowercase();
+        d.contains("'unsafe-inline'") && !d.contains("'nonce-") && !d.contains("'sha")
+    };
+
+    // `'none'` must stand alone, so it is dropped once the nonce is added
+    let with_nonce = |name: &str, directive: &str| {
+        let sources = directive.split_whitespace().skip(1).filter(|s| !s.eq_ignore_ascii_case("'none'"));
+        std::iter::once(name).chain(sources).chain([source.as_str()]).collect::<Vec<_>>().join(" ")
+    };
+
+    let mut out: Vec<String> = directives.iter().map(|d| d.to_string()).collect();
+    let mut changed = false;
+    for name in ["script-src", "script-src-elem"] {
+        if let Some(i) = find(name) {
+            if !allows_inline(&out[i]) {
+                out[i] = with_nonce(name, &out[i]);
+                changed = true;
+            }
+        }
+    }
+    if find("script-src").is_none() {
+        if let Some(i) = find("default-src") {
+            if !allows_inline(&out[i]) {
+                out.push(with_nonce("script-src", &out[i]));
+                changed = true;
+            }
+        }
+    }
+    changed.then(|| out.join("; "))
+}
+
+/// CSP nonce: 16 bytes from the OS random source, base64
+fn csp_nonce() -> Result<String, String> {
+    use base64::Engine;
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes).map_err(|e| format!("No secure random source for CSP nonce: {}", e))?;
+    Ok(base64::engine::general_purpose::STANDARD.encode(bytes))
+}
+
+/// Placeholder page when CUI has not been built yet
+fn serve_cui_not_built() -> Response {
+    Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .header("Content-Type", "text/html; charset=utf-8")
+        .body(Body::from(
+            r#"<!DOCTYPE html>
+<html><head><meta charset="utf-8"></head>
+<body style="font-family:system-ui;display:flex;justify-content:center;align-items:center;height:100vh;margin:0;background:#f5f5f5">
+<div style="text-align:center">
+<h2>CUI Not Built</h2>
+<p>Please run <code>npm run build-cui</code> to build CUI static assets first.</p>
+</div>
+</body></html>"#
+        ))
+        .unwrap()
+}
+
+/// Remove all local() entries from CSS @font-face src declarations.
+/// Prevents fontconfig fuzzy matching on Linux from resolving to wrong fonts.
+/// e.g. "src: local('md_icon'), url(...)" becomes "src: url(...)"
+fn strip_css_local_refs(css: &str) -> String {
+    let mut result = css.to_string();
     for (open, close) in &[("local('", "')"), ("local(\"", "\")")] {
         loop {
             let start = match result.find(open) {
@@ -867,16 +2335,122 @@ fn content_etag(data: &[u8]) -> String {
     format!("\"{:016x}\"", hash)
 }
 
+/// Weak comparison of an If-None-Match value (`*` or a comma-separated
+/// list, entries possibly `W/`-prefixed) against our ETag.
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match.split(',').map(str::trim).any(|tag| {
+        tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag
+    })
+}
+
+/// A `Range` header resolved against the file length
+#[derive(Debug, PartialEq)]
+enum ByteRange {
+    /// Inclusive start and end offsets
+    Partial(u64, u64),
+    /// No byte in the file matches (416)
+    Unsatisfiable,
+}
+
+/// Parse a single `bytes=start-end`, `bytes=start-` or `bytes=-suffix` range.
+/// None for malformed or multi-range headers, which are answered with the
+/// whole file.
+fn parse_range(header: &str, len: u64) -> Option<ByteRange> {
+    let spec = header.trim().strip_prefix("bytes=")?.trim();
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+    let range = if start.is_empty() {
+        let suffix: u64 = end.parse().ok()?;
+        if suffix == 0 || len == 0 {
+            return Some(ByteRange::Unsatisfiable);
+        }
+        (len.saturating_sub(suffix), len - 1)
+    } else {
+        let start: u64 = start.parse().ok()?;
+        let end = if end.is_empty() { u64::MAX } else { end.parse().ok()? };
+        if start > end {
+            return None;
+        }
+        if start >= len {
+            return Some(ByteRange::Unsatisfiable);
+        }
+        (start, end.min(len - 1))
+    };
+    Some(ByteRange::Partial(range.0, range.1))
+}
+
+/// Answer a Range request from disk (206 or 416), streaming the range so a
+/// large file is never held in memory. None if the header is unusable or the
+/// file can't be read, so the caller sends the whole file.
+async fn serve_static_range(path: &PathBuf, mime: &str, range: &str) -> Option<Response> {
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+    let len = tokio::fs::metadata(path).await.ok()?.len();
+    match parse_range(range, len)? {
+        ByteRange::Unsatisfiable => Some(Response::builder()
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header(header::CONTENT_RANGE, format!("bytes */{}", len))
+            .body(Body::empty())
+            .unwrap()),
+        ByteRange::Partial(start, end) => {
+            let mut file = tokio::fs::File::open(path).await.ok()?;
+            file.seek(std::io::SeekFrom::Start(start)).await.ok()?;
+            let length = end - start + 1;
+            let stream = tokio_util::io::ReaderStream::new(file.take(length));
+            Some(Response::builder()
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header("Content-Type", mime)
+                .header(header::ACCEPT_RANGES, "bytes")
+                .header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, len))
+                .header(header::CONTENT_LENGTH, length)
+                .header("Cache-Control", "no-cache")
+                .body(Body::from_stream(stream))
+                .unwrap())
+        }
+    }
+}
+
+/// Static assets smaller than this are sent uncompressed
+const GZIP_MIN_SIZE: usize = 1024;
+
+/// Whether the request's Accept-Encoding allows gzip
+fn accepts_gzip(headers: &http::HeaderMap) -> bool {
+    headers.get_all(header::ACCEPT_ENCODING).iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|enc| {
+            let mut parts = enc.split(';').map(str::trim);
+            let name = parts.next().unwrap_or("");
+            let refused = parts.any(|p| p.replace(' ', "") == "q=0");
+            name.eq_ignore_ascii_case("gzip") && !refused
+        })
+}
+
+/// Text-like types worth gzipping; images, fonts, wasm etc. are already compressed
+fn is_compressible_mime(mime: &str) -> bool {
+    mime.starts_with("text/")
+        || mime.starts_with("application/javascript")
+        || mime.starts_with("application/json")
+        || mime.starts_with("image/svg+xml")
+}
+
 /// Build a cacheable static response with ETag / 304 support.
 /// If `if_none_match` matches the computed ETag, returns 304 Not Modified.
+/// With `gzip`, bodies of at least `GZIP_MIN_SIZE` bytes are gzip-encoded and
+/// get a weak ETag, as the bytes on the wire differ from the file.
 fn build_static_response(
     builder: http::response::Builder,
-    data: Vec<u8>,
+    data: impl Into<bytes::Bytes>,
     if_none_match: Option<&str>,
+    gzip: bool,
 ) -> Response {
+    let data = data.into();
     let etag = content_etag(&data);
     if let Some(inm) = if_none_match {
-        if inm == etag || inm == "*" {
+        if etag_matches(inm, &etag) {
             return Response::builder()
                 .status(StatusCode::NOT_MODIFIED)
                 .header("ETag", &etag)
@@ -885,6 +2459,22 @@ fn build_static_response(
                 .unwrap();
         }
     }
+    if gzip && data.len() >= GZIP_MIN_SIZE {
+        use std::io::Write;
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        match encoder.write_all(&data).and_then(|_| encoder.finish()) {
+            Ok(compressed) => {
+                return builder
+                    .header("ETag", format!("W/{}", etag))
+                    .header(header::CONTENT_ENCODING, "gzip")
+                    .header(header::CONTENT_LENGTH, compressed.len())
+                    .header(header::VARY, "Accept-Encoding")
+                    .body(Body::from(compressed))
+                    .unwrap();
+            }
+            Err(e) => warn!("Failed to gzip static asset: {}", e),
+        }
+    }
     builder
         .header("ETag", &etag)
         .body(Body::from(data))
@@ -938,12 +2528,15 @@ async fn handle_ws_proxy(req: Request) -> Response {
     }
 
     let uri = req.uri().clone();
-    let path_and_query = uri.path_and_query()
+    let request_path_and_query = uri.path_and_query()
         .map(|pq| pq.as_str())
         .unwrap_or("/");
+    let app_conf = crate::app_conf::get_app_conf();
+    let rewritten = rewrite_path(request_path_and_query, &app_conf.path_rewrites, false);
+    let path_and_query = rewritten.as_deref().unwrap_or(request_path_and_query);
 
     // Build remote WebSocket URL (http->ws, https->wss)
-    let remote_base = state.server_url.trim_end_matches('/').to_string();
+    let remote_base = upstream_base(request_path_and_query, &app_conf.routes, &state.server_url).to_string();
     let remote_ws_url = if remote_base.starts_with("https://") {
         format!("wss://{}{}", &remote_base["https://".len()..], path_and_query)
     } else if remote_base.starts_with("http://") {
@@ -960,8 +2553,11 @@ async fn handle_ws_proxy(req: Request) -> Response {
         .and_then(|v| v.to_str().ok())
         .unwrap_or("")
         .to_string();
-    let merged_cookies = config::get_merged_cookies(&browser_cookie_header, path_and_query);
-    let token = state.token.clone();
+    let merged_cookies = config::get_merged_cookies_for(&config::cookie_host(&remote_base), &browser_cookie_header, path_and_query);
+    let token = match upstream_route(request_path_and_query, &app_conf.routes) {
+        Some(r) if !r.forward_auth => String::new(),
+        _ => state.token.clone(),
+    };
 
     // Build extra headers for VNC subprotocols
     let subprotocols: Vec<String> = req.headers()
@@ -1023,7 +2619,8 @@ async fn ws_bridge(
     }
 
     // Connect to the remote WebSocket server
-    let (remote_ws, resp) = connect_async_tls_with_config(request, None, false, None)
+    let connector = crate::tls::TlsOptions::current().ws_connector()?;
+    let (remote_ws, resp) = connect_async_tls_with_config(request, None, false, connector)
         .await
         .map_err(|e| format!("Failed to connect to remote WebSocket: {}", e))?;
 
@@ -1210,6 +2807,1490 @@ mod tests {
         assert!(!is_websocket_upgrade(&req));
     }
 
+    #[tokio::test]
+    async fn bind_with_fallback_skips_busy_port() {
+        let busy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let busy_port = busy.local_addr().unwrap().port();
+
+        let listener = bind_with_fallback(IpAddr::V4(Ipv4Addr::LOCALHOST), busy_port).await.unwrap();
+        let bound = listener.local_addr().unwrap().port();
+        assert_ne!(bound, busy_port);
+        assert!(bound > busy_port && bound <= busy_port + PORT_FALLBACK_RANGE);
+    }
+
+    #[test]
+    fn local_host_picks_the_loopback_to_bind_first() {
+        let (v4, v6) = (IpAddr::V4(Ipv4Addr::LOCALHOST), IpAddr::V6(Ipv6Addr::LOCALHOST));
+        assert_eq!(loopback_order(LocalHost::Ipv4), (v4, v6));
+        assert_eq!(loopback_order(LocalHost::Localhost), (v4, v6));
+        assert_eq!(loopback_order(LocalHost::Ipv6), (v6, v4));
+    }
+
+    #[tokio::test]
+    async fn proxy_requests_identity_from_compressing_upstream() {
+        use axum::Router;
+        use axum::response::IntoResponse;
+
+        // Upstream "compresses" whenever the client advertises gzip
+        let upstream = Router::new().fallback(|req: Request| async move {
+            let wants_gzip = req.headers()
+                .get(header::ACCEPT_ENCODING)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.contains("gzip"))
+                .unwrap_or(false);
+            if wants_gzip {
+                ([(header::CONTENT_ENCODING, "gzip")], "not really gzip").into_response()
+            } else {
+                "plain body".into_response()
+            }
+        });
+        let upstream_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream_listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(upstream_listener, upstream).await.unwrap();
+        });
+
+        let req = Request::builder()
+            .uri("/api/data")
+            .header(header::ACCEPT_ENCODING, "gzip, deflate, br")
+            .body(Body::empty())
+            .unwrap();
+
+        let resp = {
+            let _lock = config::lock_test_state().await;
+            config::update_proxy_state(&format!("http://{}", upstream_addr), "", "openapi", "");
+            let client = Client::builder().no_proxy().build().unwrap();
+            proxy_request(req, client).await
+        };
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers().get(header::CONTENT_ENCODING).is_none());
+        let body = axum::body::to_bytes(resp.into_body(), 1024).await.unwrap();
+        assert_eq!(&body[..], b"plain body");
+    }
+
+    #[tokio::test]
+    async fn conditional_requests_get_304_from_upstream() {
+        use axum::response::IntoResponse;
+
+        const MODIFIED: &str = "Wed, 21 Oct 2026 07:28:00 GMT";
+        // Upstream validates ETag / Last-Modified like a static file server
+        let upstream = axum::Router::new().fallback(|req: Request| async move {
+            let header = |name| req.headers().get(name).and_then(|v| v.to_str().ok()).unwrap_or("");
+            let fresh = header(header::IF_NONE_MATCH) == "\"v1\""
+                || header(header::IF_MODIFIED_SINCE) == MODIFIED;
+            let validators = [
+                (header::ETAG, "\"v1\""),
+                (header::LAST_MODIFIED, MODIFIED),
+                (header::CACHE_CONTROL, "max-age=60"),
+                (header::CONTENT_ENCODING, "gzip"),
+            ];
+            if fresh {
+                (StatusCode::NOT_MODIFIED, validators).into_response()
+            } else {
+                (validators, "asset").into_response()
+            }
+        });
+        let upstream_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream_listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(upstream_listener, upstream).await.unwrap();
+        });
+
+        let _lock = config::lock_test_state().await;
+        config::update_proxy_state(&format!("http://{}", upstream_addr), "", "openapi", "");
+        let client = Client::builder().no_proxy().build().unwrap();
+        let get = |name: HeaderName, value: &str| {
+            let req = Request::builder()
+                .uri("/assets/app.js")
+                .header(name, value)
+                .body(Body::empty())
+                .unwrap();
+            proxy_request(req, client.clone())
+        };
+
+        for resp in [
+            get(header::IF_NONE_MATCH, "\"v1\"").await,
+            get(header::IF_MODIFIED_SINCE, MODIFIED).await,
+        ] {
+            assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+            assert_eq!(resp.headers()[header::ETAG], "\"v1\"");
+            assert_eq!(resp.headers()[header::CACHE_CONTROL], "max-age=60");
+            assert!(resp.headers().get(header::TRANSFER_ENCODING).is_none());
+            assert!(resp.headers().get(header::CONNECTION).is_none());
+            let body = axum::body::to_bytes(resp.into_body(), 1024).await.unwrap();
+            assert!(body.is_empty());
+        }
+
+        let stale = get(header::IF_NONE_MATCH, "\"v0\"").await;
+        assert_eq!(stale.status(), StatusCode::OK);
+        config::update_proxy_state("", "", "openapi", "");
+    }
+
+    #[tokio::test]
+    async fn head_and_options_requests() {
+        use axum::response::IntoResponse;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // Upstream counts OPTIONS requests and answers them with `Allow`
+        let options_seen = Arc::new(AtomicUsize::new(0));
+        let seen = options_seen.clone();
+        let upstream = axum::Router::new().fallback(move |req: Request| {
+            let seen = seen.clone();
+            async move {
+                if req.method() == http::Method::OPTIONS {
+                    seen.fetch_add(1, Ordering::SeqCst);
+                    return ([(header::ALLOW, "GET, HEAD, POST")], "").into_response();
+                }
+                "x".repeat(5000).into_response()
+            }
+        });
+        let upstream_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream_listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(upstream_listener, upstream).await.unwrap();
+        });
+
+        let _lock = config::lock_test_state().await;
+        config::update_proxy_state(&format!("http://{}", upstream_addr), "", "openapi", "");
+        let client = Client::builder().no_proxy().build().unwrap();
+
+        // HEAD: upstream status and Content-Length, no body
+        let req = Request::builder().method(http::Method::HEAD).uri("/assets/big.js").body(Body::empty()).unwrap();
+        let resp = proxy_request(req, client.clone()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[header::CONTENT_LENGTH], "5000");
+        assert!(axum::body::to_bytes(resp.into_body(), 1024).await.unwrap().is_empty());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = format!("http://{}", listener.local_addr().unwrap());
+        let port = listener.local_addr().unwrap().port();
+        let app = router(client.clone(), std::env::temp_dir().join("no-cui-dist"), port);
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+        let origin = format!("http://localhost:{}", port);
+
+        // Preflight: answered by the CORS layer
+        let preflight = client.request(reqwest::Method::OPTIONS, format!("{}/api/items", proxy))
+            .header("Origin", &origin)
+            .header("Access-Control-Request-Method", "POST")
+            .header("Access-Control-Request-Headers", "authorization, content-type")
+            .send().await.unwrap();
+        assert!(preflight.status().is_success());
+        let h = preflight.headers();
+        assert_eq!(h["access-control-allow-origin"], origin.as_str());
+        assert_eq!(h["access-control-allow-credentials"], "true");
+        assert!(h["access-control-allow-methods"].to_str().unwrap().contains("POST"));
+        assert_eq!(h["access-control-allow-headers"], "authorization, content-type");
+        assert_eq!(options_seen.load(Ordering::SeqCst), 0);
+
+        // Plain OPTIONS: forwarded to the server
+        let options = client.request(reqwest::Method::OPTIONS, format!("{}/api/items", proxy))
+            .send().await.unwrap();
+        assert_eq!(options.headers()["allow"], "GET, HEAD, POST");
+        assert_eq!(options_seen.load(Ordering::SeqCst), 1);
+
+        config::update_proxy_state("", "", "openapi", "");
+    }
+
+    #[tokio::test]
+    async fn cors_only_reflects_proxy_origins() {
+        let serve = |permissive: bool| async move {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let app = axum::Router::new()
+                .fallback(|| async { "ok" })
+                .layer(cors_layer(port, permissive));
+            tokio::spawn(async move {
+                axum::serve(listener, app).await.unwrap();
+            });
+            port
+        };
+        let client = Client::builder().no_proxy().build().unwrap();
+        let allowed_origin = |port: u16, origin: String| {
+            let client = client.clone();
+            async move {
+                let preflight = client.request(reqwest::Method::OPTIONS, format!("http://127.0.0.1:{}/api", port))
+                    .header("Origin", &origin)
+                    .header("Access-Control-Request-Method", "POST")
+                    .send().await.unwrap();
+                let get = client.get(format!("http://127.0.0.1:{}/api", port))
+                    .header("Origin", &origin)
+                    .send().await.unwrap();
+                let allow = |r: &reqwest::Response| r.headers().get("access-control-allow-origin").cloned();
+                assert_eq!(allow(&preflight), allow(&get));
+                allow(&get)
+            }
+        };
+
+        let port = serve(false).await;
+        for origin in [format!("http://127.0.0.1:{}", port), format!("http://localhost:{}", port)] {
+            assert_eq!(allowed_origin(port, origin.clone()).await.unwrap(), origin.as_str());
+        }
+        assert_eq!(allowed_origin(port, "http://evil.example".into()).await, None);
+        assert_eq!(allowed_origin(port, "http://localhost:1".into()).await, None);
+
+        let port = serve(true).await;
+        assert_eq!(allowed_origin(port, "http://evil.example".into()).await.unwrap(), "http://evil.example");
+    }
+
+    #[tokio::test]
+    async fn uploads_stream_to_upstream_within_the_cap() {
+        // Upstream echoes the request body
+        let upstream = axum::Router::new().fallback(|body: bytes::Bytes| async move { body });
+        let upstream_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream_listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(upstream_listener, upstream).await.unwrap();
+        });
+
+        let chunked = |chunks: Vec<Vec<u8>>| {
+            let stream = futures_util::stream::iter(chunks.into_iter().map(Ok::<_, std::io::Error>));
+            Request::builder().method(http::Method::POST).uri("/api/upload").body(Body::from_stream(stream)).unwrap()
+        };
+        let _lock = config::lock_test_state().await;
+        crate::app_conf::set_app_conf(crate::app_conf::AppConf {
+            proxy: crate::app_conf::ProxyConf { max_body_mb: 1, ..Default::default() },
+            ..Default::default()
+        });
+        config::update_proxy_state(&format!("http://{}", upstream_addr), "", "openapi", "");
+        let client = Client::builder().no_proxy().build().unwrap();
+
+        // Unknown length, several chunks: the upstream gets every byte
+        let chunks: Vec<Vec<u8>> = (0..4u8).map(|i| vec![b'a' + i; 200 * 1024]).collect();
+        let resp = proxy_request(chunked(chunks.clone()), client.clone()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let echoed = axum::body::to_bytes(resp.into_body(), 2 * 1024 * 1024).await.unwrap();
+        assert_eq!(&echoed[..], &chunks.concat()[..]);
+
+        // Over the cap, whether known up front or found while streaming
+        let resp = proxy_request(chunked(vec![vec![0u8; 768 * 1024]; 2]), client.clone()).await;
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let sized = Request::builder()
+            .method(http::Method::POST)
+            .uri("/api/upload")
+            .body(Body::from(vec![0u8; 1024 * 1024 + 1]))
+            .unwrap();
+        assert_eq!(proxy_request(sized, client).await.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        crate::app_conf::set_app_conf(crate::app_conf::AppConf::default());
+        config::update_proxy_state("", "", "openapi", "");
+    }
+
+    #[tokio::test]
+    async fn multiple_set_cookies_stay_separate() {
+        use axum::response::{AppendHeaders, IntoResponse};
+
+        let upstream = axum::Router::new().fallback(|| async {
+            (
+                AppendHeaders([
+                    (header::SET_COOKIE, "a=1; Path=/"),
+                    (header::SET_COOKIE, "b=2; Path=/; HttpOnly"),
+                    (header::SET_COOKIE, "__Secure-session=s; Path=/; Secure"),
+                ]),
+                "ok",
+            ).into_response()
+        });
+        let upstream_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream_listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(upstream_listener, upstream).await.unwrap();
+        });
+
+        let _lock = config::lock_test_state().await;
+        config::COOKIE_JAR.write().clear();
+        config::update_proxy_state(&format!("http://{}", upstream_addr), "", "openapi", "");
+        let client = Client::builder().no_proxy().build().unwrap();
+        let req = Request::builder().uri("/api/login").body(Body::empty()).unwrap();
+        let resp = proxy_request(req, client).await;
+
+        let cookies: Vec<_> = resp.headers().get_all(header::SET_COOKIE).iter()
+            .map(|v| v.to_str().unwrap().to_string())
+            .collect();
+        assert_eq!(cookies, ["a=1; Path=/", "b=2; Path=/; HttpOnly"]);
+        let mut jar: Vec<_> = config::COOKIE_JAR.read().iter().map(|c| c.name.clone()).collect();
+        jar.sort();
+        assert_eq!(jar, ["__Secure-session", "a", "b"]);
+
+        config::COOKIE_JAR.write().clear();
+        config::update_proxy_state("", "", "openapi", "");
+    }
+
+    #[tokio::test]
+    async fn cookie_values_stay_out_of_the_log() {
+        use axum::response::{AppendHeaders, IntoResponse};
+
+        const SECRETS: [&str; 3] = ["jarSessionValue0123", "plainCookieValue4567", "browserOnlyValue8901"];
+        let upstream = axum::Router::new().fallback(|| async {
+            (
+                AppendHeaders([
+                    (header::SET_COOKIE, "__Secure-sid=jarSessionValue0123; Path=/; Secure; HttpOnly"),
+                    (header::SET_COOKIE, "plain=plainCookieValue4567; Path=/"),
+                ]),
+                "ok",
+            ).into_response()
+        });
+        let upstream_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream_listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(upstream_listener, upstream).await.unwrap();
+        });
+
+        let _lock = config::lock_test_state().await;
+        let _log = crate::logging::capture_for_test();
+        config::COOKIE_JAR.write().clear();
+        config::update_proxy_state(&format!("http://{}", upstream_addr), "", "openapi", "");
+        let client = Client::builder().no_proxy().build().unwrap();
+        for _ in 0..2 {
+            let req = Request::builder()
+                .uri("/api/data")
+                .header(header::COOKIE, "seen=browserOnlyValue8901")
+                .body(Body::empty())
+                .unwrap();
+            proxy_request(req, client.clone()).await;
+        }
+
+        let logged = crate::logging::recent(None);
+        for line in ["Sending cookies", "Secure cookie -> jar only", "Cookie -> jar + browser"] {
+            assert!(logged.iter().any(|e| e.message.starts_with(line)), "{} not logged", line);
+        }
+        for entry in &logged {
+            assert!(!SECRETS.iter().any(|s| entry.message.contains(s)), "{}", entry.message);
+        }
+
+        config::COOKIE_JAR.write().clear();
+        config::update_proxy_state("", "", "openapi", "");
+    }
+
+    #[tokio::test]
+    async fn stripped_headers_are_not_forwarded() {
+        use axum::{http::HeaderMap, response::IntoResponse};
+
+        let upstream = axum::Router::new().fallback(|headers: HeaderMap| async move {
+            let mut seen: Vec<_> = headers.keys().map(|k| k.as_str().to_string()).collect();
+            seen.sort();
+            (
+                [("x-tracking-id", "t1"), ("nel", "{}"), ("x-kept", "yes")],
+                format!("{} origin={}", seen.join(","), headers["origin"].to_str().unwrap()),
+            ).into_response()
+        });
+        let upstream_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream_listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(upstream_listener, upstream).await.unwrap();
+        });
+
+        let _lock = config::lock_test_state().await;
+        let mut proxy = crate::app_conf::ProxyConf::default();
+        proxy.strip_request_headers.extend(["X-WebView-Id".to_string(), "origin".to_string()]);
+        proxy.strip_response_headers.push("X-Tracking-ID".to_string());
+        crate::app_conf::set_app_conf(crate::app_conf::AppConf { proxy, ..Default::default() });
+        let server = format!("http://{}", upstream_addr);
+        config::update_proxy_state(&server, "", "openapi", "");
+        let client = Client::builder().no_proxy().build().unwrap();
+        let req = Request::builder()
+            .uri("/api/data")
+            .header("x-webview-id", "w1")
+            .header("sec-ch-ua", "\"WebView\";v=\"1\"")
+            .header("x-app", "cui")
+            .header("x-requested-with", "XMLHttpRequest")
+            .header("origin", "http://127.0.0.1:15099")
+            .body(Body::empty())
+            .unwrap();
+        let resp = proxy_request(req, client).await;
+
+        assert!(resp.headers().get("x-tracking-id").is_none());
+        assert!(resp.headers().get("nel").is_none());
+        assert_eq!(resp.headers()["x-kept"], "yes");
+        let body = axum::body::to_bytes(resp.into_body(), 1024).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        let (seen, origin) = body.split_once(" origin=").unwrap();
+        assert!(seen.split(',').any(|h| h == "x-app"), "{}", seen);
+        assert!(seen.split(',').any(|h| h == "x-requested-with"), "{}", seen);
+        assert!(!seen.split(',').any(|h| h == "x-webview-id" || h == "sec-ch-ua"), "{}", seen);
+        // Origin is rewritten for the server even when listed
+        assert_eq!(origin, server);
+
+        crate::app_conf::set_app_conf(crate::app_conf::AppConf::default());
+        config::update_proxy_state("", "", "openapi", "");
+    }
+
+    #[tokio::test]
+    async fn upstream_concurrency_is_capped() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // Upstream that records how many requests it is handling at once
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let (current, max) = (in_flight.clone(), peak.clone());
+        let upstream = axum::Router::new().fallback(move || {
+            let (current, max) = (current.clone(), max.clone());
+            async move {
+                let now = current.fetch_add(1, Ordering::SeqCst) + 1;
+                max.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                current.fetch_sub(1, Ordering::SeqCst);
+                "ok"
+            }
+        });
+        let upstream_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream_listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(upstream_listener, upstream).await.unwrap();
+        });
+
+        let get = |client: &Client| {
+            let req = Request::builder().uri("/api/slow").body(Body::empty()).unwrap();
+            proxy_request(req, client.clone())
+        };
+        let _lock = config::lock_test_state().await;
+        config::update_proxy_state(&format!("http://{}", upstream_addr), "", "openapi", "");
+        let client = Client::builder().no_proxy().build().unwrap();
+
+        set_upstream_limit(3, Duration::from_secs(10));
+        let responses = futures_util::future::join_all((0..10).map(|_| get(&client))).await;
+        assert!(responses.iter().all(|r| r.status() == StatusCode::OK));
+        assert_eq!(peak.load(Ordering::SeqCst), 3);
+
+        // With every slot taken, requests give up after the queue timeout
+        set_upstream_limit(1, Duration::from_millis(50));
+        let held = acquire_upstream_slot().await.unwrap();
+        assert_eq!(get(&client).await.status(), StatusCode::SERVICE_UNAVAILABLE);
+        drop(held);
+        assert_eq!(get(&client).await.status(), StatusCode::OK);
+
+        set_upstream_limit(0, Duration::ZERO);
+        config::update_proxy_state("", "", "openapi", "");
+    }
+
+    #[test]
+    fn decode_content_round_trips() {
+        use std::io::Write;
+
+        let plain = b"<html><head></head><body>hello</body></html>";
+
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(plain).unwrap();
+        let gzipped = gz.finish().unwrap();
+        assert_eq!(decode_content("gzip", &gzipped, 1024).unwrap(), plain);
+        // Bodies inflating past the limit are refused
+        let err = decode_content("gzip", &gzipped, 16).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::FileTooLarge);
+
+        let mut zlib = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        zlib.write_all(plain).unwrap();
+        assert_eq!(decode_content("deflate", &zlib.finish().unwrap(), 1024).unwrap(), plain);
+
+        let mut br = Vec::new();
+        {
+            let mut w = brotli::CompressorWriter::new(&mut br, 4096, 5, 22);
+            w.write_all(plain).unwrap();
+        }
+        assert_eq!(decode_content("br", &br, 1024).unwrap(), plain);
+
+        assert!(decode_content("zstd", plain, 1024).is_err());
+    }
+
+    #[tokio::test]
+    async fn proxy_decodes_gzipped_upstream_body() {
+        use axum::Router;
+        use axum::response::IntoResponse;
+        use std::io::Write;
+
+        let plain = "<html><head></head><body>compressed page</body></html>";
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(plain.as_bytes()).unwrap();
+        let gzipped = gz.finish().unwrap();
+        // Incompressible, so it stays above the buffer threshold when gzipped
+        let threshold = crate::app_conf::get_app_conf().proxy.buffer_threshold as usize;
+        let mut seed = 1u32;
+        let noise: Vec<u8> = (0..threshold * 2).map(|_| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (seed >> 24) as u8
+        }).collect();
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(&noise).unwrap();
+        let large = gz.finish().unwrap();
+        assert!(large.len() > threshold);
+        let expected_large = large.clone();
+
+        // Upstream ignores Accept-Encoding and always compresses
+        let upstream = Router::new().fallback(move |req: Request| {
+            let data = if req.uri().path() == "/large" { large.clone() } else { gzipped.clone() };
+            async move {
+                (
+                    [
+                        (header::CONTENT_ENCODING, "gzip"),
+                        (header::CONTENT_TYPE, "text/html; charset=utf-8"),
+                    ],
+                    data,
+                ).into_response()
+            }
+        });
+        let upstream_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream_listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(upstream_listener, upstream).await.unwrap();
+        });
+
+        let req = Request::builder()
+            .uri("/page")
+            .body(Body::empty())
+            .unwrap();
+
+        let _lock = config::lock_test_state().await;
+        config::update_proxy_state(&format!("http://{}", upstream_addr), "", "openapi", "");
+        let client = Client::builder().no_proxy().build().unwrap();
+        let resp = proxy_request(req, client.clone()).await;
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers().get(header::CONTENT_ENCODING).is_none());
+        assert_eq!(
+            resp.headers().get(header::CONTENT_LENGTH).unwrap().to_str().unwrap(),
+            plain.len().to_string()
+        );
+        let body = axum::body::to_bytes(resp.into_body(), 4096).await.unwrap();
+        assert_eq!(&body[..], plain.as_bytes());
+
+        // Above the buffer threshold: forwarded still encoded
+        let req = Request::builder().uri("/large").body(Body::empty()).unwrap();
+        let resp = proxy_request(req, client).await;
+        assert_eq!(resp.headers().get(header::CONTENT_ENCODING).unwrap(), "gzip");
+        let body = axum::body::to_bytes(resp.into_body(), threshold * 4).await.unwrap();
+        assert_eq!(&body[..], &expected_large[..]);
+    }
+
+    #[tokio::test]
+    async fn proxy_passes_through_partial_content() {
+        use axum::Router;
+        use axum::response::IntoResponse;
+
+        // Upstream serves bytes 0-3 of a 10-byte resource when asked for a range
+        let upstream = Router::new().fallback(|req: Request| async move {
+            match req.headers().get(header::RANGE).and_then(|v| v.to_str().ok()) {
+                Some("bytes=0-3") => (
+                    StatusCode::PARTIAL_CONTENT,
+                    [
+                        (header::CONTENT_RANGE, "bytes 0-3/10"),
+                        (header::ACCEPT_RANGES, "bytes"),
+                        (header::CONTENT_TYPE, "video/mp4"),
+                    ],
+                    "0123",
+                ).into_response(),
+                _ => "0123456789".into_response(),
+            }
+        });
+        let upstream_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream_listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(upstream_listener, upstream).await.unwrap();
+        });
+
+        let req = Request::builder()
+            .uri("/v1/file/media/abc/content")
+            .header(header::RANGE, "bytes=0-3")
+            .body(Body::empty())
+            .unwrap();
+
+        let resp = {
+            let _lock = config::lock_test_state().await;
+            config::update_proxy_state(&format!("http://{}", upstream_addr), "", "openapi", "");
+            let client = Client::builder().no_proxy().build().unwrap();
+            proxy_request(req, client).await
+        };
+
+        assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(resp.headers().get(header::CONTENT_RANGE).unwrap(), "bytes 0-3/10");
+        assert_eq!(resp.headers().get(header::ACCEPT_RANGES).unwrap(), "bytes");
+        let body = axum::body::to_bytes(resp.into_body(), 1024).await.unwrap();
+        assert_eq!(&body[..], b"0123");
+    }
+
+    #[test]
+    fn upstream_base_picks_longest_route() {
+        let routes = vec![
+            UpstreamRoute { prefix: "/ai".into(), target: "https://ai.example.com/".into(), forward_auth: false },
+            UpstreamRoute { prefix: "/ai/chat".into(), target: "https://chat.example.com".into(), forward_auth: false },
+        ];
+        let main = "https://yao.example.com/";
+        assert_eq!(upstream_base("/ai/foo?x=1", &routes, main), "https://ai.example.com");
+        assert_eq!(upstream_base("/ai/chat/1", &routes, main), "https://chat.example.com");
+        assert_eq!(upstream_base("/aix", &routes, main), "https://yao.example.com");
+        assert_eq!(upstream_base("/v1/user", &routes, main), "https://yao.example.com");
+    }
+
+    /// Upstream that answers `{name} {path}`, redirecting `/ai/login` to an
+    /// absolute URL on itself. `…/echo` answers with the Authorization and
+    /// Cookie it got and sets a `{name}_sid` cookie.
+    async fn spawn_named_upstream(name: &'static str) -> String {
+        use axum::response::IntoResponse;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let own = base.clone();
+        let app = axum::Router::new().fallback(move |req: Request| {
+            let own = own.clone();
+            async move {
+                if req.uri().path() == "/ai/login" {
+                    return (StatusCode::FOUND, [(header::LOCATION, format!("{}/ai/done", own))]).into_response();
+                }
+                if req.uri().path().ends_with("/echo") {
+                    let get = |h: header::HeaderName| {
+                        req.headers().get(h).and_then(|v| v.to_str().ok()).unwrap_or("").to_string()
+                    };
+                    let body = format!("{} auth={} cookie={}", name, get(header::AUTHORIZATION), get(header::COOKIE));
+                    return ([(header::SET_COOKIE, format!("{}_sid=1; Path=/", name))], body).into_response();
+                }
+                format!("{} {}", name, req.uri().path()).into_response()
+            }
+        });
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+        base
+    }
+
+    #[tokio::test]
+    async fn routes_send_paths_to_their_backend() {
+        let main = spawn_named_upstream("main").await;
+        let ai = spawn_named_upstream("ai").await;
+
+        let _lock = config::lock_test_state().await;
+        crate::app_conf::set_app_conf(crate::app_conf::AppConf {
+            routes: vec![UpstreamRoute { prefix: "/ai".into(), target: ai.clone(), forward_auth: false }],
+            ..Default::default()
+        });
+        config::update_proxy_state(&main, "", "openapi", "");
+        let client = Client::builder().no_proxy().build().unwrap();
+        let get = |path: &str| {
+            let req = Request::builder().uri(path).body(Body::empty()).unwrap();
+            proxy_request(req, client.clone())
+        };
+        let text = |resp: Response| async move {
+            String::from_utf8(axum::body::to_bytes(resp.into_body(), 1024).await.unwrap().to_vec()).unwrap()
+        };
+
+        let body = axum::body::to_bytes(get("/ai/foo").await.into_body(), 1024).await.unwrap();
+        assert_eq!(&body[..], b"ai /ai/foo");
+        let body = axum::body::to_bytes(get("/v1/user").await.into_body(), 1024).await.unwrap();
+        assert_eq!(&body[..], b"main /v1/user");
+
+        // Redirects to the route's backend come back through the proxy
+        let resp = get("/ai/login").await;
+        let location = resp.headers().get(header::LOCATION).unwrap().to_str().unwrap();
+        assert!(location.starts_with("http://127.0.0.1:") && location.ends_with("/ai/done"), "{}", location);
+        assert!(!location.starts_with(&ai), "{}", location);
+
+        // Cookies stay with the backend that set them (ports don't scope
+        // cookies, so the route's backend is reached as `localhost`), and
+        // the token only reaches the route once it opts in
+        let ai_host = ai.replace("127.0.0.1", "localhost");
+        let set_route = |forward_auth: bool| {
+            crate::app_conf::set_app_conf(crate::app_conf::AppConf {
+                routes: vec![UpstreamRoute { prefix: "/ai".into(), target: ai_host.clone(), forward_auth }],
+                ..Default::default()
+            });
+        };
+        set_route(false);
+        config::clear_cookies();
+        config::update_proxy_state(&main, "tok", "openapi", "");
+        assert_eq!(text(get("/echo").await).await, "main auth=Bearer tok cookie=");
+        assert_eq!(text(get("/ai/echo").await).await, "ai auth= cookie=");
+        assert_eq!(text(get("/echo").await).await, "main auth=Bearer tok cookie=main_sid=1");
+        set_route(true);
+        assert_eq!(text(get("/ai/echo").await).await, "ai auth=Bearer tok cookie=ai_sid=1");
+
+        config::clear_cookies();
+        crate::app_conf::set_app_conf(crate::app_conf::AppConf::default());
+        config::update_proxy_state("", "", "openapi", "");
+    }
+
+    #[tokio::test]
+    async fn redirects_use_the_configured_local_host() {
+        let main = spawn_named_upstream("main").await;
+
+        let _lock = config::lock_test_state().await;
+        config::update_proxy_state(&main, "", "openapi", "");
+        config::PROXY_STATE.write().port = 15099;
+        let client = Client::builder().no_proxy().build().unwrap();
+        for (local_host, expected) in [
+            (LocalHost::Ipv4, "http://127.0.0.1:15099/ai/done"),
+            (LocalHost::Localhost, "http://localhost:15099/ai/done"),
+            (LocalHost::Ipv6, "http://[::1]:15099/ai/done"),
+        ] {
+            crate::app_conf::set_app_conf(crate::app_conf::AppConf {
+                proxy: crate::app_conf::ProxyConf { local_host, ..Default::default() },
+                ..Default::default()
+            });
+            let req = Request::builder().uri("/ai/login").body(Body::empty()).unwrap();
+            let resp = proxy_request(req, client.clone()).await;
+            assert_eq!(resp.headers()[header::LOCATION], expected);
+        }
+
+        crate::app_conf::set_app_conf(crate::app_conf::AppConf::default());
+        config::PROXY_STATE.write().port = 0;
+        config::update_proxy_state("", "", "openapi", "");
+    }
+
+    fn rules(pairs: &[(&str, &str)]) -> Vec<PathRewrite> {
+        pairs.iter()
+            .map(|(f, t)| PathRewrite { from_prefix: f.to_string(), to_prefix: t.to_string() })
+            .collect()
+    }
+
+    #[test]
+    fn rewrite_path_prefix_matching() {
+        let r = rules(&[("/old-api", "/v1")]);
+        assert_eq!(rewrite_path("/old-api/users?page=2", &r, false).as_deref(), Some("/v1/users?page=2"));
+        assert_eq!(rewrite_path("/old-api", &r, false).as_deref(), Some("/v1"));
+        assert_eq!(rewrite_path("/old-api?x=1", &r, false).as_deref(), Some("/v1?x=1"));
+        // Segment boundary: no partial-segment match
+        assert_eq!(rewrite_path("/old-apix/users", &r, false), None);
+
+        let r = rules(&[("/legacy/", "/v1/")]);
+        assert_eq!(rewrite_path("/legacy/a", &r, false).as_deref(), Some("/v1/a"));
+        let r = rules(&[("/legacy/", "/v1")]);
+        assert_eq!(rewrite_path("/legacy/a", &r, false).as_deref(), Some("/v1/a"));
+    }
+
+    #[test]
+    fn rewrite_path_longest_match_wins() {
+        let r = rules(&[("/api", "/v1"), ("/api/files", "/v2/storage"), ("/api/f", "/nope")]);
+        assert_eq!(rewrite_path("/api/files/1", &r, false).as_deref(), Some("/v2/storage/1"));
+        assert_eq!(rewrite_path("/api/users", &r, false).as_deref(), Some("/v1/users"));
+    }
+
+    #[test]
+    fn rewrite_path_no_match_passes_through() {
+        let r = rules(&[("/old-api", "/v1"), ("bad", "/x"), ("/same", "/same")]);
+        assert_eq!(rewrite_path("/v1/users", &r, false), None);
+        assert_eq!(rewrite_path("/same/x", &r, false), None);
+        assert_eq!(rewrite_path("/", &[], false), None);
+    }
+
+    #[test]
+    fn rewrite_path_applies_once_and_reverses() {
+        // A cyclic table is applied a single time, never chained
+        let r = rules(&[("/a", "/b"), ("/b", "/a")]);
+        assert_eq!(rewrite_path("/a/x", &r, false).as_deref(), Some("/b/x"));
+        let r = rules(&[("/old-api", "/v1")]);
+        assert_eq!(rewrite_path("/v1/login?next=1", &r, true).as_deref(), Some("/old-api/login?next=1"));
+        assert_eq!(rewrite_path("/old-api/login", &r, true), None);
+    }
+
+    #[test]
+    fn parse_notification_requires_title() {
+        let n = parse_notification(br#"{"title":"Task done","body":"Report ready","icon":"/favicon.png"}"#).unwrap();
+        assert_eq!(n.title, "Task done");
+        assert_eq!(n.icon.as_deref(), Some("/favicon.png"));
+        let n = parse_notification(br#"{"title":"Done"}"#).unwrap();
+        assert_eq!((n.body.as_str(), n.icon), ("", None));
+
+        assert!(parse_notification(br#"{"title":"  "}"#).is_err());
+        assert!(parse_notification(br#"{"body":"x"}"#).is_err());
+        assert!(parse_notification(b"nope").is_err());
+    }
+
+    #[test]
+    fn find_requests_default_forward_and_escape_the_query() {
+        let find: FindRequest = serde_json::from_slice(br#"{"query":"agent"}"#).unwrap();
+        assert_eq!(find, FindRequest { query: "agent".into(), forward: true, match_case: false });
+        let find: FindRequest = serde_json::from_slice(br#"{"query":"a","forward":false,"matchCase":true}"#).unwrap();
+        assert!(!find.forward && find.match_case);
+        assert_eq!(serde_json::from_slice::<FindRequest>(b"{}").unwrap().query, "");
+
+        let js = find_expr(&FindRequest { query: "\");alert(1);//\n".into(), forward: false, match_case: true });
+        assert!(js.starts_with(r#"window.__yaoFind?window.__yaoFind.find("\");alert(1);//\n",false,true)"#), "{}", js);
+    }
+
+    #[test]
+    fn should_buffer_response_by_size_and_type() {
+        assert!(should_buffer_response(Some(10), "application/octet-stream", 1024));
+        assert!(should_buffer_response(Some(1024), "video/mp4", 1024));
+        assert!(!should_buffer_response(Some(1025), "text/html", 1024));
+        assert!(should_buffer_response(None, "text/html; charset=utf-8", 1024));
+        assert!(!should_buffer_response(None, "application/x-ndjson", 1024));
+        assert!(!should_buffer_response(None, "", 1024));
+    }
+
+    #[tokio::test]
+    async fn proxy_buffers_small_and_streams_large_responses() {
+        use axum::Router;
+        use axum::body::HttpBody;
+        use axum::response::IntoResponse;
+
+        let threshold = crate::app_conf::get_app_conf().proxy.buffer_threshold as usize;
+        let large = vec![0x42u8; threshold * 2];
+        let upstream = Router::new().fallback(move |req: Request| {
+            let large = large.clone();
+            async move {
+                if req.uri().path() == "/large" {
+                    ([(header::CONTENT_TYPE, "application/octet-stream")], large).into_response()
+                } else {
+                    ([(header::CONTENT_TYPE, "application/json")], "{\"ok\":true}").into_response()
+                }
+            }
+        });
+        let upstream_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream_listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(upstream_listener, upstream).await.unwrap();
+        });
+
+        let _lock = config::lock_test_state().await;
+        config::update_proxy_state(&format!("http://{}", upstream_addr), "", "openapi", "");
+        let client = Client::builder().no_proxy().build().unwrap();
+
+        // Small: buffered, so the body knows its exact size up front
+        let req = Request::builder().uri("/small").body(Body::empty()).unwrap();
+        let resp = proxy_request(req, client.clone()).await;
+        assert_eq!(resp.body().size_hint().exact(), Some(11));
+        assert_eq!(resp.headers().get(header::CONTENT_LENGTH).unwrap(), "11");
+
+        // Large: streamed, the body size is not known to the response body
+        let req = Request::builder().uri("/large").body(Body::empty()).unwrap();
+        let resp = proxy_request(req, client).await;
+        assert_eq!(resp.body().size_hint().exact(), None);
+        let body = axum::body::to_bytes(resp.into_body(), threshold * 4).await.unwrap();
+        assert_eq!(body.len(), threshold * 2);
+    }
+
+    #[tokio::test]
+    async fn send_with_retry_retries_connect_failures() {
+        // Reserve a port, then start listening on it only after the first attempt failed
+        let port = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            let listener = TcpListener::bind(("127.0.0.1", port)).await.unwrap();
+            axum::serve(listener, axum::Router::new().fallback(|| async { "up" })).await.unwrap();
+        });
+        let client = Client::builder().no_proxy().build().unwrap();
+        let url = format!("http://127.0.0.1:{}/", port);
+
+        let resp = send_with_retry(client.get(&url), None, 3).await.unwrap();
+        assert_eq!(resp.text().await.unwrap(), "up");
+
+        // Nothing listening and no retries: fails straight away
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+        assert!(send_with_retry(client.get(format!("http://127.0.0.1:{}/", closed)), None, 0).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn send_with_retry_times_out_waiting_for_headers() {
+        let app = axum::Router::new().fallback(|| async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            "late"
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let client = Client::builder().no_proxy().build().unwrap();
+        let err = send_with_retry(client.get(format!("http://{}/", addr)), Some(Duration::from_millis(100)), 2)
+            .await
+            .unwrap_err();
+        assert!(err.contains("no response within"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn proxy_returns_json_504_when_upstream_unreachable() {
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+        let _lock = config::lock_test_state().await;
+        config::update_proxy_state(&format!("http://127.0.0.1:{}", closed), "", "openapi", "");
+        let client = Client::builder().no_proxy().build().unwrap();
+
+        let req = Request::builder().method("POST").uri("/v1/chat").body(Body::from("{}")).unwrap();
+        let resp = proxy_request(req, client).await;
+        assert_eq!(resp.status(), StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(resp.headers().get(header::CONTENT_TYPE).unwrap(), "application/json");
+        let body = axum::body::to_bytes(resp.into_body(), 1024).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(json["error"].as_str().unwrap().starts_with("upstream request failed"));
+    }
+
+    #[test]
+    fn static_response_revalidates_with_returned_etag() {
+        let data = b"console.log(1)".to_vec();
+        let builder = || Response::builder().header("Content-Type", "application/javascript");
+        let first = build_static_response(builder(), data.clone(), None, false);
+        assert_eq!(first.status(), StatusCode::OK);
+        let etag = first.headers()["ETag"].to_str().unwrap().to_string();
+
+        let second = build_static_response(builder(), data.clone(), Some(&etag), false);
+        assert_eq!(second.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(second.headers()["ETag"], etag.as_str());
+
+        let listed = format!(r#""0000000000000000", W/{}"#, etag);
+        assert_eq!(build_static_response(builder(), data.clone(), Some(&listed), false).status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(build_static_response(builder(), data, Some(r#""stale""#), false).status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn accepts_gzip_and_compressible_types() {
+        let headers = |v: &str| {
+            let mut h = http::HeaderMap::new();
+            h.insert(header::ACCEPT_ENCODING, HeaderValue::from_str(v).unwrap());
+            h
+        };
+        assert!(accepts_gzip(&headers("gzip, deflate, br")));
+        assert!(accepts_gzip(&headers("br;q=1.0, GZIP;q=0.5")));
+        assert!(!accepts_gzip(&headers("gzip;q=0, br")));
+        assert!(!accepts_gzip(&headers("identity")));
+        assert!(!accepts_gzip(&http::HeaderMap::new()));
+
+        assert!(is_compressible_mime("application/javascript; charset=utf-8"));
+        assert!(is_compressible_mime("image/svg+xml"));
+        assert!(!is_compressible_mime("image/png"));
+        assert!(!is_compressible_mime("font/woff2"));
+        assert!(!is_compressible_mime("application/wasm"));
+    }
+
+    #[tokio::test]
+    async fn serve_cui_static_gzips_js_when_requested() {
+        use std::io::Read;
+
+        let dist = std::env::temp_dir().join(format!("cui-gzip-{}", std::process::id()));
+        std::fs::create_dir_all(&dist).unwrap();
+        let js = "console.log('yao');\n".repeat(200);
+        std::fs::write(dist.join("app.js"), &js).unwrap();
+        let gzip = StaticRequest { gzip: true, ..Default::default() };
+
+        let resp = serve_cui_static("/__yao_admin_root/app.js", &dist, gzip).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[header::CONTENT_ENCODING], "gzip");
+        let etag = resp.headers()["ETag"].to_str().unwrap().to_string();
+        assert!(etag.starts_with("W/"));
+        let len: usize = resp.headers()[header::CONTENT_LENGTH].to_str().unwrap().parse().unwrap();
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(len, body.len());
+        assert!(body.len() < js.len());
+        let mut plain = String::new();
+        flate2::read::GzDecoder::new(&body[..]).read_to_string(&mut plain).unwrap();
+        assert_eq!(plain, js);
+
+        // The weak tag revalidates; without gzip the body is sent as-is
+        let revalidated = serve_cui_static("/__yao_admin_root/app.js", &dist, StaticRequest { if_none_match: Some(&etag), ..gzip }).await;
+        assert_eq!(revalidated.status(), StatusCode::NOT_MODIFIED);
+        let plain_resp = serve_cui_static("/__yao_admin_root/app.js", &dist, StaticRequest::default()).await;
+        assert!(plain_resp.headers().get(header::CONTENT_ENCODING).is_none());
+        let _ = std::fs::remove_dir_all(&dist);
+    }
+
+    #[test]
+    fn csp_meta_gets_nonce_for_injected_scripts() {
+        let html = r#"<!DOCTYPE html><html><HEAD><meta charset="utf-8"><meta http-equiv="Content-Security-Policy" content="default-src 'self'; script-src 'self' https://cdn.example.com"><script src="/app.js"></script></head></html>"#;
+        let out = inject_into_html(html, "<script>a()</script><script>b()</script>");
+        assert_eq!(
+            out,
+            r#"<!DOCTYPE html><html><HEAD><script nonce="__YAO_CSP_NONCE__">a()</script><script nonce="__YAO_CSP_NONCE__">b()</script><meta charset="utf-8"><meta http-equiv="Content-Security-Policy" content="default-src 'self'; script-src 'self' https://cdn.example.com 'nonce-__YAO_CSP_NONCE__'"><script src="/app.js"></script></head></html>"#
+        );
+
+        // No CSP: scripts are injected as-is
+        assert_eq!(inject_into_html("<head></head>", "<script>a()</script>"), "<head><script>a()</script></head>");
+        assert_eq!(inject_into_html("<body><header>x</header>", "<s>"), "<s><body><header>x</header>");
+        let nonce = csp_nonce().unwrap();
+        assert_eq!(nonce.len(), 24);
+        assert_ne!(nonce, csp_nonce().unwrap());
+    }
+
+    #[test]
+    fn csp_with_nonce_only_when_inline_is_blocked() {
+        assert_eq!(
+            csp_with_nonce("default-src 'none'; img-src *", "n").as_deref(),
+            Some("default-src 'none'; img-src *; script-src 'nonce-n'")
+        );
+        assert_eq!(
+            csp_with_nonce("script-src 'self'; script-src-elem 'self'", "n").as_deref(),
+            Some("script-src 'self' 'nonce-n'; script-src-elem 'self' 'nonce-n'")
+        );
+        // Inline already allowed: adding a nonce would disable 'unsafe-inline'
+        assert_eq!(csp_with_nonce("script-src 'self' 'unsafe-inline'", "n"), None);
+        assert_eq!(csp_with_nonce("img-src 'self'", "n"), None);
+        assert_eq!(
+            csp_with_nonce("script-src 'unsafe-inline' 'sha256-abc'", "n").as_deref(),
+            Some("script-src 'unsafe-inline' 'sha256-abc' 'nonce-n'")
+        );
+    }
+
+    #[test]
+    fn bridge_query_accepts_valid_preferences() {
+        let prefs = parse_bridge_query("locale=zh-CN&theme=dark&primaryColor=%233373fc&fontSize=15.5");
+        assert_eq!(prefs, BridgePrefs {
+            locale: "zh-CN".into(),
+            theme: "dark".into(),
+            primary_color: "#3373fc".into(),
+            font_size: "15.5".into(),
+            next: String::new(),
+        });
+        assert_eq!(parse_bridge_query("fontSize=14").font_size, "14");
+        assert_eq!(parse_bridge_query(""), BridgePrefs::default());
+
+        let saved = config::UserPreferences {
+            locale: "zh-CN".into(),
+            theme: "dark".into(),
+            zoom: None,
+            font_size: Some(16.0),
+            remember_session: None,
+        };
+        let prefs = parse_bridge_query("locale=en-US").or_saved(&saved);
+        assert_eq!((prefs.locale.as_str(), prefs.theme.as_str(), prefs.font_size.as_str()), ("en-US", "dark", "16"));
+    }
+
+    #[tokio::test]
+    async fn bridge_continues_to_same_origin_paths_only() {
+        assert_eq!(parse_bridge_query("next=%2Fapi%2Fdoc%3Fid%3D1%23top").next, "/api/doc?id=1#top");
+        assert_eq!(parse_bridge_query("next=/a/../b").next, "/b");
+        for next in ["//evil.com/x", "/%5Cevil.com", "https://evil.com/", "javascript:alert(1)", "x", "/__yao_bridge?next=/"] {
+            assert_eq!(parse_bridge_query(&format!("next={}", next)).next, "", "{}", next);
+        }
+        // Quotes and brackets arrive percent-encoded, so they can't end the script string
+        let next = parse_bridge_query("next=/p%3F%22%3C/script%3E").next;
+        assert!(!next.contains(['"', '<']), "{}", next);
+
+        let req = Request::builder().uri("/__yao_bridge?next=%2Fdocs%2Fa").body(Body::empty()).unwrap();
+        let html = axum::body::to_bytes(serve_bridge_page(&req).into_body(), 64 * 1024).await.unwrap();
+        assert!(String::from_utf8_lossy(&html).contains(r#"location.replace("/docs/a")"#));
+        let req = Request::builder().uri("/__yao_bridge").body(Body::empty()).unwrap();
+        let html = axum::body::to_bytes(serve_bridge_page(&req).into_body(), 64 * 1024).await.unwrap();
+        assert!(String::from_utf8_lossy(&html).contains(r#"location.replace("/__yao_admin_root/auth/connect")"#));
+    }
+
+    #[tokio::test]
+    async fn bridge_query_drops_malicious_values() {
+        let prefs = parse_bridge_query(
+            "locale=en%22);alert(1);//&theme=%22%7D);alert(1)//\
+             &primaryColor=red%22;alert(1)&fontSize=1e3",
+        );
+        assert_eq!(prefs, BridgePrefs::default());
+        for color in ["#12", "#gggggg", "3373fc", "#3373fc;x", "#fff\""] {
+            assert!(!is_hex_color(color), "{}", color);
+        }
+        for size in ["NaN", "inf", "-14", "14px", "7", "33"] {
+            assert_eq!(parse_bridge_query(&format!("fontSize={}", size)).font_size, "", "{}", size);
+        }
+        let req = Request::builder()
+            .uri("/__yao_bridge?theme=x%22%3Balert(1)%2F%2F&primaryColor=%23abc")
+            .body(Body::empty())
+            .unwrap();
+        let html = axum::body::to_bytes(serve_bridge_page(&req).into_body(), 64 * 1024).await.unwrap();
+        let html = String::from_utf8(html.to_vec()).unwrap();
+        assert!(!html.contains("alert"));
+        assert!(html.contains("__primary_color=#abc;"));
+    }
+
+    #[tokio::test]
+    async fn injected_preferences_ignore_malicious_cookies() {
+        let dist = std::env::temp_dir().join(format!("cui-inject-{}", std::process::id()));
+        std::fs::create_dir_all(&dist).unwrap();
+        std::fs::write(dist.join("index.html"), "<html><head></head><body></body></html>").unwrap();
+
+        let _lock = config::lock_test_state().await;
+        config::COOKIE_JAR.write().clear();
+        config::store_cookie(r#"__theme=x"),alert(1),("; Path=/"#);
+        config::store_cookie(r#"__locale=en"),alert(2),("; Path=/"#);
+        assert!(config::COOKIE_JAR.read().iter().any(|c| c.value.contains("alert(1)")));
+        let resp = serve_cui_static("/__yao_admin_root/", &dist, StaticRequest::default()).await;
+        config::COOKIE_JAR.write().clear();
+
+        let html = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let html = String::from_utf8(html.to_vec()).unwrap();
+        assert!(!html.contains("alert("));
+        // An unknown locale falls back to English
+        assert!(html.contains(r#"localStorage.setItem("umi_locale","en-US")"#));
+        let _ = std::fs::remove_dir_all(&dist);
+    }
+
+    #[tokio::test]
+    async fn sse_streams_end_with_an_error_event_or_close_comment() {
+        use bytes::Bytes;
+        let active = || config::PROXY_METRICS.active_sse_streams.load(Ordering::Relaxed);
+        let before = active();
+
+        // Upstream drops mid-event
+        let chunks: Vec<Result<Bytes, String>> = vec![
+            Ok(Bytes::from_static(b"data: 1\n\n")),
+            Ok(Bytes::from_static(b"data: {\"par")),
+            Err("connection reset".to_string()),
+            Ok(Bytes::from_static(b"data: never\n\n")),
+        ];
+        let mut body = Box::pin(sse_body(futures_util::stream::iter(chunks), config::sse_stream_started(), None));
+        assert_eq!(body.next().await.unwrap().unwrap(), "data: 1\n\n");
+        assert_eq!(active(), before + 1);
+        // The truncated event never reaches the client
+        let rest: Vec<Bytes> = body.map(|c| c.unwrap()).collect().await;
+        assert_eq!(rest, ["event: error\ndata: {\"message\":\"connection reset\"}\n\n"]);
+        assert_eq!(active(), before);
+
+        // Events split across chunks go out whole, several at once if they
+        // arrive together; a tail without its blank line is dropped at EOF
+        let chunks: Vec<Result<Bytes, String>> = vec![
+            Ok(Bytes::from_static(b"event: a\r\ndata: x")),
+            Ok(Bytes::from_static(b"y\r\n\r\ndata: 2\n\ndata: 3\r")),
+            Ok(Bytes::from_static(b"\rdata: cut")),
+        ];
+        let body: Vec<Bytes> = sse_body(futures_util::stream::iter(chunks), config::sse_stream_started(), None)
+            .map(|c| c.unwrap())
+            .collect()
+            .await;
+        assert_eq!(body, ["event: a\r\ndata: xy\r\n\r\ndata: 2\n\n", "data: 3\r\r", ": upstream closed\n\n"]);
+
+        // Clean EOF
+        let chunks: Vec<Result<Bytes, String>> = vec![Ok(Bytes::from_static(b"data: 1\n\n"))];
+        let body: Vec<Bytes> = sse_body(futures_util::stream::iter(chunks), config::sse_stream_started(), None)
+            .map(|c| c.unwrap())
+            .collect()
+            .await;
+        assert_eq!(body, ["data: 1\n\n", ": upstream closed\n\n"]);
+        assert_eq!(active(), before);
+    }
+
+    #[tokio::test]
+    async fn idle_sse_streams_get_keep_alive_comments_between_lines() {
+        use bytes::Bytes;
+        use futures_util::stream;
+
+        // Sends `first`, goes quiet, then sends `second`
+        let gap = |first: &'static [u8], second: &'static [u8]| {
+            stream::iter([Ok::<_, String>(Bytes::from_static(first))]).chain(stream::once(async move {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                Ok(Bytes::from_static(second))
+            }))
+        };
+        let heartbeat = Some(Duration::from_millis(50));
+
+        let body: Vec<Bytes> = sse_body(gap(b"data: 1\n\n", b"data: 2\n\n"), config::sse_stream_started(), heartbeat)
+            .map(|c| c.unwrap())
+            .collect()
+            .await;
+        assert_eq!(body.first().unwrap(), "data: 1\n\n");
+        assert_eq!(&body[body.len() - 2..], ["data: 2\n\n", ": upstream closed\n\n"]);
+        let pings = &body[1..body.len() - 2];
+        assert!(!pings.is_empty() && pings.iter().all(|p| p == SSE_HEARTBEAT), "{:?}", body);
+
+        // A half-received event is held back, so pings never land inside it
+        let body: Vec<Bytes> = sse_body(gap(b"data: {\"par", b"tial\":1}\n\n"), config::sse_stream_started(), heartbeat)
+            .map(|c| c.unwrap())
+            .collect()
+            .await;
+        assert_eq!(&body[body.len() - 2..], ["data: {\"partial\":1}\n\n", ": upstream closed\n\n"]);
+        let pings = &body[..body.len() - 2];
+        assert!(!pings.is_empty() && pings.iter().all(|p| p == SSE_HEARTBEAT), "{:?}", body);
+    }
+
+    #[tokio::test]
+    async fn missing_assets_404_but_routes_serve_index() {
+        let dist = std::env::temp_dir().join(format!("cui-spa-{}", std::process::id()));
+        std::fs::create_dir_all(dist.join("assets")).unwrap();
+        std::fs::write(dist.join("index.html"), "<html><head></head></html>").unwrap();
+
+        for path in ["/__yao_admin_root/app.123.js", "/__yao_admin_root/assets/app.css.map"] {
+            let resp = serve_cui_static(path, &dist, StaticRequest::default()).await;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND, "{}", path);
+        }
+        for path in ["/__yao_admin_root/agents/list", "/__yao_admin_root/docs/intro.html", "/__yao_admin_root/assets"] {
+            let resp = serve_cui_static(path, &dist, StaticRequest::default()).await;
+            assert_eq!(resp.status(), StatusCode::OK, "{}", path);
+            assert!(resp.headers()["Content-Type"].to_str().unwrap().starts_with("text/html"), "{}", path);
+        }
+        let _ = std::fs::remove_dir_all(&dist);
+    }
+
+    #[test]
+    fn parse_range_forms() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Some(ByteRange::Partial(0, 99)));
+        assert_eq!(parse_range("bytes=900-", 1000), Some(ByteRange::Partial(900, 999)));
+        assert_eq!(parse_range("bytes=900-5000", 1000), Some(ByteRange::Partial(900, 999)));
+        assert_eq!(parse_range("bytes=-100", 1000), Some(ByteRange::Partial(900, 999)));
+        assert_eq!(parse_range("bytes=-5000", 1000), Some(ByteRange::Partial(0, 999)));
+        assert_eq!(parse_range("bytes=1000-", 1000), Some(ByteRange::Unsatisfiable));
+        assert_eq!(parse_range("bytes=-0", 1000), Some(ByteRange::Unsatisfiable));
+        // Malformed or multiple ranges: send the whole file
+        for h in ["bytes=5-1", "bytes=a-b", "items=0-1", "bytes=0-1,4-5", "bytes=-"] {
+            assert_eq!(parse_range(h, 1000), None, "{}", h);
+        }
+    }
+
+    #[tokio::test]
+    async fn static_media_supports_ranges() {
+        let dist = std::env::temp_dir().join(format!("cui-range-{}", std::process::id()));
+        std::fs::create_dir_all(&dist).unwrap();
+        let video: Vec<u8> = (0..=255u8).cycle().take(4096).collect();
+        std::fs::write(dist.join("demo.mp4"), &video).unwrap();
+        let get = |range: Option<&'static str>| {
+            let dist = dist.clone();
+            async move {
+                let opts = StaticRequest { range, ..Default::default() };
+                let resp = serve_cui_static("/__yao_admin_root/demo.mp4", &dist, opts).await;
+                let status = resp.status();
+                let content_range = resp.headers().get(header::CONTENT_RANGE)
+                    .map(|v| v.to_str().unwrap().to_string());
+                let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+                (status, content_range, body)
+            }
+        };
+
+        let (status, content_range, body) = get(Some("bytes=10-19")).await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(content_range.as_deref(), Some("bytes 10-19/4096"));
+        assert_eq!(&body[..], &video[10..20]);
+
+        let (status, content_range, body) = get(Some("bytes=4000-")).await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(content_range.as_deref(), Some("bytes 4000-4095/4096"));
+        assert_eq!(&body[..], &video[4000..]);
+
+        // An open range streams the rest of the file from disk
+        let (status, content_range, body) = get(Some("bytes=0-")).await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(content_range.as_deref(), Some("bytes 0-4095/4096"));
+        assert_eq!(body, video);
+
+        let (status, content_range, _) = get(Some("bytes=5000-")).await;
+        assert_eq!(status, StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(content_range.as_deref(), Some("bytes */4096"));
+
+        let resp = serve_cui_static("/__yao_admin_root/demo.mp4", &dist, StaticRequest::default()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[header::ACCEPT_RANGES], "bytes");
+        let _ = std::fs::remove_dir_all(&dist);
+    }
+
+    #[test]
+    fn dashboard_redirect_maps_to_admin_root() {
+        let map = |uri: &str, dash: &str| dashboard_redirect(&uri.parse().unwrap(), dash);
+        assert_eq!(map("/dashboard", "/dashboard").as_deref(), Some("/__yao_admin_root/"));
+        assert_eq!(map("/dashboard/", "/dashboard").as_deref(), Some("/__yao_admin_root/"));
+        assert_eq!(
+            map("/dashboard/chat/1?tab=files", "/dashboard").as_deref(),
+            Some("/__yao_admin_root/chat/1?tab=files")
+        );
+        assert_eq!(map("/admin/x", "/admin").as_deref(), Some("/__yao_admin_root/x"));
+        // Outside the dashboard, or no dashboard configured
+        assert_eq!(map("/dashboards/x", "/dashboard"), None);
+        assert_eq!(map("/api/dashboard/x", "/dashboard"), None);
+        assert_eq!(map("/dashboard/x", ""), None);
+    }
+
+    #[tokio::test]
+    async fn metrics_count_proxied_requests() {
+        use axum::response::IntoResponse;
+
+        async fn scrape() -> String {
+            let req = Request::builder().uri("/__yao_desktop/metrics").body(Body::empty()).unwrap();
+            let resp = handle_desktop_api(req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+            String::from_utf8(body.to_vec()).unwrap()
+        }
+        fn sample(text: &str, series: &str) -> u64 {
+            text.lines()
+                .find_map(|l| l.strip_prefix(series)?.strip_prefix(' ')?.parse().ok())
+                .unwrap()
+        }
+
+        let upstream = Router::new().fallback(|req: Request| async move {
+            if req.uri().path() == "/missing" {
+                (StatusCode::NOT_FOUND, "nope").into_response()
+            } else {
+                "hello".into_response()
+            }
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, upstream).await.unwrap();
+        });
+
+        let _lock = config::lock_test_state().await;
+        config::update_proxy_state(&format!("http://{}", addr), "", "openapi", "");
+        let client = Client::builder().no_proxy().build().unwrap();
+
+        let before = scrape().await;
+        assert!(before.contains("# TYPE cui_desktop_proxy_requests_total counter"));
+        assert!(before.contains("# TYPE cui_desktop_proxy_sse_streams_active gauge"));
+        for path in ["/ok", "/missing"] {
+            let req = Request::builder().uri(path).body(Body::empty()).unwrap();
+            proxy_request(req, client.clone()).await;
+        }
+        let after = scrape().await;
+        config::update_proxy_state("", "", "openapi", "");
+
+        // Other tests may proxy concurrently, so only lower bounds hold
+        let delta = |series: &str| sample(&after, series) - sample(&before, series);
+        assert!(delta("cui_desktop_proxy_requests_total") >= 2);
+        assert!(delta("cui_desktop_proxy_responses_total{class=\"2xx\"}") >= 1);
+        assert!(delta("cui_desktop_proxy_responses_total{class=\"4xx\"}") >= 1);
+        assert!(delta("cui_desktop_proxy_bytes_total") >= 9);
+    }
+
+    #[test]
+    fn status_reports_state_without_token() {
+        let _lock = config::TEST_MUTEX.blocking_lock();
+        config::update_proxy_state("https://yao.example.com", "secret-token", "token", "dashboard");
+        let status = desktop_status();
+        assert_eq!(status["server_url"], "https://yao.example.com");
+        assert_eq!(status["auth_mode"], "token");
+        assert_eq!(status["dashboard"], "/dashboard");
+        assert_eq!(status["version"], env!("CARGO_PKG_VERSION"));
+        assert!(status["uptime_secs"].is_u64());
+        assert!(!status.to_string().contains("secret-token"));
+        config::update_proxy_state("", "", "openapi", "");
+    }
+
+    #[test]
+    fn rewrite_body_urls_replaces_whole_origins_only() {
+        let remote = "https://yao.example.com";
+        let local = "http://127.0.0.1:15099";
+        let html = br#"<a href="https://yao.example.com/agents/1">x</a><img src="https://yao.example.com.cdn.net/a.png"><a href="https://yao.example.com:8443/">y</a>"#;
+        let out = String::from_utf8(rewrite_body_urls(html, remote, local).unwrap()).unwrap();
+        assert_eq!(
+            out,
+            r#"<a href="http://127.0.0.1:15099/agents/1">x</a><img src="https://yao.example.com.cdn.net/a.png"><a href="https://yao.example.com:8443/">y</a>"#
+        );
+
+        let json = br#"{"url":"https:\/\/yao.example.com\/v1\/file","home":"https://yao.example.com"}"#;
+        let out = String::from_utf8(rewrite_body_urls(json, remote, local).unwrap()).unwrap();
+        assert_eq!(out, r#"{"url":"http:\/\/127.0.0.1:15099\/v1\/file","home":"http://127.0.0.1:15099"}"#);
+
+        // No match, or not text: untouched
+        assert_eq!(rewrite_body_urls(b"{\"ok\":true}", remote, local), None);
+        let mut binary = b"https://yao.example.com/".to_vec();
+        binary.extend_from_slice(&[0xff, 0xfe, 0x00]);
+        assert_eq!(rewrite_body_urls(&binary, remote, local), None);
+
+        assert!(is_url_rewritable_type("text/html; charset=utf-8"));
+        assert!(is_url_rewritable_type("application/vnd.api+json"));
+        assert!(!is_url_rewritable_type("application/octet-stream"));
+    }
+
+    #[tokio::test]
+    async fn proxy_rewrites_server_urls_in_html_and_json() {
+        use axum::Router;
+        use axum::response::IntoResponse;
+
+        let upstream_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream_listener.local_addr().unwrap();
+        let remote = format!("http://{}", upstream_addr);
+        let remote_for_app = remote.clone();
+        let upstream = Router::new().fallback(move |req: Request| {
+            let remote = remote_for_app.clone();
+            async move {
+                match req.uri().path() {
+                    "/page" => ([(header::CONTENT_TYPE, "text/html")], format!(r#"<a href="{}/agents">go</a>"#, remote)).into_response(),
+                    "/api" => ([(header::CONTENT_TYPE, "application/json")], format!(r#"{{"next":"{}/v1/next"}}"#, remote)).into_response(),
+                    _ => ([(header::CONTENT_TYPE, "application/octet-stream")], remote.into_bytes()).into_response(),
+                }
+            }
+        });
+        tokio::spawn(async move {
+            axum::serve(upstream_listener, upstream).await.unwrap();
+        });
+
+        let _lock = config::lock_test_state().await;
+        let mut conf = crate::app_conf::AppConf::default();
+        conf.proxy.rewrite_body_urls = true;
+        crate::app_conf::set_app_conf(conf);
+        config::update_proxy_state(&remote, "", "openapi", "");
+        let local = format!("http://127.0.0.1:{}", get_proxy_state().port);
+        let client = Client::builder().no_proxy().build().unwrap();
+
+        let fetch = |path: &'static str| {
+            let client = client.clone();
+            async move {
+                let req = Request::builder().uri(path).body(Body::empty()).unwrap();
+                let resp = proxy_request(req, client).await;
+                let len: usize = resp.headers()[header::CONTENT_LENGTH].to_str().unwrap().parse().unwrap();
+                let body = axum::body::to_bytes(resp.into_body(), 4096).await.unwrap();
+                assert_eq!(len, body.len());
+                String::from_utf8(body.to_vec()).unwrap()
+            }
+        };
+        let html = fetch("/page").await;
+        let json = fetch("/api").await;
+        let binary = fetch("/bin").await;
+        crate::app_conf::set_app_conf(Default::default());
+
+        assert_eq!(html, format!(r#"<a href="{}/agents">go</a>"#, local));
+        assert_eq!(json, format!(r#"{{"next":"{}/v1/next"}}"#, local));
+        // Other content types pass through as-is
+        assert_eq!(binary, remote);
+    }
+
+    #[test]
+    fn custom_headers_expand_token() {
+        let headers = HashMap::from([
+            ("X-Tenant-Id".to_string(), "acme".to_string()),
+            ("X-Gateway-Auth".to_string(), "Bearer ${token}".to_string()),
+            ("Bad Header".to_string(), "x".to_string()),
+        ]);
+        let mut out = custom_headers(&headers, "tok123");
+        out.sort_by(|a, b| a.0.as_str().cmp(b.0.as_str()));
+        assert_eq!(out.len(), 2);
+        assert_eq!(out[0], (HeaderName::from_static("x-gateway-auth"), HeaderValue::from_static("Bearer tok123")));
+        assert_eq!(out[1], (HeaderName::from_static("x-tenant-id"), HeaderValue::from_static("acme")));
+
+        // Logged out: token headers are left off
+        let out = custom_headers(&headers, "");
+        assert_eq!(out, vec![(HeaderName::from_static("x-tenant-id"), HeaderValue::from_static("acme"))]);
+    }
+
+    #[test]
+    fn kiosk_keeps_the_main_window_fullscreen() {
+        let _lock = config::TEST_MUTEX.blocking_lock();
+        assert!(!fullscreen_target("main", false));
+        assert!(fullscreen_target("main", true));
+
+        config::set_kiosk(true);
+        let pinned = (kiosk_locked("main"), fullscreen_target("main", false));
+        let popup = (kiosk_locked("popup_1"), fullscreen_target("popup_1", false));
+        config::set_kiosk(false);
+
+        assert_eq!(pinned, (true, true));
+        assert_eq!(popup, (false, false));
+    }
+
+    #[tokio::test]
+    async fn proxy_sends_custom_headers_upstream() {
+        use axum::Router;
+
+        let upstream = Router::new().fallback(|req: Request| async move {
+            let get = |n: &str| req.headers().get_all(n).iter()
+                .map(|v| v.to_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+                .join(",");
+            format!("{}|{}", get("x-tenant-id"), get("x-gateway-auth"))
+        });
+        let upstream_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream_listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(upstream_listener, upstream).await.unwrap();
+        });
+
+        let _lock = config::lock_test_state().await;
+        crate::app_conf::set_app_conf(crate::app_conf::AppConf {
+            headers: HashMap::from([
+                ("X-Tenant-Id".to_string(), "acme".to_string()),
+                ("X-Gateway-Auth".to_string(), "${token}".to_string()),
+            ]),
+            ..Default::default()
+        });
+        config::update_proxy_state(&format!("http://{}", upstream_addr), "tok123", "openapi", "");
+        let client = Client::builder().no_proxy().build().unwrap();
+
+        // The webview's own X-Tenant-Id is replaced, not duplicated
+        let req = Request::builder()
+            .uri("/v1/ping")
+            .header("X-Tenant-Id", "from-browser")
+            .body(Body::empty())
+            .unwrap();
+        let resp = proxy_request(req, client).await;
+        let body = axum::body::to_bytes(resp.into_body(), 1024).await.unwrap();
+        crate::app_conf::set_app_conf(Default::default());
+        config::update_proxy_state(&format!("http://{}", upstream_addr), "", "openapi", "");
+        assert_eq!(&body[..], b"acme|tok123");
+    }
+
     #[tokio::test]
     async fn streaming_proxy_does_not_buffer_entire_body() {
         use axum::Router;
@@ -0,0 +1,221 @@
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+/// Rotate once the current file would grow past this size
+const MAX_LOG_BYTES: u64 = 5 * 1024 * 1024;
+
+/// Files kept: requests.log plus requests.log.1 … requests.log.4
+const MAX_LOG_FILES: usize = 5;
+
+/// Entries queued for the writer; further entries are dropped, never waited on
+const QUEUE_CAPACITY: usize = 4096;
+
+const LOG_FILE: &str = "requests.log";
+
+/// One line of the access log
+#[derive(Debug, Clone, Serialize)]
+pub struct LogEntry {
+    /// RFC 3339, UTC
+    pub ts: String,
+    pub method: String,
+    /// Request path without the query string (which may carry tokens)
+    pub path: String,
+    pub status: u16,
+    pub duration_ms: u64,
+    pub bytes: u64,
+}
+
+impl LogEntry {
+    pub fn new(method: &str, path: &str, status: u16, duration: Duration, bytes: u64) -> Self {
+        Self {
+            ts: rfc3339(SystemTime::now()),
+            method: method.to_string(),
+            path: path.to_string(),
+            status,
+            duration_ms: duration.as_millis() as u64,
+            bytes,
+        }
+    }
+}
+
+static SENDER: OnceLock<SyncSender<LogEntry>> = OnceLock::new();
+
+/// Start the writer thread logging to `{dir}/requests.log`. Only the first
+/// call has an effect.
+pub fn start(dir: PathBuf) {
+    SENDER.get_or_init(|| {
+        let (tx, rx) = sync_channel(QUEUE_CAPACITY);
+        info!("Request log: {:?}", dir.join(LOG_FILE));
+        std::thread::spawn(move || write_entries(&dir, rx));
+        tx
+    });
+}
+
+/// Whether requests should be logged (`proxy.requestLog` was set at startup)
+pub fn enabled() -> bool {
+    SENDER.get().is_some()
+}
+
+/// Queue an entry for the writer without blocking the request
+pub fn record(entry: LogEntry) {
+    let Some(tx) = SENDER.get() else {
+        return;
+    };
+    if let Err(TrySendError::Full(_)) = tx.try_send(entry) {
+        warn!("Request log queue full, dropping entry");
+    }
+}
+
+/// Writer loop: append JSON lines, rotating by size
+fn write_entries(dir: &Path, rx: Receiver<LogEntry>) {
+    let mut log = RotatingLog::new(dir.to_path_buf(), MAX_LOG_BYTES, MAX_LOG_FILES);
+    while let Ok(first) = rx.recv() {
+        let mut next = Some(first);
+        while let Some(entry) = next {
+            if let Err(e) = log.append(&entry) {
+                warn!("Failed to write request log: {}", e);
+            }
+            next = rx.try_recv().ok();
+        }
+        // Flush once the queue is drained so bursts share a write
+        if let Err(e) = log.flush() {
+            warn!("Failed to flush request log: {}", e);
+        }
+    }
+}
+
+struct RotatingLog {
+    dir: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    file: Option<BufWriter<File>>,
+    size: u64,
+}
+
+impl RotatingLog {
+    fn new(dir: PathBuf, max_bytes: u64, max_files: usize) -> Self {
+        Self { dir, max_bytes, max_files, file: None, size: 0 }
+    }
+
+    fn path(&self, index: usize) -> PathBuf {
+        if index == 0 {
+            self.dir.join(LOG_FILE)
+        } else {
+            self.dir.join(format!("{}.{}", LOG_FILE, index))
+        }
+    }
+
+    fn append(&mut self, entry: &LogEntry) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        if self.file.is_none() {
+            self.open()?;
+        }
+        if self.size > 0 && self.size + line.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        if let Some(file) = self.file.as_mut() {
+            file.write_all(&line)?;
+            self.size += line.len() as u64;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.file.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn open(&mut self) -> std::io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        let path = self.path(0);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        self.size = file.metadata()?.len();
+        self.file = Some(BufWriter::new(file));
+        Ok(())
+    }
+
+    /// requests.log → .1 → .2 …; the oldest file is deleted
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.flush()?;
+        self.file = None;
+        let _ = std::fs::remove_file(self.path(self.max_files - 1));
+        for i in (0..self.max_files - 1).rev() {
+            let from = self.path(i);
+            if from.exists() {
+                std::fs::rename(&from, self.path(i + 1))?;
+            }
+        }
+        self.open()
+    }
+}
+
+/// Format a time as `YYYY-MM-DDTHH:MM:SS.mmmZ`
+pub(crate) fn rfc3339(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs() as i64;
+    let (days, rem) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+
+    // Howard Hinnant's civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year, month, day, rem / 3600, rem % 3600 / 60, rem % 60, since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc3339_formats_utc() {
+        assert_eq!(rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        let t = UNIX_EPOCH + Duration::from_millis(1_709_210_096_789);
+        assert_eq!(rfc3339(t), "2024-02-29T12:34:56.789Z");
+    }
+
+    #[test]
+    fn rotates_by_size_and_keeps_max_files() {
+        let dir = std::env::temp_dir().join(format!("cui-reqlog-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let entry = LogEntry::new("GET", "/api/x", 200, Duration::from_millis(12), 42);
+        let line_len = serde_json::to_vec(&entry).unwrap().len() as u64 + 1;
+
+        // Two lines per file, three files
+        let mut log = RotatingLog::new(dir.clone(), line_len * 2, 3);
+        for _ in 0..9 {
+            log.append(&entry).unwrap();
+        }
+        log.flush().unwrap();
+
+        let lines = |p: PathBuf| std::fs::read_to_string(p).map(|s| s.lines().count()).unwrap_or(0);
+        assert_eq!(lines(log.path(0)), 1);
+        assert_eq!(lines(log.path(1)), 2);
+        assert_eq!(lines(log.path(2)), 2);
+        assert!(!log.path(3).exists());
+
+        let first = std::fs::read_to_string(log.path(0)).unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(first.trim()).unwrap();
+        assert_eq!(parsed["path"], "/api/x");
+        assert_eq!((parsed["status"].as_u64(), parsed["bytes"].as_u64()), (Some(200), Some(42)));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
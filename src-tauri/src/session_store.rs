@@ -0,0 +1,192 @@
+use parking_lot::RwLock;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+use crate::config::ProxyState;
+
+/// Sign-in session kept across restarts, encrypted in {app_data_dir}/session.bin.
+/// The key lives in {app_local_data_dir}/session.key (owner-only on Unix), which
+/// Windows keeps out of roaming profiles, so a copied or synced data dir
+/// doesn't carry a usable token. It does not protect against other programs
+/// running as the same user.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedSession {
+    pub server_url: String,
+    pub token: String,
+    pub auth_mode: String,
+    #[serde(default)]
+    pub refresh_token: String,
+    #[serde(default)]
+    pub token_expires_at: u64,
+}
+
+struct Files {
+    session: PathBuf,
+    key: PathBuf,
+}
+
+static FILES: RwLock<Option<Files>> = RwLock::new(None);
+
+/// Set where the session and its key are stored
+pub fn init(data_dir: &Path, key_dir: &Path) {
+    *FILES.write() = Some(Files {
+        session: data_dir.join("session.bin"),
+        key: key_dir.join("session.key"),
+    });
+}
+
+/// Save the session of `state`, or forget it when there is no token
+pub fn save(state: &ProxyState) {
+    if state.token.is_empty() {
+        wipe();
+        return;
+    }
+    let files = FILES.read();
+    let Some(files) = files.as_ref() else { return };
+    let session = SavedSession {
+        server_url: state.server_url.clone(),
+        token: state.token.clone(),
+        auth_mode: state.auth_mode.clone(),
+        refresh_token: state.refresh_token.clone(),
+        token_expires_at: state.token_expires_at,
+    };
+    let result = load_or_create_key(&files.key)
+        .and_then(|key| {
+            let plain = serde_json::to_vec(&session).map_err(|e| e.to_string())?;
+            encrypt(&key, &plain)
+        })
+        .and_then(|data| std::fs::write(&files.session, data).map_err(|e| e.to_string()));
+    if let Err(e) = result {
+        warn!("Failed to save session: {}", e);
+    }
+}
+
+/// The saved session, if any; unreadable files are discarded
+pub fn load() -> Option<SavedSession> {
+    let (data, key) = {
+        let files = FILES.read();
+        let files = files.as_ref()?;
+        (std::fs::read(&files.session).ok()?, std::fs::read(&files.key).ok())
+    };
+    let session = key
+        .and_then(|k| <[u8; 32]>::try_from(k).ok())
+        .and_then(|key| decrypt(&key, &data))
+        .and_then(|plain| serde_json::from_slice(&plain).ok());
+    if session.is_none() {
+        warn!("Saved session could not be read; discarding it");
+        wipe();
+    }
+    session
+}
+
+/// Delete the saved session (the key is kept for the next one)
+pub fn wipe() {
+    if let Some(files) = FILES.read().as_ref() {
+        match std::fs::remove_file(&files.session) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!("Failed to delete saved session: {}", e),
+        }
+    }
+}
+
+fn load_or_create_key(path: &Path) -> Result<[u8; 32], String> {
+    if let Ok(existing) = std::fs::read(path) {
+        if let Ok(key) = <[u8; 32]>::try_from(existing) {
+            return Ok(key);
+        }
+    }
+    let mut key = [0u8; 32];
+    getrandom::getrandom(&mut key).map_err(|e| format!("No randomness for session key: {}", e))?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path).map_err(|e| format!("Failed to create session key: {}", e))?;
+    std::io::Write::write_all(&mut file, &key).map_err(|e| format!("Failed to write session key: {}", e))?;
+    Ok(key)
+}
+
+/// ChaCha20-Poly1305 with a random nonce: `nonce || ciphertext || tag`
+fn encrypt(key: &[u8; 32], plain: &[u8]) -> Result<Vec<u8>, String> {
+    let key = LessSafeKey::new(UnboundKey::new(&CHACHA20_POLY1305, key).map_err(|_| "Invalid session key")?);
+    let mut nonce = [0u8; NONCE_LEN];
+    getrandom::getrandom(&mut nonce).map_err(|e| format!("No randomness for nonce: {}", e))?;
+    let mut sealed = plain.to_vec();
+    key.seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut sealed)
+        .map_err(|_| "Failed to encrypt session")?;
+    Ok([nonce.as_slice(), &sealed].concat())
+}
+
+/// None when the data was not sealed with `key` or has been altered
+fn decrypt(key: &[u8; 32], data: &[u8]) -> Option<Vec<u8>> {
+    let key = LessSafeKey::new(UnboundKey::new(&CHACHA20_POLY1305, key).ok()?);
+    let (nonce, sealed) = data.split_at_checked(NONCE_LEN)?;
+    let nonce = Nonce::try_assume_unique_for_key(nonce).ok()?;
+    let mut sealed = sealed.to_vec();
+    let plain = key.open_in_place(nonce, Aad::empty(), &mut sealed).ok()?;
+    Some(plain.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sealed_data_only_opens_with_its_key() {
+        let key = [7u8; 32];
+        let sealed = encrypt(&key, b"bearer token").unwrap();
+        assert!(!sealed.windows(6).any(|w| w == b"bearer"));
+        assert_eq!(decrypt(&key, &sealed).as_deref(), Some(&b"bearer token"[..]));
+        assert_ne!(encrypt(&key, b"bearer token").unwrap(), sealed, "nonce must differ");
+
+        assert!(decrypt(&[8u8; 32], &sealed).is_none());
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(decrypt(&key, &tampered).is_none());
+        assert!(decrypt(&key, &sealed[..5]).is_none());
+    }
+
+    #[test]
+    fn sessions_round_trip_and_wipe() {
+        let _lock = crate::config::TEST_MUTEX.blocking_lock();
+        let dir = std::env::temp_dir().join(format!("cui-session-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        init(&dir.join("data"), &dir.join("local"));
+        std::fs::create_dir_all(dir.join("data")).unwrap();
+
+        let state = ProxyState {
+            server_url: "https://yao.example.com".into(),
+            token: "tok".into(),
+            auth_mode: "oauth".into(),
+            refresh_token: "refresh".into(),
+            token_expires_at: 42,
+            ..Default::default()
+        };
+        save(&state);
+        let saved = load().unwrap();
+        assert_eq!((saved.token.as_str(), saved.refresh_token.as_str(), saved.token_expires_at), ("tok", "refresh", 42));
+        let raw = std::fs::read(dir.join("data/session.bin")).unwrap();
+        assert!(!raw.windows(7).any(|w| w == b"refresh"));
+
+        // A new key (e.g. the data dir copied to another machine) can't read it
+        std::fs::write(dir.join("local/session.key"), [1u8; 32]).unwrap();
+        assert_eq!(load(), None);
+        assert!(!dir.join("data/session.bin").exists());
+
+        save(&state);
+        save(&ProxyState { token: String::new(), ..state });
+        assert_eq!(load(), None);
+
+        *FILES.write() = None;
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
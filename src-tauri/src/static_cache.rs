@@ -0,0 +1,192 @@
+use bytes::Bytes;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// File identity used for invalidation: modification time and size
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Stamp {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+impl Stamp {
+    fn of(meta: &std::fs::Metadata) -> Self {
+        Self { modified: meta.modified().ok(), len: meta.len() }
+    }
+}
+
+struct Entry {
+    stamp: Stamp,
+    data: Bytes,
+    /// Injected HTML and the injection it was built with
+    html: Option<(String, Bytes)>,
+    last_used: u64,
+}
+
+impl Entry {
+    fn size(&self) -> usize {
+        self.data.len() + self.html.as_ref().map_or(0, |(key, html)| key.len() + html.len())
+    }
+}
+
+/// CUI static files held in memory, least recently used evicted first once
+/// the total size exceeds `limit` bytes
+struct StaticCache {
+    entries: HashMap<PathBuf, Entry>,
+    total: usize,
+    limit: usize,
+    tick: u64,
+}
+
+impl StaticCache {
+    fn new(limit: usize) -> Self {
+        Self { entries: HashMap::new(), total: 0, limit, tick: 0 }
+    }
+
+    fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        self.evict();
+    }
+
+    /// Cached bytes for `path` if the file hasn't changed since they were read
+    fn get(&mut self, path: &Path, stamp: Stamp) -> Option<Bytes> {
+        self.tick += 1;
+        let entry = self.entries.get_mut(path)?;
+        if entry.stamp != stamp {
+            return None;
+        }
+        entry.last_used = self.tick;
+        Some(entry.data.clone())
+    }
+
+    fn insert(&mut self, path: &Path, stamp: Stamp, data: Bytes) {
+        self.remove(path);
+        if data.len() > self.limit {
+            return;
+        }
+        self.tick += 1;
+        self.total += data.len();
+        self.entries.insert(path.to_path_buf(), Entry { stamp, data, html: None, last_used: self.tick });
+        self.evict();
+    }
+
+    /// Injected HTML for `path`, if it was built with the same injection
+    fn get_html(&mut self, path: &Path, inject: &str) -> Option<Bytes> {
+        match &self.entries.get(path)?.html {
+            Some((key, html)) if key == inject => Some(html.clone()),
+            _ => None,
+        }
+    }
+
+    fn set_html(&mut self, path: &Path, inject: &str, html: Bytes) {
+        let Some(entry) = self.entries.get_mut(path) else {
+            return;
+        };
+        let before = entry.size();
+        entry.html = Some((inject.to_string(), html));
+        self.total = self.total - before + entry.size();
+        self.evict();
+    }
+
+    fn remove(&mut self, path: &Path) {
+        if let Some(old) = self.entries.remove(path) {
+            self.total -= old.size();
+        }
+    }
+
+    fn evict(&mut self) {
+        while self.total > self.limit {
+            let Some(oldest) = self.entries.iter()
+                .min_by_key(|(_, e)| e.last_used)
+                .map(|(p, _)| p.clone())
+            else {
+                break;
+            };
+            self.remove(&oldest);
+        }
+    }
+}
+
+static CACHE: Lazy<Mutex<StaticCache>> = Lazy::new(|| Mutex::new(StaticCache::new(0)));
+
+/// Size limit from `proxy.staticCacheMb`; 0 disables the cache
+fn configured_limit() -> usize {
+    crate::app_conf::get_app_conf().proxy.static_cache_mb as usize * 1024 * 1024
+}
+
+/// Read a static file, from memory when it is cached and unchanged on disk
+pub async fn read(path: &Path) -> std::io::Result<Bytes> {
+    let limit = configured_limit();
+    CACHE.lock().set_limit(limit);
+    if limit == 0 {
+        return tokio::fs::read(path).await.map(Bytes::from);
+    }
+    let stamp = Stamp::of(&tokio::fs::metadata(path).await?);
+    if let Some(data) = CACHE.lock().get(path, stamp) {
+        return Ok(data);
+    }
+    let data = Bytes::from(tokio::fs::read(path).await?);
+    CACHE.lock().insert(path, stamp, data.clone());
+    Ok(data)
+}
+
+/// HTML with `inject` applied by `build`. The result is reused while the file
+/// is cached and `inject` (which carries the current locale, theme and
+/// server) is unchanged.
+pub fn injected_html(path: &Path, inject: &str, build: impl FnOnce() -> String) -> Bytes {
+    if let Some(html) = CACHE.lock().get_html(path, inject) {
+        return html;
+    }
+    let html = Bytes::from(build());
+    CACHE.lock().set_html(path, inject, html.clone());
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stamp(len: u64) -> Stamp {
+        Stamp { modified: Some(SystemTime::UNIX_EPOCH), len }
+    }
+
+    #[test]
+    fn evicts_least_recently_used_beyond_limit() {
+        let mut cache = StaticCache::new(10);
+        cache.insert(Path::new("/a.js"), stamp(4), Bytes::from_static(b"aaaa"));
+        cache.insert(Path::new("/b.js"), stamp(4), Bytes::from_static(b"bbbb"));
+        assert!(cache.get(Path::new("/a.js"), stamp(4)).is_some());
+        cache.insert(Path::new("/c.js"), stamp(4), Bytes::from_static(b"cccc"));
+        assert!(cache.get(Path::new("/b.js"), stamp(4)).is_none());
+        assert!(cache.get(Path::new("/a.js"), stamp(4)).is_some());
+        assert_eq!(cache.total, 8);
+
+        // Larger than the whole cache: not stored
+        cache.insert(Path::new("/big.js"), stamp(11), Bytes::from(vec![0; 11]));
+        assert!(cache.get(Path::new("/big.js"), stamp(11)).is_none());
+
+        cache.set_limit(0);
+        assert_eq!((cache.entries.len(), cache.total), (0, 0));
+    }
+
+    #[test]
+    fn changed_file_or_injection_misses() {
+        let mut cache = StaticCache::new(1024);
+        let index = Path::new("/index.html");
+        cache.insert(index, stamp(6), Bytes::from_static(b"<head>"));
+        assert!(cache.get(index, stamp(7)).is_none());
+
+        cache.set_html(index, "<script>en</script>", Bytes::from_static(b"<head><script>en</script>"));
+        assert!(cache.get_html(index, "<script>en</script>").is_some());
+        assert!(cache.get_html(index, "<script>zh</script>").is_none());
+        assert_eq!(cache.total, 6 + 19 + 25);
+
+        // Re-reading the file drops the stale injected copy
+        cache.insert(index, stamp(7), Bytes::from_static(b"<head>!"));
+        assert!(cache.get_html(index, "<script>en</script>").is_none());
+        assert_eq!(cache.total, 7);
+    }
+}
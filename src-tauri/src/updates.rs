@@ -0,0 +1,175 @@
+use serde::Serialize;
+use serde::ser::{SerializeStruct, Serializer};
+use std::fmt;
+use tauri::{AppHandle, Emitter};
+use tauri_plugin_updater::{Updater, UpdaterExt};
+use tracing::{info, warn};
+
+use crate::app_conf::UpdaterConf;
+
+/// Result of `check_for_update`. Without an update `version` is the running one.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UpdateInfo {
+    pub available: bool,
+    pub version: String,
+    pub notes: Option<String>,
+    /// `pub_date` (RFC 3339) from the release manifest
+    pub date: Option<String>,
+}
+
+/// Why an update check or install failed, serialized as `{kind, message}`
+/// like `CommandError`
+#[derive(Debug, Clone, PartialEq)]
+pub enum UpdateError {
+    /// `updater.active` is off in config.json
+    Disabled,
+    /// The update server couldn't be reached or returned no usable release
+    Network(String),
+    /// Endpoints or pubkey in config.json are unusable
+    Config(String),
+    /// The package signature didn't match `updater.pubkey`
+    Signature(String),
+    /// No update to install (e.g. it was withdrawn since the check)
+    NoUpdate,
+    Other(String),
+}
+
+impl UpdateError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Disabled => "disabled",
+            Self::Network(_) => "network",
+            Self::Config(_) => "config",
+            Self::Signature(_) => "signature",
+            Self::NoUpdate => "no_update",
+            Self::Other(_) => "other",
+        }
+    }
+}
+
+impl fmt::Display for UpdateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Disabled => f.write_str("Updates are disabled (updater.active is off in config.json)"),
+            Self::NoUpdate => f.write_str("No update is available"),
+            Self::Network(msg) | Self::Config(msg) | Self::Signature(msg) | Self::Other(msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl Serialize for UpdateError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("UpdateError", 2)?;
+        s.serialize_field("kind", self.kind())?;
+        s.serialize_field("message", &self.to_string())?;
+        s.end()
+    }
+}
+
+impl From<tauri_plugin_updater::Error> for UpdateError {
+    fn from(e: tauri_plugin_updater::Error) -> Self {
+        use tauri_plugin_updater::Error as E;
+        let message = e.to_string();
+        match e {
+            E::Reqwest(_) | E::Network(_) | E::ReleaseNotFound
+            | E::TargetNotFound(_) | E::TargetsNotFound(_) => Self::Network(message),
+            E::EmptyEndpoints | E::UrlParse(_) | E::InsecureTransportProtocol => Self::Config(message),
+            E::Minisign(_) | E::Base64(_) | E::SignatureUtf8(_) => Self::Signature(message),
+            _ => Self::Other(message),
+        }
+    }
+}
+
+/// Payload of `update://progress` while an update downloads
+#[derive(Debug, Clone, Serialize)]
+pub struct UpdateProgress {
+    pub downloaded: u64,
+    /// Content-Length of the package, 0 if unknown
+    pub total: u64,
+}
+
+/// Updater using the config.json endpoints and pubkey; tauri.conf.json values
+/// apply where those are empty
+fn build_updater(app: &AppHandle, conf: &UpdaterConf) -> Result<Updater, UpdateError> {
+    if !conf.active {
+        return Err(UpdateError::Disabled);
+    }
+    let mut builder = app.updater_builder();
+    if !conf.endpoints.is_empty() {
+        let endpoints = conf.endpoints.iter()
+            .map(|e| url::Url::parse(e).map_err(|err| UpdateError::Config(format!("{}: {}", e, err))))
+            .collect::<Result<Vec<_>, _>>()?;
+        builder = builder.endpoints(endpoints)?;
+    }
+    if !conf.pubkey.is_empty() {
+        builder = builder.pubkey(conf.pubkey.clone());
+    }
+    Ok(builder.build()?)
+}
+
+/// Ask the update endpoints whether a newer version is published
+pub async fn check(app: &AppHandle) -> Result<UpdateInfo, UpdateError> {
+    let updater = build_updater(app, &crate::app_conf::get_app_conf().updater)?;
+    match updater.check().await? {
+        Some(update) => {
+            info!("Update available: {} -> {}", update.current_version, update.version);
+            Ok(UpdateInfo {
+                available: true,
+                version: update.version.clone(),
+                notes: update.body.clone(),
+                date: update.raw_json.get("pub_date").and_then(|d| d.as_str()).map(str::to_string),
+            })
+        }
+        None => Ok(UpdateInfo {
+            available: false,
+            version: app.package_info().version.to_string(),
+            notes: None,
+            date: None,
+        }),
+    }
+}
+
+/// Download, verify and install the available update, emitting
+/// `update://progress`. The app must be restarted to run the new version.
+pub async fn install(app: &AppHandle) -> Result<String, UpdateError> {
+    let updater = build_updater(app, &crate::app_conf::get_app_conf().updater)?;
+    let update = updater.check().await?.ok_or(UpdateError::NoUpdate)?;
+    info!("Installing update {}", update.version);
+
+    let mut downloaded = 0u64;
+    let result = update.download_and_install(
+        |chunk, total| {
+            downloaded += chunk as u64;
+            let _ = app.emit("update://progress", UpdateProgress { downloaded, total: total.unwrap_or(0) });
+        },
+        || info!("Update downloaded"),
+    ).await;
+    if let Err(e) = result {
+        warn!("Update install failed: {}", e);
+        return Err(e.into());
+    }
+    Ok(update.version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_are_typed_for_the_frontend() {
+        let network = UpdateError::from(tauri_plugin_updater::Error::Network("timed out".into()));
+        assert_eq!(network, UpdateError::Network("`timed out`".into()));
+        assert!(matches!(
+            UpdateError::from(tauri_plugin_updater::Error::EmptyEndpoints),
+            UpdateError::Config(_)
+        ));
+        assert_eq!(
+            serde_json::to_value(UpdateError::Disabled).unwrap(),
+            serde_json::json!({"kind": "disabled", "message": UpdateError::Disabled.to_string()})
+        );
+        assert_eq!(
+            serde_json::to_value(network).unwrap(),
+            serde_json::json!({"kind": "network", "message": "`timed out`"})
+        );
+    }
+}
@@ -0,0 +1,85 @@
+use tracing::warn;
+
+use crate::tls::TlsOptions;
+
+/// Hosts that never go through `upstreamProxy` (the local proxy and CUI)
+const DIRECT_HOSTS: &str = "localhost,127.0.0.1,::1";
+
+/// reqwest client builder for connections to the Yao server and downloads:
+/// TLS options plus `upstreamProxy` (system proxy variables are ignored)
+pub fn client_builder() -> reqwest::ClientBuilder {
+    let proxy = crate::app_conf::get_app_conf().upstream_proxy;
+    with_proxy(tls_builder(), proxy.as_deref()).unwrap_or_else(|e| {
+        warn!("{}; connecting directly", e);
+        tls_builder().no_proxy()
+    })
+}
+
+fn tls_builder() -> reqwest::ClientBuilder {
+    let options = TlsOptions::current();
+    options.apply(reqwest::Client::builder()).unwrap_or_else(|e| {
+        warn!("{}; using the default trust store", e);
+        TlsOptions { ca_pem: None, ..options }
+            .apply(reqwest::Client::builder())
+            .unwrap_or_else(|_| reqwest::Client::builder())
+    })
+}
+
+/// Route through `proxy_url` (http://, https://, socks5://, credentials in
+/// the URL), or connect directly when there is none
+fn with_proxy(builder: reqwest::ClientBuilder, proxy_url: Option<&str>) -> Result<reqwest::ClientBuilder, String> {
+    let Some(proxy_url) = proxy_url.map(str::trim).filter(|p| !p.is_empty()) else {
+        return Ok(builder.no_proxy());
+    };
+    let proxy = reqwest::Proxy::all(proxy_url)
+        .map_err(|e| format!("Invalid upstreamProxy: {}", e))?
+        .no_proxy(reqwest::NoProxy::from_string(DIRECT_HOSTS));
+    Ok(builder.proxy(proxy))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Server that answers `200` and reports each request line it saw
+    async fn spawn_recorder() -> (u16, tokio::sync::mpsc::UnboundedReceiver<String>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((mut sock, _)) = listener.accept().await {
+                let mut buf = [0u8; 2048];
+                let n = sock.read(&mut buf).await.unwrap_or(0);
+                let head = String::from_utf8_lossy(&buf[..n]).to_string();
+                let _ = tx.send(head.lines().next().unwrap_or_default().to_string());
+                let _ = sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").await;
+            }
+        });
+        (port, rx)
+    }
+
+    #[tokio::test]
+    async fn requests_go_through_the_configured_proxy() {
+        let (proxy_port, mut proxied) = spawn_recorder().await;
+        let (local_port, mut direct) = spawn_recorder().await;
+        let proxy_url = format!("http://127.0.0.1:{}", proxy_port);
+        let client = with_proxy(reqwest::Client::builder(), Some(&proxy_url)).unwrap().build().unwrap();
+
+        let resp = client.get("http://yao.example.test/api/ping").send().await.unwrap();
+        assert_eq!(resp.status(), 200);
+        assert_eq!(proxied.recv().await.unwrap(), "GET http://yao.example.test/api/ping HTTP/1.1");
+
+        // Loopback stays direct
+        client.get(format!("http://127.0.0.1:{}/local", local_port)).send().await.unwrap();
+        assert_eq!(direct.recv().await.unwrap(), "GET /local HTTP/1.1");
+        assert!(proxied.try_recv().is_err());
+    }
+
+    #[test]
+    fn proxy_urls_are_validated() {
+        assert!(with_proxy(reqwest::Client::builder(), None).is_ok());
+        assert!(with_proxy(reqwest::Client::builder(), Some("socks5://user:pw@proxy.corp:1080")).is_ok());
+        assert!(with_proxy(reqwest::Client::builder(), Some("not a proxy")).is_err());
+    }
+}
@@ -0,0 +1,235 @@
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tauri::{AppHandle, Manager, WebviewWindow};
+use tokio::sync::oneshot;
+use tracing::{info, warn};
+
+use crate::config;
+
+/// How long to wait for the page to report back (IndexedDB deletes can block)
+const STORAGE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Web storage kinds that can be cleared individually
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageKind {
+    LocalStorage,
+    IndexedDb,
+    CacheStorage,
+}
+
+impl StorageKind {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "localStorage" => Some(Self::LocalStorage),
+            "indexedDB" => Some(Self::IndexedDb),
+            "cacheStorage" => Some(Self::CacheStorage),
+            _ => None,
+        }
+    }
+}
+
+/// Parse the `kinds` argument of `clear_web_storage`: known names only,
+/// duplicates collapsed, at least one kind.
+pub fn parse_kinds(names: &[String]) -> Result<Vec<StorageKind>, String> {
+    let mut kinds = Vec::new();
+    for name in names {
+        let kind = StorageKind::parse(name.trim()).ok_or_else(|| {
+            format!("Unknown storage kind '{}' (expected localStorage, indexedDB or cacheStorage)", name)
+        })?;
+        if !kinds.contains(&kind) {
+            kinds.push(kind);
+        }
+    }
+    if kinds.is_empty() {
+        return Err("No storage kinds given".to_string());
+    }
+    Ok(kinds)
+}
+
+/// Approximate web storage usage of the CUI origin
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WebStorageUsage {
+    pub origin: String,
+    /// UTF-16 size of all localStorage keys and values
+    pub local_storage_bytes: u64,
+    pub local_storage_keys: u64,
+    pub indexed_db_databases: Vec<String>,
+    pub cache_names: Vec<String>,
+    /// Origin-wide totals from navigator.storage.estimate(), when supported
+    pub usage_bytes: Option<u64>,
+    pub quota_bytes: Option<u64>,
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Outcome of a page script as reported by the page
+pub(crate) type ScriptResult = Result<serde_json::Value, String>;
+
+/// Evals waiting for the page to POST their result to /__yao_desktop/storage/result
+/// (storage scripts here, find-in-page in the proxy)
+static PENDING: Lazy<Mutex<HashMap<u64, oneshot::Sender<ScriptResult>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Deliver a result reported by the page. Returns false for unknown/expired ids.
+pub fn complete(id: u64, result: ScriptResult) -> bool {
+    match PENDING.lock().remove(&id) {
+        Some(tx) => tx.send(result).is_ok(),
+        None => false,
+    }
+}
+
+/// Run an async JS expression in the main window and wait for its value
+async fn eval_in_cui(app: &AppHandle, expr: &str) -> ScriptResult {
+    let win = app.get_webview_window("main")
+        .ok_or_else(|| "Main window not found".to_string())?;
+    let state = config::get_proxy_state();
+    let origin = crate::proxy::local_base(state.port);
+    let on_cui = win.url().map(|u| u.as_str().starts_with(&origin)).unwrap_or(false);
+    if !state.running || !on_cui {
+        return Err("CUI is not loaded".to_string());
+    }
+    eval_and_wait(&win, expr, STORAGE_TIMEOUT).await
+}
+
+/// Run an async JS expression in a proxied page and wait up to `timeout`
+/// for the value it reports back
+pub(crate) async fn eval_and_wait(win: &WebviewWindow, expr: &str, timeout: Duration) -> ScriptResult {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let (tx, rx) = oneshot::channel();
+    PENDING.lock().insert(id, tx);
+    win.eval(report_script(id, expr)).map_err(|e| {
+        PENDING.lock().remove(&id);
+        format!("Failed to run page script: {}", e)
+    })?;
+
+    match tokio::time::timeout(timeout, rx).await {
+        Ok(Ok(result)) => result,
+        Ok(Err(_)) => Err("Page script was cancelled".to_string()),
+        Err(_) => {
+            PENDING.lock().remove(&id);
+            Err("Timed out waiting for the page".to_string())
+        }
+    }
+}
+
+/// Wrap `expr` (a Promise) so its outcome is POSTed back with `id`
+fn report_script(id: u64, expr: &str) -> String {
+    format!(
+        r#"(function(){{var send=function(b){{b.id={id};fetch("/__yao_desktop/storage/result",{{method:"POST",headers:{{"Content-Type":"application/json"}},body:JSON.stringify(b)}})}};Promise.resolve().then(function(){{return {expr}}}).then(function(r){{send({{result:r}})}},function(e){{send({{error:String(e&&e.message||e)}})}})}})()"#,
+        id = id,
+        expr = expr,
+    )
+}
+
+const USAGE_JS: &str = r#"(async function(){var ls=0,n=localStorage.length;for(var i=0;i<n;i++){var k=localStorage.key(i);ls+=(k.length+(localStorage.getItem(k)||"").length)*2}var dbs=indexedDB.databases?(await indexedDB.databases()).map(function(d){return d.name}):[];var cn=self.caches?await caches.keys():[];var est=navigator.storage&&navigator.storage.estimate?await navigator.storage.estimate():{};return{origin:location.origin,local_storage_bytes:ls,local_storage_keys:n,indexed_db_databases:dbs,cache_names:cn,usage_bytes:est.usage==null?null:est.usage,quota_bytes:est.quota==null?null:est.quota}})()"#;
+
+/// JS that clears the given kinds; IndexedDB deletes resolve on success,
+/// error or blocked so an open connection can't hang the command.
+fn clear_script(kinds: &[StorageKind]) -> String {
+    let mut steps = Vec::new();
+    for kind in kinds {
+        steps.push(match kind {
+            StorageKind::LocalStorage => "localStorage.clear()",
+            StorageKind::IndexedDb => r#"(indexedDB.databases?indexedDB.databases():Promise.resolve([])).then(function(l){return Promise.all(l.map(function(d){return new Promise(function(r){var q=indexedDB.deleteDatabase(d.name);q.onsuccess=q.onerror=q.onblocked=function(){r()}})}))})"#,
+            StorageKind::CacheStorage => "(self.caches?caches.keys().then(function(k){return Promise.all(k.map(function(n){return caches.delete(n)}))}):null)",
+        });
+    }
+    format!("Promise.all([{}]).then(function(){{return true}})", steps.join(","))
+}
+
+/// Query approximate web storage usage of the CUI origin
+pub async fn usage(app: &AppHandle) -> Result<WebStorageUsage, String> {
+    let value = eval_in_cui(app, USAGE_JS).await?;
+    serde_json::from_value(value).map_err(|e| format!("Invalid storage report: {}", e))
+}
+
+/// Clear the given storage kinds on the CUI origin
+pub async fn clear(app: &AppHandle, kinds: &[StorageKind]) -> Result<(), String> {
+    eval_in_cui(app, &clear_script(kinds)).await?;
+    info!("Web storage cleared: {:?}", kinds);
+    Ok(())
+}
+
+/// Body of POST /__yao_desktop/storage/result
+#[derive(Debug, Deserialize)]
+pub struct StorageReport {
+    pub id: u64,
+    #[serde(default)]
+    pub result: Option<serde_json::Value>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+impl StorageReport {
+    /// Hand the report to the waiting command
+    pub fn deliver(self) -> bool {
+        let result = match self.error {
+            Some(e) => {
+                warn!("Page script failed: {}", e);
+                Err(e)
+            }
+            None => Ok(self.result.unwrap_or(serde_json::Value::Null)),
+        };
+        complete(self.id, result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_kinds_accepts_known_names() {
+        let names = vec!["localStorage".to_string(), "indexedDB".into(), "cacheStorage".into(), "indexedDB".into()];
+        assert_eq!(
+            parse_kinds(&names).unwrap(),
+            vec![StorageKind::LocalStorage, StorageKind::IndexedDb, StorageKind::CacheStorage]
+        );
+    }
+
+    #[test]
+    fn parse_kinds_rejects_unknown_and_empty() {
+        assert!(parse_kinds(&["cookies".to_string()]).unwrap_err().contains("cookies"));
+        assert!(parse_kinds(&["localstorage".to_string()]).is_err());
+        assert!(parse_kinds(&[]).is_err());
+    }
+
+    #[test]
+    fn clear_script_only_touches_requested_kinds() {
+        let js = clear_script(&[StorageKind::LocalStorage]);
+        assert!(js.contains("localStorage.clear()"));
+        assert!(!js.contains("deleteDatabase"));
+        assert!(!js.contains("caches"));
+
+        let js = clear_script(&[StorageKind::IndexedDb, StorageKind::CacheStorage]);
+        assert!(!js.contains("localStorage"));
+        assert!(js.contains("onblocked"));
+        assert!(js.contains("caches.delete"));
+    }
+
+    #[tokio::test]
+    async fn report_resolves_pending_request() {
+        let (tx, rx) = oneshot::channel();
+        PENDING.lock().insert(9_000_001, tx);
+        let report: StorageReport = serde_json::from_str(
+            r#"{"id":9000001,"result":{"origin":"http://127.0.0.1:15099","local_storage_bytes":42,"local_storage_keys":2,"indexed_db_databases":["umi"],"cache_names":[],"usage_bytes":null,"quota_bytes":1000}}"#,
+        ).unwrap();
+        assert!(report.deliver());
+        let usage: WebStorageUsage = serde_json::from_value(rx.await.unwrap().unwrap()).unwrap();
+        assert_eq!(usage.local_storage_bytes, 42);
+        assert_eq!(usage.indexed_db_databases, vec!["umi"]);
+        assert_eq!(usage.usage_bytes, None);
+
+        // Errors are passed through; unknown ids are ignored
+        let (tx, rx) = oneshot::channel();
+        PENDING.lock().insert(9_000_002, tx);
+        let report: StorageReport = serde_json::from_str(r#"{"id":9000002,"error":"SecurityError"}"#).unwrap();
+        assert!(report.deliver());
+        assert_eq!(rx.await.unwrap(), Err("SecurityError".to_string()));
+        assert!(!complete(9_000_003, Ok(serde_json::Value::Null)));
+    }
+}
@@ -0,0 +1,244 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tauri::{Manager, PhysicalPosition, PhysicalSize};
+use tracing::{info, warn};
+
+use crate::app_conf::WindowConf;
+
+/// Persisted main window geometry and mode
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WindowState {
+    /// Outer position/size of the normal (non-maximized) window, physical pixels
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    #[serde(default)]
+    pub maximized: bool,
+    #[serde(default)]
+    pub fullscreen: bool,
+    /// Name of the monitor the window was on
+    #[serde(default)]
+    pub monitor: Option<String>,
+}
+
+/// A connected monitor's name and bounds, physical pixels
+#[derive(Debug, Clone)]
+struct MonitorRect {
+    name: Option<String>,
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+}
+
+/// window-state.json in the app data directory
+fn state_file(app: &tauri::AppHandle) -> Option<PathBuf> {
+    app.path().app_data_dir().ok().map(|d| d.join("window-state.json"))
+}
+
+/// Load a saved state; None if missing or unreadable
+pub fn load(path: &Path) -> Option<WindowState> {
+    let data = std::fs::read_to_string(path).ok()?;
+    match serde_json::from_str(&data) {
+        Ok(state) => Some(state),
+        Err(e) => {
+            warn!("Ignoring invalid window state {:?}: {}", path, e);
+            None
+        }
+    }
+}
+
+/// Write the state to disk
+pub fn save(path: &Path, state: &WindowState) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create {:?}: {}", dir, e))?;
+    }
+    let data = serde_json::to_string_pretty(state)
+        .map_err(|e| format!("Failed to serialize window state: {}", e))?;
+    std::fs::write(path, data).map_err(|e| format!("Failed to write {:?}: {}", path, e))
+}
+
+/// Record the main window's current state. While maximized/fullscreen the
+/// previously saved normal geometry is kept, so un-maximizing after a restart
+/// returns to the size the user chose. A kiosk saves nothing.
+pub fn save_main(app: &tauri::AppHandle) {
+    if crate::config::kiosk() {
+        return;
+    }
+    let (Some(win), Some(path)) = (app.get_webview_window("main"), state_file(app)) else {
+        return;
+    };
+    let maximized = win.is_maximized().unwrap_or(false);
+    let fullscreen = win.is_fullscreen().unwrap_or(false);
+    let previous = load(&path);
+
+    let (x, y, width, height) = match (&previous, maximized || fullscreen) {
+        (Some(p), true) => (p.x, p.y, p.width, p.height),
+        _ => {
+            let (Ok(pos), Ok(size)) = (win.outer_position(), win.outer_size()) else {
+                return;
+            };
+            (pos.x, pos.y, size.width, size.height)
+        }
+    };
+    let monitor = win.current_monitor().ok().flatten()
+        .and_then(|m| m.name().cloned())
+        .or_else(|| previous.and_then(|p| p.monitor));
+
+    let state = WindowState { x, y, width, height, maximized, fullscreen, monitor };
+    if let Err(e) = save(&path, &state) {
+        warn!("{}", e);
+    }
+}
+
+/// Reapply the saved state to the freshly built main window. If the saved
+/// monitor is gone (or the window would land off-screen) the window stays
+/// centered and non-maximized. A kiosk stays fullscreen.
+pub fn restore_main(win: &tauri::WebviewWindow) {
+    if crate::config::kiosk() {
+        return;
+    }
+    let Some(state) = state_file(win.app_handle()).and_then(|p| load(&p)) else {
+        return;
+    };
+    let monitors: Vec<MonitorRect> = win.available_monitors()
+        .unwrap_or_default()
+        .iter()
+        .map(|m| MonitorRect {
+            name: m.name().cloned(),
+            x: m.position().x,
+            y: m.position().y,
+            width: m.size().width,
+            height: m.size().height,
+        })
+        .collect();
+
+    if !fits_monitors(&state, &monitors) {
+        info!("Saved window monitor unavailable, using centered default");
+        return;
+    }
+
+    let _ = win.set_size(PhysicalSize::new(state.width, state.height));
+    let _ = win.set_position(PhysicalPosition::new(state.x, state.y));
+    if state.fullscreen {
+        let _ = win.set_fullscreen(true);
+    } else if state.maximized {
+        let _ = win.maximize();
+    }
+    info!("Restored window state (maximized={}, fullscreen={})", state.maximized, state.fullscreen);
+}
+
+/// Room left for the title bar and window frame around the inner size
+const FRAME_ALLOWANCE: f64 = 40.0;
+
+/// The configured main window size, shrunk to fit a work area of `area`
+/// (logical width × height, the screen minus taskbar/dock) so the window
+/// never opens larger than the screen. The minimum shrinks along with it.
+pub fn fit_to_work_area(conf: WindowConf, area: Option<(f64, f64)>) -> WindowConf {
+    let Some((area_width, area_height)) = area.filter(|&(w, h)| w > 0.0 && h > FRAME_ALLOWANCE) else {
+        return conf;
+    };
+    let width = conf.width.min(area_width);
+    let height = conf.height.min(area_height - FRAME_ALLOWANCE);
+    WindowConf {
+        width,
+        height,
+        min_width: conf.min_width.min(width),
+        min_height: conf.min_height.min(height),
+    }
+}
+
+/// Whether the saved monitor is still connected and the window's title-bar
+/// area lies on it
+fn fits_monitors(state: &WindowState, monitors: &[MonitorRect]) -> bool {
+    if state.width == 0 || state.height == 0 {
+        return false;
+    }
+    monitors.iter()
+        .filter(|m| state.monitor.is_none() || m.name == state.monitor)
+        .any(|m| {
+            // Require the top-left 100×40 of the window to be visible
+            let (mx2, my2) = (m.x + m.width as i32, m.y + m.height as i32);
+            state.x + 100 > m.x && state.x < mx2 - 100 && state.y >= m.y && state.y < my2 - 40
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> WindowState {
+        WindowState {
+            x: 120,
+            y: 80,
+            width: 1280,
+            height: 860,
+            maximized: true,
+            fullscreen: false,
+            monitor: Some("DELL U2720Q".into()),
+        }
+    }
+
+    fn monitor(name: &str, x: i32) -> MonitorRect {
+        MonitorRect { name: Some(name.into()), x, y: 0, width: 2560, height: 1440 }
+    }
+
+    #[test]
+    fn save_load_round_trip() {
+        let path = std::env::temp_dir()
+            .join(format!("cui-window-state-{}", std::process::id()))
+            .join("window-state.json");
+        save(&path, &sample()).unwrap();
+        assert_eq!(load(&path), Some(sample()));
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn load_tolerates_missing_fields_and_garbage() {
+        let state: WindowState = serde_json::from_str(r#"{"x":1,"y":2,"width":3,"height":4}"#).unwrap();
+        assert!(!state.maximized && !state.fullscreen && state.monitor.is_none());
+
+        let path = std::env::temp_dir().join(format!("cui-window-state-bad-{}.json", std::process::id()));
+        std::fs::write(&path, "not json").unwrap();
+        assert_eq!(load(&path), None);
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(load(&path), None);
+    }
+
+    #[test]
+    fn fits_monitors_requires_saved_monitor() {
+        let state = sample();
+        assert!(fits_monitors(&state, &[monitor("DELL U2720Q", 0)]));
+        // Monitor disconnected: fall back to the default window
+        assert!(!fits_monitors(&state, &[monitor("Built-in Retina Display", 0)]));
+        assert!(!fits_monitors(&state, &[]));
+    }
+
+    #[test]
+    fn fits_monitors_rejects_off_screen_position() {
+        let mut state = sample();
+        state.x = 5000;
+        assert!(!fits_monitors(&state, &[monitor("DELL U2720Q", 0)]));
+        assert!(fits_monitors(&state, &[monitor("DELL U2720Q", 2560)]));
+        state.monitor = None;
+        state.x = 100;
+        assert!(fits_monitors(&state, &[monitor("Any", 0)]));
+    }
+
+    #[test]
+    fn initial_size_fits_the_work_area() {
+        let conf = WindowConf::default();
+        assert_eq!(fit_to_work_area(conf, None), conf);
+        assert_eq!(fit_to_work_area(conf, Some((2560.0, 1415.0))), conf);
+        // 1366×768 laptop with a 40px taskbar
+        assert_eq!(
+            fit_to_work_area(conf, Some((1366.0, 728.0))),
+            WindowConf { width: 1280.0, height: 688.0, min_width: 900.0, min_height: 600.0 }
+        );
+        assert_eq!(
+            fit_to_work_area(conf, Some((800.0, 600.0))),
+            WindowConf { width: 800.0, height: 560.0, min_width: 800.0, min_height: 560.0 }
+        );
+    }
+}
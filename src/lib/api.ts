@@ -1,21 +1,95 @@
 import { invoke } from "@tauri-apps/api/core";
+import { listen, type UnlistenFn } from "@tauri-apps/api/event";
 
 export interface AppConf {
   name: string;
+  /** Resolved for the current theme from theme.logoLight / theme.logoDark */
   logo: string;
   port: number;
   theme: {
     primaryColor: string;
+    logoLight?: string;
+    logoDark?: string;
+    trayIcon?: string;
   };
   updater: {
     active: boolean;
     endpoints: string[];
     pubkey: string;
   };
+  oauth: {
+    clientId: string;
+    scope: string;
+    authorizationEndpoint: string;
+    tokenEndpoint: string;
+    systemBrowser: boolean;
+  };
   servers: Array<{
     url: string;
     label: string;
   }>;
+  linkBehavior: "popup" | "same_window" | "external_browser";
+  /** Sign-in hosts kept in the app; `*.example.com` matches subdomains, `github.com/login` a path prefix */
+  navigationAllowlist: string[];
+  untrustedNavigation: "open_in_browser" | "confirm";
+  proxy: {
+    bufferThreshold: number;
+    requestTimeoutSecs: number;
+    maxBodyMb: number;
+    maxRetries: number;
+    rewriteBodyUrls: boolean;
+    gzipStatic: boolean;
+    staticCacheMb: number;
+    requestLog: boolean;
+    healthCheckSecs: number;
+    healthCheckPath: string;
+    maxConcurrentUpstream: number;
+    upstreamQueueSecs: number;
+    sseHeartbeatSecs: number;
+    permissiveCors: boolean;
+    localHost: "127.0.0.1" | "localhost" | "[::1]";
+    stripRequestHeaders: string[];
+    stripResponseHeaders: string[];
+  };
+  window: {
+    width: number;
+    height: number;
+    minWidth: number;
+    minHeight: number;
+  };
+  trayMenu: Array<{
+    id?: string;
+    label: string;
+    action: "open_url" | "navigate" | "command";
+    target: string;
+  }>;
+  downloadExtensionPolicy: {
+    mode: "allow_all" | "allowlist" | "blocklist";
+    extensions: string[];
+  };
+  pathRewrites: Array<{
+    fromPrefix: string;
+    toPrefix: string;
+  }>;
+  routes: Array<{
+    prefix: string;
+    target: string;
+    forwardAuth?: boolean;
+  }>;
+  logoutOnQuit: boolean;
+  closeToTray: boolean;
+  kiosk: boolean;
+  notificationsEnabled: boolean;
+  shortcut: string;
+  downloadDir: string;
+  promptSaveAs: boolean;
+  openAfterDownload: boolean;
+  downloadPaths: string[];
+  headers: Record<string, string>;
+  allowInvalidCerts: boolean;
+  caCertPath: string;
+  /** Proxy for the Yao server, password removed */
+  upstreamProxy: string | null;
 }
 
 export interface WellKnownInfo {
@@ -24,6 +98,76 @@ export interface WellKnownInfo {
   openapi: string | null;
   dashboard: string | null;
   issuer_url: string | null;
+  /** Set when the URL was normalized or the server only answered over http://; connect with this URL */
+  effective_url?: string | null;
+}
+
+export interface WindowInfo {
+  label: string;
+  url: string;
+  title: string;
+  visible: boolean;
+  focused: boolean;
+  fullscreen: boolean;
+  is_popup: boolean;
+}
+
+export interface StartupTiming {
+  milestone:
+    | "config_loaded"
+    | "tray_ready"
+    | "window_built"
+    | "proxy_bound"
+    | "proxy_ready"
+    | "first_paint";
+  elapsed_ms: number;
+}
+
+export interface CookieLoadError {
+  error: string;
+  recovered: boolean;
+}
+
+export interface WebStorageUsage {
+  origin: string;
+  local_storage_bytes: number;
+  local_storage_keys: number;
+  indexed_db_databases: string[];
+  cache_names: string[];
+  usage_bytes: number | null;
+  quota_bytes: number | null;
+}
+
+export type WebStorageKind = "localStorage" | "indexedDB" | "cacheStorage";
+
+export type DownloadStatus = "pending" | "downloading" | "completed" | "failed" | "cancelled";
+
+export interface DownloadInfo {
+  id: number;
+  url: string;
+  filename: string;
+  path: string | null;
+  status: DownloadStatus;
+  downloaded: number;
+  total: number;
+  error: string | null;
+}
+
+/** Rejection value of check_server, start_proxy and login_oauth */
+export interface CommandError {
+  kind: "network" | "http_status" | "parse" | "unauthorized" | "config" | "internal";
+  message: string;
+  /** Connection failures only */
+  cause?: "dns" | "refused" | "tls" | "timeout" | "other";
+  /** The URL is https and http:// may work */
+  http_may_help?: boolean;
+}
+
+export interface LoginResult {
+  success: boolean;
+  message: string;
+  token: string;
+  auth_mode: string;
 }
 
 export interface ProxyStatus {
@@ -32,6 +176,13 @@ export interface ProxyStatus {
   server_url: string;
   token: string;
   auth_mode: string;
+  dashboard: string;
+  /** OAuth issuer from .well-known/yao, empty if unknown */
+  issuer_url: string;
+  /** Token expiry (Unix seconds), 0 = unknown */
+  token_expires_at: number;
+  /** Last reachability check of the server, null until the first one */
+  server_online: boolean | null;
 }
 
 /** Get developer app config (config.json) */
@@ -39,19 +190,84 @@ export async function getAppConf(): Promise<AppConf> {
   return invoke<AppConf>("get_app_conf");
 }
 
-/** Check remote server availability */
+export async function reloadAppConf(): Promise<AppConf> {
+  return invoke<AppConf>("reload_app_conf");
+}
+
+/** A log line kept in memory for bug reports */
+export interface LogEntry {
+  /** Unix time in milliseconds */
+  time: number;
+  level: string;
+  target: string;
+  message: string;
+}
+
+/** The app's log level ("error" … "trace"), or the RUST_LOG filter it started with */
+export async function getLogLevel(): Promise<string> {
+  return invoke<string>("get_log_level");
+}
+
+/** Change the log level until the app quits; resolves to the level as set */
+export async function setLogLevel(level: "error" | "warn" | "info" | "debug" | "trace"): Promise<string> {
+  return invoke<string>("set_log_level", { level });
+}
+
+/** Recent log lines, oldest first (the last `limit`, or all that are kept) */
+export async function getRecentLogs(limit?: number): Promise<LogEntry[]> {
+  return invoke<LogEntry[]>("get_recent_logs", { limit: limit ?? null });
+}
+
+/** Show the log files in the OS file manager */
+export async function openLogDir(): Promise<void> {
+  return invoke<void>("open_log_dir");
+}
+
+/** User-level preferences saved in the app data dir (empty / null = unset) */
+export interface UserPreferences {
+  /** umi locale, e.g. "zh-CN" */
+  locale: string;
+  theme: "" | "dark" | "light" | "auto";
+  zoom: number | null;
+  /** Base font size in px, 8–32 */
+  font_size: number | null;
+  /** Keep the sign-in across restarts; null = on */
+  remember_session: boolean | null;
+}
+
+export async function getUserPreferences(): Promise<UserPreferences> {
+  return invoke<UserPreferences>("get_user_preferences");
+}
+
+/** Save preferences; resolves to the stored (normalized) values */
+export async function setUserPreferences(prefs: UserPreferences): Promise<UserPreferences> {
+  return invoke<UserPreferences>("set_user_preferences", { prefs });
+}
+
+/** Problems found in config.json; invalid fields fall back to defaults */
+export async function getConfigErrors(): Promise<string[]> {
+  return invoke<string[]>("get_config_errors");
+}
+
+/** Check remote server availability (rejects with a CommandError) */
 export async function checkServer(serverUrl: string): Promise<WellKnownInfo> {
   return invoke<WellKnownInfo>("check_server", { serverUrl });
 }
 
-/** Start the local proxy server */
+/** Start the local proxy server (rejects with a CommandError) */
 export async function startProxy(
   serverUrl: string,
   token: string,
   authMode: string,
-  dashboard: string = ""
+  dashboard: string = "",
+  issuerUrl: string = ""
 ): Promise<number> {
-  return invoke<number>("start_proxy", { serverUrl, token, authMode, dashboard });
+  return invoke<number>("start_proxy", { serverUrl, token, authMode, dashboard, issuerUrl });
+}
+
+/** Point the running proxy at another server; the local port stays the same */
+export async function switchServer(serverUrl: string, token = "", authMode = ""): Promise<number> {
+  return invoke<number>("switch_server", { serverUrl, token, authMode });
 }
 
 /** Get current proxy status */
@@ -60,8 +276,56 @@ export async function getProxyStatus(): Promise<ProxyStatus> {
 }
 
 /** Update the proxy auth token */
-export async function updateProxyToken(token: string): Promise<void> {
-  return invoke<void>("update_proxy_token", { token });
+export async function updateProxyToken(token: string, expiresAt?: number): Promise<void> {
+  return invoke<void>("update_proxy_token", { token, expiresAt });
+}
+
+/**
+ * Sign in with OAuth (authorization code + PKCE) using `oauth` from config.json.
+ * Requires a running proxy; resolves once the tokens are stored in it.
+ * Rejects with a CommandError (`unauthorized` if sign-in was refused or cancelled).
+ */
+export async function loginOAuth(): Promise<LoginResult> {
+  return invoke<LoginResult>("login_oauth");
+}
+
+/** Refresh the auth token now (emits `session://expired` on failure) */
+export async function refreshToken(): Promise<void> {
+  return invoke<void>("refresh_token");
+}
+
+export interface SessionInfo {
+  authenticated: boolean;
+  /** User-info payload as returned by the server */
+  user: Record<string, unknown> | null;
+  roles: string[];
+  /** Token expiry (Unix seconds); 0 = unknown */
+  expires_at: number;
+}
+
+/** Who the current session belongs to; `authenticated: false` when the server rejects it */
+export async function sessionInfo(): Promise<SessionInfo> {
+  return invoke<SessionInfo>("session_info");
+}
+
+export interface DiagnosticStep {
+  name: "well_known" | "tls" | "user_entry" | "local_port" | "proxy_round_trip";
+  status: "pass" | "fail" | "skip";
+  /** What was found, or why the step failed */
+  detail: string;
+  millis: number;
+}
+
+export interface DiagnosticReport {
+  server_url: string;
+  /** No step failed */
+  ok: boolean;
+  steps: DiagnosticStep[];
+}
+
+/** Check each hop to a server for a support report; doesn't change the proxy (rejects with a CommandError) */
+export async function diagnose(serverUrl: string): Promise<DiagnosticReport> {
+  return invoke<DiagnosticReport>("diagnose", { serverUrl });
 }
 
 /** Clear all stored cookies */
@@ -69,16 +333,96 @@ export async function clearCookies(): Promise<void> {
   return invoke<void>("clear_cookies");
 }
 
+export interface CookieImport {
+  imported: number;
+  /** Malformed, expired or redacted entries */
+  skipped: number;
+}
+
+/** Cookie jar as JSON; HttpOnly and __Secure-/__Host- values are hidden unless `redact` is false in a debug build */
+export async function exportCookies(redact = true): Promise<string> {
+  return invoke<string>("export_cookies", { redact });
+}
+
+export interface CookieInfo {
+  name: string;
+  domain: string;
+  path: string;
+  /** Unix seconds, 0 = session cookie */
+  expires_at: number;
+  http_only: boolean;
+  /** Masked unless revealed */
+  value: string;
+  value_len: number;
+}
+
+/** Cookie jar with masked values; `reveal` only works in debug builds */
+export async function getCookies(reveal = false): Promise<CookieInfo[]> {
+  return invoke<CookieInfo[]>("get_cookies", { reveal });
+}
+
+/** Remove a cookie by name (on one domain when given); resolves to the number removed */
+export async function deleteCookie(name: string, domain?: string): Promise<number> {
+  return invoke<number>("delete_cookie", { name, domain });
+}
+
+/** Merge cookies from exportCookies() JSON into the jar */
+export async function importCookies(json: string): Promise<CookieImport> {
+  return invoke<CookieImport>("import_cookies", { json });
+}
+
+/** End the session: revoke it on the server (best-effort), clear token and cookies */
+export async function logout(): Promise<void> {
+  return invoke<void>("logout");
+}
+
+/** Notified when the persisted cookie jar could not be parsed on proxy start */
+/** Payload of `server://online` / `server://offline` */
+export interface ServerHealth {
+  server_url: string;
+  /** Why the last check failed (offline only) */
+  error: string | null;
+}
+
+/** Follow server reachability changes reported by the proxy's health checks */
+export async function onServerHealth(cb: (online: boolean, health: ServerHealth) => void): Promise<UnlistenFn> {
+  const unlistenOnline = await listen<ServerHealth>("server://online", (event) => cb(true, event.payload));
+  const unlistenOffline = await listen<ServerHealth>("server://offline", (event) => cb(false, event.payload));
+  return () => {
+    unlistenOnline();
+    unlistenOffline();
+  };
+}
+
+export async function onCookieLoadError(cb: (e: CookieLoadError) => void): Promise<UnlistenFn> {
+  return listen<CookieLoadError>("cookie-load-error", (event) => cb(event.payload));
+}
+
 /** Set user preference cookies (__locale, __theme) in the proxy cookie jar */
 export async function setPreferenceCookies(locale: string, theme: string): Promise<void> {
   return invoke<void>("set_preference_cookies", { locale, theme });
 }
 
-/** Set the window theme (title bar) for all windows */
+/**
+ * Save the theme ("dark", "light" or "auto") to the preferences and `__theme` cookie
+ * and set the title bar of all windows; "auto" follows the system theme
+ */
 export async function setWindowTheme(theme: string): Promise<void> {
   return invoke<void>("set_window_theme", { theme });
 }
 
+/** Payload of `theme://changed` */
+export interface ThemeChanged {
+  /** "dark" or "light" */
+  theme: string;
+  preference: string;
+}
+
+/** The system theme changed while the theme preference is "auto" */
+export async function onThemeChanged(cb: (e: ThemeChanged) => void): Promise<UnlistenFn> {
+  return listen<ThemeChanged>("theme://changed", (event) => cb(event.payload));
+}
+
 /** Sync UI language to Rust side (persists + rebuilds tray menu) */
 export async function setUiLanguage(lang: string): Promise<void> {
   return invoke<void>("set_ui_language", { lang });
@@ -89,3 +433,119 @@ export async function syncPreferences(theme: string, lang: string): Promise<void
   return invoke<void>("sync_preferences", { theme, lang });
 }
 
+/** List all open webview windows with their URL and state */
+export async function getWindows(): Promise<WindowInfo[]> {
+  return invoke<WindowInfo[]>("get_windows");
+}
+
+/** Open popup windows, oldest first */
+export async function listWindows(): Promise<WindowInfo[]> {
+  return invoke<WindowInfo[]>("list_windows");
+}
+
+/** Close a popup window by label; other windows are refused */
+export async function closeWindow(label: string): Promise<void> {
+  return invoke("close_window", { label });
+}
+
+export interface PdfOptions {
+  page_size?: "a4" | "a3" | "letter" | "legal";
+  landscape?: boolean;
+  /** Margin on every side in millimetres, 0–50 (default 10) */
+  margin_mm?: number;
+}
+
+export interface PrintResult {
+  /** Where the PDF was saved; null when the print dialog was shown instead */
+  path: string | null;
+  dialog: boolean;
+}
+
+/** Save the focused window's page as a PDF in the download directory, or open the print dialog */
+export async function printToPdf(options?: PdfOptions): Promise<PrintResult> {
+  return invoke<PrintResult>("print_to_pdf", { options });
+}
+
+/** Fired with the saved file's path when `printToPdf` has written a PDF */
+export async function onPrintDone(cb: (path: string) => void): Promise<UnlistenFn> {
+  return listen<{ path: string }>("print://done", (event) => cb(event.payload.path));
+}
+
+/** Cold-start milestones recorded so far (ms since process start) */
+export async function getStartupTimings(): Promise<StartupTiming[]> {
+  return invoke<StartupTiming[]>("get_startup_timings");
+}
+
+/** Approximate web storage usage of the CUI origin */
+export async function getWebStorageUsage(): Promise<WebStorageUsage> {
+  return invoke<WebStorageUsage>("get_web_storage_usage");
+}
+
+/** Clear selected web storage kinds on the CUI origin */
+export async function clearWebStorage(kinds: WebStorageKind[]): Promise<void> {
+  return invoke<void>("clear_web_storage", { kinds });
+}
+
+/** Quit the app like the tray "Quit" (logout defaults to config `logoutOnQuit`); rejected in kiosk mode */
+export async function quitApp(logout?: boolean): Promise<void> {
+  return invoke<void>("quit_app", { logout });
+}
+
+/** Downloads started by the app this session, oldest first */
+export async function getDownloads(): Promise<DownloadInfo[]> {
+  return invoke<DownloadInfo[]>("get_downloads");
+}
+
+/** Change where downloads are saved (absolute path, "" = system Downloads); persisted */
+export async function setDownloadDir(dir: string): Promise<void> {
+  return invoke<void>("set_download_dir", { dir });
+}
+
+/** Abort an in-flight download and delete its partial file */
+export async function cancelDownload(id: number): Promise<void> {
+  return invoke<void>("cancel_download", { id });
+}
+
+/** Restart a failed or cancelled download */
+export async function retryDownload(id: number): Promise<void> {
+  return invoke<void>("retry_download", { id });
+}
+
+/** Notified whenever a download's status or progress changes */
+export async function onDownloadUpdated(cb: (d: DownloadInfo) => void): Promise<UnlistenFn> {
+  return listen<DownloadInfo>("download://updated", (event) => cb(event.payload));
+}
+
+export interface UpdateInfo {
+  available: boolean;
+  /** Announced version, or the running one when no update is available */
+  version: string;
+  notes: string | null;
+  date: string | null;
+}
+
+/** Rejection value of checkForUpdate / installUpdate */
+export interface UpdateError {
+  kind: "disabled" | "no_update" | "network" | "config" | "signature" | "other";
+  message: string;
+}
+
+export interface UpdateProgress {
+  downloaded: number;
+  /** 0 if unknown */
+  total: number;
+}
+
+/** Check the update endpoints from config.json (requires updater.active) */
+export async function checkForUpdate(): Promise<UpdateInfo> {
+  return invoke<UpdateInfo>("check_for_update");
+}
+
+/** Download and install the available update; resolves to its version. Restart to apply. */
+export async function installUpdate(): Promise<string> {
+  return invoke<string>("install_update");
+}
+
+export async function onUpdateProgress(cb: (p: UpdateProgress) => void): Promise<UnlistenFn> {
+  return listen<UpdateProgress>("update://progress", (event) => cb(event.payload));
+}
@@ -13,6 +13,7 @@
 
 const zhCN: Record<string, string> = {
   "app.no_servers": "尚未添加服务器",
+  "app.config_errors": "config.json 存在问题，相关设置已使用默认值：",
   "app.add_server": "+ 添加服务器",
   "app.server_url_placeholder": "服务器地址，如 https://app.example.com",
   "app.server_name_placeholder": "名称（可选）",
@@ -23,6 +24,9 @@ const zhCN: Record<string, string> = {
   "app.starting_proxy": "正在启动代理…",
   "app.connected": "已连接！正在加载 CUI…",
   "app.connection_failed": "连接失败：",
+  "app.try_http": "如果服务器未启用 HTTPS，请尝试 http:// 地址。",
+  "app.cookies_restored": "登录信息文件已损坏，已从备份恢复。",
+  "app.cookies_lost": "登录信息文件已损坏，请重新登录。",
   "app.enter_url": "请输入服务器地址",
   "app.default": "默认",
   "app.remove": "移除",
@@ -59,11 +63,14 @@ const zhCN: Record<string, string> = {
   "settings.downloading": "正在下载更新…",
   "settings.restart_now": "立即重启",
   "settings.update_error": "检查更新失败",
+  "settings.update_network_error": "无法连接更新服务器",
+  "settings.updates_disabled": "未启用自动更新",
   "settings.close": "关闭",
 };
 
 const enUS: Record<string, string> = {
   "app.no_servers": "No servers yet",
+  "app.config_errors": "config.json has problems; defaults are used for these settings:",
   "app.add_server": "+ Add server",
   "app.server_url_placeholder": "Server URL, e.g. https://app.example.com",
   "app.server_name_placeholder": "Name (optional)",
@@ -74,6 +81,9 @@ const enUS: Record<string, string> = {
   "app.starting_proxy": "Starting proxy…",
   "app.connected": "Connected! Loading CUI…",
   "app.connection_failed": "Connection failed: ",
+  "app.try_http": "If the server doesn't use HTTPS, try its http:// address.",
+  "app.cookies_restored": "Saved session file was corrupt and has been restored from backup.",
+  "app.cookies_lost": "Saved session file was corrupt. Please sign in again.",
   "app.enter_url": "Please enter a server URL.",
   "app.default": "Default",
   "app.remove": "Remove",
@@ -110,6 +120,8 @@ const enUS: Record<string, string> = {
   "settings.downloading": "Downloading update…",
   "settings.restart_now": "Restart Now",
   "settings.update_error": "Update check failed",
+  "settings.update_network_error": "Could not reach the update server",
+  "settings.updates_disabled": "Updates are not enabled for this build",
   "settings.close": "Close",
 };
 
@@ -135,7 +147,7 @@ export function setLang(lang: string): void {
   localStorage.setItem(LANG_KEY, lang);
   import("./api").then((api) => {
     api.setUiLanguage(lang).catch(() => {});
-    api.syncPreferences(getTheme(), lang).catch(() => {});
+    api.syncPreferences(getThemePreference(), lang).catch(() => {});
   }).catch(() => {});
 }
 
@@ -161,6 +173,12 @@ export function getTheme(): string {
   return window.matchMedia("(prefers-color-scheme: dark)").matches ? "dark" : "light";
 }
 
+/** The theme preference: "light", "dark", or "auto" (follow the system) */
+export function getThemePreference(): string {
+  const saved = localStorage.getItem(THEME_KEY);
+  return saved === "light" || saved === "dark" ? saved : "auto";
+}
+
 /** Set theme override, persist, and broadcast to all windows */
 export function setTheme(theme: string): void {
   localStorage.setItem(THEME_KEY, theme);
@@ -174,9 +192,10 @@ export function setTheme(theme: string): void {
 export function applyTheme(): void {
   const theme = getTheme();
   document.documentElement.setAttribute("data-theme", theme);
-  // Sync with Tauri window theme so the title bar follows the UI theme
+  // Sync with Tauri window theme so the title bar follows the UI theme;
+  // "auto" also keeps CUI pages in step with the system theme
   import("../lib/api").then(({ setWindowTheme }) => {
-    setWindowTheme(theme).catch(() => {});
+    setWindowTheme(getThemePreference()).catch(() => {});
   }).catch(() => {});
 }
 
@@ -185,8 +204,13 @@ export function getThemeForCUI(): string {
   return getTheme() === "dark" ? "dark" : "";
 }
 
-// Apply on load
+// Apply on load, and again when the system theme changes under "auto"
 applyTheme();
+import("./api").then(({ onThemeChanged }) => {
+  onThemeChanged(({ theme }) => {
+    if (getThemePreference() === "auto") document.documentElement.setAttribute("data-theme", theme);
+  }).catch(() => {});
+}).catch(() => {});
 
 // Sync initial language to Rust (tray menu)
 import("./api").then((api) => api.setUiLanguage(getLang())).catch(() => {});
@@ -1,4 +1,4 @@
-import { getProxyStatus, setPreferenceCookies } from "../lib/api";
+import { getAppConf, getProxyStatus, setPreferenceCookies } from "../lib/api";
 import { navigate } from "../lib/router";
 import { t, getLocaleForCUI, getThemeForCUI } from "../lib/i18n";
 
@@ -38,9 +38,18 @@ export async function renderApp(): Promise<void> {
   try {
     await setPreferenceCookies(locale, theme);
   } catch { /* ignore */ }
+  let primaryColor = "";
+  let localHost = "127.0.0.1";
+  try {
+    const conf = await getAppConf();
+    primaryColor = conf.theme?.primaryColor || "";
+    localHost = conf.proxy?.localHost || localHost;
+  } catch { /* ignore */ }
 
   setTimeout(() => {
-    const bridge = `http://127.0.0.1:${port}/__yao_bridge?locale=${encodeURIComponent(cuiLocale)}&theme=${encodeURIComponent(theme)}`;
+    const params = new URLSearchParams({ locale: cuiLocale, theme });
+    if (primaryColor) params.set("primaryColor", primaryColor);
+    const bridge = `http://${localHost}:${port}/__yao_bridge?${params}`;
     window.location.href = bridge;
   }, 300);
 }
@@ -1,4 +1,4 @@
-import { getAppConf, checkServer, startProxy, type AppConf } from "../lib/api";
+import { getAppConf, getConfigErrors, checkServer, startProxy, onCookieLoadError, type AppConf, type CommandError, type CookieLoadError } from "../lib/api";
 import { getSettings, saveServer, removeServer, type ServerEntry } from "../lib/store";
 import { navigate } from "../lib/router";
 import { t, getLang, setLang, getTheme, setTheme } from "../lib/i18n";
@@ -29,8 +29,10 @@ export async function renderServers(): Promise<void> {
   };
 
   let conf = DEFAULT_CONF;
+  let configErrors: string[] = [];
   try {
     conf = await getAppConf();
+    configErrors = await getConfigErrors();
   } catch {
     // Use defaults if Tauri bridge not ready
   }
@@ -62,6 +64,12 @@ export async function renderServers(): Promise<void> {
           <a class="brand-link" href="https://yaoagents.com" target="_blank">yaoagents.com</a>
         </div>
 
+        ${configErrors.length > 0 ? `
+        <div class="alert alert-error config-errors">
+          ${escapeHtml(t("app.config_errors"))}
+          <ul>${configErrors.map(e => `<li>${escapeHtml(e)}</li>`).join("")}</ul>
+        </div>` : ""}
+
         <div id="alert-area"></div>
 
         <div class="server-list" id="server-list">
@@ -193,16 +201,38 @@ async function doConnect(url: string, label: string, alertArea: HTMLElement) {
   try {
     let name = label || url.replace(/^https?:\/\//, "");
     let dashboard = "";
+    let issuerUrl = "";
     try {
       const info = await checkServer(url);
       if (info.name) name = info.name;
       if (info.dashboard) dashboard = info.dashboard;
-    } catch { /* older server */ }
+      if (info.issuer_url) issuerUrl = info.issuer_url;
+      if (info.effective_url) url = info.effective_url;
+    } catch (err) {
+      // Unreachable servers and invalid URLs fail here; other errors mean an older server
+      const e = err as CommandError;
+      if (e?.kind === "config") throw { message: e.message };
+      if (e?.cause) {
+        throw { message: e.http_may_help ? `${e.message} ${t("app.try_http")}` : e.message };
+      }
+    }
 
     await saveServer({ url, label: name, lastConnected: Date.now() });
 
     showAlert(alertArea, "info", t("app.starting_proxy"));
-    await startProxy(url, "", "openapi", dashboard);
+    const cookieErrors: CookieLoadError[] = [];
+    const unlisten = await onCookieLoadError((e) => cookieErrors.push(e));
+    try {
+      await startProxy(url, "", "openapi", dashboard, issuerUrl);
+    } finally {
+      unlisten();
+    }
+
+    if (cookieErrors.length > 0) {
+      showAlert(alertArea, "info", t(cookieErrors[0].recovered ? "app.cookies_restored" : "app.cookies_lost"));
+      setTimeout(() => navigate("/app"), 2500);
+      return;
+    }
 
     showAlert(alertArea, "success", t("app.connected"));
     setTimeout(() => navigate("/app"), 300);
@@ -1,10 +1,9 @@
-import { getAppConf, getProxyStatus, clearCookies } from "../lib/api";
+import { getAppConf, getProxyStatus, clearCookies, checkForUpdate, installUpdate, onUpdateProgress, type UpdateError } from "../lib/api";
 import { clearAll } from "../lib/store";
 import { t, getLang, setLang, getTheme, setTheme } from "../lib/i18n";
 import { getVersion } from "@tauri-apps/api/app";
 import { getCurrentWindow } from "@tauri-apps/api/window";
 import { getCurrentWebview } from "@tauri-apps/api/webview";
-import { check } from "@tauri-apps/plugin-updater";
 import { relaunch } from "@tauri-apps/plugin-process";
 
 let _settingsSyncCleanup: (() => void) | null = null;
@@ -24,9 +23,11 @@ export async function renderSettings(): Promise<void> {
   };
 
   let appName = "Yao Agents";
+  let localHost = "127.0.0.1";
   try {
     const c = await getAppConf();
     appName = c.name;
+    localHost = c.proxy?.localHost || localHost;
   } catch { /* ignore */ }
 
   let appVersion = "0.0.0";
@@ -95,7 +96,7 @@ export async function renderSettings(): Promise<void> {
         ${proxy.running ? `
         <div class="settings-item">
           <span class="label">${escapeHtml(t("settings.local"))}</span>
-          <span class="value">http://127.0.0.1:${proxy.port}</span>
+          <span class="value">http://${localHost}:${proxy.port}</span>
         </div>
         <div class="settings-item">
           <span class="label">${escapeHtml(t("settings.remote"))}</span>
@@ -144,11 +145,18 @@ export async function renderSettings(): Promise<void> {
     statusEl.textContent = t("settings.checking");
 
     try {
-      const update = await check();
-      if (update) {
+      const update = await checkForUpdate();
+      if (update.available) {
         statusEl.textContent = t("settings.new_version").replace("{version}", update.version);
         btn.textContent = t("settings.downloading");
-        await update.downloadAndInstall();
+        const unlisten = await onUpdateProgress(({ downloaded, total }) => {
+          if (total > 0) btn.textContent = `${t("settings.downloading")} ${Math.floor(downloaded * 100 / total)}%`;
+        });
+        try {
+          await installUpdate();
+        } finally {
+          unlisten();
+        }
         btn.textContent = t("settings.restart_now");
         btn.disabled = false;
         btn.onclick = async () => {
@@ -160,7 +168,8 @@ export async function renderSettings(): Promise<void> {
         btn.disabled = false;
       }
     } catch (err) {
-      statusEl.textContent = `${t("settings.update_error")}: ${err}`;
+      statusEl.textContent = updateErrorText(err as UpdateError);
+      btn.textContent = t("settings.check_update");
       btn.disabled = false;
     }
   });
@@ -191,3 +200,13 @@ function escapeHtml(s: string): string {
   d.textContent = s;
   return d.innerHTML;
 }
+
+function updateErrorText(err: UpdateError | string): string {
+  if (typeof err === "string") return `${t("settings.update_error")}: ${err}`;
+  switch (err.kind) {
+    case "disabled": return t("settings.updates_disabled");
+    case "no_update": return t("settings.up_to_date");
+    case "network": return `${t("settings.update_network_error")}: ${err.message}`;
+    default: return `${t("settings.update_error")}: ${err.message}`;
+  }
+}